@@ -0,0 +1,77 @@
+//! Synthetic jj repo shared by the benches
+
+use jj_lib::backend::{CopyId, TreeValue};
+use jj_lib::config::{ConfigLayer, ConfigSource, StackedConfig};
+use jj_lib::merge::Merge;
+use jj_lib::merged_tree::MergedTreeBuilder;
+use jj_lib::op_store::RefTarget;
+use jj_lib::ref_name::{RefName, WorkspaceName};
+use jj_lib::repo::Repo;
+use jj_lib::repo_path::RepoPathBuf;
+use jj_lib::settings::UserSettings;
+use jj_lib::workspace::Workspace;
+use pollster::FutureExt;
+use std::env;
+use std::path::Path;
+
+/// Value of an env knob, `default` if unset or not a number
+pub fn env_or(var: &str, default: u64) -> u64 {
+    env::var(var)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn settings() -> UserSettings {
+    let mut config = StackedConfig::with_defaults();
+    let mut user = ConfigLayer::empty(ConfigSource::User);
+    user.set_value("user.name", "bench").unwrap();
+    user.set_value("user.email", "bench@localhost").unwrap();
+    config.add_layer(user);
+    UserSettings::from_config(config).unwrap()
+}
+
+/// Linear history of `commits` commits touching a file each, bookmarks every 100
+pub fn build_repo(root: &Path, commits: u64) {
+    let (_, repo) = Workspace::init_simple(&settings(), root).unwrap();
+    let mut tx = repo.start_transaction();
+    let store = repo.store().clone();
+    let mut parent = store.root_commit();
+
+    for i in 0..commits {
+        let path = RepoPathBuf::from_internal_string(format!("dir{}/file{i}.txt", i % 16)).unwrap();
+        let contents = format!("line {i}\n");
+        let id = store
+            .write_file(&path, &mut contents.as_bytes())
+            .block_on()
+            .unwrap();
+        let mut tree = MergedTreeBuilder::new(parent.tree());
+        tree.set_or_remove(
+            path,
+            Merge::normal(TreeValue::File {
+                id,
+                executable: false,
+                copy_id: CopyId::placeholder(),
+            }),
+        );
+        let commit = tx
+            .repo_mut()
+            .new_commit(vec![parent.id().clone()], tree.write_tree().unwrap())
+            .set_description(format!("commit {i}"))
+            .write()
+            .unwrap();
+        if i % 100 == 0 {
+            tx.repo_mut().set_local_bookmark_target(
+                RefName::new(&format!("bm-{i}")),
+                RefTarget::normal(commit.id().clone()),
+            );
+        }
+        parent = commit;
+    }
+
+    tx.repo_mut()
+        .check_out(WorkspaceName::DEFAULT.to_owned(), &parent)
+        .unwrap();
+    tx.repo_mut().rebase_descendants().unwrap();
+    tx.commit("bench fixture").unwrap();
+}
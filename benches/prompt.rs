@@ -0,0 +1,116 @@
+//! Prompt rendering benchmarks, with criterion
+//!
+//! Against the synthetic jj repo of `prompt_latency`, in-process so the
+//! numbers are the prompt's own, without process startup:
+//! - `prompt`: the default prompt end to end, detection to rendering
+//! - `segment`: collection with one expensive segment on at a time (and
+//!   the segment cache off), against a `baseline` with none
+//! - `cache`: the cached segments with their cache warm (`hit`) and wiped
+//!   before every run (`miss`)
+//! - `render`: rendering already collected data
+//!
+//! ```sh
+//! cargo bench --bench prompt
+//! cargo bench --bench prompt -- segment/diffstat
+//! ```
+//!
+//! The fixture has `JJ_STARSHIP_BENCH_COMMITS` commits (default 2000).
+
+mod fixture;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use fixture::{build_repo, env_or};
+use jj_starship::cache::CACHE_DIR;
+use jj_starship::{Capabilities, Config, collect, detect, output, run_prompt};
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// The fixture repo, smaller when not run by `cargo bench` (e.g. by
+/// `cargo test --benches`, which runs each bench once)
+fn fixture() -> TempDir {
+    let full = env::args().any(|a| a == "--bench");
+    let commits = env_or("JJ_STARSHIP_BENCH_COMMITS", if full { 2000 } else { 50 });
+    let dir = tempfile::tempdir().unwrap();
+    build_repo(dir.path(), commits);
+    dir
+}
+
+/// Config with the segment cache off and `enable` applied
+fn uncached(enable: impl Fn(&mut Config)) -> Config {
+    let mut config = Config {
+        cache: false,
+        ..Config::default()
+    };
+    enable(&mut config);
+    config
+}
+
+/// The segments that cost the most, each enabled on its own
+fn segments() -> Vec<(&'static str, Config)> {
+    vec![
+        ("baseline", uncached(|_| {})),
+        ("diffstat", uncached(|c| c.diff_stat.enabled = true)),
+        ("files", uncached(|c| c.file_changes = true)),
+        ("untracked", uncached(|c| c.untracked = true)),
+        ("tag", uncached(|c| c.tag.enabled = true)),
+        ("trunk", uncached(|c| c.trunk_distance = true)),
+        ("heads", uncached(|c| c.anonymous_heads = true)),
+        ("stacks", uncached(|c| c.stacks = true)),
+        ("stack_conflict", uncached(|c| c.stack_conflicts = true)),
+        ("immutable", uncached(|c| c.immutable = true)),
+        ("unpushed", uncached(|c| c.unpushed_bookmarks = true)),
+    ]
+}
+
+fn benches(c: &mut Criterion) {
+    let dir = fixture();
+    let root = dir.path();
+    let caps = Capabilities::default();
+
+    c.bench_function("prompt", |b| {
+        let config = Config::default();
+        b.iter(|| run_prompt(black_box(root), &config, caps));
+    });
+
+    let detected = detect::detect(root, detect::Discovery::default());
+    let mut group = c.benchmark_group("segment");
+    for (name, config) in segments() {
+        group.bench_function(name, |b| b.iter(|| collect(black_box(&detected), &config)));
+    }
+    group.finish();
+
+    let mut config = Config::default();
+    config.diff_stat.enabled = true;
+    config.file_changes = true;
+    config.tag.enabled = true;
+    let mut group = c.benchmark_group("cache");
+    group.bench_function("hit", |b| {
+        // Prime once so the first sample isn't a miss
+        let _ = run_prompt(root, &config, caps);
+        b.iter(|| run_prompt(black_box(root), &config, caps));
+    });
+    group.bench_function("miss", |b| {
+        b.iter_batched(
+            || wipe_cache(root),
+            |()| run_prompt(black_box(root), &config, caps),
+            BatchSize::PerIteration,
+        );
+    });
+    group.finish();
+
+    let data = collect(&detected, &config).unwrap();
+    c.bench_function("render", |b| {
+        b.iter(|| output::render(black_box(&data), &config, caps));
+    });
+}
+
+/// Remove jj-starship's segment cache
+fn wipe_cache(root: &Path) {
+    let _ = fs::remove_dir_all(root.join(".jj/repo").join(CACHE_DIR));
+}
+
+criterion_group!(prompt, benches);
+criterion_main!(prompt);
@@ -0,0 +1,110 @@
+//! Prompt latency benchmark: cold start vs warm cache
+//!
+//! Builds a synthetic jj repo and times the `jj-starship` binary end to end in
+//! two regimes that regress independently:
+//! - cold: index and segment cache wiped before every run (first prompt after
+//!   clone or `jj debug reindex`)
+//! - warm: steady state, index and cache already populated
+//!
+//! Fails when a regime's p95 exceeds its budget, so it can gate CI.
+//!
+//! ```sh
+//! cargo bench --bench prompt_latency
+//! JJ_STARSHIP_BENCH_P95_WARM_MS=50 cargo bench --bench prompt_latency
+//! ```
+//!
+//! Env knobs: `JJ_STARSHIP_BENCH_COMMITS` (default 2000), `JJ_STARSHIP_BENCH_RUNS`
+//! (default 20), `JJ_STARSHIP_BENCH_P95_COLD_MS` (default 1000),
+//! `JJ_STARSHIP_BENCH_P95_WARM_MS` (default 150).
+
+mod fixture;
+
+use fixture::{build_repo, env_or};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitCode};
+use std::time::{Duration, Instant};
+
+/// Remove the jj index (like `jj debug reindex`) and jj-starship's segment cache
+fn wipe_caches(root: &Path) {
+    let index = root.join(".jj/repo/index");
+    for entry in fs::read_dir(&index).into_iter().flatten().flatten() {
+        let path = entry.path();
+        // Keep the "type" file and directories so the store still loads
+        if path.is_dir() {
+            for child in fs::read_dir(&path).into_iter().flatten().flatten() {
+                let _ = fs::remove_dir_all(child.path()).or_else(|_| fs::remove_file(child.path()));
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(root.join(".jj/repo/jj-starship-cache"));
+}
+
+fn run_prompt(root: &Path) -> Duration {
+    let start = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_jj-starship"))
+        .arg("--cwd")
+        .arg(root)
+        .arg("--diffstat")
+        .output()
+        .unwrap()
+        .status;
+    let elapsed = start.elapsed();
+    assert!(status.success(), "jj-starship failed: {status}");
+    elapsed
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    let rank = (sorted.len() * pct).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// Run a scenario and report min/median/p95; returns false if p95 is over budget
+fn scenario(name: &str, root: &Path, runs: u64, budget: Duration, before_each: impl Fn()) -> bool {
+    let mut samples: Vec<Duration> = (0..runs)
+        .map(|_| {
+            before_each();
+            run_prompt(root)
+        })
+        .collect();
+    samples.sort();
+
+    let p95 = percentile(&samples, 95);
+    let ok = p95 <= budget;
+    println!(
+        "{name:<5} min {:>8.2?}  median {:>8.2?}  p95 {:>8.2?}  budget {:>8.2?}  {}",
+        samples[0],
+        percentile(&samples, 50),
+        p95,
+        budget,
+        if ok { "ok" } else { "OVER BUDGET" }
+    );
+    ok
+}
+
+fn main() -> ExitCode {
+    // `cargo bench` passes `--bench`; anything else (e.g. `cargo test --benches`) is a smoke run
+    let full = env::args().any(|a| a == "--bench");
+    let commits = env_or("JJ_STARSHIP_BENCH_COMMITS", if full { 2000 } else { 50 });
+    let runs = env_or("JJ_STARSHIP_BENCH_RUNS", if full { 20 } else { 2 });
+    let cold_budget = Duration::from_millis(env_or("JJ_STARSHIP_BENCH_P95_COLD_MS", 1000));
+    let warm_budget = Duration::from_millis(env_or("JJ_STARSHIP_BENCH_P95_WARM_MS", 150));
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    build_repo(root, commits);
+    println!("fixture: {commits} commits, {runs} runs per scenario");
+
+    let cold_ok = scenario("cold", root, runs, cold_budget, || wipe_caches(root));
+    // Prime once so the first warm sample isn't a cold one
+    run_prompt(root);
+    let warm_ok = scenario("warm", root, runs, warm_budget, || {});
+
+    if !full || (cold_ok && warm_ok) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
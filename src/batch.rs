@@ -0,0 +1,121 @@
+//! `jj-starship --stdin`: render the prompt for many directories in one process
+//!
+//! Reads directories one per line and answers each with one line as it
+//! arrives, so a tmux or status-bar script can feed every pane through a
+//! single process, or keep one running as a coprocess. Relative directories
+//! are resolved against `--cwd`.
+
+use crate::config::Config;
+use crate::detect;
+use crate::output::Capabilities;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Line format of `--stdin` results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BatchFormat {
+    /// The prompt, empty outside a repo (lines of a multi-line format joined by spaces)
+    #[default]
+    Text,
+    /// `{"dir": ..., "prompt": ...}`, with `prompt` null outside a repo
+    Json,
+}
+
+/// Answer each directory read from `input` with a line on `output`, until
+/// `input` ends or `output` is closed
+///
+/// # Errors
+/// Reading `input` or writing `output` failed
+pub fn run(
+    cwd: &Path,
+    config: &Config,
+    caps: Capabilities,
+    format: BatchFormat,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        let dir = line.trim_end_matches('\r');
+        if dir.is_empty() {
+            continue;
+        }
+        let start = detect::start_dir(cwd.join(dir), config.resolve_symlinks);
+        let prompt = crate::run_prompt(&start, config, caps);
+        let result = match format {
+            BatchFormat::Text => prompt.unwrap_or_default().replace('\n', " "),
+            BatchFormat::Json => format!(
+                "{{\"dir\":{},\"prompt\":{}}}",
+                json_string(dir),
+                prompt
+                    .as_deref()
+                    .map_or_else(|| "null".to_string(), json_string)
+            ),
+        };
+        writeln!(output, "{result}")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// JSON string literal of `s`
+#[must_use]
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("main"), r#""main""#);
+        assert_eq!(json_string("a\"b\\c\nd"), r#""a\"b\\c\nd""#);
+        assert_eq!(json_string("\x1b[35mx"), r#""\u001b[35mx""#);
+        assert_eq!(json_string("󱗆 ✓"), "\"󱗆 ✓\"");
+    }
+
+    #[test]
+    fn test_run_one_line_per_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("plain")).unwrap();
+        let input = "plain\n\n/nonexistent/dir\r\n";
+        let run_with = |format| {
+            let mut output = Vec::new();
+            run(
+                tmp.path(),
+                &Config::default(),
+                Capabilities::default(),
+                format,
+                input.as_bytes(),
+                &mut output,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        // Blank lines are skipped, directories outside a repo get empty results
+        assert_eq!(run_with(BatchFormat::Text), "\n\n");
+        assert_eq!(
+            run_with(BatchFormat::Json),
+            "{\"dir\":\"plain\",\"prompt\":null}\n{\"dir\":\"/nonexistent/dir\",\"prompt\":null}\n"
+        );
+    }
+}
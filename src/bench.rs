@@ -0,0 +1,78 @@
+//! `jj-starship bench`: time prompt rendering against the current repo
+
+use crate::config::Config;
+use crate::detect;
+use crate::output::{self, Capabilities};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Timings of one phase across all runs
+struct Phase {
+    name: &'static str,
+    samples: Vec<Duration>,
+}
+
+impl Phase {
+    fn new(name: &'static str, runs: usize) -> Self {
+        Self {
+            name,
+            samples: Vec::with_capacity(runs),
+        }
+    }
+
+    /// Nearest-rank percentile
+    fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+        let rank = (sorted.len() * pct).div_ceil(100).max(1);
+        sorted[rank - 1]
+    }
+
+    fn report(&self) {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        println!(
+            "{:<8} {:>10.2?} {:>10.2?} {:>10.2?}",
+            self.name,
+            sorted[0],
+            Self::percentile(&sorted, 50),
+            Self::percentile(&sorted, 95),
+        );
+    }
+}
+
+/// Render the prompt `runs` times and print min/median/p95 per phase
+/// Returns false if the cwd is not in a repo or collection fails
+#[must_use]
+pub fn run(cwd: &Path, config: &Config, caps: Capabilities, runs: usize) -> bool {
+    let runs = runs.max(1);
+    let mut detect_phase = Phase::new("detect", runs);
+    let mut collect_phase = Phase::new("collect", runs);
+    let mut render_phase = Phase::new("render", runs);
+    let mut total_phase = Phase::new("total", runs);
+
+    for _ in 0..runs {
+        let start = Instant::now();
+        let result = detect::detect(cwd, config.discovery);
+        let detected = Instant::now();
+        let Some(data) = crate::collect(&result, config) else {
+            return false;
+        };
+        let collected = Instant::now();
+        let _ = output::render(&data, config, caps);
+        let rendered = Instant::now();
+
+        detect_phase.samples.push(detected - start);
+        collect_phase.samples.push(collected - detected);
+        render_phase.samples.push(rendered - collected);
+        total_phase.samples.push(rendered - start);
+    }
+
+    println!("{runs} runs");
+    println!(
+        "{:<8} {:>10} {:>10} {:>10}",
+        "phase", "min", "median", "p95"
+    );
+    for phase in [&detect_phase, &collect_phase, &render_phase, &total_phase] {
+        phase.report();
+    }
+    true
+}
@@ -0,0 +1,46 @@
+//! `--timeout`: a time budget for the expensive segments of one prompt
+//!
+//! Like starship's `command_timeout`, but the prompt still renders: the clock
+//! starts when collection does, and each expensive segment (diffstat, stack
+//! diff, tag distance, ahead/behind counts) gets whatever is left of it when
+//! its turn comes. Walks check the clock as they go, so a segment running out
+//! of time mid-way stops there and renders the `…` placeholder instead of
+//! blocking the shell; with the cache on, a background refresh fills it in
+//! for the next prompt.
+
+use std::time::{Duration, Instant};
+
+/// Time left for the expensive segments of a prompt
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    /// None for an unlimited budget
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Budget of `timeout` from now, unlimited for None
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            deadline: timeout.and_then(|timeout| Instant::now().checked_add(timeout)),
+        }
+    }
+
+    /// Whether the time is up; segments starting after that are skipped
+    pub fn exceeded(self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeded() {
+        assert!(!Budget::default().exceeded());
+        assert!(!Budget::new(None).exceeded());
+        assert!(!Budget::new(Some(Duration::from_secs(60))).exceeded());
+        assert!(Budget::new(Some(Duration::ZERO)).exceeded());
+    }
+}
@@ -0,0 +1,241 @@
+//! On-disk cache for expensive segment values
+//!
+//! Each entry is a small file `<key> <value>...` keyed by the ids the value is
+//! derived from (e.g. a commit id), so entries never go stale - a different
+//! repo state simply misses. Expensive segments can be refreshed by a detached
+//! background process while the prompt renders a placeholder.
+
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+
+/// Name of the cache directory inside `.jj/` or the git dir
+pub const CACHE_DIR: &str = "jj-starship-cache";
+
+/// Prefix of the per-workspace marker files preventing concurrent background refreshes
+const REFRESH_LOCK: &str = "refresh";
+
+/// A refresh lock older than this is assumed to belong to a dead process
+const REFRESH_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Value of an expensive segment: computed, or being computed in the background
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Deferred<T> {
+    Ready(T),
+    Pending,
+}
+
+impl<T> Deferred<T> {
+    pub const fn as_ref(&self) -> Deferred<&T> {
+        match self {
+            Self::Ready(value) => Deferred::Ready(value),
+            Self::Pending => Deferred::Pending,
+        }
+    }
+}
+
+impl<T: Default> Default for Deferred<T> {
+    fn default() -> Self {
+        Self::Ready(T::default())
+    }
+}
+
+/// Segment value cache rooted in a repo's metadata directory
+#[derive(Debug)]
+pub struct Cache {
+    /// None for a disabled cache, which always misses and stores nothing
+    dir: Option<PathBuf>,
+}
+
+impl Cache {
+    #[must_use]
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir: Some(dir) }
+    }
+
+    /// A cache that is never read or written (nor refreshed in the background)
+    #[must_use]
+    pub const fn disabled() -> Self {
+        Self { dir: None }
+    }
+
+    /// Read the numbers stored for `name` if the entry matches `key`
+    #[must_use]
+    pub fn get(&self, name: &str, key: &str) -> Option<Vec<usize>> {
+        self.read(name)
+            .and_then(|(stored, values)| (stored == key).then_some(values))
+    }
+
+    /// Read the entry for `name` whatever its key
+    #[must_use]
+    pub fn read(&self, name: &str) -> Option<(String, Vec<usize>)> {
+        let (key, fields) = self.read_fields(name)?;
+        let values = fields
+            .iter()
+            .map(|f| f.parse().ok())
+            .collect::<Option<_>>()?;
+        Some((key, values))
+    }
+
+    /// Read the text fields stored for `name` if the entry matches `key`
+    #[must_use]
+    pub fn get_fields(&self, name: &str, key: &str) -> Option<Vec<String>> {
+        self.read_fields(name)
+            .and_then(|(stored, fields)| (stored == key).then_some(fields))
+    }
+
+    fn read_fields(&self, name: &str) -> Option<(String, Vec<String>)> {
+        let contents = fs::read_to_string(self.dir.as_ref()?.join(name)).ok()?;
+        let mut fields = contents.split_whitespace().map(String::from);
+        let key = fields.next()?;
+        Some((key, fields.collect()))
+    }
+
+    /// Store numbers for `name` under `key`, replacing any previous entry
+    pub fn put(&self, name: &str, key: &str, values: &[usize]) {
+        let fields: Vec<String> = values.iter().map(ToString::to_string).collect();
+        self.put_fields(name, key, &fields);
+    }
+
+    /// Store text fields (without whitespace) for `name` under `key`
+    pub fn put_fields(&self, name: &str, key: &str, fields: &[String]) {
+        let mut line = key.to_string();
+        for field in fields {
+            line.push(' ');
+            line.push_str(field);
+        }
+        line.push('\n');
+        self.write(name, &line);
+    }
+
+    /// Read the free-form text stored for `name` if the entry matches `key`
+    #[must_use]
+    pub fn get_text(&self, name: &str, key: &str) -> Option<String> {
+        let contents = fs::read_to_string(self.dir.as_ref()?.join(name)).ok()?;
+        let (stored, text) = contents.split_once('\n')?;
+        (stored == key).then(|| text.to_string())
+    }
+
+    /// Store free-form text (any whitespace) for `name` under `key`
+    pub fn put_text(&self, name: &str, key: &str, text: &str) {
+        self.write(name, &format!("{key}\n{text}"));
+    }
+
+    /// Replace the entry file for `name`
+    /// Errors are ignored: the cache is an optimization and must never fail a prompt
+    fn write(&self, name: &str, contents: &str) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        // Write then rename so readers never see a partial entry
+        let _ = fs::create_dir_all(dir);
+        let tmp = dir.join(format!("{name}.{}.tmp", std::process::id()));
+        if fs::write(&tmp, contents).is_ok() && fs::rename(&tmp, dir.join(name)).is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+
+    /// Refresh lock of the workspace at `repo_root`: the cache is shared by all
+    /// workspaces of a repo, but each refreshes its own working copy. Keyed on
+    /// the canonical root, so a workspace reached through a symlink shares it.
+    fn refresh_lock(dir: &Path, repo_root: &Path) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        fs::canonicalize(repo_root)
+            .unwrap_or_else(|_| repo_root.to_path_buf())
+            .hash(&mut hasher);
+        dir.join(format!("{REFRESH_LOCK}-{:016x}.lock", hasher.finish()))
+    }
+
+    /// Spawn a detached `jj-starship refresh` for `repo_root` unless one is running
+    /// `args` are extra global flags selecting which segments to compute
+    pub fn spawn_refresh(&self, repo_root: &Path, args: &[String]) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let lock = Self::refresh_lock(dir, repo_root);
+        let running = fs::metadata(&lock)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok())
+            .is_some_and(|age| age < REFRESH_LOCK_TIMEOUT);
+        if running {
+            return;
+        }
+
+        let _ = fs::create_dir_all(dir);
+        if fs::write(&lock, "").is_err() {
+            return;
+        }
+        let Ok(exe) = std::env::current_exe() else {
+            return;
+        };
+        let spawned = Command::new(exe)
+            .arg("--cwd")
+            .arg(repo_root)
+            .args(args)
+            .arg("refresh")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if spawned.is_err() {
+            let _ = fs::remove_file(&lock);
+        }
+    }
+
+    /// Release the refresh lock (called by the background process when done)
+    pub fn finish_refresh(&self, repo_root: &Path) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_file(Self::refresh_lock(dir, repo_root));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_roundtrip_keyed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = Cache::new(tmp.path().join(CACHE_DIR));
+        assert_eq!(cache.get("diffstat", "abc"), None);
+
+        cache.put("diffstat", "abc", &[12, 3]);
+        assert_eq!(cache.get("diffstat", "abc"), Some(vec![12, 3]));
+        // A different key is a miss, not a stale hit
+        assert_eq!(cache.get("diffstat", "def"), None);
+
+        cache.put("diffstat", "def", &[1, 0]);
+        assert_eq!(cache.get("diffstat", "abc"), None);
+        assert_eq!(cache.get("diffstat", "def"), Some(vec![1, 0]));
+
+        let fields = ["v1.2.0".to_string(), "5".to_string()];
+        cache.put_fields("tag", "abc", &fields);
+        assert_eq!(cache.get_fields("tag", "abc").as_deref(), Some(&fields[..]));
+        // Text isn't numbers
+        assert_eq!(cache.get("tag", "abc"), None);
+
+        cache.put_text("template", "abc", "main  fix\tparser");
+        assert_eq!(
+            cache.get_text("template", "abc").as_deref(),
+            Some("main  fix\tparser")
+        );
+        assert_eq!(cache.get_text("template", "def"), None);
+    }
+
+    #[test]
+    fn test_disabled_cache_stores_nothing() {
+        let cache = Cache::disabled();
+        cache.put("diffstat", "abc", &[12, 3]);
+        assert_eq!(cache.get("diffstat", "abc"), None);
+        assert_eq!(cache.read("diffstat"), None);
+    }
+}
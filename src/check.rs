@@ -0,0 +1,51 @@
+//! `--has-conflicts`, `--is-empty`, `--is-stale`: repo state as an exit code
+//!
+//! Nothing is printed; the exit code says whether every given check holds, so
+//! shell scripts and starship `when` clauses can branch on the repo without
+//! parsing output. Only the core state is read, as with minimal output.
+
+use crate::config::Config;
+use crate::detect;
+use crate::output::PromptData;
+use std::path::Path;
+
+/// Question a check flag asks about the repo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// The working copy has conflicts
+    HasConflicts,
+    /// The working copy makes no changes (JJ: @ is empty, Git: the worktree is clean)
+    IsEmpty,
+    /// The workspace's files are behind its working copy commit (JJ only)
+    IsStale,
+}
+
+impl Check {
+    fn holds(self, data: &PromptData) -> bool {
+        match data {
+            PromptData::Jj(info) => match self {
+                Self::HasConflicts => info.conflict,
+                Self::IsEmpty => info.empty,
+                Self::IsStale => info.stale,
+            },
+            #[cfg(feature = "git")]
+            PromptData::Git(info) => match self {
+                Self::HasConflicts => info.conflicted > 0,
+                Self::IsEmpty => {
+                    info.staged + info.modified + info.untracked + info.deleted + info.conflicted
+                        == 0
+                }
+                Self::IsStale => false,
+            },
+        }
+    }
+}
+
+/// Whether all `checks` hold for the repo containing `cwd` (none do outside a repo)
+#[must_use]
+pub fn all_hold(cwd: &Path, config: &Config, checks: &[Check]) -> bool {
+    let mut config = config.clone();
+    config.minimize();
+    crate::collect(&detect::detect(cwd, config.discovery), &config)
+        .is_some_and(|data| checks.iter().all(|check| check.holds(&data)))
+}
@@ -0,0 +1,134 @@
+//! Forge CI check status of a pushed commit
+//!
+//! Queried from GitHub's checks API through the `gh` CLI, which handles
+//! authentication (and GitHub Enterprise hosts). The network round trip is far
+//! too slow for a prompt, so results are cached for a while and, with
+//! `--async`, fetched by the background refresh.
+
+use std::process::{Command, Stdio};
+
+/// Summary of a commit's check runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CheckStatus {
+    /// Every check succeeded (or was skipped)
+    Pass,
+    /// Some check failed
+    Fail,
+    /// Some check is still queued or running
+    Pending,
+}
+
+/// Cache codes of the statuses; a code past the end means "nothing to show"
+pub const STATUSES: [CheckStatus; 3] = [CheckStatus::Pass, CheckStatus::Fail, CheckStatus::Pending];
+
+/// Seconds a finished result is trusted before asking the forge again
+/// (checks can be re-run on the same commit)
+const DONE_TTL_SECS: usize = 300;
+
+/// Seconds a pending result is trusted
+const PENDING_TTL_SECS: usize = 30;
+
+/// Reduce the check runs to one of `pass`, `fail`, `pending` or `none`
+const JQ_SUMMARY: &str = r#"[.check_runs[] | if .status != "completed" then "pending" elif (.conclusion | IN("success", "neutral", "skipped")) then "pass" else "fail" end] | if any(. == "fail") then "fail" elif any(. == "pending") then "pending" elif length > 0 then "pass" else "none" end"#;
+
+/// Host and `owner/repo` of a remote URL, e.g. `git@github.com:owner/repo.git`
+/// or `https://github.com/owner/repo`
+pub fn parse_remote_url(url: &str) -> Option<(&str, &str)> {
+    let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+        let rest = rest.split_once('@').map_or(rest, |(_, rest)| rest);
+        let (host, path) = rest.split_once('/')?;
+        // Drop a port of `ssh://host:22/owner/repo`
+        (host.split_once(':').map_or(host, |(host, _)| host), path)
+    } else {
+        // scp-like `user@host:owner/repo`
+        let rest = url.split_once('@').map_or(url, |(_, rest)| rest);
+        rest.split_once(':')?
+    };
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, repo) = path.split_once('/')?;
+    let valid = |s: &str| !s.is_empty() && !s.contains('/');
+    (valid(owner) && valid(repo) && !host.is_empty()).then_some((host, path))
+}
+
+/// Seconds a cached status stays fresh (None: nothing to show)
+pub const fn ttl_secs(status: Option<CheckStatus>) -> usize {
+    match status {
+        Some(CheckStatus::Pending) => PENDING_TTL_SECS,
+        _ => DONE_TTL_SECS,
+    }
+}
+
+/// Parse the output of `JQ_SUMMARY` (None for `none`, a commit without checks)
+fn parse_summary(output: &str) -> Option<CheckStatus> {
+    match output.trim() {
+        "pass" => Some(CheckStatus::Pass),
+        "fail" => Some(CheckStatus::Fail),
+        "pending" => Some(CheckStatus::Pending),
+        _ => None,
+    }
+}
+
+/// Check status of `commit` (hex id) on the forge behind `remote_url`
+/// None if the commit has no checks or they cannot be queried (not a GitHub
+/// remote, `gh` missing or not logged in, network down).
+pub fn fetch(remote_url: &str, commit: &str) -> Option<CheckStatus> {
+    let (host, repo) = parse_remote_url(remote_url)?;
+    let output = Command::new("gh")
+        .arg("api")
+        .args(["--hostname", host])
+        .arg(format!(
+            "repos/{repo}/commits/{commit}/check-runs?per_page=100"
+        ))
+        .args(["--jq", JQ_SUMMARY])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_summary(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_url() {
+        for url in [
+            "git@github.com:owner/repo.git",
+            "https://github.com/owner/repo",
+            "https://user@github.com/owner/repo.git/",
+            "ssh://git@github.com:22/owner/repo.git",
+            "ssh://git@github.com/owner/repo",
+        ] {
+            assert_eq!(
+                parse_remote_url(url),
+                Some(("github.com", "owner/repo")),
+                "{url}"
+            );
+        }
+        assert_eq!(
+            parse_remote_url("git@ghe.example.com:team/app.git"),
+            Some(("ghe.example.com", "team/app"))
+        );
+        assert_eq!(parse_remote_url("/srv/git/repo.git"), None);
+        assert_eq!(parse_remote_url("https://gitlab.com/group/sub/repo"), None);
+    }
+
+    #[test]
+    fn test_parse_summary() {
+        assert_eq!(parse_summary("pass\n"), Some(CheckStatus::Pass));
+        assert_eq!(parse_summary("fail\n"), Some(CheckStatus::Fail));
+        assert_eq!(parse_summary("pending\n"), Some(CheckStatus::Pending));
+        assert_eq!(parse_summary("none\n"), None);
+        assert_eq!(parse_summary(""), None);
+    }
+}
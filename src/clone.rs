@@ -0,0 +1,88 @@
+//! Shallow and partial clone detection
+//!
+//! A shallow clone (`git clone --depth`) lacks history past its cut-off and a
+//! partial one (`--filter`, a promisor remote) fetches objects on demand, so
+//! ancestry-based segments such as ahead/behind and tag distance can be off.
+//! Both are read off the git directory's files rather than through git, since
+//! jj repos with the git backend are affected too.
+
+use std::fs;
+use std::path::Path;
+
+/// How a git repo's history deviates from a full clone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CloneKind {
+    /// History is cut off (`shallow` file in the git dir)
+    pub shallow: bool,
+    /// Objects are fetched lazily from a promisor remote
+    pub partial: bool,
+}
+
+impl CloneKind {
+    /// Marker text, e.g. `shallow`, None for a full clone
+    pub fn marker(self) -> Option<&'static str> {
+        match (self.shallow, self.partial) {
+            (false, false) => None,
+            (true, false) => Some("shallow"),
+            (false, true) => Some("partial"),
+            (true, true) => Some("shallow partial"),
+        }
+    }
+}
+
+/// Clone kind of the repo in the (common) git directory `git_dir`
+pub fn kind(git_dir: &Path) -> CloneKind {
+    CloneKind {
+        shallow: git_dir.join("shallow").is_file(),
+        partial: fs::read_to_string(git_dir.join("config"))
+            .is_ok_and(|config| has_promisor(&config)),
+    }
+}
+
+/// Whether a git config sets up a promisor remote: `remote.<name>.promisor`
+/// or the older `extensions.partialClone`
+fn has_promisor(config: &str) -> bool {
+    config.lines().any(|line| {
+        let line = line.split(['#', ';']).next().unwrap_or_default();
+        let Some((key, value)) = line.split_once('=') else {
+            return false;
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "promisor" => value.trim().eq_ignore_ascii_case("true"),
+            "partialclone" => !value.trim().is_empty(),
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_promisor() {
+        let partial = "[remote \"origin\"]\n\turl = https://example.com/r.git\n\tpromisor = true\n\tpartialclonefilter = blob:none\n";
+        assert!(has_promisor(partial));
+        assert!(has_promisor("[extensions]\n\tpartialClone = origin\n"));
+        assert!(!has_promisor("[remote \"origin\"]\n\tpromisor = false\n"));
+        assert!(!has_promisor("[core]\n\tbare = false\n# promisor = true\n"));
+    }
+
+    #[test]
+    fn test_kind() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(kind(tmp.path()), CloneKind::default());
+        assert_eq!(CloneKind::default().marker(), None);
+
+        fs::write(tmp.path().join("shallow"), "0123abcd\n").unwrap();
+        fs::write(
+            tmp.path().join("config"),
+            "[remote \"origin\"]\n\tpromisor = true\n",
+        )
+        .unwrap();
+        let kind = kind(tmp.path());
+        assert!(kind.shallow && kind.partial);
+        assert_eq!(kind.marker(), Some("shallow partial"));
+    }
+}
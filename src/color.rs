@@ -5,6 +5,12 @@ pub const RESET: &str = "\x1b[0m";
 pub const PURPLE: &str = "\x1b[35m"; // Color 5: Magenta
 pub const GREEN: &str = "\x1b[32m"; // Color 2: Green
 pub const RED: &str = "\x1b[31m"; // Color 1: Red
+pub const YELLOW: &str = "\x1b[33m"; // Color 3: Yellow
 pub const BLUE: &str = "\x1b[34m"; // Color 4: Blue
+pub const CYAN: &str = "\x1b[36m"; // Color 6: Cyan (standalone prompt directory)
 pub const BRIGHT_MAGENTA: &str = "\x1b[95m"; // Bright magenta (jj change_id prefix)
+pub const BRIGHT_GREEN: &str = "\x1b[92m"; // Bright green (hashed change id colors)
+pub const BRIGHT_YELLOW: &str = "\x1b[93m"; // Bright yellow (hashed change id colors)
+pub const BRIGHT_BLUE: &str = "\x1b[94m"; // Bright blue (hashed change id colors)
+pub const BRIGHT_CYAN: &str = "\x1b[96m"; // Bright cyan (hashed change id colors)
 pub const BRIGHT_BLACK: &str = "\x1b[90m"; // Bright black/gray (jj change_id rest)
@@ -1,12 +1,89 @@
 //! Configuration for jj-starship
 
+use crate::detect::Discovery;
+use crate::format::Format;
+use crate::palette::Palette;
+use crate::sources::Sources;
+use crate::style::Styles;
+use crate::symbols::{SymbolSet, Symbols};
+use clap::ValueEnum;
+use regex::{Regex, RegexSet};
 use std::borrow::Cow;
 use std::env;
+use std::time::Duration;
 
 /// Default symbol for JJ repos
 pub const DEFAULT_JJ_SYMBOL: &str = "󱗆 ";
 /// Default symbol for Git repos
 pub const DEFAULT_GIT_SYMBOL: &str = " ";
+/// Counts from this size on are abbreviated
+pub const DEFAULT_ABBREVIATE_COUNTS: usize = 1000;
+/// Base revset of the stack diff
+pub const DEFAULT_STACK_DIFF_BASE: &str = "trunk()";
+
+/// How change ids are spelled in the prompt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChangeIdEncoding {
+    /// jj's own letters, e.g. `yzxvtlmn`
+    #[default]
+    ReverseHex,
+    /// Pronounceable words, e.g. `bahog-hijol`
+    Proquint,
+}
+
+/// Built-in named profile with only the core segments, e.g. for a right or
+/// transient prompt
+pub const MINIMAL_PROFILE: &str = "minimal";
+
+/// Profile used in SSH sessions, where latency and fonts differ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SshProfile {
+    /// Degraded segments and no Nerd Font symbols
+    #[default]
+    Light,
+    /// Same as a local session
+    Full,
+}
+
+/// Whether this process runs in an SSH session
+#[must_use]
+pub fn in_ssh_session() -> bool {
+    ["SSH_CONNECTION", "SSH_TTY", "SSH_CLIENT"]
+        .iter()
+        .any(|var| env::var_os(var).is_some())
+}
+
+/// Profile used when running under CI, where nobody reads the prompt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CiProfile {
+    /// Plain core segments, computed synchronously without the cache
+    #[default]
+    Minimal,
+    /// Same as an interactive session
+    Full,
+}
+
+/// Env vars set by common CI services (`CI` covers most others)
+const CI_VARS: [&str; 9] = [
+    "CI",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "BUILDKITE",
+    "CIRCLECI",
+    "TRAVIS",
+    "JENKINS_URL",
+    "TF_BUILD",
+    "TEAMCITY_VERSION",
+];
+
+/// Whether this process runs under CI
+/// `CI=false` (or `0`), as some setups export to opt out, doesn't count.
+#[must_use]
+pub fn in_ci() -> bool {
+    CI_VARS
+        .iter()
+        .any(|var| env::var(var).is_ok_and(|value| !matches!(value.as_str(), "" | "0" | "false")))
+}
 
 /// Display options for a repo type
 #[derive(Debug, Clone, Copy, Default)]
@@ -22,6 +99,7 @@ pub struct DisplayConfig {
 }
 
 impl DisplayConfig {
+    #[must_use]
     pub const fn all_visible() -> Self {
         Self {
             show_prefix: true,
@@ -34,25 +112,271 @@ impl DisplayConfig {
     }
 }
 
+/// Diffstat segment options
+#[derive(Debug, Clone, Copy)]
+pub struct DiffStatConfig {
+    /// Compute and show +/- line counts of the working copy
+    pub enabled: bool,
+    /// Render counts as a block bar instead of numbers
+    pub bar: bool,
+    /// Number of blocks in the bar
+    pub bar_width: usize,
+}
+
+/// Default number of blocks in the diffstat bar
+pub const DEFAULT_DIFFSTAT_BAR_WIDTH: usize = 5;
+/// Upper bound for the diffstat bar width, keeps the prompt compact
+pub const MAX_DIFFSTAT_BAR_WIDTH: usize = 20;
+
+impl Default for DiffStatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bar: false,
+            bar_width: DEFAULT_DIFFSTAT_BAR_WIDTH,
+        }
+    }
+}
+
+/// Nearest tag segment options
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct TagConfig {
+    /// Compute and show the nearest ancestor tag
+    pub enabled: bool,
+    /// Append the number of commits since the tag
+    pub distance: bool,
+    /// Show version tags as a short semver, e.g. `v1.2.0` as `1.2`
+    pub semver: bool,
+}
+
+/// Default patterns marking a description as work in progress
+pub const DEFAULT_WIP_PATTERNS: &[&str] = &[r"(?i)^wip\b", r"^fixup!", r"^squash!", r"^amend!"];
+
+/// Marker of an empty description, as in `jj log`
+pub const DEFAULT_DESCRIPTION_EMPTY: &str = "(no description)";
+
+/// Description segment options
+#[derive(Debug, Clone)]
+pub struct DescriptionConfig {
+    /// Show the first line of the working copy description
+    pub enabled: bool,
+    /// Descriptions matching any of these are rendered in warning style
+    pub wip_patterns: RegexSet,
+    /// Max width of the first line, cut with an ellipsis (0 = unlimited)
+    pub max_width: usize,
+    /// Shown in place of an empty description (empty = nothing)
+    pub empty_marker: String,
+}
+
+impl Default for DescriptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            wip_patterns: RegexSet::new(DEFAULT_WIP_PATTERNS).unwrap_or_else(|_| RegexSet::empty()),
+            max_width: 0,
+            empty_marker: DEFAULT_DESCRIPTION_EMPTY.to_string(),
+        }
+    }
+}
+
+impl DescriptionConfig {
+    /// Whether the description's first line looks like work in progress
+    #[must_use]
+    pub fn is_wip(&self, first_line: &str) -> bool {
+        self.wip_patterns.is_match(first_line)
+    }
+}
+
+/// Default patterns for ticket identifiers: JIRA-style `ABC-123` and `#1234`
+pub const DEFAULT_ISSUE_PATTERNS: &[&str] = &[r"\b[A-Z][A-Z0-9]+-\d+\b", r"#\d+\b"];
+
+/// Issue key segment options
+#[derive(Debug, Clone)]
+pub struct IssueKeyConfig {
+    /// Show the ticket identifier found in the nearest bookmark or description
+    pub enabled: bool,
+    /// Patterns tried in order; capture group 1 is the key if present, else the whole match
+    pub patterns: Vec<Regex>,
+}
+
+impl Default for IssueKeyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            patterns: DEFAULT_ISSUE_PATTERNS
+                .iter()
+                .filter_map(|p| Regex::new(p).ok())
+                .collect(),
+        }
+    }
+}
+
+impl IssueKeyConfig {
+    /// Extract the first issue key from the given texts, trying patterns in order per text
+    pub fn extract<'a>(&self, texts: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+        texts.into_iter().find_map(|text| {
+            self.patterns.iter().find_map(|re| {
+                let caps = re.captures(text)?;
+                caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_str())
+            })
+        })
+    }
+}
+
+/// Default conventional-commit header: `type(scope)!: subject`, with the types
+/// of the Angular convention
+pub const DEFAULT_CONVENTIONAL_PATTERN: &str =
+    r"^(build|chore|ci|docs|feat|fix|perf|refactor|revert|style|test)(\([\w./-]+\))?!?: \S";
+
+/// Conventional-commit indicator options
+#[derive(Debug, Clone)]
+pub struct ConventionalConfig {
+    /// Check the first line of the working copy description
+    pub enabled: bool,
+    /// Pattern a compliant first line matches
+    pub pattern: Regex,
+}
+
+impl Default for ConventionalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            pattern: Regex::new(DEFAULT_CONVENTIONAL_PATTERN).expect("valid default pattern"),
+        }
+    }
+}
+
 /// Configuration options
 #[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     /// Max length for branch/bookmark name (0 = unlimited)
     pub truncate_name: usize,
     /// Length of `change_id/commit` hash to display
     pub id_length: usize,
+    /// Minimum highlighted prefix of the change id, past its unique prefix (JJ only)
+    pub id_prefix_length: usize,
+    /// Spelling of the change id (JJ only)
+    pub change_id_encoding: ChangeIdEncoding,
+    /// Color the change id by a hash of it instead of jj's magenta (JJ only)
+    pub hash_color: bool,
+    /// Color each bookmark by a hash of its name instead of green (JJ only)
+    pub hash_bookmark_colors: bool,
     /// Max depth to search for ancestor bookmarks (0 = disabled, default: 10)
     pub ancestor_bookmark_depth: usize,
+    /// Max depth to search for a descendant bookmark (0 = disabled, default: 10)
+    pub descendant_bookmark_depth: usize,
+    /// Max bookmarks shown, the rest are counted as `+N` (0 = unlimited)
+    pub max_bookmarks: usize,
     /// Symbol prefix for JJ repos
     pub jj_symbol: Cow<'static, str>,
     /// Symbol prefix for Git repos
     #[cfg_attr(not(feature = "git"), allow(dead_code))]
     pub git_symbol: Cow<'static, str>,
+    /// Glyphs of the segments
+    pub symbols: Symbols,
     /// JJ display options
     pub jj_display: DisplayConfig,
     /// Git display options
     #[cfg_attr(not(feature = "git"), allow(dead_code))]
     pub git_display: DisplayConfig,
+    /// Diffstat segment options
+    pub diff_stat: DiffStatConfig,
+    /// Description segment options
+    pub description: DescriptionConfig,
+    /// Trailer keys whose values are shown, matched case-insensitively (JJ only)
+    pub trailers: Vec<String>,
+    /// Issue key segment options
+    pub issue_key: IssueKeyConfig,
+    /// Show the number of local bookmarks without a tracked remote bookmark (JJ only)
+    pub unpushed_bookmarks: bool,
+    /// Show the number of visible heads outside trunk without bookmarks (JJ only)
+    pub anonymous_heads: bool,
+    /// Show the number of other stacks of work on trunk (JJ only)
+    pub stacks: bool,
+    /// Show commits ahead of and behind `trunk()` (JJ only)
+    pub trunk_distance: bool,
+    /// Show commits between the closest bookmark and its remote (JJ only)
+    pub remote_distance: bool,
+    /// Show the count of files staged in a colocated git index (JJ only)
+    pub git_staged: bool,
+    /// Show the colocated git HEAD branch and ref sync state (JJ only)
+    pub git_head: bool,
+    /// Show the number of conflicted files in the working copy
+    pub conflict_marker: bool,
+    /// Show `(empty)` when the working copy makes no changes (JJ only)
+    pub empty_commit: bool,
+    /// Show the files added, modified and deleted by the working copy (JJ only)
+    pub file_changes: bool,
+    /// Show the files on disk neither tracked nor ignored (JJ only)
+    pub untracked: bool,
+    /// Warn when @ is immutable or on a non-trunk immutable head (JJ only)
+    pub immutable: bool,
+    /// Check mutable ancestors and descendants of @ for conflicts (JJ only)
+    pub stack_conflicts: bool,
+    /// Verify the working copy commit's signature (JJ only)
+    pub signature: bool,
+    /// Flag an unsigned working copy commit, with `signature` (JJ only)
+    pub warn_unsigned: bool,
+    /// Nearest tag segment options
+    pub tag: TagConfig,
+    /// Conventional-commit indicator options (JJ only)
+    pub conventional: ConventionalConfig,
+    /// Base revset of the stack diff segment, None if disabled (JJ only)
+    pub stack_diff: Option<String>,
+    /// Revset standing in for `trunk()`, None for jj's (JJ only)
+    pub trunk_revset: Option<String>,
+    /// Show CI checks of the nearest pushed bookmark via `gh` (JJ only)
+    pub checks: bool,
+    /// Show which bookmarks a merge at @ (or its parent) combines (JJ only)
+    pub megamerge: bool,
+    /// Show the change id and bookmark of @'s parents (JJ only)
+    pub parent: bool,
+    /// jj template expression rendered as a segment, None if disabled (JJ only)
+    pub template: Option<String>,
+    /// Compute expensive segments in the background, rendering a placeholder until cached
+    pub async_segments: bool,
+    /// Ask a running daemon for the prompt before rendering it in-process
+    pub daemon: bool,
+    /// Snapshot the working copy like jj does before reading it, instead of
+    /// reading the repo at its op head without locking anything (JJ only)
+    pub snapshot: bool,
+    /// Prompt latency budget; repos that consistently exceed it switch to the
+    /// degraded profile (None = adaptive profiles disabled)
+    pub latency_budget: Option<Duration>,
+    /// Time budget of the expensive segments, which render a placeholder
+    /// once it runs out (None = no timeout)
+    pub timeout: Option<Duration>,
+    /// Counts at or above this are abbreviated, e.g. `1.2k` (0 = never)
+    pub abbreviate_counts: usize,
+    /// Layout of the prompt (None = built-in layout)
+    pub format: Option<Format>,
+    /// Colors of the user's starship palette replacing the built-in ones
+    pub palette: Palette,
+    /// Starship style strings of segments replacing their built-in colors
+    pub styles: Styles,
+    /// TOML snippets layered over the user's jj config, like jj's `--config-toml`
+    pub jj_config_toml: Vec<String>,
+    /// Profile applied in SSH sessions
+    pub ssh_profile: SshProfile,
+    /// Profile applied under CI
+    pub ci_profile: CiProfile,
+    /// Read and write the on-disk cache of segments and jj config
+    pub cache: bool,
+    /// Running as the background refresh, the only place CI checks are
+    /// fetched, so a network round trip never stalls the prompt
+    pub background: bool,
+    /// Resolve symlinks in the working directory before looking for the repo
+    pub resolve_symlinks: bool,
+    /// How far to walk up from the working directory looking for the repo
+    pub discovery: Discovery,
+    /// Mark shallow and partial clones
+    pub clone_marker: bool,
+    /// Show the name of JJ workspaces other than `default`
+    pub workspace: bool,
+    /// Warn about concurrent JJ operations left to merge
+    pub op_heads: bool,
 }
 
 impl Default for Config {
@@ -60,11 +384,64 @@ impl Default for Config {
         Self {
             truncate_name: 0, // unlimited
             id_length: 8,
+            id_prefix_length: 0,
+            change_id_encoding: ChangeIdEncoding::default(),
+            hash_color: false,
+            hash_bookmark_colors: false,
             ancestor_bookmark_depth: 10,
+            descendant_bookmark_depth: 10,
+            max_bookmarks: 0,
             jj_symbol: Cow::Borrowed(DEFAULT_JJ_SYMBOL),
             git_symbol: Cow::Borrowed(DEFAULT_GIT_SYMBOL),
+            symbols: Symbols::default(),
             jj_display: DisplayConfig::all_visible(),
             git_display: DisplayConfig::all_visible(),
+            diff_stat: DiffStatConfig::default(),
+            description: DescriptionConfig::default(),
+            trailers: Vec::new(),
+            issue_key: IssueKeyConfig::default(),
+            unpushed_bookmarks: false,
+            anonymous_heads: false,
+            stacks: false,
+            trunk_distance: false,
+            remote_distance: false,
+            git_staged: false,
+            git_head: false,
+            conflict_marker: false,
+            empty_commit: false,
+            file_changes: false,
+            untracked: false,
+            immutable: false,
+            stack_conflicts: false,
+            signature: false,
+            warn_unsigned: false,
+            megamerge: false,
+            parent: false,
+            template: None,
+            checks: false,
+            stack_diff: None,
+            trunk_revset: None,
+            tag: TagConfig::default(),
+            conventional: ConventionalConfig::default(),
+            async_segments: false,
+            daemon: false,
+            snapshot: false,
+            latency_budget: None,
+            timeout: None,
+            abbreviate_counts: DEFAULT_ABBREVIATE_COUNTS,
+            format: None,
+            palette: Palette::default(),
+            styles: Styles::default(),
+            jj_config_toml: Vec::new(),
+            ssh_profile: SshProfile::default(),
+            ci_profile: CiProfile::default(),
+            cache: true,
+            background: false,
+            resolve_symlinks: true,
+            discovery: Discovery::default(),
+            clone_marker: true,
+            workspace: true,
+            op_heads: true,
         }
     }
 }
@@ -82,82 +459,559 @@ pub struct DisplayFlags {
 }
 
 impl DisplayFlags {
-    fn into_config(self, env_prefix: &str) -> DisplayConfig {
+    fn into_config(self, env_prefix: &str, sources: &Sources) -> DisplayConfig {
+        let off = |flag, name| sources.flag(flag, &format!("{env_prefix}_{name}"));
         DisplayConfig {
-            show_prefix: !self.no_prefix && env::var(format!("{env_prefix}_PREFIX")).is_err(),
-            show_name: !self.no_name && env::var(format!("{env_prefix}_NAME")).is_err(),
-            show_id: !self.no_id && env::var(format!("{env_prefix}_ID")).is_err(),
-            show_status: !self.no_status && env::var(format!("{env_prefix}_STATUS")).is_err(),
-            show_color: !self.no_color && env::var(format!("{env_prefix}_COLOR")).is_err(),
-            show_prefix_color: !self.no_prefix_color
-                && env::var("JJ_STARSHIP_NO_PREFIX_COLOR").is_err(),
+            show_prefix: !off(self.no_prefix, "PREFIX"),
+            show_name: !off(self.no_name, "NAME"),
+            show_id: !off(self.no_id, "ID"),
+            show_status: !off(self.no_status, "STATUS"),
+            show_color: !off(self.no_color, "COLOR")
+                && !sources.flag(false, "JJ_STARSHIP_NO_COLOR"),
+            show_prefix_color: !sources.flag(self.no_prefix_color, "JJ_STARSHIP_NO_PREFIX_COLOR"),
         }
     }
 }
 
+/// Raw option values from the CLI, resolved against env vars and defaults
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ConfigArgs {
+    pub truncate_name: Option<usize>,
+    pub id_length: Option<usize>,
+    pub id_prefix_length: Option<usize>,
+    pub change_id_encoding: Option<ChangeIdEncoding>,
+    pub hash_color: bool,
+    pub hash_bookmark_colors: bool,
+    pub ancestor_bookmark_depth: Option<usize>,
+    pub descendant_bookmark_depth: Option<usize>,
+    pub max_bookmarks: Option<usize>,
+    pub jj_symbol: Option<String>,
+    pub git_symbol: Option<String>,
+    pub no_symbol: bool,
+    pub symbol_set: Option<SymbolSet>,
+    pub symbols: Vec<String>,
+    pub jj_flags: DisplayFlags,
+    pub git_flags: DisplayFlags,
+    pub diff_stat: bool,
+    pub diff_stat_bar: bool,
+    pub diff_stat_bar_width: Option<usize>,
+    pub description: bool,
+    pub description_width: Option<usize>,
+    pub description_empty: Option<String>,
+    pub wip_patterns: Vec<String>,
+    pub trailers: Vec<String>,
+    pub issue_key: bool,
+    pub issue_patterns: Vec<String>,
+    pub unpushed_bookmarks: bool,
+    pub anonymous_heads: bool,
+    pub stacks: bool,
+    pub trunk_distance: bool,
+    pub remote_distance: bool,
+    pub git_staged: bool,
+    pub git_head: bool,
+    pub conflict_marker: bool,
+    pub empty_commit: bool,
+    pub file_changes: bool,
+    pub untracked: bool,
+    pub immutable: bool,
+    pub stack_conflicts: bool,
+    pub signature: bool,
+    pub warn_unsigned: bool,
+    pub megamerge: bool,
+    pub parent: bool,
+    pub template: Option<String>,
+    pub checks: bool,
+    pub stack_diff: Option<String>,
+    pub trunk_revset: Option<String>,
+    pub nearest_tag: bool,
+    pub tag_distance: bool,
+    pub tag_semver: bool,
+    pub conventional: bool,
+    pub conventional_pattern: Option<String>,
+    pub async_segments: bool,
+    pub daemon: bool,
+    pub snapshot: bool,
+    pub latency_budget: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    pub abbreviate_counts: Option<usize>,
+    pub format: Option<String>,
+    pub starship_palette: bool,
+    pub styles: Vec<String>,
+    pub jj_config_toml: Vec<String>,
+    pub ssh_profile: Option<SshProfile>,
+    pub ci_profile: Option<CiProfile>,
+    pub no_resolve_symlinks: bool,
+    pub max_depth: Option<usize>,
+    pub cross_filesystems: bool,
+    pub no_clone_marker: bool,
+    pub no_workspace: bool,
+    pub no_op_heads: bool,
+    pub no_cache: bool,
+}
+
+/// Whether to resolve symlinks in the working directory, and how far to look
+/// for the repo from it
+#[must_use]
+pub fn discovery(args: &ConfigArgs, sources: &Sources) -> (bool, Discovery) {
+    let resolve_symlinks =
+        !sources.flag(args.no_resolve_symlinks, "JJ_STARSHIP_NO_RESOLVE_SYMLINKS");
+    let discovery = Discovery {
+        max_depth: args
+            .max_depth
+            .or_else(|| sources.var("JJ_STARSHIP_MAX_DEPTH")?.parse().ok()),
+        cross_filesystems: sources.flag(args.cross_filesystems, "JJ_STARSHIP_CROSS_FILESYSTEMS"),
+    };
+    (resolve_symlinks, discovery)
+}
+
+/// CLI patterns, else the env var as a single regex or the config file's list,
+/// else None for the defaults
+fn patterns_or(cli: Vec<String>, sources: &Sources, var: &str) -> Option<Vec<String>> {
+    if cli.is_empty() {
+        sources.list(var)
+    } else {
+        Some(cli)
+    }
+}
+
+/// Compile a list of regexes, dropping invalid ones
+fn compile_patterns(patterns: &[String]) -> RegexSet {
+    let valid = patterns.iter().filter(|p| regex::Regex::new(p).is_ok());
+    RegexSet::new(valid).unwrap_or_else(|_| RegexSet::empty())
+}
+
 impl Config {
-    /// Create config from CLI args and environment variables
-    /// CLI args take precedence over env vars
-    #[allow(clippy::fn_params_excessive_bools, clippy::too_many_arguments)]
-    pub fn new(
-        truncate_name: Option<usize>,
-        id_length: Option<usize>,
-        ancestor_bookmark_depth: Option<usize>,
-        jj_symbol: Option<String>,
-        git_symbol: Option<String>,
-        no_symbol: bool,
-        jj_flags: DisplayFlags,
-        git_flags: DisplayFlags,
-    ) -> Self {
+    /// Create config from CLI args, environment variables and config files
+    /// CLI args take precedence over env vars, env vars over config files
+    #[allow(clippy::too_many_lines)]
+    pub fn new(args: ConfigArgs, sources: &Sources) -> Self {
+        let (resolve_symlinks, discovery) = sources
+            .located()
+            .unwrap_or_else(|| discovery(&args, sources));
+        let ConfigArgs {
+            truncate_name,
+            id_length,
+            id_prefix_length,
+            change_id_encoding,
+            hash_color,
+            hash_bookmark_colors,
+            ancestor_bookmark_depth,
+            descendant_bookmark_depth,
+            max_bookmarks,
+            jj_symbol,
+            git_symbol,
+            no_symbol,
+            symbol_set,
+            symbols,
+            jj_flags,
+            git_flags,
+            diff_stat,
+            diff_stat_bar,
+            diff_stat_bar_width,
+            description,
+            description_width,
+            description_empty,
+            wip_patterns,
+            trailers,
+            issue_key,
+            issue_patterns,
+            unpushed_bookmarks,
+            anonymous_heads,
+            stacks,
+            trunk_distance,
+            remote_distance,
+            git_staged,
+            git_head,
+            conflict_marker,
+            empty_commit,
+            file_changes,
+            untracked,
+            immutable,
+            stack_conflicts,
+            signature,
+            warn_unsigned,
+            megamerge,
+            parent,
+            template,
+            checks,
+            stack_diff,
+            trunk_revset,
+            nearest_tag,
+            tag_distance,
+            tag_semver,
+            conventional,
+            conventional_pattern,
+            async_segments,
+            daemon,
+            snapshot,
+            latency_budget,
+            timeout_ms,
+            abbreviate_counts,
+            format,
+            starship_palette,
+            styles,
+            jj_config_toml,
+            ssh_profile,
+            ci_profile,
+            no_resolve_symlinks: _,
+            max_depth: _,
+            cross_filesystems: _,
+            no_clone_marker,
+            no_workspace,
+            no_op_heads,
+            no_cache,
+        } = args;
+
         let truncate_name = truncate_name
-            .or_else(|| env::var("JJ_STARSHIP_TRUNCATE_NAME").ok()?.parse().ok())
+            .or_else(|| sources.var("JJ_STARSHIP_TRUNCATE_NAME")?.parse().ok())
             .unwrap_or(0);
 
         let id_length = id_length
-            .or_else(|| env::var("JJ_STARSHIP_ID_LENGTH").ok()?.parse().ok())
+            .or_else(|| sources.var("JJ_STARSHIP_ID_LENGTH")?.parse().ok())
             .unwrap_or(8);
+        let id_prefix_length = id_prefix_length
+            .or_else(|| sources.var("JJ_STARSHIP_ID_PREFIX_LENGTH")?.parse().ok())
+            .unwrap_or(0);
+        let warn_unsigned = sources.flag(warn_unsigned, "JJ_STARSHIP_WARN_UNSIGNED");
 
         let ancestor_bookmark_depth = ancestor_bookmark_depth
             .or_else(|| {
-                env::var("JJ_STARSHIP_ANCESTOR_BOOKMARK_DEPTH")
-                    .ok()?
+                sources
+                    .var("JJ_STARSHIP_ANCESTOR_BOOKMARK_DEPTH")?
                     .parse()
                     .ok()
             })
             .unwrap_or(10);
 
-        let (jj_symbol, git_symbol) = if no_symbol {
+        let descendant_bookmark_depth = descendant_bookmark_depth
+            .or_else(|| {
+                sources
+                    .var("JJ_STARSHIP_DESCENDANT_BOOKMARK_DEPTH")?
+                    .parse()
+                    .ok()
+            })
+            .unwrap_or(10);
+
+        let max_bookmarks = max_bookmarks
+            .or_else(|| sources.var("JJ_STARSHIP_MAX_BOOKMARKS")?.parse().ok())
+            .unwrap_or(0);
+
+        let symbol_set = symbol_set
+            .or_else(|| SymbolSet::from_str(&sources.var("JJ_STARSHIP_SYMBOLS")?, true).ok())
+            .unwrap_or_default();
+        let symbols = Symbols::new(symbol_set, &symbols, |name| {
+            sources.var(&format!("JJ_STARSHIP_SYMBOL_{}", name.to_ascii_uppercase()))
+        });
+        let (jj_symbol, git_symbol) = if sources.flag(no_symbol, "JJ_STARSHIP_NO_SYMBOL") {
             (Cow::Borrowed(""), Cow::Borrowed(""))
         } else {
             let jj = jj_symbol
-                .or_else(|| env::var("JJ_STARSHIP_JJ_SYMBOL").ok())
-                .map_or(Cow::Borrowed(DEFAULT_JJ_SYMBOL), Cow::Owned);
+                .or_else(|| sources.var("JJ_STARSHIP_JJ_SYMBOL"))
+                .map_or(Cow::Borrowed(symbol_set.jj()), Cow::Owned);
             let git = git_symbol
-                .or_else(|| env::var("JJ_STARSHIP_GIT_SYMBOL").ok())
-                .map_or(Cow::Borrowed(DEFAULT_GIT_SYMBOL), Cow::Owned);
+                .or_else(|| sources.var("JJ_STARSHIP_GIT_SYMBOL"))
+                .map_or(Cow::Borrowed(symbol_set.git()), Cow::Owned);
             (jj, git)
         };
 
-        Self {
+        let diff_stat_bar = sources.flag(diff_stat_bar, "JJ_STARSHIP_DIFFSTAT_BAR");
+        let diff_stat = DiffStatConfig {
+            // Bar mode needs the counts, so it implies the segment
+            enabled: diff_stat_bar || sources.flag(diff_stat, "JJ_STARSHIP_DIFFSTAT"),
+            bar: diff_stat_bar,
+            bar_width: diff_stat_bar_width
+                .or_else(|| sources.var("JJ_STARSHIP_DIFFSTAT_BAR_WIDTH")?.parse().ok())
+                .unwrap_or(DEFAULT_DIFFSTAT_BAR_WIDTH)
+                .clamp(1, MAX_DIFFSTAT_BAR_WIDTH),
+        };
+
+        // CLI patterns replace the defaults; the env var holds a single regex
+        // (use alternation to list several). Invalid patterns are skipped so a
+        // typo never breaks the prompt.
+        let wip_patterns = patterns_or(wip_patterns, sources, "JJ_STARSHIP_WIP_PATTERN")
+            .unwrap_or_else(|| {
+                DEFAULT_WIP_PATTERNS
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            });
+        let description = DescriptionConfig {
+            enabled: sources.flag(description, "JJ_STARSHIP_DESCRIPTION"),
+            wip_patterns: compile_patterns(&wip_patterns),
+            max_width: description_width
+                .or_else(|| sources.var("JJ_STARSHIP_DESCRIPTION_WIDTH")?.parse().ok())
+                .unwrap_or(0),
+            empty_marker: description_empty
+                .or_else(|| sources.var("JJ_STARSHIP_DESCRIPTION_EMPTY"))
+                .unwrap_or_else(|| DEFAULT_DESCRIPTION_EMPTY.to_string()),
+        };
+
+        let issue_key = IssueKeyConfig {
+            enabled: sources.flag(issue_key, "JJ_STARSHIP_ISSUE_KEY"),
+            patterns: patterns_or(issue_patterns, sources, "JJ_STARSHIP_ISSUE_PATTERN")
+                .map_or_else(
+                    || IssueKeyConfig::default().patterns,
+                    |patterns| patterns.iter().filter_map(|p| Regex::new(p).ok()).collect(),
+                ),
+        };
+
+        // The display options need the tag, so they imply the segment
+        let tag_distance = sources.flag(tag_distance, "JJ_STARSHIP_TAG_DISTANCE");
+        let tag_semver = sources.flag(tag_semver, "JJ_STARSHIP_TAG_SEMVER");
+        let tag = TagConfig {
+            enabled: tag_distance
+                || tag_semver
+                || sources.flag(nearest_tag, "JJ_STARSHIP_NEAREST_TAG"),
+            distance: tag_distance,
+            semver: tag_semver,
+        };
+
+        // An invalid pattern keeps the default rather than failing every description
+        let conventional = ConventionalConfig {
+            enabled: sources.flag(conventional, "JJ_STARSHIP_CONVENTIONAL"),
+            pattern: conventional_pattern
+                .or_else(|| sources.var("JJ_STARSHIP_CONVENTIONAL_PATTERN"))
+                .and_then(|p| Regex::new(&p).ok())
+                .unwrap_or_else(|| ConventionalConfig::default().pattern),
+        };
+
+        // The env var holds a comma-separated list
+        let trailers = if trailers.is_empty() {
+            sources
+                .list("JJ_STARSHIP_TRAILERS")
+                .unwrap_or_default()
+                .iter()
+                .flat_map(|v| v.split(','))
+                .map(|k| k.trim().to_string())
+                .filter(|k| !k.is_empty())
+                .collect()
+        } else {
+            trailers
+        };
+
+        let mut config = Self {
             truncate_name,
             id_length,
+            id_prefix_length,
+            change_id_encoding: change_id_encoding
+                .or_else(|| {
+                    ChangeIdEncoding::from_str(
+                        &sources.var("JJ_STARSHIP_CHANGE_ID_ENCODING")?,
+                        true,
+                    )
+                    .ok()
+                })
+                .unwrap_or_default(),
+            hash_color: sources.flag(hash_color, "JJ_STARSHIP_HASH_COLOR"),
+            hash_bookmark_colors: sources
+                .flag(hash_bookmark_colors, "JJ_STARSHIP_HASH_BOOKMARK_COLORS"),
             ancestor_bookmark_depth,
+            descendant_bookmark_depth,
+            max_bookmarks,
             jj_symbol,
             git_symbol,
-            jj_display: jj_flags.into_config("JJ_STARSHIP_NO_JJ"),
-            git_display: git_flags.into_config("JJ_STARSHIP_NO_GIT"),
+            symbols,
+            jj_display: jj_flags.into_config("JJ_STARSHIP_NO_JJ", sources),
+            git_display: git_flags.into_config("JJ_STARSHIP_NO_GIT", sources),
+            diff_stat,
+            description,
+            trailers,
+            issue_key,
+            tag,
+            conventional,
+            unpushed_bookmarks: sources.flag(unpushed_bookmarks, "JJ_STARSHIP_UNPUSHED_BOOKMARKS"),
+            anonymous_heads: sources.flag(anonymous_heads, "JJ_STARSHIP_ANONYMOUS_HEADS"),
+            stacks: sources.flag(stacks, "JJ_STARSHIP_STACKS"),
+            trunk_distance: sources.flag(trunk_distance, "JJ_STARSHIP_TRUNK_DISTANCE"),
+            remote_distance: sources.flag(remote_distance, "JJ_STARSHIP_REMOTE_DISTANCE"),
+            git_staged: sources.flag(git_staged, "JJ_STARSHIP_GIT_STAGED"),
+            git_head: sources.flag(git_head, "JJ_STARSHIP_GIT_HEAD"),
+            conflict_marker: sources.flag(conflict_marker, "JJ_STARSHIP_CONFLICT_MARKER"),
+            empty_commit: sources.flag(empty_commit, "JJ_STARSHIP_EMPTY_COMMIT"),
+            file_changes: sources.flag(file_changes, "JJ_STARSHIP_FILE_CHANGES"),
+            untracked: sources.flag(untracked, "JJ_STARSHIP_UNTRACKED"),
+            immutable: sources.flag(immutable, "JJ_STARSHIP_IMMUTABLE"),
+            stack_conflicts: sources.flag(stack_conflicts, "JJ_STARSHIP_STACK_CONFLICTS"),
+            signature: sources.flag(signature, "JJ_STARSHIP_SIGNATURE") || warn_unsigned,
+            warn_unsigned,
+            megamerge: sources.flag(megamerge, "JJ_STARSHIP_MEGAMERGE"),
+            parent: sources.flag(parent, "JJ_STARSHIP_PARENT"),
+            template: template
+                .or_else(|| sources.var("JJ_STARSHIP_TEMPLATE"))
+                .filter(|t| !t.is_empty()),
+            checks: sources.flag(checks, "JJ_STARSHIP_CHECKS"),
+            // The env var holds the base, empty for the default
+            stack_diff: stack_diff.or_else(|| {
+                let base = sources.var("JJ_STARSHIP_STACK_DIFF")?;
+                Some(if base.is_empty() {
+                    DEFAULT_STACK_DIFF_BASE.to_string()
+                } else {
+                    base
+                })
+            }),
+            trunk_revset: trunk_revset
+                .or_else(|| sources.var("JJ_STARSHIP_TRUNK_REVSET"))
+                .filter(|revset| !revset.trim().is_empty()),
+            async_segments: sources.flag(async_segments, "JJ_STARSHIP_ASYNC"),
+            daemon: sources.flag(daemon, "JJ_STARSHIP_DAEMON"),
+            snapshot: sources.flag(snapshot, "JJ_STARSHIP_SNAPSHOT"),
+            latency_budget: latency_budget
+                .or_else(|| sources.var("JJ_STARSHIP_LATENCY_BUDGET")?.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+            timeout: timeout_ms
+                .or_else(|| sources.var("JJ_STARSHIP_TIMEOUT_MS")?.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+            abbreviate_counts: abbreviate_counts
+                .or_else(|| sources.var("JJ_STARSHIP_ABBREVIATE_COUNTS")?.parse().ok())
+                .unwrap_or(DEFAULT_ABBREVIATE_COUNTS),
+            format: format
+                .or_else(|| sources.var("JJ_STARSHIP_FORMAT"))
+                .map(|f| Format::parse(&f)),
+            palette: if sources.flag(starship_palette, "JJ_STARSHIP_STARSHIP_PALETTE") {
+                Palette::load()
+            } else {
+                Palette::default()
+            },
+            styles: Styles::new(&styles, |name| {
+                sources.var(&format!("JJ_STARSHIP_STYLE_{}", name.to_ascii_uppercase()))
+            }),
+            jj_config_toml: if jj_config_toml.is_empty() {
+                sources.list("JJ_STARSHIP_CONFIG_TOML").unwrap_or_default()
+            } else {
+                jj_config_toml
+            },
+            ssh_profile: ssh_profile
+                .or_else(|| {
+                    SshProfile::from_str(&sources.var("JJ_STARSHIP_SSH_PROFILE")?, true).ok()
+                })
+                .unwrap_or_default(),
+            ci_profile: ci_profile
+                .or_else(|| CiProfile::from_str(&sources.var("JJ_STARSHIP_CI_PROFILE")?, true).ok())
+                .unwrap_or_default(),
+            cache: !sources.flag(no_cache, "JJ_STARSHIP_NO_CACHE"),
+            background: false,
+            resolve_symlinks,
+            discovery,
+            clone_marker: !sources.flag(no_clone_marker, "JJ_STARSHIP_NO_CLONE_MARKER"),
+            workspace: !sources.flag(no_workspace, "JJ_STARSHIP_NO_WORKSPACE"),
+            op_heads: !sources.flag(no_op_heads, "JJ_STARSHIP_NO_OP_HEADS"),
+        };
+        if sources.profile() == Some(MINIMAL_PROFILE) {
+            config.strip();
+        }
+        config
+    }
+
+    /// Switch to the degraded profile: expensive segments never block the prompt
+    /// and the bookmark walks are skipped
+    pub fn degrade(&mut self) {
+        self.async_segments = true;
+        self.ancestor_bookmark_depth = 0;
+        self.descendant_bookmark_depth = 0;
+    }
+
+    /// Switch to the light profile for remote sessions: degraded, and the default
+    /// Nerd Font symbols dropped since the local terminal's font may lack them
+    pub fn lighten(&mut self) {
+        self.degrade();
+        if self.jj_symbol == DEFAULT_JJ_SYMBOL {
+            self.jj_symbol = Cow::Borrowed("");
         }
+        if self.git_symbol == DEFAULT_GIT_SYMBOL {
+            self.git_symbol = Cow::Borrowed("");
+        }
+    }
+
+    /// Switch to the minimal profile for CI: only the core segments, without
+    /// colors or the default symbols, computed synchronously and without
+    /// touching the cache
+    pub fn minimize(&mut self) {
+        self.lighten();
+        self.async_segments = false;
+        self.daemon = false;
+        self.snapshot = false;
+        self.cache = false;
+        self.latency_budget = None;
+        self.timeout = None;
+        self.jj_display.show_color = false;
+        self.git_display.show_color = false;
+        self.styles = Styles::default();
+        self.strip();
     }
 
-    /// Truncate a string to max length, adding ellipsis if needed
+    /// Switch off every segment but the core ones: the change id or branch,
+    /// bookmarks and status
+    pub fn strip(&mut self) {
+        self.diff_stat.enabled = false;
+        self.description.enabled = false;
+        self.trailers.clear();
+        self.issue_key.enabled = false;
+        self.unpushed_bookmarks = false;
+        self.anonymous_heads = false;
+        self.stacks = false;
+        self.trunk_distance = false;
+        self.remote_distance = false;
+        self.git_staged = false;
+        self.git_head = false;
+        self.conflict_marker = false;
+        self.empty_commit = false;
+        self.file_changes = false;
+        self.untracked = false;
+        self.immutable = false;
+        self.clone_marker = false;
+        self.stack_conflicts = false;
+        self.signature = false;
+        self.tag.enabled = false;
+        self.conventional.enabled = false;
+        self.stack_diff = None;
+        self.checks = false;
+        self.megamerge = false;
+        self.parent = false;
+        self.template = None;
+    }
+
+    /// Format a count, abbreviating large ones as `1.2k`, `34k`, `5.6M`
+    /// Rounds down so a count never looks bigger than it is (999999 is `999k`)
+    #[must_use]
+    pub fn format_count(&self, n: usize) -> String {
+        if self.abbreviate_counts == 0 || n < self.abbreviate_counts {
+            return n.to_string();
+        }
+        for (scale, suffix) in [(1_000_000_000, 'G'), (1_000_000, 'M'), (1_000, 'k')] {
+            if n >= scale {
+                let whole = n / scale;
+                let tenth = n % scale / (scale / 10);
+                return if whole < 10 && tenth > 0 {
+                    format!("{whole}.{tenth}{suffix}")
+                } else {
+                    format!("{whole}{suffix}")
+                };
+            }
+        }
+        n.to_string()
+    }
+
+    /// Truncate a name to `--truncate-name`, adding ellipsis if needed
+    #[must_use]
     pub fn truncate<'a>(&self, s: &'a str) -> Cow<'a, str> {
-        if self.truncate_name == 0 || s.chars().count() <= self.truncate_name {
+        self.truncate_to(s, self.truncate_name)
+    }
+
+    /// Truncate a string to `max` chars (0 = unlimited), adding ellipsis if needed
+    #[must_use]
+    pub fn truncate_to<'a>(&self, s: &'a str, max: usize) -> Cow<'a, str> {
+        let ellipsis = &self.symbols.ellipsis;
+        if max == 0 || s.chars().count() <= max {
             Cow::Borrowed(s)
-        } else if self.truncate_name <= 1 {
-            Cow::Borrowed("…")
+        } else if max <= ellipsis.chars().count() {
+            Cow::Owned(ellipsis.to_string())
         } else {
-            let truncated: String = s.chars().take(self.truncate_name - 1).collect();
-            Cow::Owned(truncated + "…")
+            let keep = max - ellipsis.chars().count();
+            let truncated: String = s.chars().take(keep).collect();
+            Cow::Owned(truncated + ellipsis)
         }
     }
 }
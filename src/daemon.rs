@@ -0,0 +1,324 @@
+//! `jj-starship daemon`: prompts from a long-running process over a unix socket
+//!
+//! A prompt started with `--daemon` sends its directory to the daemon and
+//! prints the answer, falling back to rendering itself when no daemon
+//! listens. The daemon remembers the last prompt of each repo together with
+//! the modification times of the repo's metadata (jj's op heads and checkout,
+//! git's HEAD, index and refs, see `DetectResult::watch_paths`); as long as
+//! they are unchanged, a query is answered without loading the repo again.
+//! Git worktree edits don't show in the metadata, so Git prompts are only
+//! reused for a couple of seconds.
+//!
+//! The client's options must match: the daemon resolves its own for each
+//! directory (its flags, env and the repo's config file) and only answers if
+//! they are the client's, rendering with the client's capabilities (color,
+//! width, shell). A client with other flags, env or profile renders itself.
+//!
+//! Protocol: the client writes the directory and a newline, then the key of
+//! its options (see `config_key`) and its capabilities, separated by spaces,
+//! and a newline; the daemon answers `+` and the prompt, `-` outside a repo,
+//! or `!` when its options differ, and closes.
+
+use crate::config::Config;
+use crate::detect::{self, RepoType};
+use crate::output::{Capabilities, Shell};
+use crate::watch::fingerprint;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a client waits for the daemon before rendering itself
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the daemon waits for a client's request
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Age after which a remembered Git prompt is rendered again
+const GIT_TTL: Duration = Duration::from_secs(2);
+
+/// Age after which a remembered JJ prompt is rendered again, for values that
+/// change without an operation (CI checks, segments computed in the background)
+const JJ_TTL: Duration = Duration::from_secs(60);
+
+/// Socket of the daemon: `$JJ_STARSHIP_SOCKET`, else `jj-starship.sock` in
+/// `$XDG_RUNTIME_DIR`, else in the temp dir with the user's name (which other
+/// users can bind first, see `owned_by_user`)
+#[must_use]
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = env::var_os("JJ_STARSHIP_SOCKET") {
+        return PathBuf::from(path);
+    }
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir).join("jj-starship.sock");
+    }
+    let user = env::var("USER").unwrap_or_default();
+    env::temp_dir().join(format!("jj-starship-{user}.sock"))
+}
+
+/// What the daemon answered for a directory
+#[derive(Debug, PartialEq, Eq)]
+pub enum Answer {
+    Prompt(String),
+    OutsideRepo,
+}
+
+/// Answer for `cwd` rendered with `config` and `caps` from the daemon at
+/// `socket`, None if no daemon of this user answered or its options differ
+#[must_use]
+pub fn query(socket: &Path, cwd: &Path, config: &Config, caps: Capabilities) -> Option<Answer> {
+    if !owned_by_user(socket) {
+        return None;
+    }
+    let mut stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    writeln!(stream, "{}", cwd.display()).ok()?;
+    writeln!(stream, "{} {}", config_key(config), caps_field(caps)).ok()?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).ok()?;
+    match answer.split_at_checked(1)? {
+        ("+", prompt) => Some(Answer::Prompt(prompt.to_string())),
+        ("-", "") => Some(Answer::OutsideRepo),
+        _ => None,
+    }
+}
+
+/// Whether `socket` is a socket of the current user, so the answer, printed
+/// as is, can't come from another user's process
+fn owned_by_user(socket: &Path) -> bool {
+    fs::metadata(socket).is_ok_and(|metadata| {
+        metadata.file_type().is_socket() && metadata.uid() == rustix::process::getuid().as_raw()
+    })
+}
+
+/// Key of the options a prompt renders with, equal in the client and the
+/// daemon when they resolved the same ones
+fn config_key(config: &Config) -> String {
+    let config = Config {
+        daemon: false,
+        ..config.clone()
+    };
+    let mut hasher = DefaultHasher::new();
+    format!("{config:?}").hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// `caps` as sent by the client: color (1 or 0), width (`-` for none), shell
+fn caps_field(caps: Capabilities) -> String {
+    let width = caps
+        .width
+        .map_or_else(|| "-".to_string(), |w| w.to_string());
+    let shell = caps
+        .shell
+        .to_possible_value()
+        .map_or_else(String::new, |value| value.get_name().to_string());
+    format!("{} {width} {shell}", u8::from(caps.color))
+}
+
+/// Capabilities sent by a client, None if malformed
+fn parse_caps(field: &str) -> Option<Capabilities> {
+    let mut parts = field.split(' ');
+    let color = match parts.next()? {
+        "1" => true,
+        "0" => false,
+        _ => return None,
+    };
+    let width = match parts.next()? {
+        "-" => None,
+        width => Some(width.parse().ok()?),
+    };
+    let shell = Shell::from_str(parts.next()?, true).ok()?;
+    Some(Capabilities {
+        color,
+        width,
+        shell,
+    })
+}
+
+/// Answer queries on `socket` one at a time, until the listener fails;
+/// `config_for` resolves the daemon's options for a directory
+///
+/// # Errors
+/// Binding `socket` or accepting on it failed
+pub fn run(socket: &Path, config_for: impl Fn(&Path) -> Config) -> io::Result<()> {
+    let listener = bind(socket)?;
+    eprintln!("jj-starship: daemon listening on {}", socket.display());
+    let mut memo = Memo::default();
+    for stream in listener.incoming() {
+        // A client going away mid-request only affects that request
+        let _ = stream.and_then(|stream| handle(&stream, &mut memo, &config_for));
+    }
+    Ok(())
+}
+
+/// Listen on `socket`, only for this user. A socket file left behind by a
+/// daemon that is gone is replaced; a live daemon's is not.
+fn bind(socket: &Path) -> io::Result<UnixListener> {
+    let listener = match UnixListener::bind(socket) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(socket).is_err() => {
+            fs::remove_file(socket)?;
+            UnixListener::bind(socket)?
+        }
+        result => result?,
+    };
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn handle(
+    stream: &UnixStream,
+    memo: &mut Memo,
+    config_for: &impl Fn(&Path) -> Config,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let (mut dir, mut options) = (String::new(), String::new());
+    reader.read_line(&mut dir)?;
+    reader.read_line(&mut options)?;
+    // The client sends its start dir, symlinks already resolved or not
+    let cwd = PathBuf::from(dir.trim_end_matches('\n'));
+    let options = options.trim_end_matches('\n');
+    let config = config_for(&cwd);
+    let caps = options
+        .split_once(' ')
+        .filter(|(key, _)| *key == config_key(&config))
+        .and_then(|(_, caps)| parse_caps(caps));
+    let answer = match caps {
+        Some(caps) => match memo.prompt(&cwd, options, &config, caps) {
+            Some(prompt) => format!("+{prompt}"),
+            None => "-".to_string(),
+        },
+        None => "!".to_string(),
+    };
+    let mut stream = stream;
+    stream.write_all(answer.as_bytes())
+}
+
+/// Last prompt of a repo and the state it was rendered in
+struct Entry {
+    fingerprint: Vec<Option<SystemTime>>,
+    rendered: Instant,
+    prompt: Option<String>,
+}
+
+/// Last prompts by directory and the client's options line
+#[derive(Default)]
+struct Memo(HashMap<(PathBuf, String), Entry>);
+
+impl Memo {
+    /// Prompt for `cwd`, rendered again only if its repo's metadata changed
+    /// or the remembered one is too old
+    fn prompt(
+        &mut self,
+        cwd: &Path,
+        options: &str,
+        config: &Config,
+        caps: Capabilities,
+    ) -> Option<String> {
+        let result = detect::detect(cwd, config.discovery);
+        if result.repo_type == RepoType::None {
+            return None;
+        }
+        let ttl = if result.repo_type == RepoType::Git {
+            GIT_TTL
+        } else {
+            JJ_TTL
+        };
+        let current = fingerprint(&result.watch_paths());
+        let key = (cwd.to_path_buf(), options.to_string());
+        if let Some(entry) = self.0.get(&key) {
+            if entry.fingerprint == current && entry.rendered.elapsed() < ttl {
+                return entry.prompt.clone();
+            }
+        }
+        let prompt = crate::run_prompt(cwd, config, caps);
+        self.0.insert(
+            key,
+            Entry {
+                fingerprint: current,
+                rendered: Instant::now(),
+                prompt: prompt.clone(),
+            },
+        );
+        prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("daemon.sock");
+        let listener = bind(&socket).unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let outside = tmp.path().to_path_buf();
+        let server = std::thread::spawn(move || {
+            let mut memo = Memo::default();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                handle(&stream, &mut memo, &|_: &Path| Config::default()).unwrap();
+            }
+        });
+        let config = Config {
+            daemon: true,
+            ..Config::default()
+        };
+        let caps = Capabilities::default();
+        assert_eq!(
+            query(&socket, &outside, &config, caps),
+            Some(Answer::OutsideRepo)
+        );
+        // Other options than the daemon's: the client renders itself
+        let other = Config {
+            id_length: 4,
+            ..config.clone()
+        };
+        assert_eq!(query(&socket, &outside, &other, caps), None);
+        server.join().unwrap();
+
+        // Nobody listens anymore: the socket file is stale
+        assert_eq!(query(&socket, &outside, &config, caps), None);
+        drop(bind(&socket).unwrap());
+    }
+
+    #[test]
+    fn test_owned_by_user() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("daemon.sock");
+        assert!(!owned_by_user(&socket));
+        let _listener = bind(&socket).unwrap();
+        assert!(owned_by_user(&socket));
+        // Only sockets: a regular file is no daemon
+        let file = tmp.path().join("file");
+        fs::write(&file, "").unwrap();
+        assert!(!owned_by_user(&file));
+    }
+
+    #[test]
+    fn test_caps_round_trip() {
+        let caps = Capabilities {
+            color: false,
+            width: Some(80),
+            shell: Shell::Zsh,
+        };
+        let parsed = parse_caps(&caps_field(caps)).unwrap();
+        assert!(!parsed.color);
+        assert_eq!(parsed.width, Some(80));
+        assert_eq!(parsed.shell, Shell::Zsh);
+        let parsed = parse_caps(&caps_field(Capabilities::default())).unwrap();
+        assert_eq!(parsed.width, None);
+        assert!(parse_caps("1 wide zsh").is_none());
+    }
+}
@@ -1,9 +1,13 @@
 //! Repo type detection - walks up from cwd to find .jj or .git
 
+use crate::cache::CACHE_DIR;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 /// Type of repository detected
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RepoType {
     /// Pure JJ repo (.jj/ only)
     Jj,
@@ -22,20 +26,227 @@ pub struct DetectResult {
     pub repo_root: Option<PathBuf>,
 }
 
-/// Detect repo type by walking up from the given path
-pub fn detect(start: &Path) -> DetectResult {
-    let mut current = start.to_path_buf();
-
-    loop {
-        let has_jj = current.join(".jj").is_dir();
-        let has_git = current.join(".git").exists(); // can be file (worktree) or dir
+impl DetectResult {
+    /// Directory holding jj-starship's cache for this repo, shared by all of its
+    /// workspaces/worktrees (inside `.jj/repo`, or the common git dir for Git repos)
+    #[must_use]
+    pub fn cache_dir(&self) -> Option<PathBuf> {
+        let root = self.repo_root.as_ref()?;
+        match self.repo_type {
+            RepoType::Jj | RepoType::JjColocated => Some(jj_cache_dir(root)),
+            RepoType::Git => {
+                resolve_git_dir(&root.join(".git")).map(|d| git_common_dir(&d).join(CACHE_DIR))
+            }
+            RepoType::None => None,
+        }
+    }
 
-        let repo_type = match (has_jj, has_git) {
-            (true, true) => RepoType::JjColocated,
-            (true, false) => RepoType::Jj,
-            (false, true) => RepoType::Git,
-            (false, false) => RepoType::None,
+    /// Metadata files and directories whose modification time changes when the
+    /// repo does: jj's op heads (every operation) and the workspace's checkout,
+    /// or git's HEAD, index and refs
+    #[must_use]
+    pub fn watch_paths(&self) -> Vec<PathBuf> {
+        let Some(root) = self.repo_root.as_ref() else {
+            return Vec::new();
         };
+        match self.repo_type {
+            RepoType::Jj | RepoType::JjColocated => {
+                let mut paths = vec![root.join(".jj/working_copy/checkout")];
+                paths.extend(jj_repo_dir(root).map(|dir| dir.join("op_heads/heads")));
+                paths
+            }
+            RepoType::Git => {
+                let Some(git_dir) = resolve_git_dir(&root.join(".git")) else {
+                    return Vec::new();
+                };
+                let common = git_common_dir(&git_dir);
+                let mut paths: Vec<PathBuf> = ["HEAD", "index", "logs/HEAD"]
+                    .iter()
+                    .map(|name| git_dir.join(name))
+                    .collect();
+                paths.extend(
+                    ["packed-refs", "FETCH_HEAD", "refs/heads"]
+                        .iter()
+                        .map(|name| common.join(name)),
+                );
+                paths
+            }
+            RepoType::None => Vec::new(),
+        }
+    }
+}
+
+/// Repo directory behind a jj workspace (`.jj/repo`, or where a secondary
+/// workspace's pointer leads)
+#[must_use]
+pub fn jj_repo_dir(workspace_root: &Path) -> Option<PathBuf> {
+    resolve_jj_repo_dir(&workspace_root.join(".jj"))
+}
+
+/// Cache directory of the repo behind a jj workspace, falling back to the
+/// workspace's `.jj` if the repo pointer can't be resolved
+#[must_use]
+pub fn jj_cache_dir(workspace_root: &Path) -> PathBuf {
+    jj_repo_dir(workspace_root)
+        .unwrap_or_else(|| workspace_root.join(".jj"))
+        .join(CACHE_DIR)
+}
+
+/// Git dir shared by all worktrees: a linked worktree's `commondir` file points
+/// at it, relative to the worktree's git dir
+#[must_use]
+pub fn git_common_dir(git_dir: &Path) -> PathBuf {
+    fs::read_to_string(git_dir.join("commondir"))
+        .ok()
+        .map(|target| git_dir.join(target.trim_end_matches(['\n', '\r'])))
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(|| git_dir.to_path_buf())
+}
+
+/// Resolve `.git` to the git directory it refers to
+/// A directory is used as is; a file (worktree or submodule) holds a `gitdir: <path>`
+/// pointer, relative to the file's directory. Returns None for stale pointers.
+fn resolve_git_dir(dot_git: &Path) -> Option<PathBuf> {
+    let meta = fs::metadata(dot_git).ok()?;
+    if meta.is_dir() {
+        return Some(dot_git.to_path_buf());
+    }
+    let contents = fs::read_to_string(dot_git).ok()?;
+    let target = contents
+        .lines()
+        .find_map(|l| l.strip_prefix("gitdir:"))?
+        .trim();
+    let git_dir = dot_git.parent()?.join(target);
+    git_dir.is_dir().then_some(git_dir)
+}
+
+/// Resolve `.jj/repo` to the repo directory
+/// Secondary workspaces store a path to the main workspace's repo dir in a file,
+/// relative to `.jj`. Returns None if the workspace points at a missing repo.
+fn resolve_jj_repo_dir(dot_jj: &Path) -> Option<PathBuf> {
+    let repo = dot_jj.join("repo");
+    if repo.is_dir() {
+        return Some(repo);
+    }
+    let target = fs::read_to_string(&repo).ok()?;
+    let repo_dir = dot_jj.join(target.trim_end_matches(['\n', '\r']));
+    repo_dir.is_dir().then_some(repo_dir)
+}
+
+/// Git directory backing the repo of a jj workspace, if it uses the git backend
+#[must_use]
+pub fn jj_git_dir(workspace_root: &Path) -> Option<PathBuf> {
+    jj_repo_dir(workspace_root).and_then(|repo_dir| jj_git_target(&repo_dir))
+}
+
+/// Git directory backing a JJ repo, if it uses the git backend
+fn jj_git_target(repo_dir: &Path) -> Option<PathBuf> {
+    let store = repo_dir.join("store");
+    let target = fs::read_to_string(store.join("git_target")).ok()?;
+    Some(store.join(target.trim_end_matches(['\n', '\r'])))
+}
+
+/// Whether two paths refer to the same directory
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Whether `dir` can be looked into for `.jj` and `.git` entries
+/// False for a deleted directory, a vanished mount or one without search
+/// permission: whether it is a repo can't be told, and walking on up would
+/// attribute it to an enclosing repo.
+fn probeable(dir: &Path) -> bool {
+    fs::metadata(dir).is_ok_and(|meta| meta.is_dir())
+        && [".jj", ".git"]
+            .iter()
+            .all(|name| match fs::symlink_metadata(dir.join(name)) {
+                Ok(_) => true,
+                Err(e) => e.kind() == io::ErrorKind::NotFound,
+            })
+}
+
+/// Classify a single directory by its `.jj` and `.git` entries
+fn repo_type_at(dir: &Path) -> RepoType {
+    let jj_repo = dir
+        .join(".jj")
+        .is_dir()
+        .then(|| resolve_jj_repo_dir(&dir.join(".jj")))
+        .flatten();
+    let git_dir = resolve_git_dir(&dir.join(".git"));
+
+    match (jj_repo, git_dir) {
+        // Colocated only if jj's git backend is this very `.git`. A `.git` owned
+        // by something else (e.g. a git worktree of another repo) leaves jj in charge.
+        (Some(repo_dir), Some(git_dir)) => {
+            if jj_git_target(&repo_dir).is_some_and(|target| same_dir(&target, &git_dir)) {
+                RepoType::JjColocated
+            } else {
+                RepoType::Jj
+            }
+        }
+        (Some(_), None) => RepoType::Jj,
+        (None, Some(_)) => RepoType::Git,
+        (None, None) => RepoType::None,
+    }
+}
+
+/// Directory to start repo discovery from: `dir` with symlinks resolved, so a
+/// `$PWD` that is a symlink into a repo still finds it and every path to a repo
+/// yields the same root (`dir` as is when not resolving, or if it can't be)
+#[must_use]
+pub fn start_dir(dir: PathBuf, resolve_symlinks: bool) -> PathBuf {
+    if resolve_symlinks {
+        fs::canonicalize(&dir).unwrap_or(dir)
+    } else {
+        dir
+    }
+}
+
+/// Whether the jj workspace at `workspace_root` is colocated with its git repo
+#[cfg(feature = "git")]
+#[must_use]
+pub fn is_colocated(workspace_root: &Path) -> bool {
+    repo_type_at(workspace_root) == RepoType::JjColocated
+}
+
+/// How far repo discovery walks up from the starting directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Discovery {
+    /// Parent directories to look in above the start (None for no limit)
+    pub max_depth: Option<usize>,
+    /// Keep walking into parents on another filesystem, which git refuses to
+    /// by default (`GIT_DISCOVERY_ACROSS_FILESYSTEM`)
+    pub cross_filesystems: bool,
+}
+
+/// Device of the filesystem holding `dir`
+#[cfg(unix)]
+fn device(dir: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(dir).ok().map(|meta| meta.dev())
+}
+
+/// Filesystems can't be told apart here, so every directory is on the same one
+#[cfg(not(unix))]
+fn device(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Detect repo type by walking up from the given path, as far as `discovery`
+/// allows
+#[must_use]
+pub fn detect(start: &Path, discovery: Discovery) -> DetectResult {
+    let mut current = start.to_path_buf();
+    let start_device = device(start);
+
+    for depth in 0.. {
+        if !probeable(&current) {
+            break;
+        }
+        let repo_type = repo_type_at(&current);
 
         if repo_type != RepoType::None {
             return DetectResult {
@@ -44,8 +255,11 @@ pub fn detect(start: &Path) -> DetectResult {
             };
         }
 
-        // Walk up
-        if !current.pop() {
+        // Walk up, unless that leaves the allowed depth or filesystem
+        if discovery.max_depth.is_some_and(|max| depth >= max) || !current.pop() {
+            break;
+        }
+        if !discovery.cross_filesystems && device(&current) != start_device {
             break;
         }
     }
@@ -57,6 +271,168 @@ pub fn detect(start: &Path) -> DetectResult {
 }
 
 /// Returns true if in any repo (for `jj-starship detect` command)
-pub fn in_repo(start: &Path) -> bool {
-    detect(start).repo_type != RepoType::None
+#[must_use]
+pub fn in_repo(start: &Path, discovery: Discovery) -> bool {
+    detect(start, discovery).repo_type != RepoType::None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Main checkout with `.jj` + `.git`, the layout `jj git init --colocate` creates
+    fn colocated_repo(root: &Path) {
+        fs::create_dir_all(root.join(".git/worktrees/wt")).unwrap();
+        fs::create_dir_all(root.join(".jj/repo/store")).unwrap();
+        write(&root.join(".jj/repo/store/git_target"), "../../../.git");
+    }
+
+    #[test]
+    fn test_detect_colocated_and_git_worktree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let main = tmp.path().join("main");
+        colocated_repo(&main);
+        assert_eq!(
+            detect(&main, Discovery::default()).repo_type,
+            RepoType::JjColocated
+        );
+
+        // Git worktree of the colocated repo, nested in the main checkout
+        let wt = main.join("wt");
+        write(
+            &wt.join(".git"),
+            &format!("gitdir: {}\n", main.join(".git/worktrees/wt").display()),
+        );
+        fs::create_dir_all(wt.join("src")).unwrap();
+        let result = detect(&wt.join("src"), Discovery::default());
+        assert_eq!(result.repo_type, RepoType::Git);
+        assert_eq!(result.repo_root, Some(wt));
+
+        // Worktrees share the main repo's cache
+        write(&main.join(".git/worktrees/wt/commondir"), "../..\n");
+        let cache_dir = result.cache_dir().unwrap();
+        assert!(same_dir(cache_dir.parent().unwrap(), &main.join(".git")));
+        // ...and watch the worktree's own HEAD but the shared refs
+        let paths = result.watch_paths();
+        assert!(paths.contains(&main.join(".git/worktrees/wt/HEAD")));
+        let refs = paths.iter().find(|p| p.ends_with("refs/heads")).unwrap();
+        assert!(same_dir(
+            refs.parent().unwrap().parent().unwrap(),
+            &main.join(".git")
+        ));
+    }
+
+    #[test]
+    fn test_detect_stops_at_deleted_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let main = tmp.path().join("main");
+        colocated_repo(&main);
+        let gone = main.join("gone");
+        fs::create_dir_all(gone.join("deeper")).unwrap();
+        assert_eq!(
+            detect(&gone, Discovery::default()).repo_type,
+            RepoType::JjColocated
+        );
+
+        // A deleted cwd is no longer shown as part of the repo that held it
+        fs::remove_dir_all(&gone).unwrap();
+        assert_eq!(
+            detect(&gone, Discovery::default()).repo_type,
+            RepoType::None
+        );
+        assert_eq!(
+            detect(&gone.join("deeper"), Discovery::default()).repo_type,
+            RepoType::None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_dir_resolves_symlink_into_repo() {
+        let tmp = tempfile::tempdir().unwrap();
+        let main = tmp.path().join("main");
+        colocated_repo(&main);
+        fs::create_dir_all(main.join("src/deep")).unwrap();
+        let link = tmp.path().join("link");
+        std::os::unix::fs::symlink(main.join("src/deep"), &link).unwrap();
+
+        // Walking up the link's own path never passes the repo root
+        assert_eq!(
+            detect(&start_dir(link.clone(), false), Discovery::default()).repo_type,
+            RepoType::None
+        );
+        let result = detect(&start_dir(link, true), Discovery::default());
+        assert_eq!(result.repo_type, RepoType::JjColocated);
+        assert_eq!(result.repo_root, Some(fs::canonicalize(&main).unwrap()));
+    }
+
+    #[test]
+    fn test_detect_jj_workspace_and_stale_pointers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let main = tmp.path().join("main");
+        colocated_repo(&main);
+
+        // Secondary jj workspace: `.jj/repo` is a pointer file, no `.git` of its own
+        let ws = tmp.path().join("ws");
+        write(&ws.join(".jj/repo"), "../../main/.jj/repo");
+        let result = detect(&ws, Discovery::default());
+        assert_eq!(result.repo_type, RepoType::Jj);
+        // ...sharing the main workspace's cache
+        let cache_dir = result.cache_dir().unwrap();
+        assert!(same_dir(
+            cache_dir.parent().unwrap(),
+            &main.join(".jj/repo")
+        ));
+        // ...and watching its op heads
+        fs::create_dir_all(main.join(".jj/repo/op_heads/heads")).unwrap();
+        let paths = result.watch_paths();
+        let op_heads = paths
+            .iter()
+            .find(|p| p.ends_with("op_heads/heads"))
+            .unwrap();
+        assert!(same_dir(op_heads, &main.join(".jj/repo/op_heads/heads")));
+        assert_eq!(
+            detect(&main, Discovery::default()).cache_dir().unwrap(),
+            main.join(".jj/repo").join(CACHE_DIR)
+        );
+
+        // Workspace whose `.git` belongs to another repo is not colocated
+        let other = tmp.path().join("other/.git");
+        fs::create_dir_all(&other).unwrap();
+        write(&ws.join(".git"), &format!("gitdir: {}", other.display()));
+        assert_eq!(detect(&ws, Discovery::default()).repo_type, RepoType::Jj);
+
+        // Stale pointers are ignored and the walk continues upwards
+        let stale = main.join("stale");
+        write(&stale.join(".git"), "gitdir: /nonexistent/.git/worktrees/x");
+        write(&stale.join(".jj/repo"), "/nonexistent/.jj/repo");
+        let result = detect(&stale, Discovery::default());
+        assert_eq!(result.repo_type, RepoType::JjColocated);
+        assert_eq!(result.repo_root, Some(main));
+    }
+
+    #[test]
+    fn test_detect_max_depth() {
+        let tmp = tempfile::tempdir().unwrap();
+        let main = tmp.path().join("main");
+        colocated_repo(&main);
+        let deep = main.join("a/b");
+        fs::create_dir_all(&deep).unwrap();
+        let within = |max_depth| Discovery {
+            max_depth: Some(max_depth),
+            ..Discovery::default()
+        };
+
+        assert_eq!(detect(&deep, within(1)).repo_type, RepoType::None);
+        let result = detect(&deep, within(2));
+        assert_eq!(result.repo_type, RepoType::JjColocated);
+        assert_eq!(result.repo_root, Some(main.clone()));
+        assert_eq!(detect(&main, within(0)).repo_type, RepoType::JjColocated);
+        assert!(!in_repo(&deep, within(0)));
+    }
 }
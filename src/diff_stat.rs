@@ -0,0 +1,18 @@
+//! Line-level change counts shared by the JJ and Git backends
+
+/// Added/removed line counts of the working copy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffStat {
+    /// Lines added
+    pub added: usize,
+    /// Lines removed
+    pub removed: usize,
+}
+
+impl DiffStat {
+    /// Total number of changed lines
+    pub const fn total(self) -> usize {
+        self.added + self.removed
+    }
+}
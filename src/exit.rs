@@ -0,0 +1,83 @@
+//! Exit codes of `--quiet` and `--when`
+//!
+//! Either flag makes the prompt usable as the `when` predicate of a starship
+//! custom module: exit 0 with the prompt (`--quiet`) or nothing (`--when`)
+//! inside a repo, else one of the codes below, with nothing on stderr either
+//! way. 2 is left to clap's usage errors, so a mistyped flag in the
+//! predicate isn't mistaken for an unreadable repo. The prompt then has to be ready within twice `--timeout-ms`: the
+//! expensive segments give up after the first half, and a repo that takes
+//! the second half as well to load is given up on.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Why no prompt was printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The directory is in no repo
+    NotARepo,
+    /// The repo was found but couldn't be read
+    LoadFailed,
+    /// The repo didn't load in time
+    TimedOut,
+}
+
+impl Failure {
+    /// Exit code of the failure
+    #[must_use]
+    pub fn code(self) -> u8 {
+        match self {
+            Self::NotARepo => 1,
+            Self::LoadFailed => 3,
+            Self::TimedOut => 4,
+        }
+    }
+}
+
+/// Result of `prompt`, or `TimedOut` if it takes longer than twice `timeout`
+///
+/// # Errors
+///
+/// `prompt` failed, or didn't finish in time
+pub fn within(
+    timeout: Option<Duration>,
+    prompt: impl FnOnce() -> Result<String, Failure> + Send + 'static,
+) -> Result<String, Failure> {
+    let Some(deadline) = timeout.and_then(|timeout| timeout.checked_mul(2)) else {
+        return prompt();
+    };
+    let (tx, rx) = mpsc::channel();
+    // Left running on timeout; the process exits right after
+    thread::spawn(move || {
+        let _ = tx.send(prompt());
+    });
+    match rx.recv_timeout(deadline) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(Failure::TimedOut),
+        // The prompt panicked
+        Err(RecvTimeoutError::Disconnected) => Err(Failure::LoadFailed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_within() {
+        assert_eq!(within(None, || Ok("ok".into())), Ok("ok".into()));
+        assert_eq!(
+            within(Some(Duration::from_secs(60)), || Err(Failure::LoadFailed)),
+            Err(Failure::LoadFailed)
+        );
+        let slow = || {
+            thread::sleep(Duration::from_secs(5));
+            Ok(String::new())
+        };
+        assert_eq!(
+            within(Some(Duration::from_millis(10)), slow),
+            Err(Failure::TimedOut)
+        );
+    }
+}
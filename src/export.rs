@@ -0,0 +1,103 @@
+//! `--output env`: the repo state as shell variable assignments
+//!
+//! Prints one `JJ_<FIELD>='value';` line per `get` field, quoted for the
+//! target shell, for a precmd hook to `eval` and reuse the values elsewhere in
+//! the prompt or in aliases without running jj-starship again. Every variable
+//! is assigned on each run, empty where it doesn't apply, so values from the
+//! previous repo don't linger.
+
+use crate::config::Config;
+use crate::detect;
+use crate::get::{self, Field};
+use crate::output::Shell;
+use clap::ValueEnum;
+use std::path::Path;
+
+/// What jj-starship prints for the prompt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputMode {
+    /// The rendered prompt
+    #[default]
+    Prompt,
+    /// `JJ_*` shell variable assignments to `eval`
+    Env,
+    /// The repo state as a JSON object
+    Json,
+}
+
+/// Assignments of every field for the repo containing `cwd`, in the syntax
+/// of `shell` (POSIX sh unless it is fish)
+#[must_use]
+pub fn render(cwd: &Path, config: &Config, shell: Shell) -> String {
+    let result = detect::detect(cwd, config.discovery);
+    let data = crate::collect(&result, config);
+    Field::value_variants()
+        .iter()
+        .map(|&field| {
+            let value = get::value(&result, data.as_ref(), field).unwrap_or_default();
+            assignment(&var_name(field), &value, shell)
+        })
+        .collect()
+}
+
+/// `JJ_` and the field's name in upper snake case, e.g. `JJ_CHANGE_ID`
+fn var_name(field: Field) -> String {
+    let name = field
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+    format!("JJ_{}", name.to_ascii_uppercase().replace('-', "_"))
+}
+
+/// One assignment line
+fn assignment(name: &str, value: &str, shell: Shell) -> String {
+    match shell {
+        Shell::Fish => format!("set -g {name} {};\n", quote_fish(value)),
+        Shell::None | Shell::Bash | Shell::Zsh => format!("{name}={};\n", quote_posix(value)),
+    }
+}
+
+/// Single-quoted for POSIX shells: nothing is special inside but the quote
+/// itself, which is closed, escaped and reopened
+fn quote_posix(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Single-quoted for fish, where `\` escapes `'` and itself
+fn quote_fish(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_var_name() {
+        assert_eq!(var_name(Field::ChangeId), "JJ_CHANGE_ID");
+        assert_eq!(var_name(Field::Bookmarks), "JJ_BOOKMARKS");
+        assert_eq!(var_name(Field::EmptyDescription), "JJ_EMPTY_DESCRIPTION");
+    }
+
+    #[test]
+    fn test_quoting() {
+        let value = "it's $HOME\\n `x`";
+        assert_eq!(
+            assignment("JJ_DESCRIPTION", value, Shell::Zsh),
+            "JJ_DESCRIPTION='it'\\''s $HOME\\n `x`';\n"
+        );
+        assert_eq!(
+            assignment("JJ_DESCRIPTION", value, Shell::Fish),
+            "set -g JJ_DESCRIPTION 'it\\'s $HOME\\\\n `x`';\n"
+        );
+    }
+
+    #[test]
+    fn test_outside_repo_clears_every_field() {
+        let tmp = tempfile::tempdir().unwrap();
+        let env = render(tmp.path(), &Config::default(), Shell::Bash);
+        assert_eq!(env.lines().count(), Field::value_variants().len());
+        assert!(env.lines().all(|line| line.ends_with("='';")));
+        assert!(env.starts_with("JJ_REPO='';\nJJ_ROOT='';\n"));
+    }
+}
@@ -0,0 +1,278 @@
+//! Prompt format strings
+//!
+//! A format string is literal text with `$name` variables, e.g.
+//! `$change_id $bookmarks$fill$status`. Variables expand to the rendered
+//! segment of that name (empty if absent), and `$fill` pads the line so the
+//! text after it is right-aligned to the terminal width. A newline (or the
+//! two characters `\n`, handy in shell args and env vars) starts a new line.
+//!
+//! As in starship, `[text](style)` renders `text`, which may hold variables,
+//! in a style string like `bold purple`; the segments inside lose their own
+//! colors unless the style includes `$style`.
+
+use crate::style::strip_escapes;
+
+/// Variable that expands to padding
+const FILL: &str = "fill";
+
+/// Style word that keeps the segments' own colors inside a text group
+const SEGMENT_STYLE: &str = "$style";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    Var(String),
+    Fill,
+    /// `[tokens](style)`
+    Group {
+        tokens: Vec<Token>,
+        style: String,
+    },
+}
+
+/// Parsed format string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Format {
+    lines: Vec<Line>,
+}
+
+/// One line of a format string
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Line {
+    tokens: Vec<Token>,
+}
+
+impl Format {
+    /// Parse a format string; a `$` not followed by a name, and a `[` that
+    /// doesn't open a well-formed `[...](...)` group, are literal
+    pub fn parse(s: &str) -> Self {
+        let lines = s
+            .split('\n')
+            .flat_map(|part| part.split("\\n"))
+            .map(|line| Line {
+                tokens: parse_tokens(line),
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+}
+
+fn parse_tokens(s: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '[' {
+            if let Some((inner, style, after)) = split_group(rest) {
+                if !text.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut text)));
+                }
+                tokens.push(Token::Group {
+                    tokens: parse_tokens(inner),
+                    style: style.to_string(),
+                });
+                rest = after;
+                continue;
+            }
+        }
+        rest = &rest[c.len_utf8()..];
+        if c != '$' {
+            text.push(c);
+            continue;
+        }
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(len);
+        rest = after;
+        if name.is_empty() {
+            text.push('$');
+            continue;
+        }
+        if !text.is_empty() {
+            tokens.push(Token::Text(std::mem::take(&mut text)));
+        }
+        tokens.push(if name == FILL {
+            Token::Fill
+        } else {
+            Token::Var(name.to_string())
+        });
+    }
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    tokens
+}
+
+/// Split `[inner](style)rest` at the `]` matching the leading `[`
+fn split_group(s: &str) -> Option<(&str, &str, &str)> {
+    let mut depth = 0;
+    let close = s.char_indices().find_map(|(i, c)| {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => {}
+        }
+        (depth == 0).then_some(i)
+    })?;
+    let (style, rest) = s[close + 1..].strip_prefix('(')?.split_once(')')?;
+    Some((&s[1..close], style, rest))
+}
+
+impl Line {
+    /// Number of `$fill` tokens
+    pub fn fills(&self) -> usize {
+        count_fills(&self.tokens)
+    }
+
+    /// Expand variables via `lookup`, splitting `fill` columns of padding
+    /// evenly between `$fill` tokens (earlier ones get the remainder). Text
+    /// groups are passed to `paint` with their style; their variables lose
+    /// the segments' colors unless the style includes `$style`.
+    pub fn render<'a>(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<&'a str>,
+        paint: &dyn Fn(&str, &str) -> String,
+        fill: usize,
+    ) -> String {
+        let mut render = Render {
+            lookup,
+            paint,
+            fill,
+            fills: self.fills(),
+            index: 0,
+        };
+        let mut out = String::with_capacity(128);
+        render.tokens(&self.tokens, true, &mut out);
+        out
+    }
+}
+
+fn count_fills(tokens: &[Token]) -> usize {
+    tokens
+        .iter()
+        .map(|token| match token {
+            Token::Fill => 1,
+            Token::Group { tokens, .. } => count_fills(tokens),
+            Token::Text(_) | Token::Var(_) => 0,
+        })
+        .sum()
+}
+
+/// State of rendering one line
+struct Render<'r, 'a> {
+    lookup: &'r dyn Fn(&str) -> Option<&'a str>,
+    paint: &'r dyn Fn(&str, &str) -> String,
+    fill: usize,
+    fills: usize,
+    /// `$fill` tokens rendered so far
+    index: usize,
+}
+
+impl Render<'_, '_> {
+    fn tokens(&mut self, tokens: &[Token], colored: bool, out: &mut String) {
+        for token in tokens {
+            match token {
+                Token::Text(text) => out.push_str(text),
+                Token::Var(name) => {
+                    let value = (self.lookup)(name).unwrap_or_default();
+                    if colored {
+                        out.push_str(value);
+                    } else {
+                        out.push_str(&strip_escapes(value));
+                    }
+                }
+                Token::Fill => {
+                    let pad =
+                        self.fill / self.fills + usize::from(self.index < self.fill % self.fills);
+                    out.extend(std::iter::repeat_n(' ', pad));
+                    self.index += 1;
+                }
+                Token::Group { tokens, style } => {
+                    let keep = style.split_whitespace().any(|word| word == SEGMENT_STYLE);
+                    let style: Vec<&str> = style
+                        .split_whitespace()
+                        .filter(|&word| word != SEGMENT_STYLE)
+                        .collect();
+                    let mut inner = String::new();
+                    self.tokens(tokens, colored && keep, &mut inner);
+                    out.push_str(&(self.paint)(&style.join(" "), &inner));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<&'static str> {
+        match name {
+            "change_id" => Some("yzxv1234"),
+            "status" => Some("[!]"),
+            _ => None,
+        }
+    }
+
+    /// Marks styled text as `<style|text>`
+    fn paint(style: &str, text: &str) -> String {
+        format!("<{style}|{text}>")
+    }
+
+    fn render(line: &Line, fill: usize) -> String {
+        line.render(&lookup, &paint, fill)
+    }
+
+    fn single_line(s: &str) -> Line {
+        let format = Format::parse(s);
+        assert_eq!(format.lines().len(), 1);
+        format.lines()[0].clone()
+    }
+
+    #[test]
+    fn test_parse_and_render() {
+        let line = single_line("$change_id ($missing) $ 5$$fill$status");
+        assert_eq!(line.fills(), 1);
+        assert_eq!(render(&line, 0), "yzxv1234 () $ 5$[!]");
+        assert_eq!(render(&line, 3), "yzxv1234 () $ 5$   [!]");
+    }
+
+    #[test]
+    fn test_fill_split_evenly() {
+        let line = single_line("a$fill$change_id$fill$status");
+        assert_eq!(render(&line, 5), "a   yzxv1234  [!]");
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        // Both a real newline and a literal `\n` split lines; other backslashes are kept
+        let format = Format::parse("$change_id\\n$status\n> \\t");
+        let lines: Vec<String> = format.lines().iter().map(|line| render(line, 0)).collect();
+        assert_eq!(lines, ["yzxv1234", "[!]", "> \\t"]);
+    }
+
+    #[test]
+    fn test_text_groups() {
+        let line = single_line("[on $change_id](bold purple) [$status]($style)$fill[x]");
+        assert_eq!(line.fills(), 1);
+        assert_eq!(render(&line, 2), "<bold purple|on yzxv1234> <|[!]>  [x]");
+        // Groups nest, and brackets not forming a group are literal
+        let line = single_line("[a [$status](red)](dimmed) [b] (c) [d");
+        assert_eq!(render(&line, 0), "<dimmed|a <red|[!]>> [b] (c) [d");
+    }
+
+    #[test]
+    fn test_text_group_strips_segment_colors() {
+        let colored = |name: &str| (name == "change_id").then_some("\x1b[35myzxv\x1b[0m");
+        let line = single_line("[$change_id](blue) [$change_id](bold $style)");
+        assert_eq!(
+            line.render(&colored, &paint, 0),
+            "<blue|yzxv> <bold|\x1b[35myzxv\x1b[0m>"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! `jj-starship get <field>`: one field of the repo state, unstyled
+//!
+//! For scripts and hand-rolled prompts that want a single value rather than
+//! the rendered segment. Only what the field needs is computed: the optional
+//! segments and the bookmark walks are off unless the field is one of them,
+//! and `repo`/`root` skip reading the repo altogether.
+
+use crate::cache::Deferred;
+use crate::config::Config;
+use crate::detect::{self, DetectResult, RepoType};
+use crate::output::PromptData;
+use std::path::Path;
+
+/// Field printed by `get`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Field {
+    /// Repo type: `jj`, `jj-colocated` or `git`
+    Repo,
+    /// Root of the workspace or worktree
+    Root,
+    /// Change ID of the working copy (JJ only)
+    ChangeId,
+    /// Bookmarks on and below the working copy, space-separated (JJ only)
+    Bookmarks,
+    /// Name of the workspace, `default` for the main one (JJ only)
+    Workspace,
+    /// Branch name, empty if detached (Git only)
+    Branch,
+    /// Short commit hash of HEAD (Git only)
+    Commit,
+    /// Description of the working copy (JJ only)
+    Description,
+    /// Whether the working copy has conflicts: `true` or `false`
+    Conflict,
+    /// Whether the working copy's change is divergent (JJ only)
+    Divergent,
+    /// Whether the working copy has no description (JJ only)
+    EmptyDescription,
+    /// Count of staged files (Git only)
+    Staged,
+    /// Count of modified files (Git only)
+    Modified,
+    /// Count of untracked files
+    Untracked,
+    /// Commits ahead of upstream (Git only)
+    Ahead,
+    /// Commits behind upstream (Git only)
+    Behind,
+    /// Lines added and removed, as `+A -R`
+    Diffstat,
+    /// Nearest tag and commits since it, as `tag+N`
+    Tag,
+}
+
+/// Value of `field` for the repo containing `cwd`, None outside a repo or if
+/// the field doesn't apply to it
+#[must_use]
+pub fn get(cwd: &Path, config: &Config, field: Field) -> Option<String> {
+    let result = detect::detect(cwd, config.discovery);
+    let data = if matches!(field, Field::Repo | Field::Root) {
+        None
+    } else {
+        Some(crate::collect(&result, &config_for(config, field))?)
+    };
+    value(&result, data.as_ref(), field)
+}
+
+/// `config` reduced to what `field` needs
+fn config_for(config: &Config, field: Field) -> Config {
+    let mut needed = config.clone();
+    needed.minimize();
+    match field {
+        Field::Bookmarks => {
+            needed.ancestor_bookmark_depth = config.ancestor_bookmark_depth;
+            needed.descendant_bookmark_depth = config.descendant_bookmark_depth;
+        }
+        Field::Diffstat => needed.diff_stat.enabled = true,
+        Field::Tag => needed.tag.enabled = true,
+        Field::Untracked => needed.untracked = true,
+        _ => {}
+    }
+    needed
+}
+
+/// Unstyled text of `field` for the detected repo and the data collected from
+/// it (only `repo` and `root` are known without)
+#[must_use]
+pub fn value(result: &DetectResult, data: Option<&PromptData>, field: Field) -> Option<String> {
+    match field {
+        Field::Repo => {
+            return match result.repo_type {
+                RepoType::Jj => Some("jj".to_string()),
+                RepoType::JjColocated => Some("jj-colocated".to_string()),
+                RepoType::Git => Some("git".to_string()),
+                RepoType::None => None,
+            };
+        }
+        Field::Root => return Some(result.repo_root.as_ref()?.display().to_string()),
+        _ => {}
+    }
+    match data? {
+        PromptData::Jj(info) => match field {
+            Field::ChangeId => Some(info.change_id.clone()),
+            Field::Workspace => Some(info.workspace.as_deref().unwrap_or("default").to_string()),
+            Field::Bookmarks => Some(
+                info.bookmarks
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            Field::Description => Some(info.description.trim_end().to_string()),
+            Field::Conflict => Some(info.conflict.to_string()),
+            Field::Divergent => Some(info.divergent.to_string()),
+            Field::EmptyDescription => Some(info.empty_desc.to_string()),
+            Field::Diffstat => match info.diff_stat? {
+                Deferred::Ready(stat) => Some(format!("+{} -{}", stat.added, stat.removed)),
+                Deferred::Pending => None,
+            },
+            Field::Untracked => match info.untracked? {
+                Deferred::Ready(files) => Some(files.untracked.to_string()),
+                Deferred::Pending => None,
+            },
+            Field::Tag => tag(info.tag.as_ref()),
+            _ => None,
+        },
+        #[cfg(feature = "git")]
+        PromptData::Git(info) => match field {
+            Field::Branch => Some(info.branch.clone().unwrap_or_default()),
+            Field::Commit => Some(info.head_short.clone()),
+            Field::Conflict => Some((info.conflicted > 0).to_string()),
+            Field::Staged => Some(info.staged.to_string()),
+            Field::Modified => Some(info.modified.to_string()),
+            Field::Untracked => Some(info.untracked.to_string()),
+            Field::Ahead | Field::Behind => match info.ahead_behind {
+                Deferred::Ready((ahead, _)) if field == Field::Ahead => Some(ahead.to_string()),
+                Deferred::Ready((_, behind)) => Some(behind.to_string()),
+                Deferred::Pending => None,
+            },
+            Field::Diffstat => match info.diff_stat? {
+                Deferred::Ready(stat) => Some(format!("+{} -{}", stat.added, stat.removed)),
+                Deferred::Pending => None,
+            },
+            Field::Tag => tag(info.tag.as_ref()),
+            _ => None,
+        },
+    }
+}
+
+fn tag(tag: Option<&Deferred<crate::tag::TagDistance>>) -> Option<String> {
+    match tag? {
+        Deferred::Ready(tag) if tag.distance == 0 => Some(tag.tag.clone()),
+        Deferred::Ready(tag) => Some(format!("{}+{}", tag.tag, tag.distance)),
+        Deferred::Pending => None,
+    }
+}
@@ -1,11 +1,22 @@
 //! Git repository info collection using git2
 
+use crate::budget::Budget;
+use crate::cache::{CACHE_DIR, Cache, Deferred};
+use crate::clone::{self, CloneKind};
+use crate::config::Config;
+use crate::detect;
+use crate::diff_stat::DiffStat;
 use crate::error::{Error, Result};
-use git2::{Repository, Status, StatusOptions};
+use crate::tag::{self, MAX_TAG_DISTANCE, TagDistance};
+use git2::{Oid, Repository, Status, StatusOptions, Statuses};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::Path;
 
 /// Git repository status info
-#[derive(Debug)]
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GitInfo {
     /// Branch name (None if detached)
     pub branch: Option<String>,
@@ -21,14 +32,24 @@ pub struct GitInfo {
     pub deleted: usize,
     /// Count of conflicted files
     pub conflicted: usize,
-    /// Commits ahead of upstream
-    pub ahead: usize,
-    /// Commits behind upstream
-    pub behind: usize,
+    /// Commits (ahead of, behind) upstream
+    pub ahead_behind: Deferred<(usize, usize)>,
+    /// Line counts of the worktree against HEAD (None if disabled)
+    pub diff_stat: Option<Deferred<DiffStat>>,
+    /// Nearest tag and commits since it (None if disabled or no tag is near)
+    pub tag: Option<Deferred<TagDistance>>,
+    /// Shallow or partial clone (full if disabled)
+    pub clone: CloneKind,
 }
 
 /// Collect Git repo info from the given path
-pub fn collect(repo_root: &Path, id_length: usize) -> Result<GitInfo> {
+///
+/// # Errors
+/// The repo couldn't be opened or HEAD read
+#[allow(clippy::too_many_lines)]
+pub fn collect(repo_root: &Path, config: &Config) -> Result<GitInfo> {
+    let budget = Budget::new(config.timeout);
+    let id_length = config.id_length;
     let repo = Repository::open(repo_root).map_err(|e| Error::Git(format!("open: {e}")))?;
 
     // Status counts - compute once for both empty and normal repos
@@ -80,6 +101,21 @@ pub fn collect(repo_root: &Path, id_length: usize) -> Result<GitInfo> {
         }
     }
 
+    let diff_stat = if config.diff_stat.enabled {
+        Some(
+            cached_diff_stat(&repo, &statuses, repo_root, config, budget)
+                .map_err(|e| Error::Git(format!("diff: {e}")))?,
+        )
+    } else {
+        None
+    };
+
+    let clone = if config.clone_marker {
+        clone::kind(&detect::git_common_dir(repo.path()))
+    } else {
+        CloneKind::default()
+    };
+
     // Get HEAD - may fail if no commits yet
     let Ok(head) = repo.head() else {
         // No commits yet - try to get branch from HEAD reference
@@ -97,8 +133,10 @@ pub fn collect(repo_root: &Path, id_length: usize) -> Result<GitInfo> {
             untracked,
             deleted,
             conflicted,
-            ahead: 0,
-            behind: 0,
+            ahead_behind: Deferred::Ready((0, 0)),
+            diff_stat,
+            tag: None,
+            clone,
         });
     };
 
@@ -121,7 +159,15 @@ pub fn collect(repo_root: &Path, id_length: usize) -> Result<GitInfo> {
     let head_short = full_hash[..id_length.min(full_hash.len())].to_string();
 
     // Ahead/behind upstream
-    let (ahead, behind) = get_ahead_behind(&repo, &head).unwrap_or((0, 0));
+    let ahead_behind = get_ahead_behind(&repo, &head, repo_root, config, budget)
+        .unwrap_or(Deferred::Ready((0, 0)));
+
+    let tag = if config.tag.enabled {
+        get_tag_distance(&repo, head_commit.id(), repo_root, config, budget)
+            .map_err(|e| Error::Git(format!("tags: {e}")))?
+    } else {
+        None
+    };
 
     Ok(GitInfo {
         branch,
@@ -131,19 +177,186 @@ pub fn collect(repo_root: &Path, id_length: usize) -> Result<GitInfo> {
         untracked,
         deleted,
         conflicted,
-        ahead,
-        behind,
+        ahead_behind,
+        diff_stat,
+        tag,
+        clone,
+    })
+}
+
+/// Segment cache inside the common git dir, shared by all worktrees
+/// (disabled unless `config.cache`)
+fn cache_for(repo: &Repository, config: &Config) -> Cache {
+    if config.cache {
+        Cache::new(detect::git_common_dir(repo.path()).join(CACHE_DIR))
+    } else {
+        Cache::disabled()
+    }
+}
+
+/// Compute expensive segments into the cache (background half of async mode)
+///
+/// # Errors
+/// As `collect`
+pub fn refresh(repo_root: &Path, config: &Config) -> Result<()> {
+    let result = collect(repo_root, config).map(|_| ());
+    if let Ok(repo) = Repository::open(repo_root) {
+        cache_for(&repo, config).finish_refresh(repo_root);
+    }
+    result
+}
+
+/// Global flags making a background refresh compute every enabled expensive segment
+fn refresh_args(config: &Config) -> Vec<String> {
+    let mut args = Vec::new();
+    for (enabled, flag) in [
+        (config.diff_stat.enabled, "--diffstat"),
+        (config.tag.enabled, "--nearest-tag"),
+    ] {
+        if enabled {
+            args.push(flag.to_string());
+        }
+    }
+    args
+}
+
+/// Number of files staged in the git index of the repo at `repo_root`, i.e.
+/// differing between the index and HEAD's tree (None if it can't be read)
+#[must_use]
+pub fn staged_files(repo_root: &Path) -> Option<usize> {
+    let repo = Repository::open(repo_root).ok()?;
+    // Unborn HEAD diffs against the empty tree
+    let head_tree = repo.head().ok().and_then(|h| h.peel_to_tree().ok());
+    let diff = repo
+        .diff_tree_to_index(head_tree.as_ref(), None, None)
+        .ok()?;
+    Some(diff.deltas().len())
+}
+
+/// HEAD and local branches of a git repo, by hex commit id
+#[derive(Debug, Default)]
+pub struct GitRefs {
+    /// Branch HEAD points at (None if detached, unborn or unreadable)
+    pub head_branch: Option<String>,
+    /// Commit HEAD resolves to (None if unborn)
+    pub head: Option<String>,
+    /// Commit of each `refs/heads/*` branch
+    pub branches: BTreeMap<String, String>,
+}
+
+/// HEAD and branches of the repo at `repo_root`, as git sees them (None if it
+/// can't be opened)
+#[must_use]
+pub fn refs(repo_root: &Path) -> Option<GitRefs> {
+    let repo = Repository::open(repo_root).ok()?;
+    let head = repo
+        .head()
+        .ok()
+        .and_then(|head| head.target())
+        .map(|oid| oid.to_string());
+    let head_branch = repo
+        .find_reference("HEAD")
+        .ok()
+        .filter(|_| head.is_some())
+        .and_then(|head| head.symbolic_target().map(str::to_string))
+        .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_string));
+    let branches = repo
+        .references_glob("refs/heads/*")
+        .ok()?
+        .flatten()
+        .filter_map(|reference| {
+            let name = reference.name()?.strip_prefix("refs/heads/")?.to_string();
+            Some((name, reference.target()?.to_string()))
+        })
+        .collect();
+    Some(GitRefs {
+        head_branch,
+        head,
+        branches,
+    })
+}
+
+/// Count added/removed lines of index and worktree against HEAD
+fn get_diff_stat(repo: &Repository) -> std::result::Result<DiffStat, git2::Error> {
+    // Unborn HEAD diffs against the empty tree
+    let head_tree = repo.head().ok().and_then(|h| h.peel_to_tree().ok());
+    let stats = repo
+        .diff_tree_to_workdir_with_index(head_tree.as_ref(), None)?
+        .stats()?;
+    Ok(DiffStat {
+        added: stats.insertions(),
+        removed: stats.deletions(),
     })
 }
 
+/// Line counts of the worktree from the cache, else computed now or - in
+/// async mode or out of time - in the background. The worktree isn't
+/// content-addressed, so the key covers HEAD, the index and the size and
+/// modification time of every changed file: editing one is a miss.
+fn cached_diff_stat(
+    repo: &Repository,
+    statuses: &Statuses<'_>,
+    repo_root: &Path,
+    config: &Config,
+    budget: Budget,
+) -> std::result::Result<Deferred<DiffStat>, git2::Error> {
+    let cache = cache_for(repo, config);
+    let key = worktree_key(repo, statuses);
+    if let Some(&[added, removed]) = cache.get("diffstat", &key).as_deref() {
+        return Ok(Deferred::Ready(DiffStat { added, removed }));
+    }
+    if config.async_segments || budget.exceeded() {
+        return Ok(computed_later(&cache, repo_root, config));
+    }
+    let stat = get_diff_stat(repo)?;
+    cache.put("diffstat", &key, &[stat.added, stat.removed]);
+    Ok(Deferred::Ready(stat))
+}
+
+/// Cache key of the worktree's changes: HEAD, the index's size and
+/// modification time, and each changed path with its status, size and
+/// modification time
+fn worktree_key(repo: &Repository, statuses: &Statuses<'_>) -> String {
+    let stamp = |path: &Path| {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.len(), metadata.modified().ok()))
+    };
+    let mut hasher = DefaultHasher::new();
+    repo.head()
+        .ok()
+        .and_then(|head| head.target())
+        .map(|oid| oid.to_string())
+        .hash(&mut hasher);
+    stamp(&repo.path().join("index")).hash(&mut hasher);
+    for entry in statuses.iter() {
+        (entry.path_bytes(), entry.status().bits()).hash(&mut hasher);
+        repo.workdir()
+            .zip(entry.path())
+            .and_then(|(workdir, path)| stamp(&workdir.join(path)))
+            .hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// Placeholder for a segment left to a background refresh, in async mode or
+/// when it didn't fit the time budget (rendered as is without the cache)
+fn computed_later<T>(cache: &Cache, repo_root: &Path, config: &Config) -> Deferred<T> {
+    cache.spawn_refresh(repo_root, &refresh_args(config));
+    Deferred::Pending
+}
+
 /// Get ahead/behind counts relative to upstream
+/// Cached by (local, upstream) commit ids; in async mode a miss is computed in the background
 fn get_ahead_behind(
     repo: &Repository,
     head: &git2::Reference<'_>,
-) -> std::result::Result<(usize, usize), git2::Error> {
+    repo_root: &Path,
+    config: &Config,
+    budget: Budget,
+) -> std::result::Result<Deferred<(usize, usize)>, git2::Error> {
     // Need a branch, not detached HEAD
     if repo.head_detached()? {
-        return Ok((0, 0));
+        return Ok(Deferred::Ready((0, 0)));
     }
 
     // Get the branch
@@ -159,5 +372,98 @@ fn get_ahead_behind(
     let local_oid = head.peel_to_commit()?.id();
     let upstream_oid = upstream.get().peel_to_commit()?.id();
 
-    repo.graph_ahead_behind(local_oid, upstream_oid)
+    let cache = cache_for(repo, config);
+    let key = format!("{local_oid}-{upstream_oid}");
+    if let Some(&[ahead, behind]) = cache.get("ahead_behind", &key).as_deref() {
+        return Ok(Deferred::Ready((ahead, behind)));
+    }
+    if config.async_segments || budget.exceeded() {
+        return Ok(computed_later(&cache, repo_root, config));
+    }
+    let (ahead, behind) = repo.graph_ahead_behind(local_oid, upstream_oid)?;
+    cache.put("ahead_behind", &key, &[ahead, behind]);
+    Ok(Deferred::Ready((ahead, behind)))
+}
+
+/// Nearest tagged ancestor of `start` (itself included) by parent steps,
+/// searched up to `MAX_TAG_DISTANCE` steps
+/// Pending if `budget` runs out first.
+fn find_nearest_tag(
+    repo: &Repository,
+    start: Oid,
+    tags: &HashMap<Oid, String>,
+    budget: Budget,
+) -> std::result::Result<Deferred<Option<(Oid, String)>>, git2::Error> {
+    let mut queue = VecDeque::from([(start, 0)]);
+    let mut visited = HashSet::new();
+    while let Some((oid, depth)) = queue.pop_front() {
+        if let Some(tag) = tags.get(&oid) {
+            return Ok(Deferred::Ready(Some((oid, tag.clone()))));
+        }
+        if budget.exceeded() {
+            return Ok(Deferred::Pending);
+        }
+        if depth >= MAX_TAG_DISTANCE || !visited.insert(oid) {
+            continue;
+        }
+        for parent in repo.find_commit(oid)?.parent_ids() {
+            queue.push_back((parent, depth + 1));
+        }
+    }
+    Ok(Deferred::Ready(None))
+}
+
+/// Nearest tag and commits since it, cached by HEAD and the repo's tags
+/// None if no tag is reachable within `MAX_TAG_DISTANCE` steps.
+fn get_tag_distance(
+    repo: &Repository,
+    head: Oid,
+    repo_root: &Path,
+    config: &Config,
+    budget: Budget,
+) -> std::result::Result<Option<Deferred<TagDistance>>, git2::Error> {
+    let mut refs = Vec::new();
+    for reference in repo.references_glob("refs/tags/*")? {
+        let reference = reference?;
+        let (Some(name), Ok(commit)) = (reference.shorthand(), reference.peel_to_commit()) else {
+            continue;
+        };
+        refs.push((name.to_string(), commit.id()));
+    }
+    if refs.is_empty() {
+        return Ok(None);
+    }
+    // Several tags on one commit: the first by name
+    refs.sort();
+    let mut tags = HashMap::new();
+    for (name, oid) in &refs {
+        tags.entry(*oid).or_insert_with(|| name.clone());
+    }
+
+    let cache = cache_for(repo, config);
+    let fingerprint = tag::tags_fingerprint(
+        refs.iter()
+            .map(|(name, oid)| (name.as_str(), oid.to_string())),
+    );
+    let key = format!("{head}-{fingerprint}");
+    if let Some(fields) = cache.get_fields("tag", &key) {
+        return Ok(TagDistance::from_fields(&fields).map(Deferred::Ready));
+    }
+    if config.async_segments || budget.exceeded() {
+        return Ok(Some(computed_later(&cache, repo_root, config)));
+    }
+    let Deferred::Ready(nearest) = find_nearest_tag(repo, head, &tags, budget)? else {
+        return Ok(Some(computed_later(&cache, repo_root, config)));
+    };
+    let Some((tagged, tag)) = nearest else {
+        cache.put_fields("tag", &key, &[]);
+        return Ok(None);
+    };
+    let mut walk = repo.revwalk()?;
+    walk.push(head)?;
+    walk.hide(tagged)?;
+    let distance = walk.take(MAX_TAG_DISTANCE).count();
+    let tag = TagDistance { tag, distance };
+    cache.put_fields("tag", &key, &tag.to_fields());
+    Ok(Some(Deferred::Ready(tag)))
 }
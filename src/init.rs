@@ -0,0 +1,220 @@
+//! `jj-starship init <shell>`: scripts that set the shell prompt directly
+//!
+//! For users who want a jj-native prompt without starship: the script makes
+//! the shell render `jj-starship standalone` (directory, repo info and prompt
+//! character) on every prompt, passing the last exit status along.
+//!
+//! With `--async` (zsh only), the prompt first renders a placeholder for the
+//! repo info, computed from repo detection alone, while the full prompt is
+//! rendered in a background job that redraws the prompt when it finishes.
+//!
+//! With `--starship`, it prints the `[custom.jj]` block for starship's config
+//! instead, for any shell starship supports.
+
+use crate::output::Shell;
+use clap::ValueEnum;
+use std::path::Path;
+
+/// Shell given to `init`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+    /// Nushell (starship config only)
+    Nu,
+    /// `PowerShell` (starship config only)
+    Powershell,
+}
+
+impl InitShell {
+    /// Shell of the standalone prompt script, None if there is none for it
+    #[must_use]
+    pub fn prompt_shell(self) -> Option<Shell> {
+        match self {
+            Self::Bash => Some(Shell::Bash),
+            Self::Zsh => Some(Shell::Zsh),
+            Self::Fish => Some(Shell::Fish),
+            Self::Nu | Self::Powershell => None,
+        }
+    }
+}
+
+/// Quote for bash/zsh: single quotes, with embedded ones as `'\''`
+fn posix_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quote for `PowerShell`: single quotes, with embedded ones doubled
+fn powershell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// TOML basic string
+fn toml_string(s: &str) -> String {
+    toml_edit::Value::from(s).to_string()
+}
+
+/// Quote for fish: single quotes, escaping `\` and `'`
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Zsh script rendering the prompt in a background job, redrawn via `zle -F`
+/// when ready. A new prompt cancels the job of the previous one.
+const ZSH_ASYNC: &str = r#"setopt prompt_subst
+typeset -g _jj_starship_prompt='' _jj_starship_fd=''
+
+_jj_starship_precmd() {
+    local last_status=$?
+    if [[ -n $_jj_starship_fd ]]; then
+        zle -F $_jj_starship_fd 2>/dev/null
+        exec {_jj_starship_fd}<&-
+    fi
+    _jj_starship_prompt="$("$_JJ_STARSHIP" --shell zsh standalone --placeholder --status $last_status)"
+    exec {_jj_starship_fd}< <("$_JJ_STARSHIP" --shell zsh standalone --status $last_status)
+    zle -F $_jj_starship_fd _jj_starship_ready
+}
+
+_jj_starship_ready() {
+    local fd=$1 prompt
+    IFS= read -r -d '' -u $fd prompt
+    zle -F $fd
+    exec {fd}<&-
+    _jj_starship_fd=''
+    if [[ -n $prompt ]]; then
+        _jj_starship_prompt=$prompt
+        zle reset-prompt
+    fi
+}
+
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd _jj_starship_precmd
+PROMPT='${_jj_starship_prompt}'
+"#;
+
+/// Init script for `shell`, running the binary at `exe`
+///
+/// # Errors
+/// For `Shell::None`, which has no prompt to set, and for `async_prompt`
+/// outside zsh
+pub fn script(shell: Shell, exe: &Path, async_prompt: bool) -> Result<String, &'static str> {
+    let exe = exe.to_string_lossy();
+    if async_prompt && shell != Shell::Zsh {
+        return Err("--async is only supported for zsh");
+    }
+    // The prompt is a command substitution (or a parameter holding its output)
+    // so it is never re-expanded by the shell: a `$` or `\` in a directory
+    // name stays literal
+    let script = match shell {
+        Shell::None => return Err("init needs a shell: bash, zsh or fish"),
+        Shell::Zsh if async_prompt => {
+            format!("_JJ_STARSHIP={}\n{ZSH_ASYNC}", posix_quote(&exe))
+        }
+        Shell::Bash => format!(
+            "_JJ_STARSHIP={}\n\
+             PS1='$(\"$_JJ_STARSHIP\" --shell bash standalone --status $?)'\n",
+            posix_quote(&exe)
+        ),
+        Shell::Zsh => format!(
+            "_JJ_STARSHIP={}\n\
+             setopt prompt_subst\n\
+             PROMPT='$(\"$_JJ_STARSHIP\" --shell zsh standalone --status $?)'\n",
+            posix_quote(&exe)
+        ),
+        Shell::Fish => format!(
+            "function fish_prompt\n    \
+                 set -l last_status $status\n    \
+                 {} --shell fish standalone --status $last_status\n\
+             end\n",
+            fish_quote(&exe)
+        ),
+    };
+    Ok(script)
+}
+
+/// Starship's `[custom.jj]` block for users of `shell`, running the binary
+/// at `exe`. `when` runs `jj-starship detect`, which walks up from the
+/// directory like the prompt does, where `detect_folders` would only see
+/// repo roots. Starship runs both through `sh` (skipping the user's shell
+/// startup files), or through `pwsh` for `PowerShell`, where `sh` may not exist.
+#[must_use]
+pub fn starship_config(shell: InitShell, exe: &Path) -> String {
+    let exe = exe.to_string_lossy();
+    let (command, runner) = match shell {
+        InitShell::Powershell => (
+            format!("& {}", powershell_quote(&exe)),
+            r#"["pwsh", "-NoProfile", "-Command", "-"]"#,
+        ),
+        _ => (posix_quote(&exe), r#"["sh"]"#),
+    };
+    format!(
+        "# jj-starship: add to ~/.config/starship.toml\n\
+         [custom.jj]\n\
+         command = {}\n\
+         when = {}\n\
+         shell = {runner}\n\
+         format = \"$output \"\n",
+        toml_string(&command),
+        toml_string(&format!("{command} detect")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_quotes_exe() {
+        let exe = Path::new(r"/opt/it's\bin/jj-starship");
+        let bash = script(Shell::Bash, exe, false).unwrap();
+        assert!(bash.starts_with(r"_JJ_STARSHIP='/opt/it'\''s\bin/jj-starship'"));
+        assert!(bash.contains("--shell bash standalone --status $?"));
+
+        let zsh = script(Shell::Zsh, exe, false).unwrap();
+        assert!(zsh.contains("setopt prompt_subst\nPROMPT="));
+
+        let fish = script(Shell::Fish, exe, false).unwrap();
+        assert!(fish.contains(r"'/opt/it\'s\\bin/jj-starship' --shell fish standalone"));
+
+        assert!(script(Shell::None, exe, false).is_err());
+    }
+
+    #[test]
+    fn test_starship_config() {
+        let exe = Path::new(r"/opt/it's\bin/jj-starship");
+        let config = starship_config(InitShell::Zsh, exe);
+        let doc: toml_edit::DocumentMut = config.parse().unwrap();
+        let jj = &doc["custom"]["jj"];
+        assert_eq!(
+            jj["command"].as_str(),
+            Some(r"'/opt/it'\''s\bin/jj-starship'")
+        );
+        assert_eq!(
+            jj["when"].as_str(),
+            Some(r"'/opt/it'\''s\bin/jj-starship' detect")
+        );
+        assert_eq!(jj["shell"].to_string().trim(), r#"["sh"]"#);
+        assert_eq!(jj["format"].as_str(), Some("$output "));
+
+        let pwsh = starship_config(InitShell::Powershell, exe);
+        let doc: toml_edit::DocumentMut = pwsh.parse().unwrap();
+        assert_eq!(
+            doc["custom"]["jj"]["command"].as_str(),
+            Some(r"& '/opt/it''s\bin/jj-starship'")
+        );
+        assert!(pwsh.contains(r#"shell = ["pwsh", "-NoProfile", "-Command", "-"]"#));
+    }
+
+    #[test]
+    fn test_async_script_zsh_only() {
+        let exe = Path::new("/usr/bin/jj-starship");
+        let zsh = script(Shell::Zsh, exe, true).unwrap();
+        assert!(zsh.starts_with("_JJ_STARSHIP='/usr/bin/jj-starship'\nsetopt prompt_subst\n"));
+        assert!(zsh.contains("standalone --placeholder"));
+        assert!(zsh.contains("zle reset-prompt"));
+
+        assert!(script(Shell::Bash, exe, true).is_err());
+        assert!(script(Shell::Fish, exe, true).is_err());
+    }
+}
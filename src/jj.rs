@@ -1,42 +1,289 @@
 //! JJ repository info collection
 
+use crate::budget::Budget;
+use crate::cache::{Cache, Deferred};
+use crate::checks::{self, CheckStatus};
+use crate::clone::{self, CloneKind};
+use crate::config::Config;
+use crate::detect;
+use crate::diff_stat::DiffStat;
 use crate::error::{Error, Result};
-use jj_lib::config::{ConfigLayer, ConfigSource, StackedConfig};
+use crate::tag::{self, MAX_TAG_DISTANCE, TagDistance};
+use crate::template;
+use crate::timing::Timings;
+use futures::StreamExt;
+use jj_lib::backend::BackendResult;
+use jj_lib::backend::Timestamp;
+use jj_lib::commit::Commit;
+use jj_lib::config::{ConfigLayer, ConfigResolutionContext, ConfigSource, StackedConfig};
+use jj_lib::conflicts::{MaterializedTreeValue, materialize_tree_value};
+use jj_lib::diff::DiffHunkKind;
+use jj_lib::diff_presentation::{LineCompareMode, diff_by_line};
+use jj_lib::gitignore::GitIgnoreFile;
 use jj_lib::hex_util::encode_reverse_hex;
+use jj_lib::matchers::{EverythingMatcher, Matcher, NothingMatcher};
+use jj_lib::merge::MergedTreeValue;
+use jj_lib::merged_tree::MergedTree;
 use jj_lib::object_id::ObjectId;
-use jj_lib::ref_name::RefName;
-use jj_lib::repo::{Repo, StoreFactories};
+use jj_lib::op_store::OperationId;
+use jj_lib::operation::Operation;
+use jj_lib::ref_name::{RefName, RemoteName, WorkspaceName};
+use jj_lib::repo::{Repo, RepoLoader, RepoLoaderError, StoreFactories};
+use jj_lib::repo_path::{RepoPath, RepoPathBuf, RepoPathUiConverter};
+use jj_lib::revset::{
+    self, RevsetAliasesMap, RevsetDiagnostics, RevsetExtensions, RevsetParseContext,
+    RevsetWorkspaceContext, SymbolResolver, SymbolResolverExtension,
+};
+use jj_lib::rewrite::merge_commit_trees;
 use jj_lib::settings::UserSettings;
+use jj_lib::signing::SigStatus;
+use jj_lib::store::Store;
 use jj_lib::str_util::{StringMatcher, StringPattern};
+use jj_lib::trailer::parse_description_trailers;
+use jj_lib::working_copy::SnapshotOptions;
 use jj_lib::workspace::{Workspace, default_working_copy_factories};
-use std::path::Path;
+use pollster::FutureExt;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
+use toml_edit::{DocumentMut, Item, Table, TableLike};
+
+/// Files and lines changed by the whole stack
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StackDiff {
+    /// Changed files
+    pub files: usize,
+    /// Changed lines
+    pub stat: DiffStat,
+}
+
+/// A parent of @ as in `jj log`: its short change id and first bookmark
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parent {
+    /// Change id, `PARENT_ID_LENGTH` long or the unique prefix if longer
+    pub change_id: String,
+    /// First local bookmark on the parent
+    pub bookmark: Option<String>,
+}
+
+/// Files added, modified and deleted by @
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileChanges {
+    pub added: usize,
+    pub modified: usize,
+    pub deleted: usize,
+}
+
+impl FileChanges {
+    /// Total number of changed files
+    #[must_use]
+    pub const fn total(self) -> usize {
+        self.added + self.modified + self.deleted
+    }
+}
+
+/// Files on disk that @ doesn't track
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UntrackedFiles {
+    /// Files neither tracked nor ignored, which a snapshot would add
+    pub untracked: usize,
+    /// Files and directories matched by ignore rules (a directory counts once)
+    pub ignored: usize,
+}
+
+/// Colocated git HEAD, and whether git and jj disagree on refs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GitHead {
+    /// Branch git's HEAD points at; None while detached, as jj leaves it
+    pub branch: Option<String>,
+    /// Git moved HEAD or branches since jj last looked (`jj git import`)
+    pub needs_import: bool,
+    /// Bookmarks moved in jj that git doesn't have yet (`jj git export`)
+    pub needs_export: bool,
+}
+
+/// Whether jj lets the working copy be rewritten
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Immutability {
+    /// Neither @ nor a head it was started on is immutable
+    Mutable,
+    /// @ is in `immutable()`, so jj refuses to rewrite it
+    Immutable,
+    /// @ is mutable but sits directly on an immutable head other than
+    /// `trunk()`, e.g. a tag or someone else's remote bookmark
+    OnImmutableHead,
+}
+
+impl Immutability {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Mutable => "mutable",
+            Self::Immutable => "immutable",
+            Self::OnImmutableHead => "on_immutable_head",
+        }
+    }
+}
 
 /// JJ repository status info
-#[derive(Debug)]
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(clippy::struct_excessive_bools)]
 pub struct JjInfo {
-    /// Short change ID (8 chars)
+    /// Short change ID, `id_length` long or the unique prefix if longer
     pub change_id: String,
     /// Shortest unique prefix length for `change_id`
     pub change_id_prefix_len: usize,
+    /// Full change ID, which the hashed change id color is derived from
+    pub change_id_full: String,
+    /// Name of the workspace, None for `default` (the one `jj git init` and
+    /// `jj git clone` make, as opposed to `jj workspace add`)
+    pub workspace: Option<String>,
     /// Bookmarks with distances: vec of (name, distance). Empty if none found.
     /// Distance 0 = directly on WC, 1+ = ancestor distance
     pub bookmarks: Vec<(String, usize)>,
+    /// Closest bookmark on a descendant of WC with its distance (WC behind it)
+    pub descendant_bookmark: Option<(String, usize)>,
+    /// Full description of the working copy commit
+    pub description: String,
+    /// Trailers from the description's last paragraph: vec of (key, value)
+    pub trailers: Vec<(String, String)>,
     /// Description is empty (needs commit message)
     pub empty_desc: bool,
     /// Has conflicts in tree
     pub conflict: bool,
+    /// Conflicted paths in the working copy's tree (None if disabled)
+    pub conflicted_files: Option<usize>,
+    /// Working copy commit makes no changes to its parents
+    pub empty: bool,
+    /// The workspace's files lag behind its working copy commit (rewritten
+    /// from another workspace, `jj workspace update-stale` catches up)
+    pub stale: bool,
     /// Multiple commits for same `change_id`
     pub divergent: bool,
+    /// Whether @ is immutable or on an immutable head (None if disabled or
+    /// the user's `immutable_heads()` doesn't evaluate)
+    pub immutable: Option<Immutability>,
+    /// Heads of the operation log, more than 1 after concurrent operations
+    /// (the next jj command merges them)
+    pub op_heads: usize,
+    /// Description of the operation the prompt was read at, e.g. `snapshot
+    /// working copy`
+    pub operation: String,
     /// Whether any bookmark has a remote
     pub has_remote: bool,
     /// Whether any bookmark is synced with remote
     pub is_synced: bool,
+    /// Local bookmarks without a tracked remote bookmark (None if disabled)
+    pub unpushed_bookmarks: Option<usize>,
+    /// Visible heads outside trunk without bookmarks (None if disabled)
+    pub anonymous_heads: Option<usize>,
+    /// Visible heads on trunk other than @'s, up to `MAX_COUNTED_COMMITS`
+    /// (None if disabled)
+    pub stacks: Option<usize>,
+    /// Commits (ahead of, behind) `trunk()`, each up to `MAX_COUNTED_COMMITS`
+    /// (None if disabled or there is no trunk)
+    pub trunk_distance: Option<AheadBehind>,
+    /// Remote tracking the closest bookmark, with commits (ahead of, behind)
+    /// it (None if disabled or nothing tracks the bookmark)
+    pub remote_distance: Option<(String, AheadBehind)>,
+    /// Shallow or partial git backend, which ancestry-based segments can't
+    /// fully see (full if disabled)
+    pub clone: CloneKind,
+    /// Files staged in the colocated git index, which `git commit` would use
+    /// instead of @ (None if disabled or not colocated)
+    pub git_staged: Option<usize>,
+    /// Colocated git HEAD and ref sync state (None if disabled or not
+    /// colocated)
+    pub git_head: Option<GitHead>,
+    /// A mutable ancestor or descendant of WC (not WC itself) has conflicts
+    pub stack_conflict: bool,
+    /// Signature verification of the WC commit, or of its parent while WC is a
+    /// fresh empty change (None if disabled or unsigned)
+    #[cfg_attr(feature = "serde", serde(with = "sig_status_serde"))]
+    pub signature: Option<Deferred<SigStatus>>,
+    /// The commit checked for a signature is unsigned and `warn_unsigned` is on
+    pub unsigned: bool,
+    /// CI checks of the nearest pushed bookmark (None if disabled, not pushed
+    /// or nothing to show)
+    pub checks: Option<Deferred<CheckStatus>>,
+    /// Nearest tag and commits since it (None if disabled or no tag is near)
+    pub tag: Option<Deferred<TagDistance>>,
+    /// Files and lines changed since the stack forked off the base revset
+    /// (None if disabled or the base doesn't resolve)
+    pub stack_diff: Option<Deferred<StackDiff>>,
+    /// Parents of the merge at WC or its parent: first bookmark of each, None if
+    /// it has none (empty if neither is a merge or disabled)
+    pub megamerge: Vec<Option<String>>,
+    /// Parents of @ (empty if disabled or @ is on the root commit)
+    pub parents: Vec<Parent>,
+    /// Output of the configured jj template (None if disabled)
+    pub template: Option<Deferred<String>>,
+    /// Line counts of @ against its parents (None if disabled)
+    pub diff_stat: Option<Deferred<DiffStat>>,
+    /// Files changed by @ against its parents (None if disabled)
+    pub file_changes: Option<Deferred<FileChanges>>,
+    /// Untracked and ignored files in the working copy (None if disabled)
+    pub untracked: Option<Deferred<UntrackedFiles>>,
+}
+
+/// (De)serialization of the signature field, as jj-lib's `SigStatus` has none
+#[cfg(feature = "serde")]
+mod sig_status_serde {
+    use crate::cache::Deferred;
+    use jj_lib::signing::SigStatus;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Status {
+        Good,
+        Unknown,
+        Bad,
+    }
+
+    // serde's `with` requires this signature
+    #[allow(clippy::ref_option, clippy::trivially_copy_pass_by_ref)]
+    pub fn serialize<S: Serializer>(
+        value: &Option<Deferred<SigStatus>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let value = value.map(|deferred| match deferred {
+            Deferred::Ready(SigStatus::Good) => Deferred::Ready(Status::Good),
+            Deferred::Ready(SigStatus::Unknown) => Deferred::Ready(Status::Unknown),
+            Deferred::Ready(SigStatus::Bad) => Deferred::Ready(Status::Bad),
+            Deferred::Pending => Deferred::Pending,
+        });
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Deferred<SigStatus>>, D::Error> {
+        let value = Option::<Deferred<Status>>::deserialize(deserializer)?;
+        Ok(value.map(|deferred| match deferred {
+            Deferred::Ready(Status::Good) => Deferred::Ready(SigStatus::Good),
+            Deferred::Ready(Status::Unknown) => Deferred::Ready(SigStatus::Unknown),
+            Deferred::Ready(Status::Bad) => Deferred::Ready(SigStatus::Bad),
+            Deferred::Pending => Deferred::Pending,
+        }))
+    }
 }
 
 /// Create minimal `UserSettings` for read-only operations
-fn create_user_settings() -> Result<UserSettings> {
+pub(crate) fn create_user_settings() -> Result<UserSettings> {
     let mut config = StackedConfig::with_defaults();
 
     // Minimal config required by UserSettings
@@ -52,13 +299,514 @@ fn create_user_settings() -> Result<UserSettings> {
     UserSettings::from_config(config).map_err(|e| Error::Jj(format!("settings: {e}")))
 }
 
+/// Files and directories jj reads user config from, lowest precedence first.
+/// `$JJ_CONFIG` (a path list) replaces the default locations, as in the jj CLI.
+fn user_config_paths() -> Vec<PathBuf> {
+    if let Some(paths) = env::var_os("JJ_CONFIG") {
+        return env::split_paths(&paths)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect();
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let xdg_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    #[cfg(target_os = "macos")]
+    let native_dir = home.as_ref().map(|h| h.join("Library/Application Support"));
+    #[cfg(not(target_os = "macos"))]
+    let native_dir: Option<PathBuf> = None;
+    let config_dirs = xdg_dir.into_iter().chain(native_dir);
+
+    let mut paths: Vec<PathBuf> = home.iter().map(|h| h.join(".jjconfig.toml")).collect();
+    for dir in config_dirs {
+        paths.push(dir.join("jj/config.toml"));
+        paths.push(dir.join("jj/conf.d"));
+    }
+    paths
+}
+
+/// Load a config file, or every `*.toml` in a directory; missing or broken files are skipped
+fn load_config_path(config: &mut StackedConfig, source: ConfigSource, path: &Path) {
+    if path.is_dir() {
+        let _ = config.load_dir(source, path);
+    } else if path.is_file() {
+        let _ = config.load_file(source, path);
+    }
+}
+
+/// Config as the jj CLI sees it, lowest precedence first: jj-lib defaults, user
+/// config (or `$JJ_CONFIG`), the repo's `config.toml`, `JJ_USER`/`JJ_EMAIL`, then
+/// `--config-toml` overrides and `--trunk-revset` as the `trunk()` alias. Unreadable
+/// or invalid sources are skipped so a broken config never fails the prompt.
+/// Conditional `[[--scope]]` tables are resolved for the workspace at
+/// `repo_root` as for `jj log`, which the prompt stands in for.
+fn load_jj_config(
+    repo_root: &Path,
+    repo_dir: &Path,
+    overrides: &[String],
+    trunk: Option<&str>,
+) -> StackedConfig {
+    let mut config = StackedConfig::with_defaults();
+    for path in user_config_paths() {
+        load_config_path(&mut config, ConfigSource::User, &path);
+    }
+    load_config_path(
+        &mut config,
+        ConfigSource::Repo,
+        &repo_dir.join("config.toml"),
+    );
+
+    let mut env_layer = ConfigLayer::empty(ConfigSource::EnvOverrides);
+    for (var, name) in [("JJ_USER", "user.name"), ("JJ_EMAIL", "user.email")] {
+        if let Ok(value) = env::var(var) {
+            let _ = env_layer.set_value(name, value);
+        }
+    }
+    config.add_layer(env_layer);
+
+    for toml in overrides {
+        if let Ok(layer) = ConfigLayer::parse(ConfigSource::CommandArg, toml) {
+            config.add_layer(layer);
+        }
+    }
+    if let Some(trunk) = trunk {
+        // Everything built on `trunk()` follows, `immutable_heads()` included
+        let mut trunk_layer = ConfigLayer::empty(ConfigSource::CommandArg);
+        let _ = trunk_layer.set_value(["revset-aliases", "trunk()"], trunk);
+        config.add_layer(trunk_layer);
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let hostname = hostname();
+    let context = ConfigResolutionContext {
+        home_dir: home.as_deref(),
+        repo_path: Some(repo_dir),
+        workspace_path: Some(repo_root),
+        command: Some("log"),
+        hostname: &hostname,
+    };
+    jj_lib::config::resolve(&config, &context).unwrap_or(config)
+}
+
+/// Name of this host, for `--when.hostnames` scopes; empty if unknown
+fn hostname() -> String {
+    let name = fs::read_to_string("/proc/sys/kernel/hostname").or_else(|_| {
+        Command::new("hostname")
+            .stderr(Stdio::null())
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    });
+    name.map(|name| name.trim().to_string()).unwrap_or_default()
+}
+
+/// `load_jj_config` through the cache: the resolved layers merged into one
+/// TOML document, which is read back as a single layer while none of the
+/// sources changed. That saves parsing jj-lib's defaults and each config file
+/// on every prompt. Workspaces of a repo share the cache, so the workspace,
+/// which scopes can match on, is part of the key.
+fn cached_jj_config(repo_root: &Path, repo_dir: &Path, config: &Config) -> StackedConfig {
+    let cache = cache_for(repo_root, config);
+    let (overrides, trunk) = (&config.jj_config_toml, config.trunk_revset.as_deref());
+    let key = jj_config_key(repo_root, repo_dir, overrides, trunk);
+    if let Some(layer) = cache
+        .get_text("jj_config", &key)
+        .and_then(|text| ConfigLayer::parse(ConfigSource::User, &text).ok())
+    {
+        let mut merged = StackedConfig::empty();
+        merged.add_layer(layer);
+        return merged;
+    }
+    let stacked = load_jj_config(repo_root, repo_dir, overrides, trunk);
+    let mut merged = Table::new();
+    for layer in stacked.layers() {
+        merge_table(&mut merged, layer.data.as_table());
+    }
+    cache.put_text("jj_config", &key, &DocumentMut::from(merged).to_string());
+    stacked
+}
+
+/// Overlay `layer` on `target` like jj layers config: tables are merged key
+/// by key, any other value replaces the one below
+fn merge_table(target: &mut dyn TableLike, layer: &dyn TableLike) {
+    for (key, item) in layer.iter() {
+        if let (Some(table), Some(below)) = (
+            item.as_table_like(),
+            target.get_mut(key).and_then(Item::as_table_like_mut),
+        ) {
+            merge_table(below, table);
+        } else {
+            target.insert(key, item.clone());
+        }
+    }
+}
+
+/// Cache key of the jj config: the size and modification time of every file
+/// `load_jj_config` reads, the env vars and options layered over them, and the
+/// workspace its scopes are resolved for
+fn jj_config_key(
+    repo_root: &Path,
+    repo_dir: &Path,
+    overrides: &[String],
+    trunk: Option<&str>,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    (repo_root, env::var_os("HOME")).hash(&mut hasher);
+    let files = user_config_paths()
+        .into_iter()
+        .chain([repo_dir.join("config.toml")])
+        .flat_map(|path| {
+            let mut entries: Vec<PathBuf> = fs::read_dir(&path)
+                .map(|dir| dir.flatten().map(|entry| entry.path()).collect())
+                .unwrap_or_default();
+            entries.sort();
+            [path].into_iter().chain(entries)
+        });
+    for path in files {
+        let metadata = fs::metadata(&path).ok();
+        (path, metadata.as_ref().map(fs::Metadata::len)).hash(&mut hasher);
+        metadata.and_then(|m| m.modified().ok()).hash(&mut hasher);
+    }
+    for var in ["JJ_USER", "JJ_EMAIL"] {
+        env::var(var).ok().hash(&mut hasher);
+    }
+    (overrides, trunk).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Fallbacks for the jj CLI's built-in revset aliases that `immutable_heads()` builds on
+const BUILTIN_REVSET_ALIASES: &[(&str, &str)] = &[
+    (
+        "trunk()",
+        r#"latest(
+            remote_bookmarks(exact:"main", exact:"origin") |
+            remote_bookmarks(exact:"master", exact:"origin") |
+            remote_bookmarks(exact:"trunk", exact:"origin") |
+            remote_bookmarks(exact:"main", exact:"upstream") |
+            remote_bookmarks(exact:"master", exact:"upstream") |
+            remote_bookmarks(exact:"trunk", exact:"upstream") |
+            root()
+        )"#,
+    ),
+    (
+        "builtin_immutable_heads()",
+        "present(trunk()) | tags() | untracked_remote_bookmarks()",
+    ),
+    ("immutable_heads()", "builtin_immutable_heads()"),
+    ("immutable()", "::(immutable_heads() | root())"),
+    ("mutable()", "~immutable()"),
+];
+
+/// Revset aliases from the built-in fallbacks overlaid with the user's `revset-aliases`
+fn revset_aliases(config: &StackedConfig) -> RevsetAliasesMap {
+    let mut aliases = RevsetAliasesMap::new();
+    for (decl, defn) in BUILTIN_REVSET_ALIASES {
+        let _ = aliases.insert(decl, *defn);
+    }
+    if let Ok(user) = config.get_table("revset-aliases") {
+        for (decl, item) in &user {
+            if let Some(defn) = item.as_str() {
+                // Invalid declarations are skipped like jj does (with a warning there)
+                let _ = aliases.insert(decl, defn);
+            }
+        }
+    }
+    aliases
+}
+
+/// Whether the user redefines any built-in alias, which may change `immutable_heads()`
+fn customizes_immutable_heads(config: &StackedConfig) -> bool {
+    config.get_table("revset-aliases").is_ok_and(|user| {
+        BUILTIN_REVSET_ALIASES
+            .iter()
+            .any(|(decl, _)| user.contains_key(decl))
+    })
+}
+
+/// The parts of a workspace revsets are evaluated in (its root for paths,
+/// its name for `@`), which unlike the `Workspace` can be shared by the
+/// threads gathering segments
+#[derive(Clone, Copy)]
+struct WorkspaceContext<'a> {
+    root: &'a Path,
+    name: &'a WorkspaceName,
+}
+
+impl<'a> WorkspaceContext<'a> {
+    fn new(workspace: &'a Workspace) -> Self {
+        Self {
+            root: workspace.workspace_root(),
+            name: workspace.workspace_name(),
+        }
+    }
+
+    fn workspace_root(&self) -> &'a Path {
+        self.root
+    }
+
+    fn workspace_name(&self) -> &'a WorkspaceName {
+        self.name
+    }
+}
+
+/// Evaluate a revset with the user's revset aliases, in `workspace` (for `@`)
+fn evaluate_revset(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    text: &str,
+) -> Result<Vec<jj_lib::backend::CommitId>> {
+    evaluate_revset_limited(repo, config, workspace, text, usize::MAX)
+}
+
+/// Like `evaluate_revset`, stopping after `limit` commits so large revsets
+/// are never walked to the end
+fn evaluate_revset_limited(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    text: &str,
+    limit: usize,
+) -> Result<Vec<jj_lib::backend::CommitId>> {
+    resolve_revset(repo, config, workspace, text)?
+        .iter()
+        .take(limit)
+        .map(|id| id.map_err(|e| Error::Jj(format!("evaluate {text}: {e}"))))
+        .collect()
+}
+
+/// Number of commits in a revset, up to `limit`; Pending if `budget` runs
+/// out before the walk ends
+fn count_revset(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    text: &str,
+    limit: usize,
+    budget: Budget,
+) -> Result<Deferred<usize>> {
+    if budget.exceeded() {
+        return Ok(Deferred::Pending);
+    }
+    let mut count = 0;
+    for id in resolve_revset(repo, config, workspace, text)?
+        .iter()
+        .take(limit)
+    {
+        if budget.exceeded() {
+            return Ok(Deferred::Pending);
+        }
+        id.map_err(|e| Error::Jj(format!("evaluate {text}: {e}")))?;
+        count += 1;
+    }
+    Ok(Deferred::Ready(count))
+}
+
+/// Parse, resolve and evaluate a revset (see `evaluate_revset`), leaving the
+/// walk to the caller
+fn resolve_revset<'a>(
+    repo: &'a Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    text: &str,
+) -> Result<Box<dyn revset::Revset + 'a>> {
+    let now = Timestamp::now()
+        .to_datetime()
+        .map_err(|e| Error::Jj(format!("timestamp: {e}")))?;
+    let aliases = revset_aliases(config);
+    let user_email: String = config.get("user.email").unwrap_or_default();
+    let root = workspace.workspace_root().to_path_buf();
+    let path_converter = RepoPathUiConverter::Fs {
+        cwd: root.clone(),
+        base: root,
+    };
+    let context = RevsetParseContext {
+        aliases_map: &aliases,
+        local_variables: HashMap::new(),
+        user_email: &user_email,
+        date_pattern_context: now.into(),
+        default_ignored_remote: Some(RemoteName::new("git")),
+        use_glob_by_default: config
+            .get("ui.revsets-use-glob-by-default")
+            .unwrap_or(false),
+        extensions: &RevsetExtensions::default(),
+        workspace: Some(RevsetWorkspaceContext {
+            path_converter: &path_converter,
+            workspace_name: workspace.workspace_name(),
+        }),
+    };
+    let expression = revset::parse(&mut RevsetDiagnostics::new(), text, &context)
+        .map_err(|e| Error::Jj(format!("parse {text}: {e}")))?;
+    let no_extensions: [Box<dyn SymbolResolverExtension>; 0] = [];
+    let resolver = SymbolResolver::new(repo.as_ref(), &no_extensions);
+    expression
+        .resolve_user_expression(repo.as_ref(), &resolver)
+        .map_err(|e| Error::Jj(format!("resolve {text}: {e}")))?
+        .evaluate(repo.as_ref())
+        .map_err(|e| Error::Jj(format!("evaluate {text}: {e}")))
+}
+
+/// Immutable heads honoring the user's `immutable_heads()`/`trunk()` aliases,
+/// falling back to the fast built-in approximation when they aren't customized
+/// or don't evaluate
+fn immutable_heads(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+) -> HashSet<jj_lib::backend::CommitId> {
+    // A definition that doesn't evaluate must not fail the prompt
+    customizes_immutable_heads(config)
+        .then(|| evaluate_revset(repo, config, workspace, "immutable_heads()").ok())
+        .flatten()
+        .map_or_else(
+            || find_immutable_heads(repo.view()),
+            |heads| heads.into_iter().collect(),
+        )
+}
+
+/// Compare git's refs with jj's record of them (the refs it last imported
+/// or exported) and with its bookmarks
+#[cfg(feature = "git")]
+fn git_head(view: &jj_lib::view::View, refs: &crate::git::GitRefs) -> GitHead {
+    let recorded: std::collections::BTreeMap<&str, String> = view
+        .git_refs()
+        .iter()
+        .filter_map(|(name, target)| {
+            let branch = name.as_str().strip_prefix("refs/heads/")?;
+            Some((branch, target.as_normal()?.hex()))
+        })
+        .collect();
+    let git_branches = refs
+        .branches
+        .iter()
+        .map(|(name, id)| (name.as_str(), id.clone()))
+        .collect::<std::collections::BTreeMap<_, _>>();
+    let recorded_head = view.git_head().as_normal().map(ObjectId::hex);
+    // Conflicted bookmarks aren't exported, so only resolved ones can lag
+    let unexported = view.local_bookmarks().any(|(name, target)| {
+        target
+            .as_normal()
+            .is_some_and(|id| recorded.get(name.as_str()) != Some(&id.hex()))
+    });
+    let deleted = recorded
+        .keys()
+        .any(|&name| view.get_local_bookmark(RefName::new(name)).is_absent());
+    GitHead {
+        branch: refs.head_branch.clone(),
+        needs_import: git_branches != recorded || refs.head != recorded_head,
+        needs_export: unexported || deleted,
+    }
+}
+
+/// Immutability of @ by the user's `immutable_heads()`, with the built-in
+/// aliases as fallback
+fn immutability(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+) -> Result<Immutability> {
+    let any = |text: &str| evaluate_revset_limited(repo, config, workspace, text, 1);
+    Ok(if !any("@ & immutable()")?.is_empty() {
+        Immutability::Immutable
+    } else if !any("parents(@) & (immutable_heads() ~ trunk())")?.is_empty() {
+        Immutability::OnImmutableHead
+    } else {
+        Immutability::Mutable
+    })
+}
+
+/// Commits (ahead, behind) another revision, Pending if counting ran out of time
+pub type AheadBehind = Deferred<(usize, usize)>;
+
+/// Cap of the commit counts against trunk and remotes, keeping the walk short
+/// when the two sides are far apart
+pub const MAX_COUNTED_COMMITS: usize = 1000;
+
+/// Commits (ahead of, behind) `trunk()`: in @ but not trunk, and in trunk but
+/// not @. None if `trunk()` doesn't resolve to a single non-root commit.
+fn trunk_distance(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    wc_id: &jj_lib::backend::CommitId,
+    budget: Budget,
+) -> Result<Option<AheadBehind>> {
+    let Ok(trunk) = evaluate_revset_limited(repo, config, workspace, "trunk()", 2) else {
+        return Ok(None);
+    };
+    let [trunk] = trunk.as_slice() else {
+        return Ok(None);
+    };
+    if trunk == repo.store().root_commit_id() {
+        return Ok(None);
+    }
+    ahead_behind(repo, config, workspace, wc_id, trunk, budget).map(Some)
+}
+
+/// Commits (in `local` but not `base`, in `base` but not `local`), each up to
+/// `MAX_COUNTED_COMMITS`
+fn ahead_behind(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    local: &jj_lib::backend::CommitId,
+    base: &jj_lib::backend::CommitId,
+    budget: Budget,
+) -> Result<AheadBehind> {
+    let (local, base) = (local.hex(), base.hex());
+    let count =
+        |text: String| count_revset(repo, config, workspace, &text, MAX_COUNTED_COMMITS, budget);
+    let Deferred::Ready(ahead) = count(format!("{base}..{local}"))? else {
+        return Ok(Deferred::Pending);
+    };
+    let Deferred::Ready(behind) = count(format!("{local}..{base}"))? else {
+        return Ok(Deferred::Pending);
+    };
+    Ok(Deferred::Ready((ahead, behind)))
+}
+
+/// Remote and commits (ahead of, behind) it of bookmark `name`, against the
+/// first remote tracking it (besides `git`). None without a tracking remote,
+/// or if either side is conflicted or deleted.
+fn remote_distance(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    name: &str,
+    budget: Budget,
+) -> Result<Option<(String, AheadBehind)>> {
+    let view = repo.view();
+    let Some(local) = view.get_local_bookmark(RefName::new(name)).as_normal() else {
+        return Ok(None);
+    };
+    let name_matcher = StringPattern::exact(name).to_matcher();
+    let tracking = view
+        .remote_bookmarks_matching(&name_matcher, &StringMatcher::All)
+        .find(|(symbol, remote_ref)| symbol.remote.as_str() != "git" && remote_ref.is_tracked());
+    let Some((symbol, remote_ref)) = tracking else {
+        return Ok(None);
+    };
+    let Some(remote) = remote_ref.target.as_normal() else {
+        return Ok(None);
+    };
+    let distance = ahead_behind(repo, config, workspace, local, remote, budget)?;
+    Ok(Some((symbol.remote.as_str().to_string(), distance)))
+}
+
+/// Heads outside trunk with no bookmark, excluding working-copy commits: work
+/// that will be hard to find again unless it gets a bookmark or is abandoned
+const ANONYMOUS_HEADS: &str = "heads(~::trunk()) ~ bookmarks() ~ working_copies()";
+
+/// Heads built on trunk other than the one of @'s stack, bookmarked or not:
+/// the other lines of work in flight. Only trunk's descendants are walked.
+fn stacks_revset(wc: &str) -> String {
+    format!("heads(trunk()::) ~ trunk() ~ {wc}::")
+}
+
 /// Find immutable head commits (trunk + tags + untracked remote bookmarks)
 /// Mirrors jj's `builtin_immutable_heads()` without revset evaluation
-fn find_immutable_heads(
-    view: &jj_lib::view::View,
-) -> std::collections::HashSet<jj_lib::backend::CommitId> {
-    use std::collections::HashSet;
-
+fn find_immutable_heads(view: &jj_lib::view::View) -> HashSet<jj_lib::backend::CommitId> {
     let mut immutable = HashSet::new();
 
     // Single pass over all remote bookmarks
@@ -103,16 +851,12 @@ fn find_ancestor_bookmarks(
     view: &jj_lib::view::View,
     wc_id: &jj_lib::backend::CommitId,
     max_depth: usize,
+    immutable_heads: &HashSet<jj_lib::backend::CommitId>,
 ) -> Result<Vec<(String, usize)>> {
-    use std::collections::{HashMap, HashSet, VecDeque};
-
     let mut queue: VecDeque<(jj_lib::backend::CommitId, usize)> = VecDeque::new();
     let mut visited = HashSet::new();
     let mut bookmarks_with_distances: HashMap<String, usize> = HashMap::new();
 
-    // Pre-compute immutable heads to stop traversal at trunk/tags/untracked remotes
-    let immutable_heads = find_immutable_heads(view);
-
     // Start BFS from WC commit parents
     let wc_commit = repo
         .store()
@@ -159,30 +903,1059 @@ fn find_ancestor_bookmarks(
         }
     }
 
-    // Convert to vec and sort by distance
+    // Convert to vec and sort by distance, then name for a stable order
     let mut result: Vec<(String, usize)> = bookmarks_with_distances.into_iter().collect();
-    result.sort_by_key(|(_, distance)| *distance);
+    result.sort_by(|(a, a_distance), (b, b_distance)| (a_distance, a).cmp(&(b_distance, b)));
     Ok(result)
 }
 
-/// Collect JJ repo info from the given path
-pub fn collect(repo_root: &Path, id_length: usize, ancestor_depth: usize) -> Result<JjInfo> {
-    let settings = create_user_settings()?;
-
-    let workspace = Workspace::load(
-        &settings,
-        repo_root,
-        &StoreFactories::default(),
-        &default_working_copy_factories(),
+/// Whether bookmark `name` has a remote (besides `git`), and whether it is
+/// synced with one of them (trivially true without remotes)
+fn remote_sync_status(view: &jj_lib::view::View, name: &str) -> (bool, bool) {
+    let local_target = view.get_local_bookmark(RefName::new(name));
+    let name_matcher = StringPattern::exact(name).to_matcher();
+    let mut has_remote = false;
+
+    for (symbol, remote_ref) in view.remote_bookmarks_matching(&name_matcher, &StringMatcher::All) {
+        if symbol.remote.as_str() == "git" {
+            continue;
+        }
+        has_remote = true;
+        if remote_ref.target == *local_target {
+            return (true, true);
+        }
+    }
+    (has_remote, !has_remote)
+}
+
+/// Parents of the "megamerge" at WC, or at its parent when WC sits on top of
+/// one, each with its first bookmark. Empty if neither is a merge.
+fn find_megamerge(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    view: &jj_lib::view::View,
+    commit: &Commit,
+) -> Result<Vec<Option<String>>> {
+    let merge = match commit.parent_ids() {
+        [_, _, ..] => commit.clone(),
+        [parent_id] => repo
+            .store()
+            .get_commit(parent_id)
+            .map_err(|e| Error::Jj(format!("get commit: {e}")))?,
+        [] => return Ok(Vec::new()),
+    };
+    if merge.parent_ids().len() < 2 {
+        return Ok(Vec::new());
+    }
+    Ok(merge
+        .parent_ids()
+        .iter()
+        .map(|id| {
+            view.local_bookmarks_for_commit(id)
+                .next()
+                .map(|(name, _)| name.as_str().to_string())
+        })
+        .collect())
+}
+
+/// Shortest length of a parent's change id
+const PARENT_ID_LENGTH: usize = 4;
+
+/// Parents of WC with their short change id and first bookmark, none if WC
+/// sits on the root commit
+fn find_parents(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    view: &jj_lib::view::View,
+    commit: &Commit,
+) -> Result<Vec<Parent>> {
+    let root = repo.store().root_commit_id();
+    commit
+        .parent_ids()
+        .iter()
+        .filter(|id| *id != root)
+        .map(|id| {
+            let parent = repo
+                .store()
+                .get_commit(id)
+                .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+            let full = encode_reverse_hex(parent.change_id().as_bytes());
+            let prefix_len = repo
+                .shortest_unique_change_id_prefix_len(parent.change_id())
+                .unwrap_or(PARENT_ID_LENGTH);
+            Ok(Parent {
+                change_id: full[..PARENT_ID_LENGTH.max(prefix_len).min(full.len())].to_string(),
+                bookmark: view
+                    .local_bookmarks_for_commit(id)
+                    .next()
+                    .map(|(name, _)| name.as_str().to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Count local bookmarks that no remote tracks, i.e. that were never pushed
+/// (the `git` pseudo-remote of colocated repos doesn't count)
+fn count_unpushed_bookmarks(view: &jj_lib::view::View) -> usize {
+    view.bookmarks()
+        .filter(|(_, targets)| {
+            targets.local_target.is_present()
+                && !targets
+                    .remote_refs
+                    .iter()
+                    .any(|(remote, remote_ref)| remote.as_str() != "git" && remote_ref.is_tracked())
+        })
+        .count()
+}
+
+/// Find the closest bookmark on a descendant of the WC commit, e.g. after
+/// `jj edit` into history. Candidates are filtered through the index, then each
+/// is walked back towards WC, at most `max_depth` commits.
+fn find_descendant_bookmark(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    view: &jj_lib::view::View,
+    wc_id: &jj_lib::backend::CommitId,
+    max_depth: usize,
+) -> Result<Option<(String, usize)>> {
+    let index = repo.index();
+    let is_descendant = |id: &jj_lib::backend::CommitId| {
+        index
+            .is_ancestor(wc_id, id)
+            .map_err(|e| Error::Jj(format!("index: {e}")))
+    };
+
+    let mut closest: Option<(String, usize)> = None;
+    for (name, target) in view.local_bookmarks() {
+        let Some(target_id) = target.as_normal() else {
+            continue;
+        };
+        if target_id == wc_id || !is_descendant(target_id)? {
+            continue;
+        }
+        // No need to walk further than the closest match so far
+        let limit = closest.as_ref().map_or(max_depth, |(_, d)| *d);
+        if let Some(distance) = distance_from(repo, target_id, wc_id, limit, &is_descendant)? {
+            if closest.as_ref().is_none_or(|(_, d)| distance < *d) {
+                closest = Some((name.as_str().to_string(), distance));
+            }
+        }
+    }
+    Ok(closest)
+}
+
+/// Shortest number of parent steps from `start` back to `ancestor`, if within `limit`
+/// Only commits descending from `ancestor` are walked.
+fn distance_from(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    start: &jj_lib::backend::CommitId,
+    ancestor: &jj_lib::backend::CommitId,
+    limit: usize,
+    is_descendant: &impl Fn(&jj_lib::backend::CommitId) -> Result<bool>,
+) -> Result<Option<usize>> {
+    let mut queue = VecDeque::from([(start.clone(), 0)]);
+    let mut visited = HashSet::new();
+    while let Some((commit_id, depth)) = queue.pop_front() {
+        if commit_id == *ancestor {
+            return Ok(Some(depth));
+        }
+        if depth >= limit || !visited.insert(commit_id.clone()) {
+            continue;
+        }
+        let commit = repo
+            .store()
+            .get_commit(&commit_id)
+            .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+        for parent_id in commit.parent_ids() {
+            if parent_id == ancestor || is_descendant(parent_id)? {
+                queue.push_back((parent_id.clone(), depth + 1));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Read a tree value as text, None for binaries, symlinks and conflicts
+async fn read_text(
+    store: &Store,
+    path: &RepoPath,
+    value: MergedTreeValue,
+) -> BackendResult<Option<Vec<u8>>> {
+    match materialize_tree_value(store, path, value).await? {
+        MaterializedTreeValue::Absent => Ok(Some(Vec::new())),
+        MaterializedTreeValue::File(mut file) => {
+            let contents = file.read_all(path).await?;
+            Ok((!contents.contains(&0)).then_some(contents))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Count added/removed lines between @ and its parents
+fn compute_diff_stat(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    commit: &Commit,
+    budget: Budget,
+) -> Result<Deferred<DiffStat>> {
+    let parent_tree = commit
+        .parent_tree(repo.as_ref())
+        .map_err(|e| Error::Jj(format!("parent tree: {e}")))?;
+    let diff = diff_trees(repo.store(), &parent_tree, &commit.tree(), budget)?;
+    Ok(match diff {
+        Deferred::Ready((_, stat)) => Deferred::Ready(stat),
+        Deferred::Pending => Deferred::Pending,
+    })
+}
+
+/// Count files added, modified and deleted between @ and its parents, Pending
+/// if `budget` runs out first. Only tree entries are compared, no contents
+/// are read, so this is cheaper than the diffstat.
+fn compute_file_changes(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    commit: &Commit,
+    budget: Budget,
+) -> Result<Deferred<FileChanges>> {
+    let parent_tree = commit
+        .parent_tree(repo.as_ref())
+        .map_err(|e| Error::Jj(format!("parent tree: {e}")))?;
+    let tree = commit.tree();
+    async {
+        let mut changes = FileChanges::default();
+        let mut diffs = parent_tree.diff_stream(&tree, &EverythingMatcher);
+        while let Some(entry) = diffs.next().await {
+            if budget.exceeded() {
+                return Ok(Deferred::Pending);
+            }
+            let values = entry.values?;
+            if values.before.is_absent() {
+                changes.added += 1;
+            } else if values.after.is_absent() {
+                changes.deleted += 1;
+            } else {
+                changes.modified += 1;
+            }
+        }
+        Ok(Deferred::Ready(changes))
+    }
+    .block_on()
+    .map_err(|e: jj_lib::backend::BackendError| Error::Jj(format!("diff: {e}")))
+}
+
+/// Count changed files and added/removed lines from `from` to `to`, Pending
+/// if `budget` runs out first
+/// Binary files, symlinks and conflicts count as changed files without lines.
+fn diff_trees(
+    store: &Store,
+    from: &MergedTree,
+    to: &MergedTree,
+    budget: Budget,
+) -> Result<Deferred<(usize, DiffStat)>> {
+    async {
+        let mut files = 0;
+        let mut stat = DiffStat::default();
+        let mut diffs = from.diff_stream(to, &EverythingMatcher);
+        while let Some(entry) = diffs.next().await {
+            if budget.exceeded() {
+                return Ok(Deferred::Pending);
+            }
+            let values = entry.values?;
+            files += 1;
+            let path = entry.path.as_ref();
+            let (Some(before), Some(after)) = (
+                read_text(store, path, values.before).await?,
+                read_text(store, path, values.after).await?,
+            ) else {
+                continue;
+            };
+
+            let diff = diff_by_line([&before[..], &after[..]], &LineCompareMode::Exact);
+            for hunk in diff.hunks() {
+                if hunk.kind == DiffHunkKind::Different {
+                    stat.removed += hunk.contents[0].split_inclusive(|b| *b == b'\n').count();
+                    stat.added += hunk.contents[1].split_inclusive(|b| *b == b'\n').count();
+                }
+            }
+        }
+        Ok(Deferred::Ready((files, stat)))
+    }
+    .block_on()
+    .map_err(|e: jj_lib::backend::BackendError| Error::Jj(format!("diff: {e}")))
+}
+
+/// Changes of the whole stack: from where @ forked off `base` to @
+fn compute_stack_diff(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    fork_points: &[Commit],
+    commit: &Commit,
+    budget: Budget,
+) -> Result<Deferred<StackDiff>> {
+    let base_tree = merge_commit_trees(repo.as_ref(), fork_points)
+        .block_on()
+        .map_err(|e| Error::Jj(format!("fork point tree: {e}")))?;
+    let diff = diff_trees(repo.store(), &base_tree, &commit.tree(), budget)?;
+    Ok(match diff {
+        Deferred::Ready((files, stat)) => Deferred::Ready(StackDiff { files, stat }),
+        Deferred::Pending => Deferred::Pending,
+    })
+}
+
+/// Stack diff against the configured base from the cache (keyed by @ and the
+/// fork points), else computed now or in the background in async mode
+/// None if the base revset doesn't resolve, so a typo never breaks the prompt.
+fn cached_stack_diff(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    jj_config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    commit: &Commit,
+    base: &str,
+    config: &Config,
+    budget: Budget,
+) -> Result<Option<Deferred<StackDiff>>> {
+    let repo_root = workspace.workspace_root();
+    let wc = commit.id().hex();
+    let fork_revset = format!("heads(::({base}) & ::{wc})");
+    let Ok(fork_ids) = evaluate_revset(repo, jj_config, workspace, &fork_revset) else {
+        return Ok(None);
+    };
+    if fork_ids.is_empty() {
+        return Ok(None);
+    }
+
+    let cache = cache_for(repo_root, config);
+    let forks: Vec<String> = fork_ids.iter().map(ObjectId::hex).collect();
+    let key = format!("{wc}..{}", forks.join("+"));
+    if let Some(&[files, added, removed]) = cache.get("stackdiff", &key).as_deref() {
+        let stat = DiffStat { added, removed };
+        return Ok(Some(Deferred::Ready(StackDiff { files, stat })));
+    }
+    if config.async_segments || budget.exceeded() {
+        return Ok(Some(computed_later(&cache, repo_root, config)));
+    }
+    let fork_points = fork_ids
+        .iter()
+        .map(|id| repo.store().get_commit(id))
+        .collect::<BackendResult<Vec<_>>>()
+        .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+    let Deferred::Ready(diff) = compute_stack_diff(repo, &fork_points, commit, budget)? else {
+        return Ok(Some(computed_later(&cache, repo_root, config)));
+    };
+    cache.put(
+        "stackdiff",
+        &key,
+        &[diff.files, diff.stat.added, diff.stat.removed],
+    );
+    Ok(Some(Deferred::Ready(diff)))
+}
+
+/// Nearest tagged ancestor of `start` (itself included) by parent steps,
+/// searched up to `MAX_TAG_DISTANCE` steps
+/// Pending if `budget` runs out first.
+fn find_nearest_tag(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    start: &jj_lib::backend::CommitId,
+    tags: &HashMap<jj_lib::backend::CommitId, &str>,
+    budget: Budget,
+) -> Result<Deferred<Option<(jj_lib::backend::CommitId, String)>>> {
+    let mut queue = VecDeque::from([(start.clone(), 0)]);
+    let mut visited = HashSet::new();
+    while let Some((commit_id, depth)) = queue.pop_front() {
+        if let Some(tag) = tags.get(&commit_id) {
+            return Ok(Deferred::Ready(Some((commit_id, (*tag).to_string()))));
+        }
+        if budget.exceeded() {
+            return Ok(Deferred::Pending);
+        }
+        if depth >= MAX_TAG_DISTANCE || !visited.insert(commit_id.clone()) {
+            continue;
+        }
+        let commit = repo
+            .store()
+            .get_commit(&commit_id)
+            .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+        for parent_id in commit.parent_ids() {
+            queue.push_back((parent_id.clone(), depth + 1));
+        }
+    }
+    Ok(Deferred::Ready(None))
+}
+
+/// Nearest tag and commits since it, cached by @ and the repo's tags
+/// None if no tag is reachable within `MAX_TAG_DISTANCE` steps.
+fn cached_tag_distance(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    jj_config: &StackedConfig,
+    workspace: &WorkspaceContext<'_>,
+    commit: &Commit,
+    config: &Config,
+    budget: Budget,
+) -> Result<Option<Deferred<TagDistance>>> {
+    // Several tags on one commit: the first by name
+    let mut tags = HashMap::new();
+    for (name, target) in repo.view().local_tags() {
+        if let Some(id) = target.as_normal() {
+            tags.entry(id.clone()).or_insert(name.as_str());
+        }
+    }
+    if tags.is_empty() {
+        return Ok(None);
+    }
+
+    let repo_root = workspace.workspace_root();
+    let cache = cache_for(repo_root, config);
+    let fingerprint = tag::tags_fingerprint(
+        repo.view()
+            .local_tags()
+            .filter_map(|(name, target)| Some((name.as_str(), target.as_normal()?.hex()))),
+    );
+    let key = format!("{}-{fingerprint}", commit.id().hex());
+    if let Some(fields) = cache.get_fields("tag", &key) {
+        return Ok(TagDistance::from_fields(&fields).map(Deferred::Ready));
+    }
+    if config.async_segments || budget.exceeded() {
+        return Ok(Some(computed_later(&cache, repo_root, config)));
+    }
+    let Deferred::Ready(nearest) = find_nearest_tag(repo, commit.id(), &tags, budget)? else {
+        return Ok(Some(computed_later(&cache, repo_root, config)));
+    };
+    let Some((tagged, tag)) = nearest else {
+        cache.put_fields("tag", &key, &[]);
+        return Ok(None);
+    };
+    let since = format!("{}..{}", tagged.hex(), commit.id().hex());
+    let distance = evaluate_revset(repo, jj_config, workspace, &since)?
+        .len()
+        .min(MAX_TAG_DISTANCE);
+    let tag = TagDistance { tag, distance };
+    cache.put_fields("tag", &key, &tag.to_fields());
+    Ok(Some(Deferred::Ready(tag)))
+}
+
+/// Segment cache of a JJ workspace, shared with the repo's other workspaces
+/// (disabled unless `config.cache`)
+fn cache_for(repo_root: &Path, config: &Config) -> Cache {
+    if config.cache {
+        Cache::new(detect::jj_cache_dir(repo_root))
+    } else {
+        Cache::disabled()
+    }
+}
+
+/// Load the workspace with settings from the user's jj config (so e.g. signing
+/// backends are configured as in jj), retrying with minimal settings if that
+/// config is unusable
+fn load_workspace(repo_root: &Path, jj_config: &StackedConfig) -> Result<Workspace> {
+    let load = |settings: &UserSettings| {
+        Workspace::load(
+            settings,
+            repo_root,
+            &StoreFactories::default(),
+            &default_working_copy_factories(),
+        )
+    };
+    if let Some(workspace) = UserSettings::from_config(jj_config.clone())
+        .ok()
+        .and_then(|settings| load(&settings).ok())
+    {
+        return Ok(workspace);
+    }
+    load(&create_user_settings()?).map_err(|e| Error::Jj(format!("load workspace: {e}")))
+}
+
+/// Placeholder for a segment left to a background refresh, in async mode or
+/// when it didn't fit the time budget (rendered as is without the cache)
+fn computed_later<T>(cache: &Cache, repo_root: &Path, config: &Config) -> Deferred<T> {
+    cache.spawn_refresh(repo_root, &refresh_args(config));
+    Deferred::Pending
+}
+
+/// Global flags making a background refresh compute every enabled expensive
+/// segment, with the options its cache keys depend on (the jj config and trunk
+/// revsets resolve with, the bookmarks whose checks are shown) as resolved here,
+/// from the CLI and the selected profile too
+#[must_use]
+pub fn refresh_args(config: &Config) -> Vec<String> {
+    let mut args = Vec::new();
+    for (enabled, flag) in [
+        (config.diff_stat.enabled, "--diffstat"),
+        (config.file_changes, "--file-changes"),
+        (config.signature, "--signature"),
+        (config.checks, "--checks"),
+        (config.tag.enabled, "--nearest-tag"),
+    ] {
+        if enabled {
+            args.push(flag.to_string());
+        }
+    }
+    if let Some(base) = &config.stack_diff {
+        args.push(format!("--stack-diff={base}"));
+    }
+    if let Some(template) = &config.template {
+        args.push(format!("--template={template}"));
+    }
+    if let Some(trunk) = &config.trunk_revset {
+        args.push(format!("--trunk-revset={trunk}"));
+    }
+    for toml in &config.jj_config_toml {
+        args.push(format!("--config-toml={toml}"));
+    }
+    args.push(format!(
+        "--ancestor-bookmark-depth={}",
+        config.ancestor_bookmark_depth
+    ));
+    args.push(format!(
+        "--descendant-bookmark-depth={}",
+        config.descendant_bookmark_depth
+    ));
+    args
+}
+
+/// Diffstat from the cache (keyed by commit id, so always valid), else computed
+/// now or - in async mode - by a background refresh while a placeholder renders
+fn cached_diff_stat(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    commit: &Commit,
+    repo_root: &Path,
+    config: &Config,
+    budget: Budget,
+) -> Result<Deferred<DiffStat>> {
+    let cache = cache_for(repo_root, config);
+    let key = commit.id().hex();
+    if let Some(&[added, removed]) = cache.get("diffstat", &key).as_deref() {
+        return Ok(Deferred::Ready(DiffStat { added, removed }));
+    }
+    if config.async_segments || budget.exceeded() {
+        return Ok(computed_later(&cache, repo_root, config));
+    }
+    let Deferred::Ready(stat) = compute_diff_stat(repo, commit, budget)? else {
+        return Ok(computed_later(&cache, repo_root, config));
+    };
+    cache.put("diffstat", &key, &[stat.added, stat.removed]);
+    Ok(Deferred::Ready(stat))
+}
+
+/// File change counts from the cache (keyed by commit id, so always valid),
+/// else computed now or - in async mode or out of time - in the background
+fn cached_file_changes(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    commit: &Commit,
+    repo_root: &Path,
+    config: &Config,
+    budget: Budget,
+) -> Result<Deferred<FileChanges>> {
+    let cache = cache_for(repo_root, config);
+    let key = commit.id().hex();
+    if let Some(&[added, modified, deleted]) = cache.get("files", &key).as_deref() {
+        return Ok(Deferred::Ready(FileChanges {
+            added,
+            modified,
+            deleted,
+        }));
+    }
+    if config.async_segments || budget.exceeded() {
+        return Ok(computed_later(&cache, repo_root, config));
+    }
+    let Deferred::Ready(changes) = compute_file_changes(repo, commit, budget)? else {
+        return Ok(computed_later(&cache, repo_root, config));
+    };
+    cache.put(
+        "files",
+        &key,
+        &[changes.added, changes.modified, changes.deleted],
+    );
+    Ok(Deferred::Ready(changes))
+}
+
+/// Shortest unique prefix length of the working copy's change id, cached per
+/// operation: only a new operation can add a change sharing the prefix, and
+/// the lookup grows with the repo's history while a cache hit is one read.
+/// Uses the direct repo API (faster than `IdPrefixContext`, which evaluates
+/// a revset); always computed on a miss, as the change id needs it.
+fn cached_prefix_len(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    commit: &Commit,
+    repo_root: &Path,
+    config: &Config,
+) -> usize {
+    let cache = cache_for(repo_root, config);
+    let key = format!("{}-{}", repo.op_id().hex(), commit.change_id().hex());
+    if let Some(&[len]) = cache.get("prefix", &key).as_deref() {
+        return len;
+    }
+    let Ok(len) = repo.shortest_unique_change_id_prefix_len(commit.change_id()) else {
+        return config.id_length;
+    };
+    cache.put("prefix", &key, &[len]);
+    len
+}
+
+/// Output of the configured template, cached per operation and working copy
+/// commit since evaluating it spawns `jj`, and computed in the background in
+/// async mode or once the time budget is spent. A failure shows nothing and
+/// is only remembered for a while (`template::ERROR_TTL_SECS`); `doctor`
+/// tells why.
+fn cached_template(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    commit: &Commit,
+    repo_root: &Path,
+    template: &str,
+    config: &Config,
+    budget: Budget,
+) -> Deferred<String> {
+    let cache = cache_for(repo_root, config);
+    let op_id = repo.op_id().hex();
+    let jj_config = template::config_args(&config.jj_config_toml, config.trunk_revset.as_deref());
+    let key = template::cache_key(&op_id, &commit.id().hex(), template, &jj_config);
+    if let Some(text) = cache.get_text("template", &key) {
+        return Deferred::Ready(text);
+    }
+    let now = now_secs();
+    if let Some(&[failed]) = cache.get("template_error", &key).as_deref() {
+        if now.saturating_sub(failed) < template::ERROR_TTL_SECS {
+            return Deferred::Ready(String::new());
+        }
+    }
+    if config.async_segments || budget.exceeded() {
+        return computed_later(&cache, repo_root, config);
+    }
+    if let Ok(text) = template::evaluate(repo_root, &op_id, template, &jj_config) {
+        cache.put_text("template", &key, &text);
+        Deferred::Ready(text)
+    } else {
+        cache.put("template_error", &key, &[now]);
+        Deferred::Ready(String::new())
+    }
+}
+
+/// Cache codes of signature verification results
+const SIG_STATUSES: [SigStatus; 3] = [SigStatus::Good, SigStatus::Unknown, SigStatus::Bad];
+
+/// Verification status of a signed commit, cached by commit id (verifying
+/// runs gpg or ssh-keygen) and computed in the background in async mode.
+/// A verification error (e.g. a missing gpg binary) is `Unknown` and not cached.
+fn cached_signature(
+    commit: &Commit,
+    repo_root: &Path,
+    config: &Config,
+    budget: Budget,
+) -> Deferred<SigStatus> {
+    let cache = cache_for(repo_root, config);
+    let key = commit.id().hex();
+    if let Some(&[code]) = cache.get("signature", &key).as_deref() {
+        if let Some(&status) = SIG_STATUSES.get(code) {
+            return Deferred::Ready(status);
+        }
+    }
+    if config.async_segments || budget.exceeded() {
+        return computed_later(&cache, repo_root, config);
+    }
+    match commit.verification() {
+        Ok(Some(verification)) => {
+            let status = verification.status;
+            if let Some(code) = SIG_STATUSES.iter().position(|&s| s == status) {
+                cache.put("signature", &key, &[code]);
+            }
+            Deferred::Ready(status)
+        }
+        Ok(None) | Err(_) => Deferred::Ready(SigStatus::Unknown),
+    }
+}
+
+/// The commit whose signature counts: WC, or its parent while WC is a fresh
+/// empty change on top of it (after `jj new` or `jj commit`), as the parent
+/// is what gets pushed
+fn signature_commit(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    commit: &Commit,
+    empty: bool,
+) -> Result<Commit> {
+    match commit.parent_ids() {
+        [parent]
+            if empty
+                && commit.description().is_empty()
+                && parent != repo.store().root_commit_id() =>
+        {
+            repo.store()
+                .get_commit(parent)
+                .map_err(|e| Error::Jj(format!("get parent: {e}")))
+        }
+        _ => Ok(commit.clone()),
+    }
+}
+
+/// Nearest bookmark pushed to a remote (besides `git`): the remote and the
+/// commit the bookmark points to there
+fn nearest_pushed(
+    view: &jj_lib::view::View,
+    bookmarks: &[(String, usize)],
+) -> Option<(String, jj_lib::backend::CommitId)> {
+    bookmarks.iter().find_map(|(name, _)| {
+        let name_matcher = StringPattern::exact(name).to_matcher();
+        view.remote_bookmarks_matching(&name_matcher, &StringMatcher::All)
+            .filter(|(symbol, _)| symbol.remote.as_str() != "git")
+            .find_map(|(symbol, remote_ref)| {
+                let id = remote_ref.target.as_normal()?;
+                Some((symbol.remote.as_str().to_string(), id.clone()))
+            })
+    })
+}
+
+/// Fetch URL of `remote` in the backing git repo
+fn remote_url(repo: &Arc<jj_lib::repo::ReadonlyRepo>, remote: &str) -> Option<String> {
+    let git_repo = jj_lib::git::get_git_repo(repo.store()).ok()?;
+    let url = git_repo
+        .config_snapshot()
+        .string(format!("remote.{remote}.url").as_str())?
+        .to_string();
+    Some(url)
+}
+
+/// Evaluate the configured template at the current operation of the
+/// workspace at `repo_root`, for `doctor`
+///
+/// # Errors
+/// Why the template shows nothing: `jj` missing or the template's error
+pub fn check_template(repo_root: &Path, template: &str, config: &Config) -> Result<()> {
+    let jj_config = template::config_args(&config.jj_config_toml, config.trunk_revset.as_deref());
+    template::evaluate(repo_root, "@", template, &jj_config)
+        .map(|_| ())
+        .map_err(Error::Jj)
+}
+
+/// Seconds since the epoch, for cache entries that expire
+fn now_secs() -> usize {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| usize::try_from(d.as_secs()).unwrap_or(usize::MAX))
+}
+
+/// CI checks of the nearest pushed bookmark, cached by commit id for a while
+/// (see `checks::ttl_secs`). Only the background refresh asks the forge: the
+/// prompt shows the last known status, or pending, while it runs.
+fn cached_checks(
+    repo: &Arc<jj_lib::repo::ReadonlyRepo>,
+    bookmarks: &[(String, usize)],
+    repo_root: &Path,
+    config: &Config,
+    budget: Budget,
+) -> Option<Deferred<CheckStatus>> {
+    let (remote, commit_id) = nearest_pushed(repo.view(), bookmarks)?;
+    let cache = cache_for(repo_root, config);
+    let key = commit_id.hex();
+    let now = now_secs();
+    if let Some(&[code, fetched]) = cache.get("checks", &key).as_deref() {
+        let status = checks::STATUSES.get(code).copied();
+        let fresh = now.saturating_sub(fetched) < checks::ttl_secs(status);
+        if fresh || !config.background {
+            if !fresh {
+                cache.spawn_refresh(repo_root, &refresh_args(config));
+            }
+            return status.map(Deferred::Ready);
+        }
+    }
+    if !config.background || budget.exceeded() {
+        return Some(computed_later(&cache, repo_root, config));
+    }
+    let status = remote_url(repo, &remote).and_then(|url| checks::fetch(&url, &key));
+    let code = status
+        .and_then(|status| checks::STATUSES.iter().position(|&s| s == status))
+        .unwrap_or(checks::STATUSES.len());
+    cache.put("checks", &key, &[code, now]);
+    status.map(Deferred::Ready)
+}
+
+/// Compute expensive segments into the cache (background half of async mode)
+///
+/// # Errors
+/// As `collect`
+pub fn refresh(repo_root: &Path, config: &Config) -> Result<()> {
+    let config = Config {
+        background: true,
+        ..config.clone()
+    };
+    let result = collect(repo_root, &config).map(|_| ());
+    cache_for(repo_root, &config).finish_refresh(repo_root);
+    result
+}
+
+/// Whether the files on disk are behind the working copy commit: the working
+/// copy was last updated at another operation and to a different tree. Only
+/// reads the tree state (a per-file record) when the operations differ.
+fn is_stale(workspace: &Workspace, repo: &jj_lib::repo::ReadonlyRepo, commit: &Commit) -> bool {
+    let working_copy = workspace.working_copy();
+    working_copy.operation_id() != repo.op_id()
+        && working_copy
+            .tree()
+            .is_ok_and(|tree| tree.tree_ids() != commit.tree_ids())
+}
+
+/// Largest new file a snapshot starts tracking, jj's default for
+/// `snapshot.max-new-file-size`
+const DEFAULT_MAX_NEW_FILE_SIZE: u64 = 1024 * 1024;
+
+/// `snapshot.max-new-file-size`, where 0 means no limit as in jj. Sizes
+/// written with units (`"10MiB"`) fall back to the default
+fn max_new_file_size(jj_config: &StackedConfig) -> u64 {
+    match jj_config.get("snapshot.max-new-file-size") {
+        Ok(0) => u64::MAX,
+        Ok(size) => size,
+        Err(_) => DEFAULT_MAX_NEW_FILE_SIZE,
+    }
+}
+
+/// Matcher for the new files a snapshot starts tracking, from the
+/// `snapshot.auto-track` fileset (default `all()`) with paths relative to the
+/// workspace root. A fileset that doesn't parse tracks nothing, as jj refuses
+/// to snapshot then
+fn auto_track_matcher(jj_config: &StackedConfig, repo_root: &Path) -> Box<dyn Matcher> {
+    let fileset = jj_config
+        .get::<String>("snapshot.auto-track")
+        .unwrap_or_else(|_| "all()".to_owned());
+    let path_converter = RepoPathUiConverter::Fs {
+        cwd: repo_root.to_path_buf(),
+        base: repo_root.to_path_buf(),
+    };
+    jj_lib::fileset::parse(
+        &mut jj_lib::fileset::FilesetDiagnostics::new(),
+        &fileset,
+        &path_converter,
+    )
+    .map_or_else(
+        |_| Box::new(NothingMatcher) as Box<dyn Matcher>,
+        |expression| expression.to_matcher(),
     )
-    .map_err(|e| Error::Jj(format!("load workspace: {e}")))?;
+}
 
-    let repo: Arc<jj_lib::repo::ReadonlyRepo> = workspace
-        .repo_loader()
-        .load_at_head()
+/// Ignore files jj applies on top of the workspace's `.gitignore`s: the user's
+/// global git excludes and, for the git backend, `info/exclude`
+fn base_ignores(repo_root: &Path) -> Arc<GitIgnoreFile> {
+    let global = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("git").join("ignore"));
+    let exclude = detect::jj_git_dir(repo_root).map(|dir| dir.join("info").join("exclude"));
+    global
+        .into_iter()
+        .chain(exclude)
+        .fold(GitIgnoreFile::empty(), |ignores, file| {
+            ignores.chain_with_file("", file.clone()).unwrap_or(ignores)
+        })
+}
+
+/// The repo at the most recently finished of `op_heads`, without merging them
+fn load_newest_op_head(
+    loader: &RepoLoader,
+    op_heads: &[OperationId],
+) -> std::result::Result<Arc<jj_lib::repo::ReadonlyRepo>, RepoLoaderError> {
+    let op_store = loader.op_store();
+    let mut newest: Option<Operation> = None;
+    for id in op_heads {
+        let data = op_store.read_operation(id).block_on()?;
+        let op = Operation::new(op_store.clone(), id.clone(), data);
+        let end = |op: &Operation| op.metadata().time.end.timestamp;
+        if newest.as_ref().is_none_or(|newest| end(&op) > end(newest)) {
+            newest = Some(op);
+        }
+    }
+    match newest {
+        Some(op) => loader.load_at(&op),
+        None => loader.load_at_head(),
+    }
+}
+
+/// Cap of the directory entries `count_untracked` looks at, so huge or
+/// mostly-ignored working copies can't stall the prompt
+pub const MAX_SCANNED_ENTRIES: usize = 10_000;
+
+/// Count the files on disk that `tree` doesn't track, walking the working
+/// copy as a snapshot would: `.gitignore`s apply per directory on top of
+/// `base_ignores`, `.jj`/`.git` are skipped, and only files matching
+/// `snapshot.auto-track` within `snapshot.max-new-file-size` count. Stops after
+/// `MAX_SCANNED_ENTRIES`, so counts are lower bounds then; Pending if
+/// `budget` runs out first.
+fn count_untracked(
+    repo_root: &Path,
+    tree: &MergedTree,
+    jj_config: &StackedConfig,
+    budget: Budget,
+) -> Result<Deferred<UntrackedFiles>> {
+    let auto_track = auto_track_matcher(jj_config, repo_root);
+    let max_size = max_new_file_size(jj_config);
+    let walk_error = |e: &dyn std::fmt::Display| Error::Jj(format!("untracked files: {e}"));
+    let root_ignores = base_ignores(repo_root)
+        .chain_with_file("", repo_root.join(".gitignore"))
+        .map_err(|e| walk_error(&e))?;
+    let mut counts = UntrackedFiles::default();
+    let mut scanned = 0;
+    let mut dirs = vec![(repo_root.to_path_buf(), String::new(), root_ignores)];
+    while let Some((dir, prefix, ignores)) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if scanned == MAX_SCANNED_ENTRIES {
+                return Ok(Deferred::Ready(counts));
+            }
+            if budget.exceeded() {
+                return Ok(Deferred::Pending);
+            }
+            scanned += 1;
+            let name = entry.file_name();
+            let Some(name) = name
+                .to_str()
+                .filter(|name| !matches!(*name, ".jj" | ".git"))
+            else {
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                let dir_prefix = format!("{path}/");
+                if ignores.matches(&dir_prefix) {
+                    counts.ignored += 1;
+                } else {
+                    let ignores = ignores
+                        .chain_with_file(&dir_prefix, entry.path().join(".gitignore"))
+                        .map_err(|e| walk_error(&e))?;
+                    dirs.push((entry.path(), dir_prefix, ignores));
+                }
+                continue;
+            }
+            let repo_path =
+                RepoPathBuf::from_internal_string(path.as_str()).map_err(|e| walk_error(&e))?;
+            let tracked = tree
+                .path_value(&repo_path)
+                .map_err(|e| walk_error(&e))?
+                .is_present();
+            if tracked {
+                continue;
+            }
+            if ignores.matches(&path) {
+                counts.ignored += 1;
+            } else if auto_track.matches(&repo_path)
+                && entry.metadata().is_ok_and(|meta| meta.len() <= max_size)
+            {
+                counts.untracked += 1;
+            }
+        }
+    }
+    Ok(Deferred::Ready(counts))
+}
+
+/// Snapshot the working copy into @ as jj does before a command, recording an
+/// operation if any file changed. Returns the repo after the snapshot, or
+/// `repo` itself when the working copy isn't at its operation (stale, or jj
+/// is updating it right now).
+fn snapshot_working_copy(
+    workspace: &mut Workspace,
+    repo: Arc<jj_lib::repo::ReadonlyRepo>,
+    jj_config: &StackedConfig,
+    repo_root: &Path,
+) -> Result<Arc<jj_lib::repo::ReadonlyRepo>> {
+    let snapshot_error = |e: &dyn std::fmt::Display| Error::Jj(format!("snapshot: {e}"));
+    let workspace_name = workspace.workspace_name().to_owned();
+    let Some(wc_id) = repo.view().get_wc_commit_id(&workspace_name).cloned() else {
+        return Ok(repo);
+    };
+    let wc_commit = repo
+        .store()
+        .get_commit(&wc_id)
+        .map_err(|e| snapshot_error(&e))?;
+    let mut locked = workspace
+        .start_working_copy_mutation()
+        .map_err(|e| snapshot_error(&e))?;
+    if locked.locked_wc().old_operation_id() != repo.op_id() {
+        return Ok(repo);
+    }
+    let start_tracking = auto_track_matcher(jj_config, repo_root);
+    let options = SnapshotOptions {
+        base_ignores: base_ignores(repo_root),
+        progress: None,
+        start_tracking_matcher: start_tracking.as_ref(),
+        force_tracking_matcher: &NothingMatcher,
+        max_new_file_size: max_new_file_size(jj_config),
+    };
+    let (tree, _) = locked
+        .locked_wc()
+        .snapshot(&options)
+        .block_on()
+        .map_err(|e| snapshot_error(&e))?;
+    if tree.tree_ids() == wc_commit.tree_ids() {
+        return Ok(repo);
+    }
+    let mut tx = repo.start_transaction();
+    tx.set_is_snapshot(true);
+    tx.repo_mut()
+        .rewrite_commit(&wc_commit)
+        .set_tree(tree)
+        .write()
+        .map_err(|e| snapshot_error(&e))?;
+    tx.repo_mut()
+        .rebase_descendants()
+        .map_err(|e| snapshot_error(&e))?;
+    let repo = tx
+        .commit("snapshot working copy")
+        .map_err(|e| snapshot_error(&e))?;
+    locked
+        .finish(repo.op_id().clone())
+        .map_err(|e| snapshot_error(&e))?;
+    Ok(repo)
+}
+
+/// Collect JJ repo info from the given path
+///
+/// # Errors
+/// The workspace or repo couldn't be loaded, or the working copy commit read
+pub fn collect(repo_root: &Path, config: &Config) -> Result<JjInfo> {
+    collect_timed(repo_root, config, &Timings::default())
+}
+
+/// `collect`, recording the time of each phase and segment in `timings`
+///
+/// # Errors
+/// The workspace or repo couldn't be loaded, or the working copy commit read
+#[allow(clippy::too_many_lines)]
+pub fn collect_timed(repo_root: &Path, config: &Config, timings: &Timings) -> Result<JjInfo> {
+    let budget = Budget::new(config.timeout);
+    let id_length = config.id_length;
+    let ancestor_depth = config.ancestor_bookmark_depth;
+    let descendant_depth = config.descendant_bookmark_depth;
+    let repo_dir = detect::jj_repo_dir(repo_root).unwrap_or_else(|| repo_root.join(".jj/repo"));
+    let jj_config = timings.time("jj config", || {
+        cached_jj_config(repo_root, &repo_dir, config)
+    });
+    let mut workspace = timings.time("load workspace", || load_workspace(repo_root, &jj_config))?;
+
+    // Read-only by default: the repo at its op head, without locking or
+    // writing the working copy. Concurrent operations leave several op
+    // heads, which loading at head would merge into a new operation, so
+    // the newest is read instead and the merge left to jj
+    let loader = workspace.repo_loader();
+    let op_heads = timings.time("op heads", || {
+        loader
+            .op_heads_store()
+            .get_op_heads()
+            .block_on()
+            .unwrap_or_default()
+    });
+    let mut repo: Arc<jj_lib::repo::ReadonlyRepo> = timings
+        .time("load repo", || {
+            if op_heads.len() > 1 && !config.snapshot {
+                load_newest_op_head(loader, &op_heads)
+            } else {
+                loader.load_at_head()
+            }
+        })
         .map_err(|e| Error::Jj(format!("load repo: {e}")))?;
+    // Loading at head with `--snapshot` merged them
+    let op_heads = if config.snapshot {
+        1
+    } else {
+        op_heads.len().max(1)
+    };
+    if config.snapshot {
+        repo = timings.time("snapshot", || {
+            snapshot_working_copy(&mut workspace, repo, &jj_config, repo_root)
+        })?;
+    }
 
     let view = repo.view();
+    let context = WorkspaceContext::new(&workspace);
 
     // Get WC commit ID
     let wc_id = view
@@ -198,77 +1971,316 @@ pub fn collect(repo_root: &Path, id_length: usize, ancestor_depth: usize) -> Res
 
     // Change ID in JJ's reverse hex format
     let change_id_full = encode_reverse_hex(commit.change_id().as_bytes());
-    let change_id = change_id_full[..id_length.min(change_id_full.len())].to_string();
 
-    // Compute shortest unique prefix length for change_id coloring
-    // Uses direct repo API (faster than IdPrefixContext which requires revset evaluation)
-    let change_id_prefix_len = repo
-        .shortest_unique_change_id_prefix_len(commit.change_id())
-        .unwrap_or(id_length)
-        .min(change_id.len());
+    // Shortest unique prefix length for change_id coloring
+    let change_id_prefix_len = timings
+        .time("change_id", || {
+            cached_prefix_len(&repo, &commit, repo_root, config)
+        })
+        .min(change_id_full.len());
+    // Shortened like `jj log` does: `id_length` long, or longer when that
+    // wouldn't cover the unique prefix
+    let change_id_len = id_length
+        .max(change_id_prefix_len)
+        .min(change_id_full.len());
+    let change_id = change_id_full[..change_id_len].to_string();
 
     // Empty description check
     let empty_desc = commit.description().trim().is_empty();
 
+    // Trailers like `Fixes: #123` or `Change-Id: I…`
+    let trailers = parse_description_trailers(commit.description())
+        .into_iter()
+        .map(|t| (t.key, t.value))
+        .collect();
+
     // Conflict check
     let conflict = commit.has_conflict();
+    let conflicted_files = config.conflict_marker.then(|| {
+        if conflict {
+            commit.tree().conflicts().count()
+        } else {
+            0
+        }
+    });
+    let empty = timings
+        .time("empty", || commit.is_empty(repo.as_ref()))
+        .map_err(|e| Error::Jj(format!("empty check: {e}")))?;
+    let stale = timings.time("stale", || is_stale(&workspace, &repo, &commit));
 
     // Divergent check - multiple commits for same change_id
-    let divergent = repo
-        .resolve_change_id(commit.change_id())
-        .ok()
-        .flatten()
-        .is_some_and(|commits| commits.len() > 1);
-
-    // Find bookmarks - first check direct bookmarks on WC (distance 0)
-    let mut bookmarks: Vec<(String, usize)> = view
-        .local_bookmarks_for_commit(wc_id)
-        .map(|(name, _)| (name.as_str().to_string(), 0))
-        .collect();
+    let divergent = timings.time("divergent", || {
+        repo.resolve_change_id(commit.change_id())
+            .ok()
+            .flatten()
+            .is_some_and(|commits| commits.len() > 1)
+    });
 
-    // Always search ancestors if enabled (useful for stacked PR context)
-    // Ancestor bookmarks are disjoint from direct bookmarks (different commits)
-    if ancestor_depth > 0 {
-        let ancestors = find_ancestor_bookmarks(&repo, view, wc_id, ancestor_depth)?;
-        bookmarks.extend(ancestors);
-    }
-
-    // Check remote sync status for first (closest) bookmark only
-    // For stacked PRs, this reflects whether current stack position needs pushing
-    let (has_remote, is_synced) = if bookmarks.is_empty() {
-        (false, true)
-    } else {
-        let (bm_name, _) = &bookmarks[0];
-        let local_target = view.get_local_bookmark(RefName::new(bm_name));
+    let immutable = config
+        .immutable
+        .then(|| {
+            timings.time("immutable", || {
+                immutability(&repo, &jj_config, &context).ok()
+            })
+        })
+        .flatten();
 
-        let name_matcher = StringPattern::exact(bm_name).to_matcher();
-        let mut has_remote = false;
-        let mut is_synced = false;
-
-        for (symbol, remote_ref) in
-            view.remote_bookmarks_matching(&name_matcher, &StringMatcher::All)
-        {
-            if symbol.remote.as_str() == "git" {
-                continue;
+    // The slow segments walk history or the tree independently of each
+    // other, so each gets a thread of its own sharing the loaded repo...
+    thread::scope(|scope| {
+        let stack_conflict = scope.spawn(|| -> Result<bool> {
+            if !config.stack_conflicts {
+                return Ok(false);
             }
-            has_remote = true;
-            if remote_ref.target == *local_target {
-                is_synced = true;
-                break;
+            let wc = wc_id.hex();
+            let conflicts = format!("(conflicts() & mutable() & (::{wc} | {wc}::)) ~ {wc}");
+            timings.time("stack_conflict", || {
+                Ok(!evaluate_revset(&repo, &jj_config, &context, &conflicts)?.is_empty())
+            })
+        });
+        let anonymous_heads = scope.spawn(|| {
+            config
+                .anonymous_heads
+                .then(|| {
+                    timings.time("heads", || {
+                        evaluate_revset(&repo, &jj_config, &context, ANONYMOUS_HEADS)
+                    })
+                })
+                .transpose()
+                .map(|heads| heads.map(|heads| heads.len()))
+        });
+        let stacks = scope.spawn(|| {
+            config
+                .stacks
+                .then(|| {
+                    timings.time("stacks", || {
+                        let revset = stacks_revset(&wc_id.hex());
+                        evaluate_revset_limited(
+                            &repo,
+                            &jj_config,
+                            &context,
+                            &revset,
+                            MAX_COUNTED_COMMITS,
+                        )
+                    })
+                })
+                .transpose()
+                .map(|heads| heads.map(|heads| heads.len()))
+        });
+        let trunk_distance = scope.spawn(|| {
+            if config.trunk_distance {
+                timings.time("trunk", || {
+                    trunk_distance(&repo, &jj_config, &context, wc_id, budget)
+                })
+            } else {
+                Ok(None)
             }
+        });
+        let stack_diff = scope.spawn(|| match &config.stack_diff {
+            Some(base) => timings.time("stack_diff", || {
+                cached_stack_diff(&repo, &jj_config, &context, &commit, base, config, budget)
+            }),
+            None => Ok(None),
+        });
+        let tag = scope.spawn(|| {
+            if config.tag.enabled {
+                timings.time("tag", || {
+                    cached_tag_distance(&repo, &jj_config, &context, &commit, config, budget)
+                })
+            } else {
+                Ok(None)
+            }
+        });
+        let diff_stat = scope.spawn(|| {
+            config
+                .diff_stat
+                .enabled
+                .then(|| {
+                    timings.time("diffstat", || {
+                        cached_diff_stat(&repo, &commit, repo_root, config, budget)
+                    })
+                })
+                .transpose()
+        });
+        let untracked = scope.spawn(|| {
+            config
+                .untracked
+                .then(|| {
+                    timings.time("untracked", || {
+                        count_untracked(repo_root, &commit.tree(), &jj_config, budget)
+                    })
+                })
+                .transpose()
+        });
+        let file_changes = scope.spawn(|| {
+            config
+                .file_changes
+                .then(|| {
+                    timings.time("files", || {
+                        cached_file_changes(&repo, &commit, repo_root, config, budget)
+                    })
+                })
+                .transpose()
+        });
+        #[cfg(feature = "git")]
+        let git_staged = scope.spawn(|| {
+            (config.git_staged && detect::is_colocated(repo_root))
+                .then(|| timings.time("staged", || crate::git::staged_files(repo_root)))
+                .flatten()
+        });
+
+        // ...while the bookmarks, and what depends on them, are found here.
+        // First direct bookmarks on WC (distance 0)
+        let mut bookmarks: Vec<(String, usize)> = view
+            .local_bookmarks_for_commit(wc_id)
+            .map(|(name, _)| (name.as_str().to_string(), 0))
+            .collect();
+
+        // Always search ancestors if enabled (useful for stacked PR context)
+        // Ancestor bookmarks are disjoint from direct bookmarks (different commits)
+        if ancestor_depth > 0 {
+            // Traversal stops at immutable heads (trunk/tags/untracked remotes by default)
+            let ancestors = timings.time("bookmarks", || {
+                let immutable_heads = immutable_heads(&repo, &jj_config, &context);
+                find_ancestor_bookmarks(&repo, view, wc_id, ancestor_depth, &immutable_heads)
+            })?;
+            bookmarks.extend(ancestors);
         }
 
-        (has_remote, is_synced || !has_remote)
-    };
+        // Nearest bookmark ahead of WC, for when WC sits in history
+        let descendant_bookmark = if descendant_depth > 0 {
+            timings.time("descendant", || {
+                find_descendant_bookmark(&repo, view, wc_id, descendant_depth)
+            })?
+        } else {
+            None
+        };
+
+        // Check remote sync status for first (closest) bookmark only
+        // For stacked PRs, this reflects whether current stack position needs pushing
+        let (has_remote, is_synced) = bookmarks
+            .first()
+            .map_or((false, true), |(name, _)| remote_sync_status(view, name));
+
+        let unpushed_bookmarks = config
+            .unpushed_bookmarks
+            .then(|| timings.time("unpushed", || count_unpushed_bookmarks(view)));
+        let remote_distance = match bookmarks.first() {
+            Some((name, _)) if config.remote_distance => timings.time("remote", || {
+                remote_distance(&repo, &jj_config, &context, name, budget)
+            })?,
+            _ => None,
+        };
+
+        let clone = config
+            .clone_marker
+            .then(|| {
+                timings.time("clone", || {
+                    detect::jj_git_dir(repo_root).map(|dir| clone::kind(&dir))
+                })
+            })
+            .flatten()
+            .unwrap_or_default();
+
+        #[cfg(feature = "git")]
+        let git_head = (config.git_head && detect::is_colocated(repo_root))
+            .then(|| timings.time("git_head", || crate::git::refs(repo_root)))
+            .flatten()
+            .map(|refs| git_head(view, &refs));
+        #[cfg(not(feature = "git"))]
+        let git_head = None;
+
+        let checks = if config.checks {
+            timings.time("checks", || {
+                cached_checks(&repo, &bookmarks, repo_root, config, budget)
+            })
+        } else {
+            None
+        };
 
-    Ok(JjInfo {
-        change_id,
-        change_id_prefix_len,
-        bookmarks,
-        empty_desc,
-        conflict,
-        divergent,
-        has_remote,
-        is_synced,
+        let megamerge = if config.megamerge {
+            timings.time("megamerge", || find_megamerge(&repo, view, &commit))?
+        } else {
+            Vec::new()
+        };
+
+        let parents = if config.parent {
+            timings.time("parent", || find_parents(&repo, view, &commit))?
+        } else {
+            Vec::new()
+        };
+
+        let template = config.template.as_deref().map(|template| {
+            timings.time("template", || {
+                cached_template(&repo, &commit, repo_root, template, config, budget)
+            })
+        });
+
+        let signed = config
+            .signature
+            .then(|| signature_commit(&repo, &commit, empty))
+            .transpose()?;
+        let signature = signed
+            .as_ref()
+            .filter(|signed| signed.is_signed())
+            .map(|signed| {
+                timings.time("signature", || {
+                    cached_signature(signed, repo_root, config, budget)
+                })
+            });
+        let unsigned = config.warn_unsigned && signed.is_some_and(|signed| !signed.is_signed());
+
+        Ok(JjInfo {
+            change_id,
+            change_id_prefix_len,
+            change_id_full,
+            workspace: (workspace.workspace_name() != WorkspaceName::DEFAULT)
+                .then(|| workspace.workspace_name().as_str().to_string()),
+            bookmarks,
+            descendant_bookmark,
+            description: commit.description().to_string(),
+            trailers,
+            empty_desc,
+            conflict,
+            empty,
+            stale,
+            divergent,
+            immutable,
+            op_heads,
+            operation: repo.operation().metadata().description.clone(),
+            has_remote,
+            is_synced,
+            conflicted_files,
+            unpushed_bookmarks,
+            anonymous_heads: join(anonymous_heads)?,
+            stacks: join(stacks)?,
+            trunk_distance: join(trunk_distance)?,
+            remote_distance,
+            clone,
+            #[cfg(feature = "git")]
+            git_staged: join(git_staged),
+            #[cfg(not(feature = "git"))]
+            git_staged: None,
+            git_head,
+            stack_conflict: join(stack_conflict)?,
+            signature,
+            unsigned,
+            checks,
+            tag: join(tag)?,
+            stack_diff: join(stack_diff)?,
+            megamerge,
+            parents,
+            template,
+            diff_stat: join(diff_stat)?,
+            file_changes: join(file_changes)?,
+            untracked: join(untracked)?,
+        })
     })
 }
+
+/// Value of a segment gathered on its own thread, re-raising its panic
+fn join<T>(handle: thread::ScopedJoinHandle<'_, T>) -> T {
+    handle
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
@@ -0,0 +1,359 @@
+//! `--json`: the collected repo state as one JSON object
+//!
+//! Unlike `--output env`, values keep their types: counts are numbers, flags
+//! are booleans, and bookmarks, trailers and the like are arrays and objects,
+//! so tmux scripts, editors and starship `custom` modules can use them without
+//! parsing ANSI text. Everything the config enables is included; disabled or
+//! not yet computed values are null. Outside a repo the output is `null`.
+
+use crate::batch::json_string;
+use crate::cache::Deferred;
+use crate::checks::CheckStatus;
+use crate::clone::CloneKind;
+use crate::config::Config;
+use crate::detect::{self, DetectResult};
+use crate::diff_stat::DiffStat;
+use crate::get::{self, Field};
+use crate::jj::{FileChanges, GitHead, Immutability, JjInfo, Parent};
+use crate::output::PromptData;
+use crate::tag::TagDistance;
+use jj_lib::signing::SigStatus;
+use std::path::Path;
+
+/// JSON object of the repo containing `cwd`, `null` outside a repo
+pub fn render(cwd: &Path, config: &Config) -> String {
+    let result = detect::detect(cwd, config.discovery);
+    crate::collect(&result, config).map_or_else(null, |data| state(&result, &data))
+}
+
+/// Object of a detected repo and the data collected from it
+fn state(result: &DetectResult, data: &PromptData) -> String {
+    let mut object = Object::default();
+    object.raw(
+        "repo",
+        nullable(get::value(result, None, Field::Repo).as_deref()),
+    );
+    object.raw(
+        "root",
+        nullable(get::value(result, None, Field::Root).as_deref()),
+    );
+    match data {
+        PromptData::Jj(info) => jj_fields(&mut object, info),
+        #[cfg(feature = "git")]
+        PromptData::Git(info) => {
+            object.raw("branch", nullable(info.branch.as_deref()));
+            object.raw("commit", json_string(&info.head_short));
+            object.number("staged", info.staged);
+            object.number("modified", info.modified);
+            object.number("untracked", info.untracked);
+            object.number("deleted", info.deleted);
+            object.number("conflicted", info.conflicted);
+            let (ahead, behind) = match info.ahead_behind {
+                Deferred::Ready((ahead, behind)) => (ahead.to_string(), behind.to_string()),
+                Deferred::Pending => (null(), null()),
+            };
+            object.raw("ahead", ahead);
+            object.raw("behind", behind);
+            object.raw(
+                "diffstat",
+                ready(info.diff_stat.as_ref(), |&stat| diff_stat(stat)),
+            );
+            object.raw("tag", ready(info.tag.as_ref(), tag));
+            object.raw("clone", clone(info.clone));
+        }
+    }
+    object.finish()
+}
+
+fn jj_fields(object: &mut Object, info: &JjInfo) {
+    object.raw("change_id", json_string(&info.change_id));
+    object.number("change_id_prefix_len", info.change_id_prefix_len);
+    object.raw("change_id_full", json_string(&info.change_id_full));
+    object.raw("workspace", nullable(info.workspace.as_deref()));
+    let bookmark = |(name, distance): &(String, usize)| {
+        let mut bookmark = Object::default();
+        bookmark.raw("name", json_string(name));
+        bookmark.number("distance", *distance);
+        bookmark.finish()
+    };
+    object.raw("bookmarks", array(info.bookmarks.iter().map(bookmark)));
+    object.raw(
+        "descendant_bookmark",
+        info.descendant_bookmark
+            .as_ref()
+            .map_or_else(null, bookmark),
+    );
+    object.raw("description", json_string(&info.description));
+    let trailer = |(key, value): &(String, String)| {
+        let mut trailer = Object::default();
+        trailer.raw("key", json_string(key));
+        trailer.raw("value", json_string(value));
+        trailer.finish()
+    };
+    object.raw("trailers", array(info.trailers.iter().map(trailer)));
+    state_fields(object, info);
+    let count = |count: Option<usize>| count.map_or_else(null, |n| n.to_string());
+    object.raw("unpushed_bookmarks", count(info.unpushed_bookmarks));
+    object.raw("anonymous_heads", count(info.anonymous_heads));
+    object.raw("stacks", count(info.stacks));
+    object.raw("conflicted_files", count(info.conflicted_files));
+    object.raw(
+        "remote",
+        info.remote_distance
+            .as_ref()
+            .map_or_else(null, |(remote, counts)| match counts {
+                Deferred::Ready(counts) => distance(Some(remote), *counts),
+                Deferred::Pending => null(),
+            }),
+    );
+    object.raw(
+        "trunk",
+        ready(info.trunk_distance.as_ref(), |&counts| {
+            distance(None, counts)
+        }),
+    );
+    object.raw("git_staged", count(info.git_staged));
+    object.raw(
+        "git_head",
+        info.git_head.as_ref().map_or_else(null, git_head),
+    );
+    object.raw("clone", clone(info.clone));
+    object.raw(
+        "signature",
+        if info.unsigned {
+            json_string("unsigned")
+        } else {
+            ready(info.signature.as_ref(), |status| {
+                json_string(match status {
+                    SigStatus::Good => "good",
+                    SigStatus::Unknown => "unknown",
+                    SigStatus::Bad => "bad",
+                })
+            })
+        },
+    );
+    object.raw(
+        "checks",
+        ready(info.checks.as_ref(), |status| {
+            json_string(match status {
+                CheckStatus::Pass => "pass",
+                CheckStatus::Fail => "fail",
+                CheckStatus::Pending => "pending",
+            })
+        }),
+    );
+    object.raw("tag", ready(info.tag.as_ref(), tag));
+    object.raw(
+        "stack_diff",
+        ready(info.stack_diff.as_ref(), |stack| {
+            let mut object = Object::default();
+            object.number("files", stack.files);
+            object.number("added", stack.stat.added);
+            object.number("removed", stack.stat.removed);
+            object.finish()
+        }),
+    );
+    object.raw(
+        "megamerge",
+        array(info.megamerge.iter().map(|name| nullable(name.as_deref()))),
+    );
+    object.raw("parents", array(info.parents.iter().map(parent)));
+    object.raw(
+        "template",
+        ready(info.template.as_ref(), |text| json_string(text)),
+    );
+    working_copy_fields(object, info);
+}
+
+/// Flags of the working copy and of the operation it was read at
+fn state_fields(object: &mut Object, info: &JjInfo) {
+    object.bool("empty_description", info.empty_desc);
+    object.bool("conflict", info.conflict);
+    object.bool("empty", info.empty);
+    object.bool("stale", info.stale);
+    object.bool("divergent", info.divergent);
+    object.raw(
+        "immutable",
+        nullable(info.immutable.map(Immutability::as_str)),
+    );
+    object.number("op_heads", info.op_heads);
+    object.raw("operation", json_string(&info.operation));
+    object.bool("has_remote", info.has_remote);
+    object.bool("synced", info.is_synced);
+    object.bool("stack_conflict", info.stack_conflict);
+}
+
+/// Changes of the working copy against its parents and on disk
+fn working_copy_fields(object: &mut Object, info: &JjInfo) {
+    object.raw(
+        "diffstat",
+        ready(info.diff_stat.as_ref(), |&stat| diff_stat(stat)),
+    );
+    object.raw("files", ready(info.file_changes.as_ref(), file_changes));
+    object.raw(
+        "untracked",
+        ready(info.untracked.as_ref(), |files| files.untracked.to_string()),
+    );
+    object.raw(
+        "ignored",
+        ready(info.untracked.as_ref(), |files| files.ignored.to_string()),
+    );
+}
+
+/// Members of a JSON object, in insertion order
+#[derive(Default)]
+struct Object(Vec<String>);
+
+impl Object {
+    /// Add a member whose value is already JSON
+    fn raw(&mut self, key: &str, value: String) {
+        self.0.push([json_string(key), value].join(":"));
+    }
+
+    fn number(&mut self, key: &str, value: usize) {
+        self.raw(key, value.to_string());
+    }
+
+    fn bool(&mut self, key: &str, value: bool) {
+        self.raw(key, value.to_string());
+    }
+
+    fn finish(self) -> String {
+        format!("{{{}}}", self.0.join(","))
+    }
+}
+
+fn null() -> String {
+    "null".to_string()
+}
+
+fn nullable(value: Option<&str>) -> String {
+    value.map_or_else(null, json_string)
+}
+
+fn array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+/// JSON of a ready value, null if disabled or pending
+fn ready<T>(value: Option<&Deferred<T>>, json: impl FnOnce(&T) -> String) -> String {
+    match value {
+        Some(Deferred::Ready(value)) => json(value),
+        Some(Deferred::Pending) | None => null(),
+    }
+}
+
+fn diff_stat(stat: DiffStat) -> String {
+    let mut object = Object::default();
+    object.number("added", stat.added);
+    object.number("removed", stat.removed);
+    object.finish()
+}
+
+fn git_head(head: &GitHead) -> String {
+    let mut object = Object::default();
+    object.raw("branch", nullable(head.branch.as_deref()));
+    object.bool("needs_import", head.needs_import);
+    object.bool("needs_export", head.needs_export);
+    object.finish()
+}
+
+fn parent(parent: &Parent) -> String {
+    let mut object = Object::default();
+    object.raw("change_id", json_string(&parent.change_id));
+    object.raw("bookmark", nullable(parent.bookmark.as_deref()));
+    object.finish()
+}
+
+fn file_changes(changes: &FileChanges) -> String {
+    let mut object = Object::default();
+    object.number("added", changes.added);
+    object.number("modified", changes.modified);
+    object.number("deleted", changes.deleted);
+    object.finish()
+}
+
+/// `{"ahead": ..., "behind": ...}`, after the `name` of the other side if given
+fn distance(name: Option<&str>, (ahead, behind): (usize, usize)) -> String {
+    let mut object = Object::default();
+    if let Some(name) = name {
+        object.raw("name", json_string(name));
+    }
+    object.number("ahead", ahead);
+    object.number("behind", behind);
+    object.finish()
+}
+
+fn tag(tag: &TagDistance) -> String {
+    let mut object = Object::default();
+    object.raw("name", json_string(&tag.tag));
+    object.number("distance", tag.distance);
+    object.finish()
+}
+
+fn clone(kind: CloneKind) -> String {
+    let mut object = Object::default();
+    object.bool("shallow", kind.shallow);
+    object.bool("partial", kind.partial);
+    object.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::RepoType;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_jj_state() {
+        let result = DetectResult {
+            repo_type: RepoType::Jj,
+            repo_root: Some(PathBuf::from("/src/app")),
+        };
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 2)],
+            description: "fix: \"quoted\"\n".into(),
+            conflict: true,
+            unpushed_bookmarks: Some(1),
+            tag: Some(Deferred::Pending),
+            megamerge: vec![Some("a".into()), None],
+            parents: vec![Parent {
+                change_id: "kmqp".into(),
+                bookmark: Some("main".into()),
+            }],
+            diff_stat: Some(Deferred::Ready(DiffStat {
+                added: 12,
+                removed: 3,
+            })),
+            ..JjInfo::default()
+        };
+        let json = state(&result, &PromptData::Jj(info));
+        assert!(json.starts_with(
+            r#"{"repo":"jj","root":"/src/app","change_id":"yzxv1234","change_id_prefix_len":4,"#
+        ));
+        for member in [
+            r#""bookmarks":[{"name":"main","distance":2}]"#,
+            r#""workspace":null"#,
+            r#""immutable":null,"op_heads":0,"operation":"""#,
+            r#""git_head":null"#,
+            r#""descendant_bookmark":null"#,
+            r#""description":"fix: \"quoted\"\n""#,
+            r#""conflict":true"#,
+            r#""unpushed_bookmarks":1,"anonymous_heads":null"#,
+            r#""tag":null"#,
+            r#""megamerge":["a",null]"#,
+            r#""parents":[{"change_id":"kmqp","bookmark":"main"}]"#,
+            r#""diffstat":{"added":12,"removed":3},"files":null,"untracked":null,"ignored":null}"#,
+        ] {
+            assert!(json.contains(member), "{member} missing from {json}");
+        }
+    }
+
+    #[test]
+    fn test_outside_repo_is_null() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(render(tmp.path(), &Config::default()), "null");
+    }
+}
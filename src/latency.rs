@@ -0,0 +1,167 @@
+//! Adaptive per-repo latency profiles
+//!
+//! Each prompt records how long it took in the repo's cache. A repo that
+//! consistently exceeds the latency budget switches to the degraded profile
+//! (see `Config::degrade`); after a while in degraded mode it retries the full
+//! profile, so a repo that got faster (e.g. after `jj gc`) recovers on its own.
+
+use crate::cache::Cache;
+use std::time::Duration;
+
+/// Cache entry holding the learned state
+const LATENCY_ENTRY: &str = "latency";
+
+/// Number of recent prompts kept
+pub const WINDOW: usize = 10;
+
+/// Over-budget prompts within a full window that trigger degradation
+pub const SLOW_THRESHOLD: usize = 8;
+
+/// Prompts rendered in degraded mode before the full profile is retried
+pub const PROBE_AFTER: usize = 50;
+
+/// Segment profile used for a repo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    #[default]
+    Full,
+    Degraded,
+}
+
+impl Profile {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Degraded => "degraded",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "full" => Some(Self::Full),
+            "degraded" => Some(Self::Degraded),
+            _ => None,
+        }
+    }
+}
+
+/// Learned latency state of a repo
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyState {
+    pub profile: Profile,
+    /// Prompts rendered since the profile last changed
+    pub prompts: usize,
+    /// Most recent prompt latencies in microseconds, oldest first
+    pub samples: Vec<usize>,
+}
+
+impl LatencyState {
+    /// Load the state from the cache, starting fresh if missing or corrupt
+    #[must_use]
+    pub fn load(cache: &Cache) -> Self {
+        cache
+            .read(LATENCY_ENTRY)
+            .and_then(|(profile, values)| {
+                let (&prompts, samples) = values.split_first()?;
+                Some(Self {
+                    profile: Profile::parse(&profile)?,
+                    prompts,
+                    samples: samples.to_vec(),
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, cache: &Cache) {
+        let mut values = Vec::with_capacity(self.samples.len() + 1);
+        values.push(self.prompts);
+        values.extend(&self.samples);
+        cache.put(LATENCY_ENTRY, self.profile.as_str(), &values);
+    }
+
+    /// Number of recent samples over `budget`
+    #[must_use]
+    pub fn over_budget(&self, budget: Duration) -> usize {
+        let budget = budget.as_micros();
+        self.samples
+            .iter()
+            .filter(|&&us| us as u128 > budget)
+            .count()
+    }
+
+    /// Record a prompt latency and switch profiles when warranted
+    pub fn record(&mut self, latency: Duration, budget: Duration) {
+        let us = usize::try_from(latency.as_micros()).unwrap_or(usize::MAX);
+        self.samples.push(us);
+        if self.samples.len() > WINDOW {
+            self.samples.remove(0);
+        }
+        self.prompts = self.prompts.saturating_add(1);
+
+        let next = match self.profile {
+            Profile::Full
+                if self.samples.len() == WINDOW && self.over_budget(budget) >= SLOW_THRESHOLD =>
+            {
+                Profile::Degraded
+            }
+            Profile::Degraded if self.prompts >= PROBE_AFTER => Profile::Full,
+            profile => profile,
+        };
+        if next != self.profile {
+            // Samples from the other profile say nothing about this one
+            *self = Self {
+                profile: next,
+                ..Self::default()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Duration = Duration::from_millis(100);
+    const FAST: Duration = Duration::from_millis(20);
+    const SLOW: Duration = Duration::from_millis(300);
+
+    #[test]
+    fn test_degrades_only_when_consistently_slow() {
+        let mut state = LatencyState::default();
+        // A few slow prompts in a full window are tolerated
+        for _ in 0..=(WINDOW - SLOW_THRESHOLD) {
+            state.record(FAST, BUDGET);
+        }
+        for _ in 0..SLOW_THRESHOLD - 1 {
+            state.record(SLOW, BUDGET);
+        }
+        assert_eq!(state.profile, Profile::Full);
+        assert_eq!(state.samples.len(), WINDOW);
+
+        state.record(SLOW, BUDGET);
+        assert_eq!(state.profile, Profile::Degraded);
+        assert!(state.samples.is_empty());
+    }
+
+    #[test]
+    fn test_degraded_probes_full_and_roundtrips() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = Cache::new(tmp.path().to_path_buf());
+        assert_eq!(LatencyState::load(&cache), LatencyState::default());
+
+        let mut state = LatencyState {
+            profile: Profile::Degraded,
+            ..LatencyState::default()
+        };
+        for _ in 0..PROBE_AFTER - 1 {
+            state.record(FAST, BUDGET);
+        }
+        state.save(&cache);
+        let mut loaded = LatencyState::load(&cache);
+        assert_eq!(loaded, state);
+
+        loaded.record(FAST, BUDGET);
+        assert_eq!(loaded.profile, Profile::Full);
+    }
+}
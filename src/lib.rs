@@ -0,0 +1,151 @@
+//! jj-starship - Unified Git/JJ Starship prompt module
+//!
+//! Besides the binary, the crate exposes the repo state it renders:
+//!
+//! ```no_run
+//! use jj_starship::{Config, PromptData, RepoStatus};
+//! use std::path::Path;
+//!
+//! if let Some(status) = RepoStatus::gather(Path::new("."), &Config::default()) {
+//!     if let PromptData::Jj(info) = &status.info {
+//!         println!("{} {:?}", info.change_id, info.bookmarks);
+//!     }
+//! }
+//! ```
+
+pub mod batch;
+pub mod bench;
+mod budget;
+pub mod cache;
+pub mod check;
+mod checks;
+mod clone;
+mod color;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod detect;
+mod diff_stat;
+mod error;
+pub mod exit;
+pub mod export;
+mod format;
+pub mod get;
+#[cfg(feature = "git")]
+pub mod git;
+pub mod init;
+pub mod jj;
+pub mod json;
+pub mod latency;
+pub mod output;
+mod palette;
+mod proquint;
+#[cfg(feature = "python")]
+mod python;
+pub mod serve;
+#[cfg(test)]
+mod snapshot_tests;
+pub mod sources;
+mod status;
+mod style;
+pub mod symbols;
+mod tag;
+mod template;
+pub mod timing;
+pub mod trust;
+pub mod watch;
+
+pub use config::Config;
+#[cfg(feature = "git")]
+pub use git::GitInfo;
+pub use jj::JjInfo;
+pub use output::{Capabilities, PromptData};
+pub use status::RepoStatus;
+
+use cache::Cache;
+use detect::RepoType;
+use exit::Failure;
+use latency::{LatencyState, Profile};
+use std::borrow::Cow;
+use std::path::Path;
+use std::time::Instant;
+use timing::Timings;
+
+/// Run prompt generation, returning None on error (silent fail for prompts)
+#[must_use]
+pub fn run_prompt(cwd: &Path, config: &Config, caps: Capabilities) -> Option<String> {
+    try_run_prompt(cwd, config, caps).ok()
+}
+
+/// Run prompt generation, telling why there is no prompt
+///
+/// # Errors
+///
+/// `cwd` is in no repo, or the repo couldn't be read
+pub fn try_run_prompt(cwd: &Path, config: &Config, caps: Capabilities) -> Result<String, Failure> {
+    let start = Instant::now();
+    let result = detect::detect(cwd, config.discovery);
+    if result.repo_type == RepoType::None {
+        return Err(Failure::NotARepo);
+    }
+
+    // With a latency budget, the repo's learned profile picks the segments
+    let learned = config
+        .latency_budget
+        .zip(result.cache_dir())
+        .map(|(budget, dir)| {
+            let cache = Cache::new(dir);
+            let state = LatencyState::load(&cache);
+            (budget, cache, state)
+        });
+    let mut config = Cow::Borrowed(config);
+    if learned
+        .as_ref()
+        .is_some_and(|(_, _, state)| state.profile == Profile::Degraded)
+    {
+        config.to_mut().degrade();
+    }
+
+    let data = collect(&result, &config).ok_or(Failure::LoadFailed)?;
+    let output = output::render(&data, &config, caps);
+
+    if let Some((budget, cache, mut state)) = learned {
+        state.record(start.elapsed(), budget);
+        state.save(&cache);
+    }
+    Ok(output)
+}
+
+/// Collect the state of the detected repo
+#[must_use]
+pub fn collect(result: &detect::DetectResult, config: &Config) -> Option<PromptData> {
+    collect_timed(result, config, &Timings::default())
+}
+
+/// `collect`, recording the time of its phases in `timings`
+#[allow(unreachable_patterns)]
+pub fn collect_timed(
+    result: &detect::DetectResult,
+    config: &Config,
+    timings: &Timings,
+) -> Option<PromptData> {
+    match result.repo_type {
+        RepoType::Jj | RepoType::JjColocated => {
+            let repo_root = result.repo_root.as_ref()?;
+            jj::collect_timed(repo_root, config, timings)
+                .ok()
+                .map(PromptData::Jj)
+        }
+        #[cfg(feature = "git")]
+        RepoType::Git => {
+            let repo_root = result.repo_root.as_ref()?;
+            timings
+                .time("collect", || git::collect(repo_root, config))
+                .ok()
+                .map(PromptData::Git)
+        }
+        RepoType::None => None,
+        // Catch disabled variants
+        _ => None,
+    }
+}
@@ -1,22 +1,34 @@
 //! jj-starship - Unified Git/JJ Starship prompt module
 
-mod color;
-mod config;
-mod detect;
-mod error;
+#[cfg(unix)]
+use jj_starship::daemon;
 #[cfg(feature = "git")]
-mod git;
-mod jj;
-mod output;
+use jj_starship::git;
+use jj_starship::{
+    batch, bench, cache, check, collect, config, detect, exit, export, get, init, jj, json,
+    latency, output, serve, sources, symbols, timing, trust, try_run_prompt, watch,
+};
 
+use batch::BatchFormat;
+use cache::{Cache, Deferred};
+use check::Check;
 #[cfg(feature = "git")]
 use clap::Args;
-use clap::{Parser, Subcommand};
-use config::{Config, DisplayFlags};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{ChangeIdEncoding, CiProfile, Config, ConfigArgs, DisplayFlags, SshProfile};
 use detect::RepoType;
+use export::OutputMode;
+use init::InitShell;
+use latency::{LatencyState, Profile};
+use output::{Capabilities, Shell};
+use sources::Sources;
 use std::env;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::time::Duration;
+use symbols::SymbolSet;
+use trust::TrustStore;
 
 #[derive(Parser)]
 #[command(name = "jj-starship")]
@@ -31,6 +43,58 @@ struct Cli {
     #[arg(long, global = true)]
     cwd: Option<PathBuf>,
 
+    /// Named profile: the `[profiles.<NAME>]` tables of the config files
+    /// override their top-level keys; `minimal` also leaves only the core
+    /// segments, e.g. for a right prompt
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Read directories from stdin, one per line, and print the prompt for
+    /// each on its own line (relative ones are resolved against --cwd)
+    #[arg(long)]
+    stdin: bool,
+
+    /// Line format of --stdin results
+    #[arg(long, value_enum, default_value_t, requires = "stdin")]
+    stdin_format: BatchFormat,
+
+    /// Print the prompt, `JJ_*` shell variable assignments to eval (quoted
+    /// for --shell), or the repo state as JSON
+    #[arg(long, value_enum, default_value_t, conflicts_with = "stdin")]
+    output: OutputMode,
+
+    /// Print the collected repo state as a JSON object (same as --output json)
+    #[arg(long, conflicts_with_all = ["stdin", "output"])]
+    json: bool,
+
+    /// Print the prompt, exiting 1 outside a repo, 3 if the repo can't be
+    /// read and 4 if it takes over twice --timeout-ms, never writing to stderr
+    /// (2 is a usage error)
+    #[arg(long, conflicts_with_all = ["stdin", "output", "json"])]
+    quiet: bool,
+
+    /// Like --quiet, but print nothing (for starship's `when`)
+    #[arg(long, conflicts_with_all = ["stdin", "output", "json", "quiet"])]
+    when: bool,
+
+    /// Print the prompt, and to stderr the time spent loading the repo, on
+    /// each segment and rendering
+    #[arg(long, conflicts_with_all = ["stdin", "output", "json", "quiet", "when"])]
+    debug_timing: bool,
+
+    /// Print nothing; exit 0 if the working copy has conflicts (with other
+    /// check flags, if all of them hold)
+    #[arg(long, conflicts_with_all = ["stdin", "output", "json"])]
+    has_conflicts: bool,
+
+    /// Print nothing; exit 0 if the working copy makes no changes
+    #[arg(long, conflicts_with_all = ["stdin", "output", "json"])]
+    is_empty: bool,
+
+    /// Print nothing; exit 0 if the workspace is stale (JJ only)
+    #[arg(long, conflicts_with_all = ["stdin", "output", "json"])]
+    is_stale: bool,
+
     /// Max length for branch/bookmark name (0 = unlimited)
     #[arg(long, global = true)]
     truncate_name: Option<usize>,
@@ -39,10 +103,36 @@ struct Cli {
     #[arg(long, global = true)]
     id_length: Option<usize>,
 
+    /// Highlight at least N characters of the change id, even where a shorter
+    /// prefix is unique (default: just the unique prefix)
+    #[arg(long, global = true, value_name = "N")]
+    id_prefix_length: Option<usize>,
+
+    /// Spelling of change ids: `reverse-hex` (default, as in jj) or `proquint`
+    /// pronounceable words like `bahog-hijol`
+    #[arg(long, global = true, value_enum)]
+    change_id_encoding: Option<ChangeIdEncoding>,
+
+    /// Color the change id by a hash of it, so different changes stand apart
+    #[arg(long, global = true)]
+    hash_color: bool,
+
+    /// Color each bookmark by a hash of its name, so bookmarks stand apart
+    #[arg(long, global = true)]
+    hash_bookmark_colors: bool,
+
     /// Max depth to search for ancestor bookmarks (0 = disabled, default: 10)
     #[arg(long, global = true)]
     ancestor_bookmark_depth: Option<usize>,
 
+    /// Max depth to search for a descendant bookmark when @ is behind one (0 = disabled, default: 10)
+    #[arg(long, global = true)]
+    descendant_bookmark_depth: Option<usize>,
+
+    /// Max bookmarks shown, the rest are counted as `+N` (0 = unlimited)
+    #[arg(long, global = true)]
+    max_bookmarks: Option<usize>,
+
     /// Symbol prefix for JJ repos (default: "󱗆")
     #[arg(long, global = true)]
     jj_symbol: Option<String>,
@@ -51,10 +141,51 @@ struct Cli {
     #[arg(long, global = true)]
     no_symbol: bool,
 
+    /// Glyphs of the prompt: `nerd-font` (default), `unicode` (no Nerd Font
+    /// repo icons) or `ascii`
+    #[arg(long, global = true, value_enum, value_name = "SET")]
+    symbols: Option<SymbolSet>,
+
+    /// Glyph replacing one of the set, e.g. `ahead=↑` or `ellipsis=...`
+    /// (repeatable)
+    #[arg(long, global = true, value_name = "NAME=TEXT")]
+    symbol: Vec<String>,
+
     /// Disable output styling
     #[arg(long, global = true)]
     no_color: bool,
 
+    /// Look for the repo from the working directory as given, without
+    /// resolving symlinks
+    #[arg(long, global = true)]
+    no_resolve_symlinks: bool,
+
+    /// Look for the repo at most N parent directories above the working
+    /// directory (0: only the working directory itself)
+    #[arg(long, global = true, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Keep looking for the repo in parent directories on other filesystems
+    #[arg(long, global = true)]
+    cross_filesystems: bool,
+
+    /// Don't mark shallow and partial clones
+    #[arg(long, global = true)]
+    no_clone_marker: bool,
+
+    /// Hide the name of JJ workspaces other than `default`
+    #[arg(long, global = true)]
+    no_workspace: bool,
+
+    /// Don't warn about concurrent JJ operations left to merge
+    #[arg(long, global = true)]
+    no_op_heads: bool,
+
+    /// Don't read or write the on-disk cache of segments and jj config, e.g.
+    /// to rule out stale values
+    #[arg(long, global = true)]
+    no_cache: bool,
+
     // JJ display flags
     /// Hide "on {symbol}" prefix for JJ repos
     #[arg(long, global = true)]
@@ -72,6 +203,233 @@ struct Cli {
     #[arg(long, global = true)]
     no_prefix_color: bool,
 
+    /// Show added/removed line counts of the working copy
+    #[arg(long, global = true)]
+    diffstat: bool,
+    /// Render line counts as a log-scaled block bar (implies --diffstat)
+    #[arg(long, global = true)]
+    diffstat_bar: bool,
+    /// Number of blocks in the diffstat bar (default: 5, max: 20)
+    #[arg(long, global = true)]
+    diffstat_bar_width: Option<usize>,
+
+    /// Show the first line of the working copy description (JJ only)
+    #[arg(long, global = true)]
+    description: bool,
+    /// Max width of the description, cut with an ellipsis (default: 0 = unlimited)
+    #[arg(long, global = true, value_name = "N")]
+    description_width: Option<usize>,
+    /// Shown for an empty description, empty for nothing (default: `(no description)`)
+    #[arg(long, global = true, value_name = "TEXT")]
+    description_empty: Option<String>,
+    /// Regex marking a description as work in progress, repeatable
+    /// (default: wip, fixup!, squash!, amend! at the start)
+    #[arg(long = "wip-pattern", global = true, value_name = "REGEX")]
+    wip_patterns: Vec<String>,
+    /// Show the value of a description trailer, e.g. `Fixes` or `Change-Id` (repeatable)
+    #[arg(long = "trailer", global = true, value_name = "KEY")]
+    trailers: Vec<String>,
+
+    /// Show the ticket id (e.g. ABC-123, #1234) from the nearest bookmark or description
+    #[arg(long, global = true)]
+    issue_key: bool,
+    /// Regex for ticket ids, repeatable; group 1 is the key if present
+    /// (default: ABC-123 and #1234 styles)
+    #[arg(long = "issue-pattern", global = true, value_name = "REGEX")]
+    issue_patterns: Vec<String>,
+
+    /// Mark whether the working copy description follows conventional commits (JJ only)
+    #[arg(long, global = true)]
+    conventional: bool,
+    /// Regex a conventional first line matches
+    /// (default: `type(scope)!: subject` with the Angular types)
+    #[arg(long, global = true, value_name = "REGEX")]
+    conventional_pattern: Option<String>,
+
+    /// Show how many local bookmarks have no tracked remote bookmark, i.e. were never pushed
+    #[arg(long, global = true)]
+    unpushed_bookmarks: bool,
+
+    /// Show how many visible heads outside trunk have no bookmark (forgotten work)
+    #[arg(long, global = true)]
+    anonymous_heads: bool,
+
+    /// Show how many other heads are built on trunk besides @'s stack, e.g. `⌥4`
+    #[arg(long, global = true)]
+    stacks: bool,
+
+    /// Show how many commits @ is ahead of `trunk()` and `trunk()` is past
+    /// their fork point, e.g. `⇡3 ⇣1`
+    #[arg(long, global = true)]
+    trunk_distance: bool,
+
+    /// Show how many commits the closest bookmark is ahead of and behind the
+    /// remote tracking it, e.g. `origin ⇡2`
+    #[arg(long, global = true)]
+    remote_distance: bool,
+
+    /// Show how many files are staged in a colocated git index, where `git
+    /// commit` and jj see different changes
+    #[arg(long, global = true)]
+    git_staged: bool,
+
+    /// Show the branch a colocated git HEAD is on, and whether git and jj
+    /// need `jj git import`/`export` to agree
+    #[arg(long, global = true)]
+    git_head: bool,
+
+    /// Show a red `✖` with the number of conflicted files when the working copy
+    /// has conflicts
+    #[arg(long, global = true)]
+    conflict_marker: bool,
+
+    /// Show the files added, modified and deleted by the working copy, e.g.
+    /// `+3 ~2 -1` (JJ only, cached per commit, honors `--timeout-ms`)
+    #[arg(long, global = true)]
+    file_changes: bool,
+
+    /// Warn when the JJ working copy is immutable or sits on an immutable
+    /// head other than trunk, by the user's `immutable_heads()`
+    #[arg(long, global = true)]
+    immutable: bool,
+
+    /// Show the number of files in the working copy that are neither tracked
+    /// nor ignored, e.g. `?3` (JJ only, scans the working copy)
+    #[arg(long, global = true)]
+    untracked: bool,
+
+    /// Show a green `(empty)` when the working copy makes no changes to its
+    /// parents, as in `jj log` (JJ only)
+    #[arg(long, global = true)]
+    empty_commit: bool,
+
+    /// Mark conflicts in mutable ancestors or descendants of @ with `×` in the status
+    #[arg(long, global = true)]
+    stack_conflicts: bool,
+
+    /// Verify the signature of @ with jj's signing backends: ✓ good, ? unknown key, ✗ bad
+    #[arg(long, global = true)]
+    signature: bool,
+
+    /// Like --signature, and show `unsigned` when the commit isn't signed
+    #[arg(long, global = true)]
+    warn_unsigned: bool,
+
+    /// Show the bookmarks a merge at @ (or its parent) combines, e.g. `⊕{feat-a,feat-b,+2}`
+    #[arg(long, global = true)]
+    megamerge: bool,
+
+    /// Show the change id and bookmark of @'s parent, e.g. `on main@kmqp`, or
+    /// of each parent of a merge, e.g. `on ⊕{main@kmqp,zsvn}`
+    #[arg(long, global = true)]
+    parent: bool,
+
+    /// Show a jj template expression evaluated against @, e.g.
+    /// `separate(" ", bookmarks, description.first_line())` (runs `jj`, cached per operation)
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    template: Option<String>,
+
+    /// Show CI checks of the nearest pushed bookmark (GitHub, via the `gh` CLI)
+    #[arg(long, global = true)]
+    checks: bool,
+
+    /// Show the nearest ancestor tag
+    #[arg(long, global = true)]
+    nearest_tag: bool,
+
+    /// Also show commits since the nearest tag, e.g. `v1.2.0+5` (implies --nearest-tag)
+    #[arg(long, global = true)]
+    tag_distance: bool,
+
+    /// Show version tags as short semver, e.g. `v1.2.0` as `1.2` (implies --nearest-tag)
+    #[arg(long, global = true)]
+    tag_semver: bool,
+
+    /// Show files/lines changed by the whole stack since it forked off BASE
+    /// (a revset, default `trunk()`)
+    #[arg(
+        long,
+        global = true,
+        value_name = "BASE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = config::DEFAULT_STACK_DIFF_BASE
+    )]
+    stack_diff: Option<String>,
+
+    /// Revset to treat as `trunk()` for trunk distance, anonymous heads, the
+    /// stack diff base and where bookmark search stops, e.g.
+    /// `bookmarks(exact:develop)` (JJ only; default: jj's `trunk()`)
+    #[arg(long, global = true, value_name = "REVSET")]
+    trunk_revset: Option<String>,
+
+    /// Compute expensive segments (diffstat, ahead/behind) in the background,
+    /// showing a placeholder until the next prompt
+    #[arg(long = "async", global = true)]
+    async_segments: bool,
+
+    /// Ask a running `jj-starship daemon` for the prompt, rendering it here
+    /// if none answers (unix only)
+    #[arg(long, global = true)]
+    daemon: bool,
+
+    /// Snapshot the working copy before reading it, as `jj status` does, so
+    /// file edits show without running jj (takes the working-copy lock)
+    #[arg(long, global = true)]
+    snapshot: bool,
+
+    /// Prompt latency budget in ms; repos that consistently exceed it switch
+    /// to a degraded profile (0 = disabled, default)
+    #[arg(long, global = true, value_name = "MS")]
+    latency_budget: Option<u64>,
+
+    /// Time budget in ms of the expensive segments (diffstat, ahead/behind,
+    /// tags); ones that don't fit render `…` (0 = no timeout, default)
+    #[arg(long, global = true, value_name = "MS")]
+    timeout_ms: Option<u64>,
+
+    /// Prompt layout with `$name` segment variables, e.g. `$change_id$fill$status`
+    #[arg(long, global = true)]
+    format: Option<String>,
+
+    /// Use the colors of the active palette in starship.toml (`$STARSHIP_CONFIG`
+    /// or `~/.config/starship.toml`) for the built-in colors it redefines
+    #[arg(long, global = true)]
+    starship_palette: bool,
+
+    /// Style of a segment as a starship style string, e.g.
+    /// `change_id=bold purple` or `bookmarks=fg:#ff9e64`, replacing its
+    /// built-in colors (repeatable)
+    #[arg(long, global = true, value_name = "SEGMENT=STYLE")]
+    style: Vec<String>,
+
+    /// Profile in SSH sessions: `light` (default) degrades segments and drops
+    /// the Nerd Font symbols, `full` behaves like a local session
+    #[arg(long, global = true, value_enum)]
+    ssh_profile: Option<SshProfile>,
+
+    /// Profile under CI (`CI`, `GITHUB_ACTIONS`, ... set): `minimal` (default)
+    /// renders plain core segments without the cache, `full` behaves as usual
+    #[arg(long, global = true, value_enum)]
+    ci_profile: Option<CiProfile>,
+
+    /// Extra jj config as TOML, layered over user and repo config like
+    /// jj's `--config-toml` (repeatable)
+    #[arg(long, global = true, value_name = "TOML")]
+    config_toml: Vec<String>,
+
+    /// Terminal width in columns; segments are dropped to fit and `$fill` pads to it
+    #[arg(long, global = true, value_name = "COLUMNS")]
+    width: Option<usize>,
+
+    /// Escape output for embedding in this shell's prompt directly (PS1/PROMPT)
+    #[arg(long, global = true, value_enum)]
+    shell: Option<Shell>,
+
+    /// Abbreviate counts at or above N, e.g. 1.2k (0 = never, default: 1000)
+    #[arg(long, global = true, value_name = "N")]
+    abbreviate_counts: Option<usize>,
+
     #[cfg(feature = "git")]
     #[command(flatten)]
     git: GitArgs,
@@ -106,93 +464,685 @@ enum Command {
     Detect,
     /// Print version and build info
     Version,
+    /// Print one field of the repo state, unstyled (exit 1 if it doesn't apply)
+    Get {
+        #[arg(value_enum)]
+        field: get::Field,
+    },
+    /// Show the detected repo and its learned latency profile
+    Doctor,
+    /// Output a complete prompt line (directory, repo info, prompt character)
+    /// for use without starship
+    Standalone {
+        /// Exit status of the last command; non-zero turns the prompt character red
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        status: i32,
+        /// Render a placeholder for the repo info without reading the repo
+        #[arg(long)]
+        placeholder: bool,
+    },
+    /// Print a script that sets the shell prompt to `standalone`, e.g.
+    /// `eval "$(jj-starship init bash)"`. With `--async` (zsh only), the repo
+    /// info renders in a background job behind a placeholder. With
+    /// `--starship`, print the starship.toml block for the shell instead.
+    Init {
+        /// Shell to set the prompt of
+        // Its own id, apart from the global `--shell`
+        #[arg(value_enum, id = "init_shell", value_name = "SHELL")]
+        shell: InitShell,
+        /// Print the `[custom.jj]` block for starship.toml instead
+        #[arg(long)]
+        starship: bool,
+    },
+    /// Keep printing the prompt whenever it changes, one line per update (for
+    /// tmux status lines, editor panes and widgets)
+    Watch {
+        /// Seconds between re-renders (fractions allowed)
+        #[arg(long, default_value = "2", value_parser = parse_interval)]
+        interval: Duration,
+        /// Also re-render as soon as the repo's metadata changes
+        #[arg(long)]
+        events: bool,
+    },
+    /// Render the prompt repeatedly and report min/median/p95 per phase
+    Bench {
+        /// Number of renders
+        #[arg(long, default_value_t = 20)]
+        runs: usize,
+    },
+    /// Serve prompt data as JSON on localhost: `GET /prompt?path=<dir>`
+    Serve {
+        /// Port to listen on (127.0.0.1 only)
+        #[arg(long, default_value_t = serve::DEFAULT_PORT)]
+        port: u16,
+        /// Origin allowed to read responses in a browser (CORS), e.g.
+        /// `http://localhost:3000` or `*`
+        #[arg(long)]
+        allow_origin: Option<String>,
+    },
+    /// Keep answering prompt queries from `--daemon` over a unix socket,
+    /// re-reading a repo only when its metadata changes
+    #[cfg(unix)]
+    Daemon {
+        /// Socket to listen on (default: `$JJ_STARSHIP_SOCKET`, else in
+        /// `$XDG_RUNTIME_DIR` or the temp dir)
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Manage the repos trusted to configure jj-starship themselves
+    Trust {
+        #[command(subcommand)]
+        action: TrustAction,
+    },
+    /// Compute expensive segments into the cache (spawned by --async)
+    #[command(hide = true)]
+    Refresh,
+}
+
+#[derive(Subcommand)]
+enum TrustAction {
+    /// Trust the repo containing PATH (default: the current directory), or
+    /// every repo with `*`
+    Add { path: Option<PathBuf> },
+    /// Stop trusting the repo containing PATH (default: the current directory)
+    Remove { path: Option<PathBuf> },
+    /// List the trusted repos
+    List,
+}
+
+/// What the `prompt` command prints
+enum PromptMode {
+    /// The prompt
+    Render,
+    /// A prompt line for each directory read from stdin (`--stdin`)
+    Batch(BatchFormat),
+    /// Shell variable assignments (`--output env`)
+    Env,
+    /// The repo state as JSON (`--json`)
+    Json,
+    /// Nothing, answering with the exit code (`--has-conflicts`, ...)
+    Check(Vec<Check>),
+    /// The prompt, or nothing with `--when`, failing with an exit code that
+    /// tells why (`--quiet`)
+    Quiet { print: bool },
+    /// The prompt, with the time of each phase on stderr (`--debug-timing`)
+    Timing,
+}
+
+impl Cli {
+    fn prompt_mode(&self) -> PromptMode {
+        let checks: Vec<Check> = [
+            (self.has_conflicts, Check::HasConflicts),
+            (self.is_empty, Check::IsEmpty),
+            (self.is_stale, Check::IsStale),
+        ]
+        .into_iter()
+        .filter_map(|(set, check)| set.then_some(check))
+        .collect();
+        if !checks.is_empty() {
+            PromptMode::Check(checks)
+        } else if self.debug_timing {
+            PromptMode::Timing
+        } else if self.quiet || self.when {
+            PromptMode::Quiet { print: !self.when }
+        } else if self.stdin {
+            PromptMode::Batch(self.stdin_format)
+        } else if self.json || self.output == OutputMode::Json {
+            PromptMode::Json
+        } else if self.output == OutputMode::Env {
+            PromptMode::Env
+        } else {
+            PromptMode::Render
+        }
+    }
+
+    /// Config options, to be resolved against env vars by `Config::new`
+    fn into_config_args(self) -> ConfigArgs {
+        let jj_flags = DisplayFlags {
+            no_prefix: self.no_jj_prefix,
+            no_name: self.no_jj_name,
+            no_id: self.no_jj_id,
+            no_status: self.no_jj_status,
+            no_color: self.no_color,
+            no_prefix_color: self.no_prefix_color,
+        };
+
+        #[cfg(feature = "git")]
+        let (git_symbol, git_flags) = (
+            self.git.git_symbol,
+            DisplayFlags {
+                no_prefix: self.git.no_git_prefix,
+                no_name: self.git.no_git_name,
+                no_id: self.git.no_git_id,
+                no_status: self.git.no_git_status,
+                no_color: self.no_color,
+                no_prefix_color: false, // N/A for git
+            },
+        );
+        #[cfg(not(feature = "git"))]
+        let (git_symbol, git_flags): (Option<String>, DisplayFlags) =
+            (None, DisplayFlags::default());
+
+        ConfigArgs {
+            truncate_name: self.truncate_name,
+            id_length: self.id_length,
+            id_prefix_length: self.id_prefix_length,
+            change_id_encoding: self.change_id_encoding,
+            hash_color: self.hash_color,
+            hash_bookmark_colors: self.hash_bookmark_colors,
+            ancestor_bookmark_depth: self.ancestor_bookmark_depth,
+            descendant_bookmark_depth: self.descendant_bookmark_depth,
+            max_bookmarks: self.max_bookmarks,
+            jj_symbol: self.jj_symbol,
+            git_symbol,
+            no_symbol: self.no_symbol,
+            symbol_set: self.symbols,
+            symbols: self.symbol,
+            jj_flags,
+            git_flags,
+            diff_stat: self.diffstat,
+            diff_stat_bar: self.diffstat_bar,
+            diff_stat_bar_width: self.diffstat_bar_width,
+            description: self.description,
+            description_width: self.description_width,
+            description_empty: self.description_empty,
+            wip_patterns: self.wip_patterns,
+            trailers: self.trailers,
+            issue_key: self.issue_key,
+            issue_patterns: self.issue_patterns,
+            unpushed_bookmarks: self.unpushed_bookmarks,
+            anonymous_heads: self.anonymous_heads,
+            stacks: self.stacks,
+            trunk_distance: self.trunk_distance,
+            remote_distance: self.remote_distance,
+            git_staged: self.git_staged,
+            git_head: self.git_head,
+            conflict_marker: self.conflict_marker,
+            empty_commit: self.empty_commit,
+            file_changes: self.file_changes,
+            untracked: self.untracked,
+            immutable: self.immutable,
+            stack_conflicts: self.stack_conflicts,
+            signature: self.signature,
+            warn_unsigned: self.warn_unsigned,
+            megamerge: self.megamerge,
+            parent: self.parent,
+            template: self.template,
+            checks: self.checks,
+            stack_diff: self.stack_diff,
+            trunk_revset: self.trunk_revset,
+            nearest_tag: self.nearest_tag,
+            tag_distance: self.tag_distance,
+            tag_semver: self.tag_semver,
+            conventional: self.conventional,
+            conventional_pattern: self.conventional_pattern,
+            async_segments: self.async_segments,
+            daemon: self.daemon,
+            snapshot: self.snapshot,
+            latency_budget: self.latency_budget,
+            timeout_ms: self.timeout_ms,
+            abbreviate_counts: self.abbreviate_counts,
+            format: self.format,
+            starship_palette: self.starship_palette,
+            styles: self.style,
+            jj_config_toml: self.config_toml,
+            ssh_profile: self.ssh_profile,
+            ci_profile: self.ci_profile,
+            no_resolve_symlinks: self.no_resolve_symlinks,
+            max_depth: self.max_depth,
+            cross_filesystems: self.cross_filesystems,
+            no_clone_marker: self.no_clone_marker,
+            no_workspace: self.no_workspace,
+            no_op_heads: self.no_op_heads,
+            no_cache: self.no_cache,
+        }
+    }
 }
 
 fn main() -> ExitCode {
-    let cli = Cli::parse();
-    let Some(cwd) = cli.cwd.or_else(|| env::current_dir().ok()) else {
+    let mut cli = Cli::parse();
+    // A deleted or unreadable cwd has no path; the empty path is then in no
+    // repo, so the prompt is empty but other commands still work
+    let cwd = cli
+        .cwd
+        .take()
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_default();
+    let command = cli.command.take().unwrap_or(Command::Prompt);
+    let mode = cli.prompt_mode();
+    // `init --async` asks for the async script; the env var must not
+    let async_flag = cli.async_segments;
+    let (width, shell) = (cli.width, cli.shell);
+    let profile = cli.profile.take();
+    let args = cli.into_config_args();
+    let sources = Sources::load(&cwd, profile.clone(), &args);
+    let caps = Capabilities {
+        width: width.or_else(|| sources.var("JJ_STARSHIP_WIDTH")?.parse().ok()),
+        shell: shell
+            .or_else(|| Shell::from_str(&sources.var("JJ_STARSHIP_SHELL")?, true).ok())
+            .unwrap_or_default(),
+        ..Capabilities::default()
+    };
+    // The daemon resolves the options again for each directory it's asked for
+    #[cfg(unix)]
+    let daemon_args = matches!(command, Command::Daemon { .. }).then(|| args.clone());
+    let mut config = Config::new(args, &sources);
+    apply_environment_profiles(&mut config);
+    let cwd = detect::start_dir(cwd, config.resolve_symlinks);
+
+    match command {
+        Command::Prompt => print_prompt(&cwd, &config, caps, mode),
+        Command::Detect => {
+            if detect::in_repo(&cwd, config.discovery) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Command::Get { field } => print_field(&cwd, &config, field),
+        Command::Version => {
+            print_version();
+            ExitCode::SUCCESS
+        }
+        Command::Doctor => {
+            print_doctor(&cwd, &config, &sources);
+            ExitCode::SUCCESS
+        }
+        Command::Standalone {
+            status,
+            placeholder,
+        } => {
+            let result = detect::detect(&cwd, config.discovery);
+            let data = if placeholder {
+                (result.repo_type != RepoType::None).then_some(Deferred::Pending)
+            } else {
+                collect(&result, &config).map(Deferred::Ready)
+            };
+            print!(
+                "{}",
+                output::render_standalone(
+                    &display_dir(&cwd),
+                    data.as_ref().map(Deferred::as_ref),
+                    &config,
+                    caps,
+                    status == 0
+                )
+            );
+            ExitCode::SUCCESS
+        }
+        Command::Init { shell, starship } => print_init(shell, starship, async_flag),
+        Command::Watch { interval, events } => {
+            watch::run(&cwd, &config, caps, interval, events);
+            ExitCode::SUCCESS
+        }
+        Command::Bench { runs } => {
+            if bench::run(&cwd, &config, caps, runs) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Command::Serve { port, allow_origin } => {
+            run_serve(&cwd, &config, caps, port, allow_origin.as_deref())
+        }
+        #[cfg(unix)]
+        Command::Daemon { socket } => {
+            run_daemon(socket, &daemon_args.unwrap_or_default(), profile.as_deref())
+        }
+        Command::Trust { action } => run_trust(&cwd, action),
+        Command::Refresh => {
+            // The background half always computes synchronously and to the end
+            config.async_segments = false;
+            config.timeout = None;
+            if run_refresh(&cwd, &config).is_some() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+/// Print the shell integration script for `init`
+fn print_init(shell: InitShell, starship: bool, async_flag: bool) -> ExitCode {
+    let exe = env::current_exe().unwrap_or_else(|_| PathBuf::from("jj-starship"));
+    if starship {
+        print!("{}", init::starship_config(shell, &exe));
+        return ExitCode::SUCCESS;
+    }
+    let script = shell.prompt_shell().map_or(
+        Err("the standalone prompt needs bash, zsh or fish (use --starship)"),
+        |shell| init::script(shell, &exe, async_flag),
+    );
+    match script {
+        Ok(script) => {
+            print!("{script}");
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("jj-starship: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Serve prompt data on `port` until the listener fails
+fn run_serve(
+    cwd: &Path,
+    config: &Config,
+    caps: Capabilities,
+    port: u16,
+    allow_origin: Option<&str>,
+) -> ExitCode {
+    match serve::run(cwd, config, caps, port, allow_origin) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("jj-starship: serve on port {port}: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Answer `--daemon` prompts on `socket` until the listener fails
+#[cfg(unix)]
+fn run_daemon(socket: Option<PathBuf>, args: &ConfigArgs, profile: Option<&str>) -> ExitCode {
+    let socket = socket.unwrap_or_else(daemon::socket_path);
+    let config_for = |dir: &Path| {
+        let sources = Sources::load(dir, profile.map(String::from), args);
+        let mut config = Config::new(args.clone(), &sources);
+        apply_environment_profiles(&mut config);
+        config
+    };
+    match daemon::run(&socket, config_for) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("jj-starship: daemon on {}: {e}", socket.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run a `trust` subcommand
+fn run_trust(cwd: &Path, action: TrustAction) -> ExitCode {
+    let Some(store) = TrustStore::open() else {
+        eprintln!("jj-starship: no home directory to keep the trust file in");
         return ExitCode::FAILURE;
     };
-    let jj_symbol = cli.jj_symbol;
-    let jj_flags = DisplayFlags {
-        no_prefix: cli.no_jj_prefix,
-        no_name: cli.no_jj_name,
-        no_id: cli.no_jj_id,
-        no_status: cli.no_jj_status,
-        no_color: cli.no_color,
-        no_prefix_color: cli.no_prefix_color,
+    let root = |path: Option<PathBuf>| match path {
+        Some(path) if path.as_os_str() == "*" => path,
+        path => trust::repo_root_of(&path.map_or_else(|| cwd.to_path_buf(), |path| cwd.join(path))),
+    };
+    let result = match action {
+        TrustAction::Add { path } => {
+            let root = root(path);
+            store.add(&root).map(|added| {
+                let state = if added { "trusted" } else { "already trusted" };
+                println!("{state}: {}", root.display());
+            })
+        }
+        TrustAction::Remove { path } => {
+            let root = root(path);
+            store.remove(&root).map(|removed| {
+                let state = if removed { "untrusted" } else { "not trusted" };
+                println!("{state}: {}", root.display());
+            })
+        }
+        TrustAction::List => {
+            for entry in store.list() {
+                println!("{entry}");
+            }
+            Ok(())
+        }
     };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("jj-starship: {}: {e}", store.path().display());
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    #[cfg(feature = "git")]
-    let (git_symbol, git_flags) = (
-        cli.git.git_symbol,
-        DisplayFlags {
-            no_prefix: cli.git.no_git_prefix,
-            no_name: cli.git.no_git_name,
-            no_id: cli.git.no_git_id,
-            no_status: cli.git.no_git_status,
-            no_color: cli.no_color,
-            no_prefix_color: false, // N/A for git
-        },
-    );
-    #[cfg(not(feature = "git"))]
-    let (git_symbol, git_flags): (Option<String>, DisplayFlags) = (None, DisplayFlags::default());
-
-    let config = Config::new(
-        cli.truncate_name,
-        cli.id_length,
-        cli.ancestor_bookmark_depth,
-        jj_symbol,
-        git_symbol,
-        cli.no_symbol,
-        jj_flags,
-        git_flags,
-    );
+/// Parse a positive number of seconds, e.g. `0.5`
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!("expected a positive number of seconds, got `{s}`")),
+    }
+}
+
+/// `cwd` for display, with the home directory shortened to `~`
+fn display_dir(cwd: &Path) -> String {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    match home.as_deref().and_then(|home| cwd.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => cwd.display().to_string(),
+    }
+}
+
+/// Fill the segment cache for the repo at `cwd`
+#[allow(unreachable_patterns)]
+fn run_refresh(cwd: &Path, config: &Config) -> Option<()> {
+    let result = detect::detect(cwd, config.discovery);
+    let repo_root = result.repo_root?;
+
+    match result.repo_type {
+        RepoType::Jj | RepoType::JjColocated => jj::refresh(&repo_root, config).ok(),
+        #[cfg(feature = "git")]
+        RepoType::Git => git::refresh(&repo_root, config).ok(),
+        _ => None,
+    }
+}
 
-    match cli.command.unwrap_or(Command::Prompt) {
-        Command::Prompt => {
-            if let Some(output) = run_prompt(&cwd, &config) {
+/// Print the prompt for `cwd` in the way `mode` asks for
+fn print_prompt(cwd: &Path, config: &Config, caps: Capabilities, mode: PromptMode) -> ExitCode {
+    match mode {
+        PromptMode::Render => match daemon_or_run_prompt(cwd, config, caps) {
+            Ok(output) => {
                 print!("{output}");
                 ExitCode::SUCCESS
+            }
+            Err(_) => ExitCode::FAILURE,
+        },
+        PromptMode::Quiet { print } => {
+            let timeout = config.timeout;
+            let (cwd, config) = (cwd.to_path_buf(), config.clone());
+            let prompt = move || daemon_or_run_prompt(&cwd, &config, caps);
+            match exit::within(timeout, prompt) {
+                Ok(output) => {
+                    if print {
+                        print!("{output}");
+                    }
+                    ExitCode::SUCCESS
+                }
+                Err(failure) => ExitCode::from(failure.code()),
+            }
+        }
+        PromptMode::Timing => {
+            let (prompt, timings) = timing::run(cwd, config, caps);
+            if let Some(prompt) = &prompt {
+                print!("{prompt}");
+            }
+            eprint!("{}", timings.report());
+            if prompt.is_some() {
+                ExitCode::SUCCESS
             } else {
                 ExitCode::FAILURE
             }
         }
-        Command::Detect => {
-            if detect::in_repo(&cwd) {
+        PromptMode::Batch(format) => {
+            let (stdin, stdout) = (io::stdin().lock(), io::stdout().lock());
+            // A closed stdout just means the reader is gone
+            let _ = batch::run(cwd, config, caps, format, stdin, stdout);
+            ExitCode::SUCCESS
+        }
+        PromptMode::Env => {
+            print!("{}", export::render(cwd, config, caps.shell));
+            ExitCode::SUCCESS
+        }
+        PromptMode::Json => {
+            println!("{}", json::render(cwd, config));
+            ExitCode::SUCCESS
+        }
+        PromptMode::Check(checks) => {
+            if check::all_hold(cwd, config, &checks) {
                 ExitCode::SUCCESS
             } else {
                 ExitCode::FAILURE
             }
         }
-        Command::Version => {
-            print_version();
+    }
+}
+
+/// The prompt from the daemon with `--daemon`, else (or if none answers)
+/// rendered here
+fn daemon_or_run_prompt(
+    cwd: &Path,
+    config: &Config,
+    caps: Capabilities,
+) -> Result<String, exit::Failure> {
+    #[cfg(unix)]
+    if config.daemon {
+        match daemon::query(&daemon::socket_path(), cwd, config, caps) {
+            Some(daemon::Answer::Prompt(prompt)) => return Ok(prompt),
+            Some(daemon::Answer::OutsideRepo) => return Err(exit::Failure::NotARepo),
+            None => {}
+        }
+    }
+    try_run_prompt(cwd, config, caps)
+}
+
+/// Print one field of the repo state for `get`
+fn print_field(cwd: &Path, config: &Config, field: get::Field) -> ExitCode {
+    match get::get(cwd, config, field) {
+        Some(value) => {
+            println!("{value}");
             ExitCode::SUCCESS
         }
+        None => ExitCode::FAILURE,
     }
 }
 
-/// Run prompt generation, returning None on error (silent fail for prompts)
-#[allow(unreachable_patterns)]
-fn run_prompt(cwd: &Path, config: &Config) -> Option<String> {
-    let result = detect::detect(cwd);
+/// Apply the SSH and CI profiles if this process runs in such an environment
+fn apply_environment_profiles(config: &mut Config) {
+    if config.ssh_profile == SshProfile::Light && config::in_ssh_session() {
+        config.lighten();
+    }
+    if config.ci_profile == CiProfile::Minimal && config::in_ci() {
+        config.minimize();
+    }
+}
 
-    match result.repo_type {
-        RepoType::Jj | RepoType::JjColocated => {
-            let repo_root = result.repo_root?;
-            let info =
-                jj::collect(&repo_root, config.id_length, config.ancestor_bookmark_depth).ok()?;
-            Some(output::format_jj(&info, config))
+/// Print the operation a JJ prompt is read at and whether its template works
+fn print_jj_doctor(root: &Path, config: &Config) {
+    let mut light = config.clone();
+    light.minimize();
+    if let Ok(info) = jj::collect(root, &light) {
+        println!("op:      {}", info.operation);
+        if info.op_heads > 1 {
+            println!(
+                "         {} op heads from concurrent operations, merged by the next jj command",
+                info.op_heads
+            );
         }
-        #[cfg(feature = "git")]
-        RepoType::Git => {
-            let repo_root = result.repo_root?;
-            let info = git::collect(&repo_root, config.id_length).ok()?;
-            Some(output::format_git(&info, config))
+    }
+    if let Some(template) = &config.template {
+        match jj::check_template(root, template, config) {
+            Ok(()) => println!("template: ok"),
+            Err(e) => println!("template: {e} (shows nothing)"),
         }
-        RepoType::None => None,
-        // Catch disabled variants
-        _ => None,
+    }
+}
+
+/// Print repo detection and the learned latency state for `cwd`
+fn print_doctor(cwd: &Path, config: &Config, sources: &Sources) {
+    let result = detect::detect(cwd, config.discovery);
+    let files: Vec<String> = sources
+        .paths()
+        .map(|path| path.display().to_string())
+        .collect();
+    if files.is_empty() {
+        println!("config:  none");
+    } else {
+        println!("config:  {}", files.join(", "));
+    }
+    if let Some(profile) = sources.profile() {
+        println!("profile: {profile} (set --profile)");
+    }
+    if config::in_ssh_session() {
+        let profile = match config.ssh_profile {
+            SshProfile::Light => "light",
+            SshProfile::Full => "full",
+        };
+        println!("ssh:     {profile} profile (set --ssh-profile)");
+    }
+    if config::in_ci() {
+        let profile = match config.ci_profile {
+            CiProfile::Minimal => "minimal",
+            CiProfile::Full => "full",
+        };
+        println!("ci:      {profile} profile (set --ci-profile)");
+    }
+    println!("repo:    {:?}", result.repo_type);
+    if let Some(root) = &result.repo_root {
+        println!("root:    {}", root.display());
+        let trusted = TrustStore::open().is_some_and(|store| store.is_trusted(root));
+        println!(
+            "trust:   {} (set with `jj-starship trust`)",
+            if trusted { "trusted" } else { "not trusted" }
+        );
+        if matches!(result.repo_type, RepoType::Jj | RepoType::JjColocated) {
+            print_jj_doctor(root, config);
+        }
+    }
+    let Some(dir) = result.cache_dir() else {
+        return;
+    };
+    if config.cache {
+        println!("cache:   {}", dir.display());
+    } else {
+        println!("cache:   disabled");
+    }
+
+    let Some(budget) = config.latency_budget else {
+        println!("latency: adaptive profiles disabled (set --latency-budget)");
+        return;
+    };
+    let state = LatencyState::load(&Cache::new(dir));
+    println!("budget:  {}ms", budget.as_millis());
+    println!(
+        "profile: {} ({} prompts)",
+        state.profile.as_str(),
+        state.prompts
+    );
+    match state.profile {
+        Profile::Full => println!(
+            "         degrades when {}/{} recent prompts exceed the budget",
+            latency::SLOW_THRESHOLD,
+            latency::WINDOW
+        ),
+        Profile::Degraded => println!(
+            "         async segments, no bookmark walks; retries full after {} prompts",
+            latency::PROBE_AFTER
+        ),
+    }
+    if !state.samples.is_empty() {
+        let samples: Vec<String> = state
+            .samples
+            .iter()
+            .map(|&us| {
+                format!(
+                    "{:.1}",
+                    Duration::from_micros(us as u64).as_secs_f64() * 1000.0
+                )
+            })
+            .collect();
+        println!(
+            "recent:  {} ms ({} over budget)",
+            samples.join(" "),
+            state.over_budget(budget)
+        );
     }
 }
 
@@ -211,6 +1161,10 @@ fn print_version() {
     let mut features: Vec<&str> = Vec::new();
     #[cfg(feature = "git")]
     features.push("git");
+    #[cfg(feature = "serde")]
+    features.push("serde");
+    #[cfg(feature = "python")]
+    features.push("python");
 
     if features.is_empty() {
         println!("features: none");
@@ -218,3 +1172,72 @@ fn print_version() {
         println!("features: {}", features.join(", "));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_exit_codes_distinct_from_usage_errors() {
+        let Err(error) = Cli::try_parse_from(["jj-starship", "--when", "--bogus"]) else {
+            panic!("unknown flag parsed");
+        };
+        let usage = error.exit_code();
+        for failure in [
+            exit::Failure::NotARepo,
+            exit::Failure::LoadFailed,
+            exit::Failure::TimedOut,
+        ] {
+            assert_ne!(i32::from(failure.code()), usage, "{failure:?}");
+        }
+    }
+
+    #[test]
+    fn test_refresh_args() {
+        // A trunk other than jj's changes the stack diff's cache key
+        let by_flag = Config {
+            stack_diff: Some("trunk()".into()),
+            trunk_revset: Some("@".into()),
+            async_segments: true,
+            ..Config::default()
+        };
+        let by_jj_config = Config {
+            trunk_revset: None,
+            jj_config_toml: vec!["revset-aliases.'trunk()' = '@'".into()],
+            ..by_flag.clone()
+        };
+        let settings = jj_lib::config::StackedConfig::with_defaults();
+        let settings = jj_lib::settings::UserSettings::from_config(settings).unwrap();
+        for config in [by_flag, by_jj_config] {
+            let dir = tempfile::tempdir().unwrap();
+            jj_lib::workspace::Workspace::init_simple(&settings, dir.path()).unwrap();
+            let root = dir.path().to_str().unwrap();
+            let args = ["jj-starship", "--cwd", root]
+                .into_iter()
+                .map(String::from)
+                .chain(jj::refresh_args(&config))
+                .chain(["refresh".into()]);
+            let cli = Cli::try_parse_from(args).unwrap();
+            let background = Config::new(cli.into_config_args(), &Sources::default());
+            jj::refresh(dir.path(), &background).unwrap();
+
+            let info = jj::collect(dir.path(), &config).unwrap();
+            assert!(matches!(info.stack_diff, Some(Deferred::Ready(_))));
+        }
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_interval("0.25"), Ok(Duration::from_millis(250)));
+        for bad in ["0", "-1", "inf", "soon"] {
+            assert!(parse_interval(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -1,15 +1,257 @@
 //! Output formatting for prompt strings
 
-#[cfg(feature = "git")]
 use std::borrow::Cow;
 #[cfg(feature = "git")]
 use std::fmt::Write;
 
-use crate::color::{BLUE, BRIGHT_BLACK, BRIGHT_MAGENTA, GREEN, PURPLE, RED, RESET};
-use crate::config::Config;
+use crate::cache::Deferred;
+use crate::checks::CheckStatus;
+use crate::color::{
+    BLUE, BRIGHT_BLACK, BRIGHT_BLUE, BRIGHT_CYAN, BRIGHT_GREEN, BRIGHT_MAGENTA, BRIGHT_YELLOW,
+    CYAN, GREEN, PURPLE, RED, RESET, YELLOW,
+};
+use crate::config::{ChangeIdEncoding, Config};
+use crate::diff_stat::DiffStat;
+use crate::format::Format;
 #[cfg(feature = "git")]
 use crate::git::GitInfo;
-use crate::jj::JjInfo;
+use crate::jj::{FileChanges, Immutability, JjInfo, Parent, StackDiff};
+use crate::proquint;
+use crate::style::{strip_escapes, style_escape};
+use crate::symbols::Symbols;
+use crate::tag::{self, TagDistance};
+use jj_lib::signing::SigStatus;
+
+/// Collected repo state, ready to render
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+// One value per prompt, so boxing the bigger variant would buy nothing
+#[allow(clippy::large_enum_variant)]
+pub enum PromptData {
+    Jj(JjInfo),
+    #[cfg(feature = "git")]
+    Git(GitInfo),
+}
+
+/// Output capabilities of the target terminal, injectable for deterministic rendering
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    /// Emit ANSI colors (false overrides the config)
+    pub color: bool,
+    /// Max visible width in columns (None = unlimited)
+    pub width: Option<usize>,
+    /// Shell whose prompt the output is embedded in, for escaping
+    pub shell: Shell,
+}
+
+/// Target shell for prompt escaping
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    /// No escaping, e.g. for starship's `custom` module, which does its own
+    #[default]
+    None,
+    /// Wrap escape sequences in readline's `\x01`/`\x02` markers
+    Bash,
+    /// Wrap escape sequences in `%{`/`%}` and escape `%`
+    Zsh,
+    /// Fish measures escape sequences itself
+    Fish,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            color: true,
+            width: None,
+            shell: Shell::None,
+        }
+    }
+}
+
+/// Render prompt data to a string
+/// Pure function of its inputs: no environment, terminal or repo access.
+/// Over `caps.width`, low-priority segments are dropped before anything is cut.
+#[must_use]
+pub fn render(data: &PromptData, config: &Config, caps: Capabilities) -> String {
+    let segments = if caps.color {
+        segments(data, config)
+    } else {
+        let mut config = config.clone();
+        config.jj_display.show_color = false;
+        config.git_display.show_color = false;
+        segments(data, &config)
+    };
+    let format = config.format.as_ref();
+    let paint = |style: &str, text: &str| {
+        let escape = style_escape(style, &config.palette);
+        if caps.color && !escape.is_empty() {
+            format!("{escape}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    };
+    let out = match caps.width {
+        Some(width) => segments.fit(format, &paint, width, &config.symbols.ellipsis),
+        None => segments.render(format, &paint, None),
+    };
+    escape_for_shell(&out, caps.shell)
+}
+
+/// Complete prompt line for use without starship: `{dir} {repo info} ❯ `
+/// The prompt character is red after a failed command, and pending repo info
+/// renders as a placeholder. Pure like `render`; the repo info gets whatever
+/// width the directory and prompt character leave.
+#[must_use]
+pub fn render_standalone(
+    dir: &str,
+    data: Option<Deferred<&PromptData>>,
+    config: &Config,
+    caps: Capabilities,
+    success: bool,
+) -> String {
+    let paint = |text, color| {
+        let text = format_segment(text, color, caps.color);
+        config.palette.recolor(&text).into_owned()
+    };
+    let mut out = paint(dir, CYAN);
+    if matches!(data, Some(Deferred::Pending)) {
+        out.push(' ');
+        out.push_str(&paint(&config.symbols.ellipsis, BRIGHT_BLACK));
+    } else if let Some(Deferred::Ready(data)) = data {
+        let inner = Capabilities {
+            width: caps.width.map(|w| {
+                w.saturating_sub(visible_width(dir) + visible_width(&config.symbols.prompt) + 3)
+            }),
+            shell: Shell::None,
+            ..caps
+        };
+        let info = render(data, config, inner);
+        if !info.is_empty() {
+            out.push(' ');
+            out.push_str(&info);
+        }
+    }
+    out.push(' ');
+    out.push_str(&paint(
+        &config.symbols.prompt,
+        if success { GREEN } else { RED },
+    ));
+    out.push(' ');
+    escape_for_shell(&out, caps.shell)
+}
+
+fn segments(data: &PromptData, config: &Config) -> Segments {
+    let mut segments = match data {
+        PromptData::Jj(info) => jj_segments(info, config),
+        #[cfg(feature = "git")]
+        PromptData::Git(info) => git_segments(info, config),
+    };
+    let show_color = match data {
+        PromptData::Jj(_) => config.jj_display.show_color,
+        #[cfg(feature = "git")]
+        PromptData::Git(_) => config.git_display.show_color,
+    };
+    for segment in &mut segments.0 {
+        let escape = config
+            .styles
+            .get(segment.name)
+            .map(|style| style_escape(style, &config.palette))
+            .filter(|escape| show_color && !escape.is_empty());
+        if let Some(escape) = escape {
+            segment.text = format!("{escape}{}{RESET}", strip_escapes(&segment.text));
+        } else if let Cow::Owned(text) = config.palette.recolor(&segment.text) {
+            segment.text = text;
+        }
+    }
+    segments
+}
+
+/// Mark escape sequences as zero-width so the shell measures each prompt line
+/// correctly. Sequences are wrapped individually, so markers never span lines.
+fn escape_for_shell(s: &str, shell: Shell) -> String {
+    let (start, end) = match shell {
+        Shell::None | Shell::Fish => return s.to_string(),
+        Shell::Bash => ("\x01", "\x02"),
+        Shell::Zsh => ("%{", "%}"),
+    };
+    let mut out = String::with_capacity(s.len() * 2);
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push_str(start);
+            out.push(c);
+            for c in chars.by_ref() {
+                out.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            out.push_str(end);
+        } else if c == '%' && shell == Shell::Zsh {
+            out.push_str("%%");
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Number of visible characters, skipping ANSI escape sequences
+fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequence: ESC [ params final-byte
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Cut a styled string to `width` visible characters, ending in `ellipsis`
+/// when cut. Escape sequences are kept intact and styling is reset after the cut
+fn truncate_visible(s: &str, width: usize, ellipsis: &str) -> String {
+    if visible_width(s) <= width {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut remaining = width.saturating_sub(visible_width(ellipsis));
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            out.push(c);
+            for c in chars.by_ref() {
+                out.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else if remaining > 0 {
+            out.push(c);
+            remaining -= 1;
+        } else {
+            break;
+        }
+    }
+    if width > 0 {
+        out.push_str(ellipsis);
+    }
+    if out.contains('\x1b') {
+        out.push_str(RESET);
+    }
+    out
+}
 
 fn format_segment(text: &str, color: &str, show_color: bool) -> String {
     if show_color {
@@ -20,112 +262,890 @@ fn format_segment(text: &str, color: &str, show_color: bool) -> String {
 }
 
 /// Format `change_id` with unique prefix highlighting (matching jj log style)
-/// Prefix is bright magenta, rest is gray
-fn format_change_id(change_id: &str, prefix_len: usize, show_prefix_color: bool) -> String {
-    if !show_prefix_color {
-        return change_id.to_string();
-    }
+/// Prefix is `prefix_color` (bright magenta by default), rest is gray
+fn format_change_id(change_id: &str, prefix_len: usize, prefix_color: &str) -> String {
     let prefix_len = prefix_len.min(change_id.len());
     let prefix = &change_id[..prefix_len];
     let rest = &change_id[prefix_len..];
     if rest.is_empty() {
-        format!("{BRIGHT_MAGENTA}{prefix}{RESET}")
+        format!("{prefix_color}{prefix}{RESET}")
+    } else {
+        format!("{prefix_color}{prefix}{RESET}{BRIGHT_BLACK}{rest}{RESET}")
+    }
+}
+
+/// Colors a change id or bookmark can hash to: none is red (status) or gray
+/// (the rest of the id), and all are theme colors, like the rest of the prompt
+const HASH_COLORS: [&str; 10] = [
+    BLUE,
+    CYAN,
+    GREEN,
+    YELLOW,
+    PURPLE,
+    BRIGHT_BLUE,
+    BRIGHT_CYAN,
+    BRIGHT_GREEN,
+    BRIGHT_YELLOW,
+    BRIGHT_MAGENTA,
+];
+
+/// Color of a change id by its FNV-1a hash, which unlike std's hasher is
+/// stable across Rust versions, so a change keeps its color
+fn hash_color(change_id: &str) -> &'static str {
+    let hash = change_id
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    HASH_COLORS[usize::try_from(hash % HASH_COLORS.len() as u64).expect("index")]
+}
+
+/// Change id in the configured encoding, with the length of its highlighted
+/// prefix: the unique one, or `id_prefix_length` if longer
+fn spell_change_id<'a>(info: &'a JjInfo, config: &Config) -> (Cow<'a, str>, usize) {
+    let prefix_len = info
+        .change_id_prefix_len
+        .max(config.id_prefix_length)
+        .min(info.change_id.len());
+    match config.change_id_encoding {
+        ChangeIdEncoding::ReverseHex => (Cow::Borrowed(&info.change_id), prefix_len),
+        ChangeIdEncoding::Proquint => match proquint::encode(&info.change_id) {
+            Some(encoded) => {
+                let prefix_len = proquint::encoded_prefix_len(&encoded, prefix_len);
+                (Cow::Owned(encoded), prefix_len)
+            }
+            None => (Cow::Borrowed(&info.change_id), prefix_len),
+        },
+    }
+}
+
+/// Split a log-scaled number of filled blocks between added and removed lines
+/// Returns (added blocks, removed blocks), each non-zero side gets at least one block
+/// when the bar has room for both
+fn diff_stat_blocks(stat: DiffStat, width: usize) -> (usize, usize) {
+    let total = stat.total();
+    if total == 0 {
+        return (0, 0);
+    }
+    // Bit length = floor(log2(total)) + 1: 1 line fills 1 block, 4 lines 3, 1k lines 10
+    let filled = ((usize::BITS - total.leading_zeros()) as usize).min(width);
+    let mut added = (filled * stat.added + total / 2) / total;
+    if stat.added > 0 && added == 0 {
+        added = 1;
+    }
+    if stat.removed > 0 && added == filled && filled > 1 {
+        added -= 1;
+    }
+    (added, filled - added)
+}
+
+/// Format line counts as `+12 -3`, or as a block bar like `▰▰▰▱▱`
+/// Returns None when nothing changed
+fn format_diff_stat(stat: DiffStat, config: &Config, show_color: bool) -> Option<String> {
+    if stat.total() == 0 {
+        return None;
+    }
+
+    let options = &config.diff_stat;
+    if options.bar {
+        let (added, removed) = diff_stat_blocks(stat, options.bar_width);
+        let empty = options.bar_width - added - removed;
+        let mut parts = Vec::with_capacity(3);
+        for (count, block, color) in [
+            (added, &config.symbols.bar_filled, GREEN),
+            (removed, &config.symbols.bar_filled, RED),
+            (empty, &config.symbols.bar_empty, BRIGHT_BLACK),
+        ] {
+            if count > 0 {
+                parts.push(format_segment(&block.repeat(count), color, show_color));
+            }
+        }
+        return Some(parts.concat());
+    }
+
+    let mut parts = Vec::with_capacity(2);
+    if stat.added > 0 {
+        parts.push(format_segment(
+            &format!("+{}", config.format_count(stat.added)),
+            GREEN,
+            show_color,
+        ));
+    }
+    if stat.removed > 0 {
+        parts.push(format_segment(
+            &format!("-{}", config.format_count(stat.removed)),
+            RED,
+            show_color,
+        ));
+    }
+    Some(parts.join(" "))
+}
+
+/// Format file counts as `+3 ~2 -1` (added, modified, deleted), leaving out
+/// zeros. Returns None when no file changed
+fn format_file_changes(changes: FileChanges, config: &Config, show_color: bool) -> Option<String> {
+    if changes.total() == 0 {
+        return None;
+    }
+    let parts: Vec<String> = [
+        ('+', changes.added, GREEN),
+        ('~', changes.modified, YELLOW),
+        ('-', changes.deleted, RED),
+    ]
+    .into_iter()
+    .filter(|&(_, count, _)| count > 0)
+    .map(|(sign, count, color)| {
+        format_segment(
+            &format!("{sign}{}", config.format_count(count)),
+            color,
+            show_color,
+        )
+    })
+    .collect();
+    Some(parts.join(" "))
+}
+
+/// Format the description's first line, yellow when it looks like work in
+/// progress, or the dim marker of an empty description
+fn format_description(info: &JjInfo, config: &Config, show_color: bool) -> Option<String> {
+    if !config.description.enabled {
+        return None;
+    }
+    let options = &config.description;
+    let first_line = info.description.lines().next().unwrap_or_default().trim();
+    if first_line.is_empty() {
+        return (!options.empty_marker.is_empty())
+            .then(|| format_segment(&options.empty_marker, BRIGHT_BLACK, show_color));
+    }
+    let text = config.truncate_to(first_line, options.max_width);
+    if options.is_wip(first_line) {
+        Some(format_segment(&text, YELLOW, show_color))
+    } else {
+        Some(text.into_owned())
+    }
+}
+
+/// Conventional-commit compliance of the description's first line: a dim
+/// `cc✓`, or a red `cc✗` (nothing for an empty description, already marked `?`)
+fn format_conventional(info: &JjInfo, config: &Config, show_color: bool) -> Option<String> {
+    if !config.conventional.enabled {
+        return None;
+    }
+    let first_line = info.description.lines().next()?.trim();
+    if first_line.is_empty() {
+        None
+    } else if config.conventional.pattern.is_match(first_line) {
+        let text = format!("cc{}", config.symbols.good);
+        Some(format_segment(&text, BRIGHT_BLACK, show_color))
+    } else {
+        let text = format!("cc{}", config.symbols.bad);
+        Some(format_segment(&text, RED, show_color))
+    }
+}
+
+/// Marker of a working copy without changes, as in `jj log`
+const EMPTY_MARKER: &str = "(empty)";
+
+/// Bookmarks shown by name in a megamerge; the rest are counted
+const MEGAMERGE_NAMES: usize = 2;
+
+/// Composition of a megamerge, e.g. `⊕{feat-a,feat-b,+2}`: the first bookmarked
+/// parents by name, then the count of the rest (unbookmarked ones included)
+fn format_megamerge(info: &JjInfo, config: &Config) -> Option<String> {
+    if info.megamerge.is_empty() {
+        return None;
+    }
+    let names: Vec<Cow<str>> = info
+        .megamerge
+        .iter()
+        .flatten()
+        .take(MEGAMERGE_NAMES)
+        .map(|name| config.truncate(name))
+        .collect();
+    let rest = info.megamerge.len() - names.len();
+    let rest = (rest > 0).then(|| Cow::Owned(format!("+{}", config.format_count(rest))));
+    let parts: Vec<Cow<str>> = names.into_iter().chain(rest).collect();
+    Some(format!(
+        "{}{{{}}}",
+        config.symbols.megamerge,
+        parts.join(",")
+    ))
+}
+
+/// Parents of @, e.g. `on main@kmqp`, or for a merge `on ⊕{main@kmqp,zsvn,+1}`
+/// like the megamerge
+fn format_parents(info: &JjInfo, config: &Config) -> Option<String> {
+    let spell = |parent: &Parent| match &parent.bookmark {
+        Some(bookmark) => format!("{}@{}", config.truncate(bookmark), parent.change_id),
+        None => parent.change_id.clone(),
+    };
+    match info.parents.as_slice() {
+        [] => None,
+        [parent] => Some(format!("on {}", spell(parent))),
+        parents => {
+            let mut parts: Vec<String> = parents.iter().take(MEGAMERGE_NAMES).map(spell).collect();
+            let rest = parents.len() - parts.len();
+            if rest > 0 {
+                parts.push(format!("+{}", config.format_count(rest)));
+            }
+            Some(format!(
+                "on {}{{{}}}",
+                config.symbols.megamerge,
+                parts.join(",")
+            ))
+        }
+    }
+}
+
+/// Stack diff, e.g. `3 files +120 -40`, with the line counts as in the diffstat
+fn format_stack_diff(diff: StackDiff, config: &Config, show_color: bool) -> Option<String> {
+    if diff.files == 0 {
+        return None;
+    }
+    let noun = if diff.files == 1 { "file" } else { "files" };
+    let files = format!("{} {noun}", config.format_count(diff.files));
+    let mut parts = vec![format_segment(&files, BLUE, show_color)];
+    parts.extend(format_diff_stat(diff.stat, config, show_color));
+    Some(parts.join(" "))
+}
+
+/// Nearest tag, optionally as a short version, and with the commits since it,
+/// e.g. `v1.2.0+5` (just the tag on the tagged commit)
+fn format_tag(tag: Deferred<&TagDistance>, config: &Config, show_color: bool) -> String {
+    match tag {
+        Deferred::Ready(tag) => {
+            let version = config
+                .tag
+                .semver
+                .then(|| tag::short_version(&tag.tag))
+                .flatten();
+            let name = version.map_or_else(|| config.truncate(&tag.tag), Cow::Owned);
+            let text = if !config.tag.distance || tag.distance == 0 {
+                name.into_owned()
+            } else {
+                format!("{name}+{}", config.format_count(tag.distance))
+            };
+            format_segment(&text, CYAN, show_color)
+        }
+        Deferred::Pending => format_segment(&config.symbols.ellipsis, BRIGHT_BLACK, show_color),
+    }
+}
+
+/// CI checks: green `CI ✓` passed, red `CI ✗` failed, yellow `CI ●` running
+fn format_checks(checks: Deferred<CheckStatus>, config: &Config, show_color: bool) -> String {
+    let symbols = &config.symbols;
+    let (symbol, color) = match checks {
+        Deferred::Ready(CheckStatus::Pass) => (&symbols.good, GREEN),
+        Deferred::Ready(CheckStatus::Fail) => (&symbols.bad, RED),
+        Deferred::Ready(CheckStatus::Pending) => (&symbols.running, YELLOW),
+        Deferred::Pending => (&symbols.ellipsis, BRIGHT_BLACK),
+    };
+    format_segment(&format!("CI {symbol}"), color, show_color)
+}
+
+/// Signature verification: green `✓` good, yellow `?` unknown key, red `✗` bad
+fn format_signature(signature: Deferred<SigStatus>, config: &Config, show_color: bool) -> String {
+    let symbols = &config.symbols;
+    let (symbol, color) = match signature {
+        Deferred::Ready(SigStatus::Good) => (symbols.good.as_ref(), GREEN),
+        Deferred::Ready(SigStatus::Unknown) => ("?", YELLOW),
+        Deferred::Ready(SigStatus::Bad) => (symbols.bad.as_ref(), RED),
+        Deferred::Pending => (symbols.ellipsis.as_ref(), BRIGHT_BLACK),
+    };
+    format_segment(symbol, color, show_color)
+}
+
+/// Format values of the configured trailers, in config order
+fn format_trailers(info: &JjInfo, config: &Config, show_color: bool) -> Option<String> {
+    let values: Vec<&str> = config
+        .trailers
+        .iter()
+        .flat_map(|key| {
+            info.trailers
+                .iter()
+                .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.as_str())
+        })
+        .collect();
+    if values.is_empty() {
+        None
     } else {
-        format!("{BRIGHT_MAGENTA}{prefix}{RESET}{BRIGHT_BLACK}{rest}{RESET}")
+        Some(format_segment(&values.join(" "), BRIGHT_BLACK, show_color))
+    }
+}
+
+/// Segment priority: under width pressure the lowest are dropped first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    /// Prefix, description, trailers
+    Low,
+    /// Diffstat, issue key, git commit hash
+    Medium,
+    /// Bookmarks
+    High,
+    /// Status indicators
+    Critical,
+    /// Change id or branch name, never dropped
+    Essential,
+}
+
+#[derive(Debug)]
+struct Segment {
+    /// Variable name in format strings, e.g. `bookmarks`
+    name: &'static str,
+    text: String,
+    priority: Priority,
+    /// Joined to the previous segment without a space
+    glued: bool,
+}
+
+/// Prompt segments in display order
+#[derive(Debug, Default)]
+struct Segments(Vec<Segment>);
+
+impl Segments {
+    /// Add a space-separated segment
+    fn push(&mut self, name: &'static str, priority: Priority, text: String) {
+        self.0.push(Segment {
+            name,
+            text,
+            priority,
+            glued: false,
+        });
+    }
+
+    /// Add a segment directly after the previous one
+    fn glue(&mut self, name: &'static str, priority: Priority, text: String) {
+        self.0.push(Segment {
+            name,
+            text,
+            priority,
+            glued: true,
+        });
+    }
+
+    fn join(&self) -> String {
+        let mut out = String::with_capacity(128);
+        for segment in &self.0 {
+            if !segment.glued && !out.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(&segment.text);
+        }
+        out
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.text.as_str())
+    }
+
+    /// Expand `format` line by line if given, else join. With a `width`,
+    /// `$fill` pads each line to it; without, each `$fill` is one space.
+    /// Text groups are styled by `paint`.
+    fn render(
+        &self,
+        format: Option<&Format>,
+        paint: &dyn Fn(&str, &str) -> String,
+        width: Option<usize>,
+    ) -> String {
+        let Some(format) = format else {
+            return self.join();
+        };
+        let lookup = |name: &str| self.get(name);
+        let lines: Vec<String> = format
+            .lines()
+            .iter()
+            .map(|line| {
+                // Each $fill keeps at least one column between its groups
+                let min_fill = line.fills();
+                let fill = width.map_or(min_fill, |width| {
+                    width
+                        .saturating_sub(visible_width(&line.render(&lookup, paint, 0)))
+                        .max(min_fill)
+                });
+                line.render(&lookup, paint, fill)
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Render within `width` columns per line, dropping segments lowest priority
+    /// first (rightmost first among equals); essential segments are cut instead,
+    /// ending in `ellipsis`
+    fn fit(
+        mut self,
+        format: Option<&Format>,
+        paint: &dyn Fn(&str, &str) -> String,
+        width: usize,
+        ellipsis: &str,
+    ) -> String {
+        loop {
+            let out = self.render(format, paint, Some(width));
+            if out.split('\n').all(|line| visible_width(line) <= width) {
+                return out;
+            }
+            let drop = self
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.priority < Priority::Essential)
+                .min_by_key(|&(i, s)| (s.priority, std::cmp::Reverse(i)))
+                .map(|(i, _)| i);
+            let Some(i) = drop else {
+                let lines: Vec<String> = out
+                    .split('\n')
+                    .map(|line| truncate_visible(line, width, ellipsis))
+                    .collect();
+                return lines.join("\n");
+            };
+            self.0.remove(i);
+        }
     }
 }
 
 /// Format JJ info as prompt string
 /// Pattern: `on {symbol}{change_id} ({bookmarks}) [{status}]`
-pub fn format_jj(info: &JjInfo, config: &Config) -> String {
-    let mut out = String::with_capacity(128);
+#[cfg(test)]
+pub(crate) fn format_jj(info: &JjInfo, config: &Config) -> String {
+    jj_segments(info, config).join()
+}
+
+/// Bookmarks by proximity, e.g. `(pr-3, main~5)`; a descendant bookmark
+/// (WC behind it) comes last as `name-N`
+fn format_bookmarks(info: &JjInfo, config: &Config, show_color: bool) -> Option<String> {
+    // Each bookmark in the color of its name, else all of them in green
+    let hashed = config.hash_bookmark_colors && show_color;
+    let paint = |name: &str, text: String| {
+        if hashed {
+            format_segment(&text, hash_color(name), true)
+        } else {
+            text
+        }
+    };
+    let mut bookmark_strs: Vec<String> = info
+        .bookmarks
+        .iter()
+        .map(|(name, dist)| {
+            let truncated = config.truncate(name);
+            let text = if *dist > 0 {
+                format!("{truncated}~{}", config.format_count(*dist))
+            } else {
+                truncated.into_owned()
+            };
+            paint(name, text)
+        })
+        .collect();
+    if let Some((name, dist)) = &info.descendant_bookmark {
+        let text = format!("{}-{}", config.truncate(name), config.format_count(*dist));
+        bookmark_strs.push(paint(name, text));
+    }
+    if config.max_bookmarks > 0 && bookmark_strs.len() > config.max_bookmarks {
+        let hidden = bookmark_strs.len() - config.max_bookmarks;
+        bookmark_strs.truncate(config.max_bookmarks);
+        bookmark_strs.push(format!("+{}", config.format_count(hidden)));
+    }
+    if bookmark_strs.is_empty() {
+        return None;
+    }
+    let text = format!("({})", bookmark_strs.join(", "));
+    Some(if hashed {
+        text
+    } else {
+        format_segment(&text, GREEN, show_color)
+    })
+}
+
+/// Status indicators (priority: ! > × > ⇔ > ? > ⇡), e.g. `[!?]`
+fn jj_status(info: &JjInfo, symbols: &Symbols) -> Option<String> {
+    let mut status = String::new();
+    if info.conflict {
+        status.push('!');
+    }
+    if info.stack_conflict {
+        status.push_str(&symbols.stack_conflict);
+    }
+    if info.divergent {
+        status.push_str(&symbols.divergent);
+    }
+    if info.empty_desc {
+        status.push('?');
+    }
+    if info.has_remote && !info.is_synced {
+        status.push_str(&symbols.ahead);
+    }
+    (!status.is_empty()).then(|| format!("[{status}]"))
+}
+
+#[allow(clippy::too_many_lines)]
+fn jj_segments(info: &JjInfo, config: &Config) -> Segments {
+    let mut out = Segments::default();
     let display = &config.jj_display;
 
     // "on {symbol}" prefix
     if display.show_prefix {
-        out.push_str("on ");
-        out.push_str(&format_segment(&config.jj_symbol, BLUE, display.show_color));
+        out.glue(
+            "prefix",
+            Priority::Low,
+            format!(
+                "on {}",
+                format_segment(&config.jj_symbol, BLUE, display.show_color)
+            ),
+        );
     }
 
     // change_id with prefix coloring (controlled by show_id)
     if display.show_id {
         let use_prefix_color = display.show_color && display.show_prefix_color;
-        if use_prefix_color {
-            out.push_str(&format_change_id(
-                &info.change_id,
-                info.change_id_prefix_len,
-                true,
-            ));
+        let (spelled, prefix_len) = spell_change_id(info, config);
+        let hashed = config.hash_color.then(|| hash_color(&info.change_id_full));
+        let change_id = if use_prefix_color {
+            format_change_id(&spelled, prefix_len, hashed.unwrap_or(BRIGHT_MAGENTA))
         } else {
-            out.push_str(&format_segment(&info.change_id, PURPLE, display.show_color));
-        }
+            format_segment(&spelled, hashed.unwrap_or(PURPLE), display.show_color)
+        };
+        out.glue("change_id", Priority::Essential, change_id);
     }
 
     // Bookmarks in parentheses (controlled by show_name - they're names/labels)
-    if display.show_name && !info.bookmarks.is_empty() {
-        if !out.is_empty() {
-            out.push(' ');
+    if display.show_name {
+        if let Some(text) = format_bookmarks(info, config, display.show_color) {
+            out.push("bookmarks", Priority::High, text);
         }
+    }
 
-        let bookmark_strs: Vec<String> = info
-            .bookmarks
-            .iter()
-            .map(|(name, dist)| {
-                let truncated = config.truncate(name);
-                if *dist > 0 {
-                    format!("{truncated}~{dist}")
-                } else {
-                    truncated.into_owned()
-                }
-            })
-            .collect();
-        let bookmarks_text = format!("({})", bookmark_strs.join(", "));
-        out.push_str(&format_segment(&bookmarks_text, GREEN, display.show_color));
+    // Workspace as jj shows its working copy, e.g. `feature@`
+    if let Some(workspace) = info.workspace.as_ref().filter(|_| config.workspace) {
+        out.push(
+            "workspace",
+            Priority::High,
+            format_segment(&format!("{workspace}@"), CYAN, display.show_color),
+        );
     }
 
-    // Status indicators in red (priority: ! > ⇔ > ? > ⇡)
-    if display.show_status {
-        let mut status = String::new();
-        if info.conflict {
-            status.push('!');
+    if let Some((remote, distance)) = &info.remote_distance {
+        let remote = match *distance {
+            Deferred::Ready(distance) => {
+                format_distance(distance, config).map(|text| (format!("{remote} {text}"), YELLOW))
+            }
+            Deferred::Pending => Some((
+                format!("{remote} {}", config.symbols.ellipsis),
+                BRIGHT_BLACK,
+            )),
+        };
+        if let Some((text, color)) = remote {
+            out.push(
+                "remote",
+                Priority::Medium,
+                format_segment(&text, color, display.show_color),
+            );
         }
-        if info.divergent {
-            status.push('⇔');
+    }
+
+    let trunk = match info.trunk_distance {
+        Some(Deferred::Ready(distance)) => {
+            format_distance(distance, config).map(|text| (Cow::Owned(text), CYAN))
         }
-        if info.empty_desc {
-            status.push('?');
+        Some(Deferred::Pending) => Some((
+            Cow::Borrowed(config.symbols.ellipsis.as_ref()),
+            BRIGHT_BLACK,
+        )),
+        None => None,
+    };
+    if let Some((text, color)) = trunk {
+        out.push(
+            "trunk",
+            Priority::Medium,
+            format_segment(&text, color, display.show_color),
+        );
+    }
+
+    if let Some(tag) = &info.tag {
+        out.push(
+            "tag",
+            Priority::Low,
+            format_tag(tag.as_ref(), config, display.show_color),
+        );
+    }
+
+    if let Some(marker) = info.clone.marker() {
+        out.push(
+            "clone",
+            Priority::Low,
+            format_segment(marker, YELLOW, display.show_color),
+        );
+    }
+
+    // Working copy jj won't let you rewrite, or started on a commit that
+    // isn't yours to build on
+    let immutable = match info.immutable {
+        Some(Immutability::Immutable) => Some(("immutable", RED)),
+        Some(Immutability::OnImmutableHead) => Some(("immutable parent", YELLOW)),
+        Some(Immutability::Mutable) | None => None,
+    };
+    if let Some((text, color)) = immutable {
+        out.push(
+            "immutable",
+            Priority::High,
+            format_segment(text, color, display.show_color),
+        );
+    }
+
+    // Concurrent operations, e.g. `2 op heads`, merged by the next jj command
+    if config.op_heads && info.op_heads > 1 {
+        out.push(
+            "op_heads",
+            Priority::High,
+            format_segment(
+                &format!("{} op heads", info.op_heads),
+                YELLOW,
+                display.show_color,
+            ),
+        );
+    }
+
+    // Repo-wide counts of forgotten work, e.g. `2 unpushed 3 heads`, and
+    // files staged behind jj's back
+    for (name, count) in [
+        ("unpushed", info.unpushed_bookmarks),
+        ("heads", info.anonymous_heads),
+        ("staged", info.git_staged),
+    ] {
+        if let Some(count) = count.filter(|&n| n > 0) {
+            let text = format!("{} {name}", config.format_count(count));
+            out.push(
+                name,
+                Priority::Low,
+                format_segment(&text, YELLOW, display.show_color),
+            );
         }
-        if info.has_remote && !info.is_synced {
-            status.push('⇡');
+    }
+
+    // Other stacks of work on trunk, e.g. `⌥4`
+    if let Some(stacks) = info.stacks.filter(|&n| n > 0) {
+        let text = format!("{}{}", config.symbols.stacks, config.format_count(stacks));
+        out.push(
+            "stacks",
+            Priority::Low,
+            format_segment(&text, CYAN, display.show_color),
+        );
+    }
+
+    // Branch a colocated git HEAD is on (jj leaves it detached), and refs
+    // git and jj disagree on
+    if let Some(head) = &info.git_head {
+        let parts: Vec<String> = [
+            head.branch
+                .as_ref()
+                .map(|branch| (format!("git:{branch}"), CYAN)),
+            head.needs_import.then(|| ("import".to_string(), YELLOW)),
+            head.needs_export.then(|| ("export".to_string(), YELLOW)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(text, color)| format_segment(&text, color, display.show_color))
+        .collect();
+        if !parts.is_empty() {
+            out.push("git_head", Priority::Low, parts.join(" "));
         }
+    }
 
-        if !status.is_empty() {
-            if !out.is_empty() {
-                out.push(' ');
-            }
-            let status_text = format!("[{}]", &status);
-            out.push_str(&format_segment(&status_text, RED, display.show_color));
+    // Issue key, first line of the description, then configured trailer values
+    let issue_key = config
+        .issue_key
+        .enabled
+        .then(|| {
+            let nearest = info.bookmarks.first().map(|(name, _)| name.as_str());
+            config
+                .issue_key
+                .extract(nearest.into_iter().chain([info.description.as_str()]))
+        })
+        .flatten()
+        .map(|key| format_segment(key, BLUE, display.show_color));
+    let empty = (config.empty_commit && info.empty)
+        .then(|| format_segment(EMPTY_MARKER, GREEN, display.show_color));
+    for (name, priority, text) in [
+        ("issue_key", Priority::Medium, issue_key),
+        ("empty", Priority::Medium, empty),
+        (
+            "description",
+            Priority::Low,
+            format_description(info, config, display.show_color),
+        ),
+        (
+            "trailers",
+            Priority::Low,
+            format_trailers(info, config, display.show_color),
+        ),
+        (
+            "conventional",
+            Priority::Low,
+            format_conventional(info, config, display.show_color),
+        ),
+        (
+            "template",
+            Priority::Low,
+            format_template(info.template.as_ref(), config, display.show_color),
+        ),
+    ] {
+        if let Some(text) = text {
+            out.push(name, priority, text);
+        }
+    }
+
+    // Line counts of the working copy, merge composition and signature
+    let diff_stat = match info.diff_stat {
+        Some(Deferred::Ready(stat)) => format_diff_stat(stat, config, display.show_color),
+        Some(Deferred::Pending) => Some(format_segment(
+            &config.symbols.ellipsis,
+            BRIGHT_BLACK,
+            display.show_color,
+        )),
+        None => None,
+    };
+    let files = match info.file_changes {
+        Some(Deferred::Ready(changes)) => format_file_changes(changes, config, display.show_color),
+        Some(Deferred::Pending) => Some(format_segment(
+            &config.symbols.ellipsis,
+            BRIGHT_BLACK,
+            display.show_color,
+        )),
+        None => None,
+    };
+    let untracked = match info.untracked {
+        Some(Deferred::Ready(files)) => (files.untracked > 0).then(|| {
+            format_segment(
+                &format!("?{}", config.format_count(files.untracked)),
+                YELLOW,
+                display.show_color,
+            )
+        }),
+        Some(Deferred::Pending) => Some(format_segment(
+            &config.symbols.ellipsis,
+            BRIGHT_BLACK,
+            display.show_color,
+        )),
+        None => None,
+    };
+    let megamerge =
+        format_megamerge(info, config).map(|text| format_segment(&text, GREEN, display.show_color));
+    let parent = format_parents(info, config)
+        .map(|text| format_segment(&text, BRIGHT_BLACK, display.show_color));
+    let signature = if info.unsigned {
+        Some(format_segment("unsigned", YELLOW, display.show_color))
+    } else {
+        info.signature
+            .map(|signature| format_signature(signature, config, display.show_color))
+    };
+    let checks = info
+        .checks
+        .map(|checks| format_checks(checks, config, display.show_color));
+    let stack_diff = match info.stack_diff {
+        Some(Deferred::Ready(diff)) => format_stack_diff(diff, config, display.show_color),
+        Some(Deferred::Pending) => Some(format_segment(
+            &config.symbols.ellipsis,
+            BRIGHT_BLACK,
+            display.show_color,
+        )),
+        None => None,
+    };
+    for (name, text) in [
+        ("diffstat", diff_stat),
+        ("files", files),
+        ("untracked", untracked),
+        ("stack_diff", stack_diff),
+        ("parent", parent),
+        ("megamerge", megamerge),
+        ("signature", signature),
+        ("checks", checks),
+    ] {
+        if let Some(text) = text {
+            out.push(name, Priority::Medium, text);
+        }
+    }
+
+    if let Some(count) = info.conflicted_files.filter(|&n| n > 0) {
+        out.push(
+            "conflict",
+            Priority::Critical,
+            format_conflict(count, config, display.show_color),
+        );
+    }
+
+    if display.show_status {
+        if let Some(status) = jj_status(info, &config.symbols) {
+            out.push(
+                "status",
+                Priority::Critical,
+                format_segment(&status, RED, display.show_color),
+            );
         }
     }
 
     out
 }
 
+/// Commits ahead of and behind trunk or a remote, e.g. `⇡3 ⇣1` (None if level)
+fn format_distance((ahead, behind): (usize, usize), config: &Config) -> Option<String> {
+    let parts: Vec<String> = [
+        (&config.symbols.ahead, ahead),
+        (&config.symbols.behind, behind),
+    ]
+    .into_iter()
+    .filter(|&(_, count)| count > 0)
+    .map(|(arrow, count)| format!("{arrow}{}", config.format_count(count)))
+    .collect();
+    (!parts.is_empty()).then(|| parts.join(" "))
+}
+
+/// Conflict marker with the number of conflicted files, e.g. `✖3`
+fn format_conflict(count: usize, config: &Config, show_color: bool) -> String {
+    let text = format!("{}{}", config.symbols.conflict, config.format_count(count));
+    format_segment(&text, RED, show_color)
+}
+
+/// Output of the configured jj template as is (None if disabled or empty)
+fn format_template(
+    template: Option<&Deferred<String>>,
+    config: &Config,
+    show_color: bool,
+) -> Option<String> {
+    match template? {
+        Deferred::Ready(text) if text.is_empty() => None,
+        Deferred::Ready(text) => Some(text.clone()),
+        Deferred::Pending => Some(format_segment(
+            &config.symbols.ellipsis,
+            BRIGHT_BLACK,
+            show_color,
+        )),
+    }
+}
+
 /// Format Git info as prompt string
 /// Pattern: `on {symbol}{name} ({id}) [{status}]`
+#[cfg(all(test, feature = "git"))]
+pub(crate) fn format_git(info: &GitInfo, config: &Config) -> String {
+    git_segments(info, config).join()
+}
+
 #[cfg(feature = "git")]
-pub fn format_git(info: &GitInfo, config: &Config) -> String {
-    let mut out = String::with_capacity(128);
+#[allow(clippy::too_many_lines)]
+fn git_segments(info: &GitInfo, config: &Config) -> Segments {
+    let mut out = Segments::default();
     let display = &config.git_display;
 
     // "on {symbol}" prefix
     if display.show_prefix {
-        out.push_str("on ");
-        out.push_str(&format_segment(
-            &config.git_symbol,
-            BLUE,
-            display.show_color,
-        ));
+        out.glue(
+            "prefix",
+            Priority::Low,
+            format!(
+                "on {}",
+                format_segment(&config.git_symbol, BLUE, display.show_color)
+            ),
+        );
     }
 
     // Name in purple (branch or HEAD)
@@ -134,28 +1154,86 @@ pub fn format_git(info: &GitInfo, config: &Config) -> String {
             .branch
             .as_ref()
             .map_or(Cow::Borrowed("HEAD"), |b| config.truncate(b));
-        out.push_str(&format_segment(&name, PURPLE, display.show_color));
+        out.glue(
+            "branch",
+            Priority::Essential,
+            format_segment(&name, PURPLE, display.show_color),
+        );
     }
 
     // ID in green
     if display.show_id {
-        if !out.is_empty() {
-            out.push(' ');
-        }
         let id_text = format!("({})", &info.head_short);
-        out.push_str(&format_segment(&id_text, GREEN, display.show_color));
+        out.push(
+            "commit",
+            Priority::Medium,
+            format_segment(&id_text, GREEN, display.show_color),
+        );
     }
 
-    // Status indicators in red
-    if display.show_status {
-        let mut status = String::new();
+    if let Some(tag) = &info.tag {
+        out.push(
+            "tag",
+            Priority::Low,
+            format_tag(tag.as_ref(), config, display.show_color),
+        );
+    }
 
-        // File status (order: = > + > ! > ? > ✘)
-        if info.conflicted > 0 {
-            status.push('=');
-        }
-        if info.staged > 0 {
-            status.push('+');
+    if let Some(marker) = info.clone.marker() {
+        out.push(
+            "clone",
+            Priority::Low,
+            format_segment(marker, YELLOW, display.show_color),
+        );
+    }
+
+    // Issue key from the branch name
+    if config.issue_key.enabled {
+        if let Some(key) = info
+            .branch
+            .as_deref()
+            .and_then(|b| config.issue_key.extract([b]))
+        {
+            out.push(
+                "issue_key",
+                Priority::Medium,
+                format_segment(key, BLUE, display.show_color),
+            );
+        }
+    }
+
+    // Line counts of index and worktree
+    let diff_stat = match info.diff_stat {
+        Some(Deferred::Ready(stat)) => format_diff_stat(stat, config, display.show_color),
+        Some(Deferred::Pending) => Some(format_segment(
+            &config.symbols.ellipsis,
+            BRIGHT_BLACK,
+            display.show_color,
+        )),
+        None => None,
+    };
+    if let Some(text) = diff_stat {
+        out.push("diffstat", Priority::Medium, text);
+    }
+
+    if config.conflict_marker && info.conflicted > 0 {
+        out.push(
+            "conflict",
+            Priority::Critical,
+            format_conflict(info.conflicted, config, display.show_color),
+        );
+    }
+
+    // Status indicators in red
+    if display.show_status {
+        let mut status = String::new();
+
+        // File status (order: = > + > ! > ? > ✘)
+        if info.conflicted > 0 {
+            status.push('=');
+        }
+        if info.staged > 0 {
+            status.push('+');
         }
         if info.modified > 0 {
             status.push('!');
@@ -164,23 +1242,39 @@ pub fn format_git(info: &GitInfo, config: &Config) -> String {
             status.push('?');
         }
         if info.deleted > 0 {
-            status.push('✘');
+            status.push_str(&config.symbols.deleted);
         }
 
         // Ahead/behind
-        if info.ahead > 0 {
-            let _ = write!(status, "⇡{}", info.ahead);
-        }
-        if info.behind > 0 {
-            let _ = write!(status, "⇣{}", info.behind);
+        match info.ahead_behind {
+            Deferred::Ready((ahead, behind)) => {
+                if ahead > 0 {
+                    let _ = write!(
+                        status,
+                        "{}{}",
+                        config.symbols.ahead,
+                        config.format_count(ahead)
+                    );
+                }
+                if behind > 0 {
+                    let _ = write!(
+                        status,
+                        "{}{}",
+                        config.symbols.behind,
+                        config.format_count(behind)
+                    );
+                }
+            }
+            Deferred::Pending => status.push_str(&config.symbols.ellipsis),
         }
 
         if !status.is_empty() {
-            if !out.is_empty() {
-                out.push(' ');
-            }
             let status_text = format!("[{}]", &status);
-            out.push_str(&format_segment(&status_text, RED, display.show_color));
+            out.push(
+                "status",
+                Priority::Critical,
+                format_segment(&status_text, RED, display.show_color),
+            );
         }
     }
 
@@ -192,10 +1286,15 @@ mod tests {
     use super::*;
     use std::borrow::Cow;
 
+    use crate::clone::CloneKind;
     #[cfg(feature = "git")]
     use crate::config::DEFAULT_GIT_SYMBOL;
     use crate::config::DEFAULT_JJ_SYMBOL;
     use crate::config::DisplayConfig;
+    use crate::palette::Palette;
+    use crate::style::Styles;
+    use crate::symbols::SymbolSet;
+    use regex::Regex;
 
     #[allow(dead_code)]
     fn default_config() -> Config {
@@ -212,278 +1311,1409 @@ mod tests {
             git_symbol: Cow::Borrowed(""),
             jj_display: DisplayConfig::all_visible(),
             git_display: DisplayConfig::all_visible(),
+            ..Config::default()
         }
     }
 
     #[test]
-    fn test_jj_format_clean() {
+    fn test_jj_format_clean() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 0)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: true,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &no_symbol_config()),
+            format!(
+                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main){RESET}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_jj_format_dirty() {
+        // When no bookmarks, only change_id is shown
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![],
+            empty_desc: true,
+            conflict: true,
+            divergent: false,
+            has_remote: false,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &no_symbol_config()),
+            format!(
+                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {RED}[!?]{RESET}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_jj_format_with_symbol() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 0)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: true,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &default_config()),
+            format!(
+                "on {BLUE}{DEFAULT_JJ_SYMBOL}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main){RESET}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_lighten_drops_default_symbols_only() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let mut config = default_config();
+        config.jj_display.show_color = false;
+        config.lighten();
+        assert_eq!(format_jj(&info, &config), "on yzxv1234");
+        assert!(config.async_segments);
+        assert_eq!(config.ancestor_bookmark_depth, 0);
+
+        let mut config = Config {
+            jj_symbol: "jj ".into(),
+            ..config
+        };
+        config.lighten();
+        assert_eq!(format_jj(&info, &config), "on jj yzxv1234");
+    }
+
+    #[test]
+    fn test_minimize_renders_core_segments_plainly() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let mut config = Config {
+            async_segments: true,
+            latency_budget: Some(std::time::Duration::from_millis(50)),
+            megamerge: true,
+            checks: true,
+            ..default_config()
+        };
+        config.diff_stat.enabled = true;
+        config.tag.enabled = true;
+        config.minimize();
+        assert_eq!(format_jj(&info, &config), "on yzxv1234");
+        assert!(!config.cache);
+        assert!(!config.async_segments);
+        assert_eq!(config.latency_budget, None);
+        assert!(!config.diff_stat.enabled && !config.tag.enabled);
+        assert!(!config.megamerge && !config.checks);
+    }
+
+    #[test]
+    fn test_jj_format_truncated() {
+        let config = Config {
+            truncate_name: 5,
+            id_length: 8,
+            ancestor_bookmark_depth: 10,
+            jj_symbol: Cow::Borrowed(""),
+            git_symbol: Cow::Borrowed(""),
+            jj_display: DisplayConfig::all_visible(),
+            git_display: DisplayConfig::all_visible(),
+            ..Config::default()
+        };
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("very-long-bookmark-name".into(), 0)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: false,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &config),
+            format!(
+                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(very…){RESET}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_jj_format_ancestor_bookmark() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 3)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: true,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &no_symbol_config()),
+            format!(
+                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main~3){RESET}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_jj_format_no_bookmarks() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: false,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &no_symbol_config()),
+            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_jj_format_multiple_bookmarks() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("feature".into(), 1), ("main".into(), 2)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: false,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &no_symbol_config()),
+            format!(
+                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(feature~1, main~2){RESET}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_jj_format_no_color() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 0)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: true,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let config = Config {
+            truncate_name: 0,
+            id_length: 8,
+            ancestor_bookmark_depth: 10,
+            jj_symbol: Cow::Borrowed("󱗆 "),
+            git_symbol: Cow::Borrowed(" "),
+            jj_display: DisplayConfig {
+                show_prefix: true,
+                show_name: true,
+                show_id: true,
+                show_status: true,
+                show_color: false,
+                show_prefix_color: true,
+            },
+            git_display: DisplayConfig::all_visible(),
+            ..Config::default()
+        };
+        assert_eq!(format_jj(&info, &config), "on 󱗆 yzxv1234 (main)");
+    }
+
+    #[test]
+    fn test_jj_format_no_id_hides_change_id() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 0)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: false,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let config = Config {
+            truncate_name: 0,
+            id_length: 8,
+            ancestor_bookmark_depth: 10,
+            jj_symbol: Cow::Borrowed(""),
+            git_symbol: Cow::Borrowed(""),
+            jj_display: DisplayConfig {
+                show_prefix: true,
+                show_name: true,
+                show_id: false, // --no-jj-id
+                show_status: false,
+                show_color: true,
+                show_prefix_color: true,
+            },
+            git_display: DisplayConfig::all_visible(),
+            ..Config::default()
+        };
+        // --no-jj-id hides change_id, shows only bookmarks
+        assert_eq!(
+            format_jj(&info, &config),
+            format!("on {BLUE}{RESET} {GREEN}(main){RESET}")
+        );
+    }
+
+    #[test]
+    fn test_jj_format_no_name_hides_bookmarks() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 0)],
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: false,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let config = Config {
+            truncate_name: 0,
+            id_length: 8,
+            ancestor_bookmark_depth: 10,
+            jj_symbol: Cow::Borrowed(""),
+            git_symbol: Cow::Borrowed(""),
+            jj_display: DisplayConfig {
+                show_prefix: true,
+                show_name: false, // --no-jj-name
+                show_id: true,
+                show_status: false,
+                show_color: true,
+                show_prefix_color: true,
+            },
+            git_display: DisplayConfig::all_visible(),
+            ..Config::default()
+        };
+        // --no-jj-name hides bookmarks, shows only change_id with prefix coloring
+        assert_eq!(
+            format_jj(&info, &config),
+            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_jj_format_direct_bookmark_distance_zero() {
+        // Verifies that when WC has a direct bookmark (distance 0),
+        // it shows without ~N suffix even with ancestor search enabled
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 0)], // distance 0 = directly on WC
+            empty_desc: false,
+            conflict: false,
+            divergent: false,
+            has_remote: false,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info, &no_symbol_config()),
+            format!(
+                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main){RESET}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_jj_format_descendant_bookmark() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            descendant_bookmark: Some(("main".into(), 2)),
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 (main-2)");
+
+        let info = JjInfo {
+            bookmarks: vec![("base".into(), 1)],
+            ..info
+        };
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 (base~1, main-2)");
+    }
+
+    #[test]
+    fn test_format_distance() {
+        let config = Config::default();
+        assert_eq!(format_distance((0, 0), &config), None);
+        assert_eq!(format_distance((3, 0), &config).as_deref(), Some("⇡3"));
+        assert_eq!(
+            format_distance((3, 1500), &config).as_deref(),
+            Some("⇡3 ⇣1.5k")
+        );
+    }
+
+    #[test]
+    fn test_jj_format_max_bookmarks() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("pr-3".into(), 0), ("pr-2".into(), 1), ("main".into(), 5)],
+            descendant_bookmark: Some(("next".into(), 1)),
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.max_bookmarks = 2;
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 (pr-3, pr-2~1, +2)");
+
+        config.max_bookmarks = 4;
+        assert_eq!(
+            format_jj(&info, &config),
+            "on yzxv1234 (pr-3, pr-2~1, main~5, next-1)"
+        );
+    }
+
+    #[test]
+    fn test_jj_format_unpushed_bookmarks() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("feature".into(), 0)],
+            is_synced: true,
+            unpushed_bookmarks: Some(3),
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        assert_eq!(
+            format_jj(&info, &config),
+            "on yzxv1234 (feature) 3 unpushed"
+        );
+
+        // Nothing forgotten, nothing shown
+        let info = JjInfo {
+            unpushed_bookmarks: Some(0),
+            ..info
+        };
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 (feature)");
+    }
+
+    #[test]
+    fn test_format_clone_marker() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            clone: CloneKind {
+                shallow: true,
+                partial: false,
+            },
+            ..JjInfo::default()
+        };
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 shallow");
+
+        #[cfg(feature = "git")]
+        {
+            config.git_display.show_color = false;
+            let info = GitInfo {
+                branch: Some("main".into()),
+                head_short: "a3b4c5d".into(),
+                ahead_behind: Deferred::Ready((0, 0)),
+                clone: CloneKind {
+                    shallow: false,
+                    partial: true,
+                },
+                ..GitInfo::default()
+            };
+            assert_eq!(format_git(&info, &config), "on main (a3b4c5d) partial");
+        }
+    }
+
+    #[test]
+    fn test_jj_format_git_staged() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            git_staged: Some(2),
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 2 staged");
+
+        let info = JjInfo {
+            git_staged: Some(0),
+            ..info
+        };
+        assert_eq!(format_jj(&info, &config), "on yzxv1234");
+    }
+
+    #[test]
+    fn test_jj_format_stack_conflict() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            conflict: true,
+            stack_conflict: true,
+            empty_desc: true,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 [!×?]");
+    }
+
+    #[test]
+    fn test_jj_format_signature() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        let info = |signature| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            signature,
+            ..JjInfo::default()
+        };
+        for (signature, expected) in [
+            (None, "on yzxv1234"),
+            (Some(Deferred::Ready(SigStatus::Good)), "on yzxv1234 ✓"),
+            (Some(Deferred::Ready(SigStatus::Unknown)), "on yzxv1234 ?"),
+            (Some(Deferred::Ready(SigStatus::Bad)), "on yzxv1234 ✗"),
+            (Some(Deferred::Pending), "on yzxv1234 …"),
+        ] {
+            assert_eq!(format_jj(&info(signature), &config), expected);
+        }
+        let unsigned = JjInfo {
+            unsigned: true,
+            ..info(None)
+        };
+        assert_eq!(format_jj(&unsigned, &config), "on yzxv1234 unsigned");
+    }
+
+    #[test]
+    fn test_jj_format_conventional() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.conventional.enabled = true;
+        let info = |description: &str| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            description: description.into(),
+            ..JjInfo::default()
+        };
+        for (description, expected) in [
+            ("feat(cli): add watch mode\n\nbody", "on yzxv1234 cc✓"),
+            ("fix!: drop old flag", "on yzxv1234 cc✓"),
+            ("Add watch mode", "on yzxv1234 cc✗"),
+            ("feat:missing space", "on yzxv1234 cc✗"),
+            ("", "on yzxv1234"),
+        ] {
+            assert_eq!(
+                format_jj(&info(description), &config),
+                expected,
+                "{description}"
+            );
+        }
+
+        config.conventional.pattern = Regex::new(r"^[A-Z]+-\d+ ").unwrap();
+        assert_eq!(
+            format_jj(&info("ABC-12 Add watch mode"), &config),
+            "on yzxv1234 cc✓"
+        );
+    }
+
+    #[test]
+    fn test_jj_format_tag() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.tag.enabled = true;
+        let info = |tag| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            tag,
+            ..JjInfo::default()
+        };
+        let tag = |distance| {
+            Some(Deferred::Ready(TagDistance {
+                tag: "v1.2.0".into(),
+                distance,
+            }))
+        };
+        assert_eq!(format_jj(&info(None), &config), "on yzxv1234");
+        // The distance only shows when asked for
+        assert_eq!(format_jj(&info(tag(5)), &config), "on yzxv1234 v1.2.0");
+        config.tag.distance = true;
+        assert_eq!(format_jj(&info(tag(0)), &config), "on yzxv1234 v1.2.0");
+        assert_eq!(format_jj(&info(tag(5)), &config), "on yzxv1234 v1.2.0+5");
+        config.tag.semver = true;
+        assert_eq!(format_jj(&info(tag(5)), &config), "on yzxv1234 1.2+5");
+        assert_eq!(
+            format_jj(&info(Some(Deferred::Pending)), &config),
+            "on yzxv1234 …"
+        );
+    }
+
+    #[test]
+    fn test_jj_format_stack_diff() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        let info = |stack_diff| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            stack_diff,
+            ..JjInfo::default()
+        };
+        let diff = |files, added, removed| {
+            Some(Deferred::Ready(StackDiff {
+                files,
+                stat: DiffStat { added, removed },
+            }))
+        };
+        assert_eq!(format_jj(&info(None), &config), "on yzxv1234");
+        assert_eq!(format_jj(&info(diff(0, 0, 0)), &config), "on yzxv1234");
+        assert_eq!(
+            format_jj(&info(diff(3, 120, 40)), &config),
+            "on yzxv1234 3 files +120 -40"
+        );
+        // A binary file changes no lines
+        assert_eq!(
+            format_jj(&info(diff(1, 0, 0)), &config),
+            "on yzxv1234 1 file"
+        );
+        assert_eq!(
+            format_jj(&info(Some(Deferred::Pending)), &config),
+            "on yzxv1234 …"
+        );
+    }
+
+    #[test]
+    fn test_jj_format_checks() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        let info = |checks| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            checks,
+            ..JjInfo::default()
+        };
+        assert_eq!(format_jj(&info(None), &config), "on yzxv1234");
+        for (checks, expected) in [
+            (Deferred::Ready(CheckStatus::Pass), "on yzxv1234 CI ✓"),
+            (Deferred::Ready(CheckStatus::Fail), "on yzxv1234 CI ✗"),
+            (Deferred::Ready(CheckStatus::Pending), "on yzxv1234 CI ●"),
+            (Deferred::Pending, "on yzxv1234 CI …"),
+        ] {
+            assert_eq!(format_jj(&info(Some(checks)), &config), expected);
+        }
+    }
+
+    #[test]
+    fn test_jj_format_ascii_symbols() {
+        let mut config = Config {
+            symbols: Symbols::new(SymbolSet::Ascii, &["divergent=~".into()], |_| None),
+            ..no_symbol_config()
+        };
+        config.jj_display.show_color = false;
+        config.diff_stat.enabled = true;
+        config.diff_stat.bar = true;
+        config.diff_stat.bar_width = 5;
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            stack_conflict: true,
+            divergent: true,
+            has_remote: true,
+            megamerge: vec![Some("feat".into()), None],
+            checks: Some(Deferred::Ready(CheckStatus::Pass)),
+            diff_stat: Some(Deferred::Ready(DiffStat {
+                added: 3,
+                removed: 0,
+            })),
+            ..JjInfo::default()
+        };
+        let text = format_jj(&info, &config);
+        assert!(text.is_ascii(), "{text}");
+        for part in ["&{feat,+1}", "CI +", "##---", "[*~^]"] {
+            assert!(text.contains(part), "{part} missing from {text}");
+        }
+    }
+
+    #[test]
+    fn test_jj_format_megamerge() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        let info = |parents: &[Option<&str>]| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            megamerge: parents.iter().map(|p| p.map(String::from)).collect(),
+            ..JjInfo::default()
+        };
+        assert_eq!(format_jj(&info(&[]), &config), "on yzxv1234");
+        assert_eq!(
+            format_jj(&info(&[Some("feat-a"), Some("feat-b")]), &config),
+            "on yzxv1234 ⊕{feat-a,feat-b}"
+        );
+        // Unbookmarked parents only add to the count
+        assert_eq!(
+            format_jj(
+                &info(&[Some("feat-a"), None, Some("feat-b"), Some("feat-c")]),
+                &config
+            ),
+            "on yzxv1234 ⊕{feat-a,feat-b,+2}"
+        );
+        assert_eq!(
+            format_jj(&info(&[None, None]), &config),
+            "on yzxv1234 ⊕{+2}"
+        );
+    }
+
+    #[test]
+    fn test_jj_format_parents() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        let info = |parents: &[(&str, Option<&str>)]| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            parents: parents
+                .iter()
+                .map(|&(change_id, bookmark)| Parent {
+                    change_id: change_id.into(),
+                    bookmark: bookmark.map(String::from),
+                })
+                .collect(),
+            ..JjInfo::default()
+        };
+        assert_eq!(format_jj(&info(&[]), &config), "on yzxv1234");
+        assert_eq!(
+            format_jj(&info(&[("kmqp", Some("main"))]), &config),
+            "on yzxv1234 on main@kmqp"
+        );
+        assert_eq!(
+            format_jj(&info(&[("kmqpx", None)]), &config),
+            "on yzxv1234 on kmqpx"
+        );
+        // A merge lists its first two parents and counts the rest
+        assert_eq!(
+            format_jj(
+                &info(&[("kmqp", Some("main")), ("zsvn", None), ("wqtl", None)]),
+                &config
+            ),
+            "on yzxv1234 on ⊕{main@kmqp,zsvn,+1}"
+        );
+    }
+
+    #[test]
+    fn test_jj_format_anonymous_heads() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            unpushed_bookmarks: Some(1),
+            anonymous_heads: Some(1500),
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        assert_eq!(
+            format_jj(&info, &config),
+            "on yzxv1234 1 unpushed 1.5k heads"
+        );
+    }
+
+    #[test]
+    fn test_jj_format_stacks() {
+        let info = |stacks| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            is_synced: true,
+            stacks,
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        assert_eq!(format_jj(&info(Some(4)), &config), "on yzxv1234 ⌥4");
+        assert_eq!(format_jj(&info(Some(0)), &config), "on yzxv1234");
+        config.symbols = Symbols::preset(SymbolSet::Ascii);
+        assert_eq!(format_jj(&info(Some(2)), &config), "on yzxv1234 %2");
+    }
+
+    #[test]
+    fn test_jj_format_description_wip() {
+        let mut config = no_symbol_config();
+        config.description.enabled = true;
+        let info = |description: &str| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            description: description.into(),
+            is_synced: true,
+            ..JjInfo::default()
+        };
+
+        assert_eq!(
+            format_jj(&info("fix parser\n\nbody"), &config),
+            format!(
+                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} fix parser"
+            )
+        );
+        for wip in ["WIP: parser", "fixup! fix parser", "squash! fix parser"] {
+            assert_eq!(
+                format_jj(&info(wip), &config),
+                format!(
+                    "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {YELLOW}{wip}{RESET}"
+                )
+            );
+        }
+        // Keyword must lead the description
+        assert!(!config.description.is_wip("wipe caches"));
+        assert!(!config.description.is_wip("revert fixup! commit"));
+    }
+
+    #[test]
+    fn test_jj_format_empty_commit() {
+        let mut config = no_symbol_config();
+        config.description.enabled = true;
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            description: "fix parser".into(),
+            empty: true,
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let prefix =
+            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET}");
+        assert_eq!(format_jj(&info, &config), format!("{prefix} fix parser"));
+        config.empty_commit = true;
+        assert_eq!(
+            format_jj(&info, &config),
+            format!("{prefix} {GREEN}(empty){RESET} fix parser")
+        );
+        let changed = JjInfo {
+            empty: false,
+            ..info
+        };
+        assert_eq!(format_jj(&changed, &config), format!("{prefix} fix parser"));
+    }
+
+    #[test]
+    fn test_jj_format_description_width_and_empty() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.description.enabled = true;
+        config.description.max_width = 10;
+        let info = |description: &str| JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            description: description.into(),
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(
+            format_jj(&info("fix the parser\n"), &config),
+            "on yzxv1234 fix the p…"
+        );
+        assert_eq!(
+            format_jj(&info("fix parser"), &config),
+            "on yzxv1234 fix parser"
+        );
+        assert_eq!(
+            format_jj(&info(""), &config),
+            "on yzxv1234 (no description)"
+        );
+        config.description.empty_marker = String::new();
+        assert_eq!(format_jj(&info(""), &config), "on yzxv1234");
+    }
+
+    #[test]
+    fn test_jj_format_trailers() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.trailers = vec!["change-id".into(), "Fixes".into()];
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            trailers: vec![
+                ("Fixes".into(), "#123".into()),
+                ("Signed-off-by".into(), "A <a@b>".into()),
+                ("Change-Id".into(), "I0123abcd".into()),
+            ],
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 I0123abcd #123");
+    }
+
+    #[test]
+    fn test_issue_key_extract() {
+        let issue_key = crate::config::IssueKeyConfig::default();
+        assert_eq!(issue_key.extract(["feat/PROJ-42-login"]), Some("PROJ-42"));
+        assert_eq!(
+            issue_key.extract(["fix-tests", "Closes #1234"]),
+            Some("#1234")
+        );
+        assert_eq!(issue_key.extract(["fix-2", "refactor"]), None);
+    }
+
+    #[test]
+    fn test_jj_format_issue_key() {
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.issue_key.enabled = true;
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("abc/ENG-7-cache".into(), 1), ("main".into(), 4)],
+            description: "ENG-9: unrelated".into(),
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        // Nearest bookmark wins over the description
+        assert_eq!(
+            format_jj(&info, &config),
+            "on yzxv1234 (abc/ENG-7-cache~1, main~4) ENG-7"
+        );
+    }
+
+    #[test]
+    fn test_render_capabilities() {
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("feature".into(), 1), ("main".into(), 2)],
+            is_synced: true,
+            ..JjInfo::default()
+        };
+        let data = PromptData::Jj(info);
+        let config = no_symbol_config();
+
+        let no_color = Capabilities {
+            color: false,
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            render(&data, &config, no_color),
+            "on yzxv1234 (feature~1, main~2)"
+        );
+        let width = |width| Capabilities {
+            width: Some(width),
+            ..no_color
+        };
+        // Low-priority prefix goes first, then bookmarks; the change id is kept
+        assert_eq!(
+            render(&data, &config, width(29)),
+            "yzxv1234 (feature~1, main~2)"
+        );
+        assert_eq!(render(&data, &config, width(16)), "yzxv1234");
+        // Cutting colored output keeps escapes and resets styling
+        assert_eq!(
+            render(
+                &data,
+                &config,
+                Capabilities {
+                    width: Some(5),
+                    ..Capabilities::default()
+                }
+            ),
+            format!("{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}…{RESET}")
+        );
+    }
+
+    #[test]
+    fn test_render_drops_lowest_priority_first() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
             change_id_prefix_len: 4,
             bookmarks: vec![("main".into(), 0)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: true,
+            description: "fix parser".into(),
+            diff_stat: Some(Deferred::Ready(DiffStat {
+                added: 12,
+                removed: 3,
+            })),
+            conflict: true,
             is_synced: true,
+            ..JjInfo::default()
         };
-        assert_eq!(
-            format_jj(&info, &no_symbol_config()),
-            format!(
-                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main){RESET}"
+        let data = PromptData::Jj(info);
+        let mut config = no_symbol_config();
+        config.description.enabled = true;
+        config.diff_stat.enabled = true;
+        let render_width = |width| {
+            render(
+                &data,
+                &config,
+                Capabilities {
+                    color: false,
+                    width: Some(width),
+                    ..Capabilities::default()
+                },
             )
-        );
+        };
+
+        assert_eq!(render_width(80), "on yzxv1234 (main) fix parser +12 -3 [!]");
+        // Description is dropped before the prefix (rightmost of equal priority first)
+        assert_eq!(render_width(30), "on yzxv1234 (main) +12 -3 [!]");
+        assert_eq!(render_width(26), "yzxv1234 (main) +12 -3 [!]");
+        assert_eq!(render_width(20), "yzxv1234 (main) [!]");
+        assert_eq!(render_width(16), "yzxv1234 [!]");
     }
 
     #[test]
-    fn test_jj_format_dirty() {
-        // When no bookmarks, only change_id is shown
+    fn test_render_format_fill() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
             change_id_prefix_len: 4,
-            bookmarks: vec![],
-            empty_desc: true,
+            bookmarks: vec![("main".into(), 0)],
             conflict: true,
-            divergent: false,
-            has_remote: false,
             is_synced: true,
+            ..JjInfo::default()
         };
+        let data = PromptData::Jj(info);
+        let mut config = no_symbol_config();
+        config.format = Some(Format::parse("$change_id $bookmarks$fill$status"));
+        let caps = |width| Capabilities {
+            color: false,
+            width,
+            ..Capabilities::default()
+        };
+
+        assert_eq!(render(&data, &config, caps(None)), "yzxv1234 (main) [!]");
         assert_eq!(
-            format_jj(&info, &no_symbol_config()),
-            format!(
-                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {RED}[!?]{RESET}"
-            )
+            render(&data, &config, caps(Some(24))),
+            "yzxv1234 (main)      [!]"
         );
+        // Too narrow: bookmarks are dropped, status stays right-aligned
+        assert_eq!(render(&data, &config, caps(Some(14))), "yzxv1234   [!]");
     }
 
     #[test]
-    fn test_jj_format_with_symbol() {
+    fn test_render_format_text_groups() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
-            change_id_prefix_len: 4,
+            change_id_prefix_len: 8,
             bookmarks: vec![("main".into(), 0)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: true,
             is_synced: true,
+            ..JjInfo::default()
         };
+        let data = PromptData::Jj(info);
+        let mut config = no_symbol_config();
+        config.jj_display.show_prefix_color = false;
+        config.format = Some(Format::parse(
+            "[$change_id](bold blue) [$bookmarks]($style)",
+        ));
+
         assert_eq!(
-            format_jj(&info, &default_config()),
-            format!(
-                "on {BLUE}{DEFAULT_JJ_SYMBOL}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main){RESET}"
-            )
+            render(&data, &config, Capabilities::default()),
+            format!("\x1b[1m{BLUE}yzxv1234{RESET} {GREEN}(main){RESET}")
         );
+        let plain = Capabilities {
+            color: false,
+            ..Capabilities::default()
+        };
+        assert_eq!(render(&data, &config, plain), "yzxv1234 (main)");
     }
 
     #[test]
-    fn test_jj_format_truncated() {
-        let config = Config {
-            truncate_name: 5,
-            id_length: 8,
-            ancestor_bookmark_depth: 10,
-            jj_symbol: Cow::Borrowed(""),
-            git_symbol: Cow::Borrowed(""),
-            jj_display: DisplayConfig::all_visible(),
-            git_display: DisplayConfig::all_visible(),
-        };
+    fn test_render_multi_line_shell_escaping() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
-            change_id_prefix_len: 4,
-            bookmarks: vec![("very-long-bookmark-name".into(), 0)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: false,
+            change_id_prefix_len: 8,
+            bookmarks: vec![("100%".into(), 0)],
             is_synced: true,
+            ..JjInfo::default()
+        };
+        let data = PromptData::Jj(info);
+        let mut config = no_symbol_config();
+        config.jj_display.show_prefix_color = false;
+        config.format = Some(Format::parse(r"$change_id$fill$bookmarks\n> "));
+        let caps = |shell| Capabilities {
+            width: Some(16),
+            shell,
+            ..Capabilities::default()
         };
+
+        // $fill pads only its own line
         assert_eq!(
-            format_jj(&info, &config),
+            render(&data, &config, caps(Shell::Fish)),
+            format!("{PURPLE}yzxv1234{RESET}  {GREEN}(100%){RESET}\n> ")
+        );
+        assert_eq!(
+            render(&data, &config, caps(Shell::Bash)),
             format!(
-                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(very…){RESET}"
+                "\x01{PURPLE}\x02yzxv1234\x01{RESET}\x02  \x01{GREEN}\x02(100%)\x01{RESET}\x02\n> "
             )
         );
+        assert_eq!(
+            render(&data, &config, caps(Shell::Zsh)),
+            format!("%{{{PURPLE}%}}yzxv1234%{{{RESET}%}}  %{{{GREEN}%}}(100%%)%{{{RESET}%}}\n> ")
+        );
     }
 
     #[test]
-    fn test_jj_format_ancestor_bookmark() {
-        let info = JjInfo {
+    fn test_render_standalone() {
+        let data = PromptData::Jj(JjInfo {
             change_id: "yzxv1234".into(),
             change_id_prefix_len: 4,
-            bookmarks: vec![("main".into(), 3)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: true,
+            empty_desc: true,
             is_synced: true,
+            ..JjInfo::default()
+        });
+        let config = no_symbol_config();
+        let plain = Capabilities {
+            color: false,
+            ..Capabilities::default()
         };
         assert_eq!(
-            format_jj(&info, &no_symbol_config()),
-            format!(
-                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main~3){RESET}"
-            )
+            render_standalone(
+                "~/src/100%",
+                Some(Deferred::Ready(&data)),
+                &config,
+                plain,
+                true
+            ),
+            "~/src/100% on yzxv1234 [?] ❯ "
+        );
+        assert_eq!(
+            render_standalone("~/src", Some(Deferred::Pending), &config, plain, true),
+            "~/src … ❯ "
+        );
+        assert_eq!(
+            render_standalone("/tmp", None, &config, plain, true),
+            "/tmp ❯ "
+        );
+
+        // Failed command: red prompt char; zsh escaping covers the directory too
+        let zsh = Capabilities {
+            shell: Shell::Zsh,
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            render_standalone("100%", None, &config, zsh, false),
+            "%{\x1b[36m%}100%%%{\x1b[0m%} %{\x1b[31m%}❯%{\x1b[0m%} "
         );
     }
 
     #[test]
-    fn test_jj_format_no_bookmarks() {
+    fn test_jj_format_proquint_change_id() {
         let info = JjInfo {
-            change_id: "yzxv1234".into(),
-            change_id_prefix_len: 4,
-            bookmarks: vec![],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: false,
+            change_id: "skzzzzzy".into(),
+            change_id_prefix_len: 3,
             is_synced: true,
+            ..JjInfo::default()
         };
+        let mut config = no_symbol_config();
+        config.change_id_encoding = ChangeIdEncoding::Proquint;
+        // The unique prefix is highlighted in whole words
         assert_eq!(
-            format_jj(&info, &no_symbol_config()),
-            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET}")
+            format_jj(&info, &config),
+            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}lusab{RESET}{BRIGHT_BLACK}-babad{RESET}")
         );
+        config.jj_display.show_color = false;
+        assert_eq!(format_jj(&info, &config), "on lusab-babad");
     }
 
     #[test]
-    fn test_jj_format_multiple_bookmarks() {
+    fn test_jj_format_id_prefix_length() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
-            change_id_prefix_len: 4,
-            bookmarks: vec![("feature".into(), 1), ("main".into(), 2)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: false,
+            change_id_prefix_len: 2,
             is_synced: true,
+            ..JjInfo::default()
         };
+        let mut config = no_symbol_config();
         assert_eq!(
-            format_jj(&info, &no_symbol_config()),
-            format!(
-                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(feature~1, main~2){RESET}"
-            )
+            format_jj(&info, &config),
+            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yz{RESET}{BRIGHT_BLACK}xv1234{RESET}")
+        );
+        // A longer highlight past the unique prefix, never past the id
+        config.id_prefix_length = 4;
+        assert_eq!(
+            format_jj(&info, &config),
+            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET}")
+        );
+        config.id_prefix_length = 12;
+        assert_eq!(
+            format_jj(&info, &config),
+            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv1234{RESET}")
         );
     }
 
     #[test]
-    fn test_jj_format_no_color() {
+    fn test_hash_color_deterministic() {
+        // Pinned so a change keeps its color across runs and releases
+        assert_eq!(hash_color("yzxvtlmnqpsrwkkuozynqnxrpswvtlmz"), BRIGHT_BLUE);
+        assert_eq!(hash_color("zzzzzzzz"), YELLOW);
+        assert_eq!(hash_color("mmmmmmmm"), CYAN);
+        assert_eq!(hash_color("yyyyyyyy"), BRIGHT_MAGENTA);
+    }
+
+    #[test]
+    fn test_jj_format_hash_color() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
             change_id_prefix_len: 4,
-            bookmarks: vec![("main".into(), 0)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: true,
+            change_id_full: "yzxvtlmnqpsrwkkuozynqnxrpswvtlmz".into(),
             is_synced: true,
+            ..JjInfo::default()
         };
         let config = Config {
-            truncate_name: 0,
-            id_length: 8,
-            ancestor_bookmark_depth: 10,
-            jj_symbol: Cow::Borrowed("󱗆 "),
-            git_symbol: Cow::Borrowed(" "),
-            jj_display: DisplayConfig {
-                show_prefix: true,
-                show_name: true,
-                show_id: true,
-                show_status: true,
-                show_color: false,
-                show_prefix_color: true,
-            },
-            git_display: DisplayConfig::all_visible(),
+            hash_color: true,
+            ..no_symbol_config()
         };
-        assert_eq!(format_jj(&info, &config), "on 󱗆 yzxv1234 (main)");
+        let color = hash_color(&info.change_id_full);
+        assert_eq!(
+            format_jj(&info, &config),
+            format!("on {BLUE}{RESET}{color}yzxv{RESET}{BRIGHT_BLACK}1234{RESET}")
+        );
     }
 
     #[test]
-    fn test_jj_format_no_id_hides_change_id() {
+    fn test_jj_format_hash_bookmark_colors() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
             change_id_prefix_len: 4,
-            bookmarks: vec![("main".into(), 0)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: false,
+            bookmarks: vec![("main".into(), 0), ("feature-x".into(), 2)],
+            descendant_bookmark: Some(("main".into(), 1)),
             is_synced: true,
+            ..JjInfo::default()
         };
-        let config = Config {
-            truncate_name: 0,
-            id_length: 8,
-            ancestor_bookmark_depth: 10,
-            jj_symbol: Cow::Borrowed(""),
-            git_symbol: Cow::Borrowed(""),
-            jj_display: DisplayConfig {
-                show_prefix: true,
-                show_name: true,
-                show_id: false, // --no-jj-id
-                show_status: false,
-                show_color: true,
-                show_prefix_color: true,
-            },
-            git_display: DisplayConfig::all_visible(),
+        let mut config = Config {
+            hash_bookmark_colors: true,
+            ..no_symbol_config()
         };
-        // --no-jj-id hides change_id, shows only bookmarks
+        config.jj_display.show_prefix = false;
+        config.jj_display.show_id = false;
+        let (main, feature) = (hash_color("main"), hash_color("feature-x"));
+        assert_ne!(main, feature);
         assert_eq!(
             format_jj(&info, &config),
-            format!("on {BLUE}{RESET} {GREEN}(main){RESET}")
+            format!("({main}main{RESET}, {feature}feature-x~2{RESET}, {main}main-1{RESET})")
         );
+        config.jj_display.show_color = false;
+        assert_eq!(format_jj(&info, &config), "(main, feature-x~2, main-1)");
     }
 
+    #[cfg(feature = "serde")]
     #[test]
-    fn test_jj_format_no_name_hides_bookmarks() {
-        let info = JjInfo {
+    fn test_prompt_data_serde_roundtrip() {
+        let data = PromptData::Jj(JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 2)],
+            description: "fix parser\n".into(),
+            conflict: true,
+            signature: Some(Deferred::Ready(SigStatus::Good)),
+            checks: Some(Deferred::Pending),
+            tag: Some(Deferred::Ready(TagDistance {
+                tag: "v1.2.0".into(),
+                distance: 5,
+            })),
+            diff_stat: Some(Deferred::Ready(DiffStat {
+                added: 12,
+                removed: 3,
+            })),
+            ..JjInfo::default()
+        });
+        let text = toml_edit::ser::to_string(&data).unwrap();
+        assert!(text.contains("checks = \"pending\""), "{text}");
+        let back: PromptData = toml_edit::de::from_str(&text).unwrap();
+        assert_eq!(format!("{back:?}"), format!("{data:?}"));
+    }
+
+    #[test]
+    fn test_format_template() {
+        let ready = |text: &str| Deferred::Ready(text.to_string());
+        let config = Config::default();
+        assert_eq!(
+            format_template(Some(&ready("main fix")), &config, true).as_deref(),
+            Some("main fix")
+        );
+        assert_eq!(format_template(Some(&ready("")), &config, true), None);
+        assert_eq!(format_template(None, &config, true), None);
+        assert_eq!(
+            format_template(Some(&Deferred::Pending), &config, false).as_deref(),
+            Some("…")
+        );
+    }
+
+    #[test]
+    fn test_render_with_starship_palette() {
+        let data = PromptData::Jj(JjInfo {
             change_id: "yzxv1234".into(),
             change_id_prefix_len: 4,
             bookmarks: vec![("main".into(), 0)],
-            empty_desc: false,
-            conflict: false,
-            divergent: false,
-            has_remote: false,
             is_synced: true,
+            ..JjInfo::default()
+        });
+        let config = Config {
+            palette: Palette::parse(
+                "palette = \"p\"\n[palettes.p]\nbright-purple = \"#ff9e64\"\ncyan = \"33\"\n",
+            ),
+            ..no_symbol_config()
         };
+        let caps = Capabilities::default();
+        assert_eq!(
+            render(&data, &config, caps),
+            format!(
+                "on {BLUE}{RESET}\x1b[38;2;255;158;100myzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main){RESET}"
+            )
+        );
+        assert_eq!(
+            render_standalone("~", None, &config, caps, true),
+            format!("\x1b[38;5;33m~{RESET} {GREEN}❯{RESET} ")
+        );
+    }
+
+    #[test]
+    fn test_render_with_segment_styles() {
+        let data = PromptData::Jj(JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            bookmarks: vec![("main".into(), 0)],
+            is_synced: true,
+            ..JjInfo::default()
+        });
         let config = Config {
-            truncate_name: 0,
-            id_length: 8,
-            ancestor_bookmark_depth: 10,
-            jj_symbol: Cow::Borrowed(""),
-            git_symbol: Cow::Borrowed(""),
-            jj_display: DisplayConfig {
-                show_prefix: true,
-                show_name: false, // --no-jj-name
-                show_id: true,
-                show_status: false,
-                show_color: true,
-                show_prefix_color: true,
-            },
-            git_display: DisplayConfig::all_visible(),
+            palette: Palette::parse("palette = \"p\"\n[palettes.p]\naccent = \"#ff9e64\"\n"),
+            styles: Styles::new(
+                &["change_id=bold accent".into(), "bookmarks=sparkly".into()],
+                |_| None,
+            ),
+            ..no_symbol_config()
         };
-        // --no-jj-name hides bookmarks, shows only change_id with prefix coloring
+        // A styled segment drops its own colors; a style without any valid
+        // word keeps them
         assert_eq!(
-            format_jj(&info, &config),
-            format!("on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET}")
+            render(&data, &config, Capabilities::default()),
+            format!(
+                "on {BLUE}{RESET}\x1b[1m\x1b[38;2;255;158;100myzxv1234{RESET} {GREEN}(main){RESET}"
+            )
         );
+        let plain = Capabilities {
+            color: false,
+            ..Capabilities::default()
+        };
+        assert_eq!(render(&data, &config, plain), "on yzxv1234 (main)");
     }
 
     #[test]
-    fn test_jj_format_direct_bookmark_distance_zero() {
-        // Verifies that when WC has a direct bookmark (distance 0),
-        // it shows without ~N suffix even with ancestor search enabled
+    fn test_diff_stat_blocks_log_scaled() {
+        let stat = |added, removed| DiffStat { added, removed };
+        assert_eq!(diff_stat_blocks(stat(0, 0), 5), (0, 0));
+        assert_eq!(diff_stat_blocks(stat(1, 0), 5), (1, 0));
+        assert_eq!(diff_stat_blocks(stat(3, 4), 5), (1, 2));
+        assert_eq!(diff_stat_blocks(stat(1000, 0), 5), (5, 0));
+        // Small side still gets a block
+        assert_eq!(diff_stat_blocks(stat(1, 999), 5), (1, 4));
+        assert_eq!(diff_stat_blocks(stat(999, 1), 5), (4, 1));
+    }
+
+    #[test]
+    fn test_jj_format_diff_stat() {
         let info = JjInfo {
             change_id: "yzxv1234".into(),
             change_id_prefix_len: 4,
-            bookmarks: vec![("main".into(), 0)], // distance 0 = directly on WC
+            bookmarks: vec![],
             empty_desc: false,
             conflict: false,
             divergent: false,
             has_remote: false,
             is_synced: true,
+            diff_stat: Some(Deferred::Ready(DiffStat {
+                added: 12,
+                removed: 3,
+            })),
+            ..JjInfo::default()
         };
-        assert_eq!(
-            format_jj(&info, &no_symbol_config()),
-            format!(
-                "on {BLUE}{RESET}{BRIGHT_MAGENTA}yzxv{RESET}{BRIGHT_BLACK}1234{RESET} {GREEN}(main){RESET}"
-            )
-        );
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.diff_stat.enabled = true;
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 +12 -3");
+
+        config.diff_stat.bar = true;
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 ▰▰▰▰▱");
+
+        // Placeholder while a background refresh computes the value
+        let info = JjInfo {
+            diff_stat: Some(Deferred::Pending),
+            ..info
+        };
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 …");
+    }
+
+    #[test]
+    fn test_format_count_abbreviated() {
+        let mut config = Config::default();
+        let cases = [
+            (999, "999"),
+            (1000, "1k"),
+            (1234, "1.2k"),
+            (1299, "1.2k"),
+            (34_567, "34k"),
+            (999_999, "999k"),
+            (3_456_789, "3.4M"),
+            (1_234_567_890, "1.2G"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(config.format_count(n), expected, "{n}");
+        }
+
+        config.abbreviate_counts = 100_000;
+        assert_eq!(config.format_count(34_567), "34567");
+        config.abbreviate_counts = 0;
+        assert_eq!(config.format_count(3_456_789), "3456789");
+
+        let info = JjInfo {
+            change_id: "yzxv1234".into(),
+            change_id_prefix_len: 4,
+            diff_stat: Some(Deferred::Ready(DiffStat {
+                added: 123_456,
+                removed: 7_890,
+            })),
+            ..JjInfo::default()
+        };
+        let mut config = no_symbol_config();
+        config.jj_display.show_color = false;
+        config.jj_display.show_status = false;
+        config.diff_stat.enabled = true;
+        assert_eq!(format_jj(&info, &config), "on yzxv1234 +123k -7.8k");
     }
 
     #[cfg(feature = "git")]
@@ -497,8 +2727,8 @@ mod tests {
             untracked: 0,
             deleted: 0,
             conflicted: 0,
-            ahead: 0,
-            behind: 0,
+            ahead_behind: Deferred::Ready((0, 0)),
+            ..GitInfo::default()
         };
         assert_eq!(
             format_git(&info, &no_symbol_config()),
@@ -517,8 +2747,8 @@ mod tests {
             untracked: 1,
             deleted: 0,
             conflicted: 0,
-            ahead: 2,
-            behind: 1,
+            ahead_behind: Deferred::Ready((2, 1)),
+            ..GitInfo::default()
         };
         assert_eq!(
             format_git(&info, &no_symbol_config()),
@@ -539,8 +2769,8 @@ mod tests {
             untracked: 0,
             deleted: 0,
             conflicted: 0,
-            ahead: 0,
-            behind: 0,
+            ahead_behind: Deferred::Ready((0, 0)),
+            ..GitInfo::default()
         };
         assert_eq!(
             format_git(&info, &default_config()),
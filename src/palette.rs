@@ -0,0 +1,211 @@
+//! Named colors from the user's starship palette
+//!
+//! starship.toml can define `[palettes.<name>]` tables of named colors and pick
+//! one with `palette = "<name>"`. A palette entry named like a built-in color
+//! (`blue`, `bright-black`, ...) replaces that color everywhere in starship, so
+//! jj-starship does the same for the colors it renders with, keeping the
+//! segment in line with the rest of the prompt.
+
+use crate::color::{
+    BLUE, BRIGHT_BLACK, BRIGHT_BLUE, BRIGHT_CYAN, BRIGHT_GREEN, BRIGHT_MAGENTA, BRIGHT_YELLOW,
+    CYAN, GREEN, PURPLE, RED, YELLOW,
+};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use toml_edit::DocumentMut;
+
+/// Colors jj-starship renders with, by their starship names
+const BUILTIN: [(&str, &str); 12] = [
+    ("red", RED),
+    ("green", GREEN),
+    ("yellow", YELLOW),
+    ("blue", BLUE),
+    ("purple", PURPLE),
+    ("cyan", CYAN),
+    ("bright-green", BRIGHT_GREEN),
+    ("bright-yellow", BRIGHT_YELLOW),
+    ("bright-blue", BRIGHT_BLUE),
+    ("bright-purple", BRIGHT_MAGENTA),
+    ("bright-cyan", BRIGHT_CYAN),
+    ("bright-black", BRIGHT_BLACK),
+];
+
+/// ANSI color numbers of starship's color names (bright variants add 8)
+const ANSI_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "purple", "cyan", "white",
+];
+
+/// The active starship palette: color names to foreground escape sequences
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Palette {
+    colors: BTreeMap<String, String>,
+}
+
+impl Palette {
+    /// Palette of the user's starship config (`$STARSHIP_CONFIG`, else
+    /// `~/.config/starship.toml`), empty if there is none
+    pub fn load() -> Self {
+        let path = env::var_os("STARSHIP_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config/starship.toml")));
+        path.and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| Self::parse(&text))
+            .unwrap_or_default()
+    }
+
+    /// Palette selected by a starship config; entries that aren't valid
+    /// starship colors are skipped
+    pub fn parse(text: &str) -> Self {
+        let Ok(doc) = text.parse::<DocumentMut>() else {
+            return Self::default();
+        };
+        let Some(name) = doc.get("palette").and_then(|item| item.as_str()) else {
+            return Self::default();
+        };
+        let Some(table) = doc
+            .get("palettes")
+            .and_then(|palettes| palettes.get(name))
+            .and_then(|palette| palette.as_table_like())
+        else {
+            return Self::default();
+        };
+        let colors = table
+            .iter()
+            .filter_map(|(key, value)| Some((key.to_string(), parse_color(value.as_str()?)?)))
+            .collect();
+        Self { colors }
+    }
+
+    /// Escape sequence of a color defined by the palette
+    pub fn color(&self, name: &str) -> Option<&str> {
+        self.colors.get(name).map(String::as_str)
+    }
+
+    /// Replace the built-in colors in rendered `text` with the palette's
+    /// colors of the same names
+    pub fn recolor<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.colors.is_empty() || !text.contains('\x1b') {
+            return Cow::Borrowed(text);
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        // Sequences are replaced in one pass, so a palette mapping `blue` to
+        // `red` and `red` to `green` doesn't turn blue into green
+        while let Some(start) = rest.find('\x1b') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let replacement = BUILTIN
+                .iter()
+                .find(|(_, code)| rest.starts_with(code))
+                .and_then(|&(name, code)| Some((self.color(name)?, code.len())));
+            let len = if let Some((color, len)) = replacement {
+                out.push_str(color);
+                len
+            } else {
+                out.push('\x1b');
+                1
+            };
+            rest = &rest[len..];
+        }
+        out.push_str(rest);
+        Cow::Owned(out)
+    }
+}
+
+/// Foreground escape sequence of a starship color: a name like `blue` or
+/// `bright-blue`, an ANSI color number `0`-`255`, or a hex `#rrggbb`
+pub fn parse_color(value: &str) -> Option<String> {
+    let value = value.trim().to_ascii_lowercase();
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(format!(
+            "\x1b[38;2;{};{};{}m",
+            channel(0)?,
+            channel(2)?,
+            channel(4)?
+        ));
+    }
+    if let Ok(n) = value.parse::<u8>() {
+        return Some(format!("\x1b[38;5;{n}m"));
+    }
+    let (bright, name) = value
+        .strip_prefix("bright-")
+        .map_or((false, value.as_str()), |name| (true, name));
+    let n = ANSI_NAMES.iter().position(|&known| known == name)?;
+    Some(if bright {
+        format!("\x1b[{}m", 90 + n)
+    } else {
+        format!("\x1b[{}m", 30 + n)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::RESET;
+
+    const CONFIG: &str = r##"
+palette = "night"
+
+[palettes.day]
+blue = "#0000ff"
+
+[palettes.night]
+blue = "#7aa2f7"
+red = "bright-red"
+green = "114"
+accent = "purple"
+broken = "#12"
+"##;
+
+    #[test]
+    fn test_parse_selected_palette() {
+        let palette = Palette::parse(CONFIG);
+        assert_eq!(palette.color("blue"), Some("\x1b[38;2;122;162;247m"));
+        assert_eq!(palette.color("red"), Some("\x1b[91m"));
+        assert_eq!(palette.color("green"), Some("\x1b[38;5;114m"));
+        assert_eq!(palette.color("accent"), Some(PURPLE));
+        assert_eq!(palette.color("broken"), None);
+        assert_eq!(palette.color("cyan"), None);
+    }
+
+    #[test]
+    fn test_parse_without_palette() {
+        assert_eq!(
+            Palette::parse("[palettes.night]\nblue = \"red\"\n"),
+            Palette::default()
+        );
+        assert_eq!(
+            Palette::parse("palette = \"missing\"\n"),
+            Palette::default()
+        );
+        assert_eq!(Palette::parse("not toml ["), Palette::default());
+    }
+
+    #[test]
+    fn test_recolor_builtin_colors() {
+        let palette = Palette::parse(CONFIG);
+        let text = format!("on {BLUE}main{RESET} {RED}x{RESET} {CYAN}dir{RESET}");
+        assert_eq!(
+            palette.recolor(&text),
+            format!("on \x1b[38;2;122;162;247mmain{RESET} \x1b[91mx{RESET} {CYAN}dir{RESET}")
+        );
+        assert_eq!(Palette::default().recolor(&text), text);
+    }
+
+    #[test]
+    fn test_recolor_single_pass() {
+        let palette =
+            Palette::parse("palette = \"p\"\n[palettes.p]\nblue = \"red\"\nred = \"green\"\n");
+        assert_eq!(
+            palette.recolor(&format!("{BLUE}a{RED}b")),
+            format!("{RED}a{GREEN}b")
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Proquint encoding of change ids
+//!
+//! [Proquints](https://arxiv.org/html/0901.4016) spell 16 bits as a
+//! pronounceable consonant-vowel-consonant-vowel-consonant word, so the change
+//! id `zyxwvuts` (two 16-bit chunks) reads as `bahog-hijol` - easier to say
+//! and remember than a run of letters. A tail of fewer than four change id
+//! characters is kept as is after a last `-`.
+
+/// Consonants of a word, 4 bits each
+const CONSONANTS: &[u8; 16] = b"bdfghjklmnprstvz";
+/// Vowels of a word, 2 bits each
+const VOWELS: &[u8; 4] = b"aiou";
+/// Digits of jj's reverse hex change ids, `z` being 0
+const REVERSE_HEX: &[u8; 16] = b"zyxwvutsrqponmlk";
+
+/// Change id characters encoded per word
+const CHARS_PER_WORD: usize = 4;
+/// Length of a word
+const WORD_LEN: usize = 5;
+
+fn nibble(c: u8) -> Option<u16> {
+    REVERSE_HEX
+        .iter()
+        .position(|&d| d == c)
+        .map(|n| u16::try_from(n).expect("nibble"))
+}
+
+fn encode_word(value: u16) -> [u8; WORD_LEN] {
+    let consonant = |shift: u16| CONSONANTS[usize::from(value >> shift & 0xf)];
+    let vowel = |shift: u16| VOWELS[usize::from(value >> shift & 0x3)];
+    [
+        consonant(12),
+        vowel(10),
+        consonant(6),
+        vowel(4),
+        consonant(0),
+    ]
+}
+
+/// Proquint spelling of a (possibly shortened) reverse hex change id
+/// None if it isn't reverse hex.
+pub fn encode(change_id: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for chunk in change_id.as_bytes().chunks(CHARS_PER_WORD) {
+        if chunk.len() < CHARS_PER_WORD {
+            // The tail is kept as is, but must be a change id too
+            for &c in chunk {
+                nibble(c)?;
+            }
+            parts.push(String::from_utf8(chunk.to_vec()).ok()?);
+            break;
+        }
+        let value = chunk
+            .iter()
+            .try_fold(0, |value, &c| Some(value << 4 | nibble(c)?))?;
+        parts.push(String::from_utf8(encode_word(value).to_vec()).ok()?);
+    }
+    Some(parts.join("-"))
+}
+
+/// Length of the part of `encoded` spelling the first `prefix_len` change id
+/// characters, rounded up to whole words so a highlighted unique prefix stays
+/// pronounceable
+pub fn encoded_prefix_len(encoded: &str, prefix_len: usize) -> usize {
+    let mut covered = 0;
+    let mut len = 0;
+    for part in encoded.split('-') {
+        if covered >= prefix_len {
+            break;
+        }
+        if len > 0 {
+            len += 1;
+        }
+        if part.len() == WORD_LEN {
+            covered += CHARS_PER_WORD;
+            len += WORD_LEN;
+        } else {
+            let take = part.len().min(prefix_len - covered);
+            covered += take;
+            len += take;
+        }
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_word(word: &[u8]) -> Option<u16> {
+        let [c1, v1, c2, v2, c3] = word else {
+            return None;
+        };
+        let consonant = |c: &u8| CONSONANTS.iter().position(|d| d == c);
+        let vowel = |v: &u8| VOWELS.iter().position(|d| d == v);
+        let value = consonant(c1)? << 12
+            | vowel(v1)? << 10
+            | consonant(c2)? << 6
+            | vowel(v2)? << 4
+            | consonant(c3)?;
+        u16::try_from(value).ok()
+    }
+
+    /// Change id spelled by `encode`'s output (None if it isn't one)
+    fn decode(encoded: &str) -> Option<String> {
+        let mut change_id = String::new();
+        let mut parts = encoded.split('-').peekable();
+        while let Some(part) = parts.next() {
+            if part.len() == WORD_LEN {
+                let value = decode_word(part.as_bytes())?;
+                for shift in [12, 8, 4, 0] {
+                    change_id.push(char::from(REVERSE_HEX[usize::from(value >> shift & 0xf)]));
+                }
+            } else if parts.peek().is_none() && part.len() < CHARS_PER_WORD {
+                for c in part.bytes() {
+                    nibble(c)?;
+                }
+                change_id.push_str(part);
+            } else {
+                return None;
+            }
+        }
+        Some(change_id)
+    }
+
+    #[test]
+    fn test_encode_known_values() {
+        assert_eq!(encode("zzzz").as_deref(), Some("babab"));
+        assert_eq!(encode("kkkk").as_deref(), Some("zuzuz"));
+        // 0x7f00 0x0001, the paper's 127.0.0.1
+        assert_eq!(encode("skzzzzzy").as_deref(), Some("lusab-babad"));
+        assert_eq!(encode("").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_encode_keeps_tail() {
+        assert_eq!(encode("zzzzyx").as_deref(), Some("babab-yx"));
+        assert_eq!(encode("yx").as_deref(), Some("yx"));
+        assert_eq!(encode("abcd"), None);
+        assert_eq!(encode("zzzza"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for change_id in [
+            "",
+            "k",
+            "yzxv",
+            "yzxvtlmn",
+            "qpvuntsmwlqt",
+            "kkkkzzzzrs",
+            "zyxwvutsrqponmlk",
+        ] {
+            let encoded = encode(change_id).unwrap();
+            assert_eq!(decode(&encoded).as_deref(), Some(change_id), "{encoded}");
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_non_proquints() {
+        assert_eq!(decode("babab-zzzz"), None);
+        assert_eq!(decode("yx-babab"), None);
+        assert_eq!(decode("aaaaa"), None);
+        assert_eq!(decode("babab-ab"), None);
+    }
+
+    #[test]
+    fn test_encoded_prefix_len() {
+        let encoded = "lusab-babad-yx";
+        assert_eq!(encoded_prefix_len(encoded, 0), 0);
+        assert_eq!(encoded_prefix_len(encoded, 1), 5);
+        assert_eq!(encoded_prefix_len(encoded, 4), 5);
+        assert_eq!(encoded_prefix_len(encoded, 5), 11);
+        assert_eq!(encoded_prefix_len(encoded, 9), 13);
+        assert_eq!(encoded_prefix_len(encoded, 10), 14);
+        assert_eq!(encoded_prefix_len(encoded, 20), 14);
+    }
+}
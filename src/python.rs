@@ -0,0 +1,190 @@
+//! Python bindings, the `jj_starship_py` module
+//!
+//! Built with maturin (see `pyproject.toml`), which enables the `python`
+//! feature. Python prompt frameworks and scripts then get the in-process path
+//! without spawning the binary:
+//!
+//! ```python
+//! import jj_starship_py
+//!
+//! jj_starship_py.render(".", {"diffstat": True, "no_color": True})
+//! jj_starship_py.collect(".")["change_id"]
+//! ```
+//!
+//! Options are given as a dict keyed like the config files (`id_length`,
+//! `diffstat`, ...) and layered over them, so the user's `jj-starship.toml`
+//! still applies. The GIL is released while a repo is read.
+
+// The pyo3 macros expand to unsafe FFI glue
+#![allow(unsafe_code)]
+
+use crate::config::{Config, ConfigArgs};
+use crate::detect;
+use crate::json;
+use crate::output::Capabilities;
+use crate::sources::Sources;
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, DocumentMut, Value};
+
+/// Repo state of `path` as a dict, like `--json`; None outside a repo
+#[pyfunction]
+#[pyo3(signature = (path, config = None))]
+fn collect<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    config: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let config = load_config(&path, config)?;
+    let text = py.detach(|| json::render(&start_dir(path, &config), &config));
+    py.import("json")?.call_method1("loads", (text,))
+}
+
+/// Prompt of `path`; None outside a repo or if it can't be read
+#[pyfunction]
+#[pyo3(signature = (path, config = None))]
+fn render(
+    py: Python<'_>,
+    path: PathBuf,
+    config: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Option<String>> {
+    let config = load_config(&path, config)?;
+    Ok(
+        py.detach(|| {
+            crate::run_prompt(&start_dir(path, &config), &config, Capabilities::default())
+        }),
+    )
+}
+
+#[pymodule]
+fn jj_starship_py(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(collect, module)?)?;
+    module.add_function(wrap_pyfunction!(render, module)?)?;
+    Ok(())
+}
+
+/// Config from the files of `path` with the `options` dict over them
+fn load_config(path: &Path, options: Option<&Bound<'_, PyDict>>) -> PyResult<Config> {
+    let mut sources = Sources::default();
+    if let Some(options) = options {
+        sources = sources.with_document(to_document(options)?);
+    }
+    let args = ConfigArgs::default();
+    let sources = sources.read_files(path, None, &args);
+    Ok(Config::new(args, &sources))
+}
+
+fn start_dir(path: PathBuf, config: &Config) -> PathBuf {
+    detect::start_dir(path, config.resolve_symlinks)
+}
+
+/// Config file equivalent of an options dict: booleans, numbers, strings and
+/// lists of strings
+fn to_document(options: &Bound<'_, PyDict>) -> PyResult<DocumentMut> {
+    let mut doc = DocumentMut::new();
+    for (key, value) in options {
+        let key: String = key.extract()?;
+        doc[key.as_str()] = toml_edit::value(to_value(&key, &value)?);
+    }
+    Ok(doc)
+}
+
+fn to_value(key: &str, value: &Bound<'_, PyAny>) -> PyResult<Value> {
+    // bool before int: Python's bools are ints
+    if let Ok(flag) = value.extract::<bool>() {
+        Ok(flag.into())
+    } else if let Ok(n) = value.extract::<i64>() {
+        Ok(n.into())
+    } else if let Ok(n) = value.extract::<f64>() {
+        Ok(n.into())
+    } else if let Ok(text) = value.extract::<String>() {
+        Ok(text.into())
+    } else if let Ok(items) = value.cast::<PyList>() {
+        let items = items
+            .iter()
+            .map(|item| item.extract::<String>())
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(Value::Array(items.iter().collect::<Array>()))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "option {key}: expected a bool, number, string or list of strings"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyModule;
+
+    fn module(py: Python<'_>) -> Bound<'_, PyModule> {
+        let module = PyModule::new(py, "jj_starship_py").unwrap();
+        jj_starship_py(&module).unwrap();
+        module
+    }
+
+    #[test]
+    fn test_outside_repo() {
+        Python::initialize();
+        let dir = tempfile::tempdir().unwrap();
+        Python::attach(|py| {
+            let module = module(py);
+            let path = dir.path().to_path_buf();
+            let prompt = module.getattr("render").unwrap().call1((&path,)).unwrap();
+            assert!(prompt.is_none());
+            let state = module.getattr("collect").unwrap().call1((&path,)).unwrap();
+            assert!(state.is_none());
+        });
+    }
+
+    #[test]
+    fn test_jj_repo() {
+        Python::initialize();
+        let dir = tempfile::tempdir().unwrap();
+        let settings = crate::jj::create_user_settings().unwrap();
+        jj_lib::workspace::Workspace::init_simple(&settings, dir.path()).unwrap();
+        Python::attach(|py| {
+            let module = module(py);
+            let path = dir.path().to_path_buf();
+            let options = PyDict::new(py);
+            options.set_item("no_color", true).unwrap();
+            options.set_item("no_symbol", true).unwrap();
+            let prompt: String = module
+                .getattr("render")
+                .unwrap()
+                .call1((&path, &options))
+                .unwrap()
+                .extract()
+                .unwrap();
+            let state = module.getattr("collect").unwrap().call1((&path,)).unwrap();
+            let change_id: String = state.get_item("change_id").unwrap().extract().unwrap();
+            assert!(
+                prompt.starts_with(&format!("on {}", &change_id[..4])),
+                "{prompt}"
+            );
+            let repo: String = state.get_item("repo").unwrap().extract().unwrap();
+            assert_eq!(repo, "jj");
+        });
+    }
+
+    #[test]
+    fn test_options() {
+        Python::initialize();
+        Python::attach(|py| {
+            let options = PyDict::new(py);
+            options.set_item("id_length", 6).unwrap();
+            options.set_item("diffstat", true).unwrap();
+            options.set_item("trailers", vec!["Fixes"]).unwrap();
+            let sources = Sources::default().with_document(to_document(&options).unwrap());
+            let config = Config::new(ConfigArgs::default(), &sources);
+            assert_eq!(config.id_length, 6);
+            assert!(config.diff_stat.enabled);
+            assert_eq!(config.trailers, ["Fixes"]);
+
+            options.set_item("format", PyDict::new(py)).unwrap();
+            assert!(to_document(&options).is_err());
+        });
+    }
+}
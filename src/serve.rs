@@ -0,0 +1,312 @@
+//! `jj-starship serve`: prompt data over HTTP on localhost
+//!
+//! Answers `GET /prompt?path=<dir>` with a JSON object holding the rendered
+//! prompt and the fields of `get`, so GUI widgets, SwiftBar/xbar scripts and
+//! browser dashboards can show repo status without spawning a process per
+//! refresh. Relative paths are resolved against `--cwd`.
+//!
+//! The server only listens on the loopback interface, and requests must name
+//! a local host in their `Host` header, so a web page can't reach it through
+//! DNS rebinding. Browsers only let pages read responses from origins given
+//! with `--allow-origin`.
+
+use crate::batch::json_string;
+use crate::config::Config;
+use crate::detect;
+use crate::get::{self, Field};
+use crate::output::{self, Capabilities};
+use clap::ValueEnum;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+
+/// Default port of `serve`
+pub const DEFAULT_PORT: u16 = 7463;
+
+/// How long a client may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request line and headers beyond this are refused
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// Serve requests on `127.0.0.1:port` one at a time, until the listener fails
+///
+/// # Errors
+/// Binding the port or accepting on it failed
+pub fn run(
+    cwd: &Path,
+    config: &Config,
+    caps: Capabilities,
+    port: u16,
+    allow_origin: Option<&str>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    eprintln!("jj-starship: serving on http://{}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        // A client going away mid-request only affects that request
+        let _ = stream.and_then(|stream| handle(stream, cwd, config, caps, allow_origin));
+    }
+    Ok(())
+}
+
+fn handle(
+    mut stream: TcpStream,
+    cwd: &Path,
+    config: &Config,
+    caps: Capabilities,
+    allow_origin: Option<&str>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let request = read_request(&stream)?;
+    let (status, body) = respond(request.as_ref(), cwd, config, caps);
+    let mut head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+        body.len()
+    );
+    if let Some(origin) = allow_origin {
+        let _ = write!(head, "Access-Control-Allow-Origin: {origin}\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+/// Method, target and `Host` header of an HTTP request
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    method: String,
+    target: String,
+    host: Option<String>,
+}
+
+/// Read the request line and headers (None if malformed or too large)
+fn read_request(stream: &TcpStream) -> io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream).take(MAX_REQUEST as u64);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+    let mut host = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            // Cut off before the blank line ending the headers
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            }
+        }
+    }
+    Ok(Some(Request {
+        method,
+        target,
+        host,
+    }))
+}
+
+/// Status line and JSON body answering `request`
+fn respond(
+    request: Option<&Request>,
+    cwd: &Path,
+    config: &Config,
+    caps: Capabilities,
+) -> (&'static str, String) {
+    let error = |status, message: &str| (status, format!("{{\"error\":{}}}", json_string(message)));
+    let Some(request) = request else {
+        return error("400 Bad Request", "malformed request");
+    };
+    if !request.host.as_deref().is_some_and(is_local_host) {
+        return error("403 Forbidden", "not a local host");
+    }
+    if request.method != "GET" {
+        return error("405 Method Not Allowed", "only GET is supported");
+    }
+    let (route, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((&request.target, ""));
+    if route != "/prompt" {
+        return error("404 Not Found", "no such endpoint, try /prompt?path=...");
+    }
+    let Some(path) = query_param(query, "path") else {
+        return error("400 Bad Request", "missing path parameter");
+    };
+    ("200 OK", prompt_json(&path, cwd, config, caps))
+}
+
+/// Whether a `Host` header names this machine (with or without a port)
+fn is_local_host(host: &str) -> bool {
+    let name = if host.starts_with('[') {
+        host.split_inclusive(']').next().unwrap_or(host)
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+    matches!(name, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+/// Percent-decoded value of `name` in a query string
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (percent_decode(key) == name).then(|| percent_decode(value))
+    })
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = text
+                    .get(i + 1..i + 3)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                if let Some(byte) = hex {
+                    out.push(byte);
+                    i += 2;
+                } else {
+                    out.push(b'%');
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// `{"path": ..., "prompt": ..., "fields": {...}}` for the repo containing
+/// `path`, with `prompt` and fields that don't apply null
+fn prompt_json(path: &str, cwd: &Path, config: &Config, caps: Capabilities) -> String {
+    let start = detect::start_dir(cwd.join(path), config.resolve_symlinks);
+    let result = detect::detect(&start, config.discovery);
+    let data = crate::collect(&result, config);
+    let prompt = data.as_ref().map(|data| output::render(data, config, caps));
+    let fields: Vec<String> = Field::value_variants()
+        .iter()
+        .map(|&field| {
+            let name = field
+                .to_possible_value()
+                .map(|value| value.get_name().replace('-', "_"))
+                .unwrap_or_default();
+            let value = get::value(&result, data.as_ref(), field)
+                .map_or_else(|| "null".to_string(), |value| json_string(&value));
+            format!("{}:{value}", json_string(&name))
+        })
+        .collect();
+    format!(
+        "{{\"path\":{},\"prompt\":{},\"fields\":{{{}}}}}",
+        json_string(path),
+        prompt.map_or_else(|| "null".to_string(), |prompt| json_string(&prompt)),
+        fields.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, target: &str, host: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            host: Some(host.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(
+            query_param("path=%2Fhome%2Fme%2Fmy+repo&x=1", "path").as_deref(),
+            Some("/home/me/my repo")
+        );
+        assert_eq!(query_param("x=1", "path"), None);
+        assert_eq!(query_param("path=100%", "path").as_deref(), Some("100%"));
+    }
+
+    #[test]
+    fn test_is_local_host() {
+        assert!(is_local_host("localhost:7463"));
+        assert!(is_local_host("127.0.0.1"));
+        assert!(is_local_host("[::1]:7463"));
+        assert!(!is_local_host("evil.example:7463"));
+        assert!(!is_local_host("localhost.evil.example"));
+    }
+
+    #[test]
+    fn test_respond() {
+        let tmp = tempfile::tempdir().unwrap();
+        let respond = |request| {
+            respond(
+                Some(&request),
+                tmp.path(),
+                &Config::default(),
+                Capabilities::default(),
+            )
+        };
+
+        let (status, body) = respond(request("GET", "/prompt?path=.", "localhost:7463"));
+        assert_eq!(status, "200 OK");
+        assert!(body.starts_with(r#"{"path":".","prompt":null,"fields":{"repo":null,"#));
+
+        assert_eq!(
+            respond(request("GET", "/prompt", "localhost")).0,
+            "400 Bad Request"
+        );
+        assert_eq!(
+            respond(request("POST", "/prompt?path=.", "localhost")).0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(respond(request("GET", "/", "localhost")).0, "404 Not Found");
+        assert_eq!(
+            respond(request("GET", "/prompt?path=.", "rebound.example")).0,
+            "403 Forbidden"
+        );
+    }
+
+    #[test]
+    fn test_serves_over_tcp() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle(
+                stream,
+                &root,
+                &Config::default(),
+                Capabilities::default(),
+                Some("*"),
+            )
+            .unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        write!(
+            client,
+            "GET /prompt?path=. HTTP/1.1\r\nHost: {addr}\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+        assert!(response.ends_with("}}"));
+    }
+}
@@ -0,0 +1,1478 @@
+//! Snapshot tests rendering fixture repos end to end
+//!
+//! Fixtures are built with jj-lib (and git2) in temp dirs with a fixed
+//! randomness seed, so change ids and output are stable. Review changes with
+//! `cargo insta review`.
+
+use crate::batch::{self, BatchFormat};
+use crate::cache::{Cache, Deferred};
+use crate::check::{self, Check};
+use crate::config::{ChangeIdEncoding, Config, TagConfig};
+use crate::detect;
+use crate::exit::Failure;
+use crate::get;
+use crate::jj::{self, create_user_settings};
+use crate::output::{Capabilities, PromptData, Shell, render};
+use crate::template;
+use crate::timing;
+use crate::{RepoStatus, try_run_prompt};
+use jj_lib::backend::{CopyId, TreeValue};
+use jj_lib::commit::Commit;
+use jj_lib::config::{ConfigLayer, ConfigSource};
+use jj_lib::merge::Merge;
+use jj_lib::merged_tree::MergedTreeBuilder;
+use jj_lib::object_id::ObjectId;
+use jj_lib::op_store::{RefTarget, RemoteRef, RemoteRefState};
+use jj_lib::ref_name::{RefName, RemoteName, WorkspaceName};
+use jj_lib::repo::{ReadonlyRepo, Repo};
+use jj_lib::repo_path::RepoPathBuf;
+use jj_lib::rewrite::merge_commit_trees;
+use jj_lib::settings::UserSettings;
+use jj_lib::signing::{SigStatus, SignBehavior};
+use jj_lib::transaction::Transaction;
+use jj_lib::workspace::Workspace;
+use pollster::FutureExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+const NO_COLOR: Capabilities = Capabilities {
+    color: false,
+    width: None,
+    shell: Shell::None,
+};
+
+/// Settings with fixed change-id randomness and timestamps
+fn fixture_settings() -> UserSettings {
+    let mut config = create_user_settings().unwrap().config().clone();
+    let mut debug = ConfigLayer::empty(ConfigSource::User);
+    debug.set_value("debug.randomness-seed", 42).unwrap();
+    debug
+        .set_value("debug.commit-timestamp", "2001-02-03T04:05:06+07:00")
+        .unwrap();
+    debug
+        .set_value("debug.operation-timestamp", "2001-02-03T04:05:06+07:00")
+        .unwrap();
+    config.add_layer(debug);
+    UserSettings::from_config(config).unwrap()
+}
+
+/// Fresh jj repo with an empty working-copy commit
+fn init_jj() -> (TempDir, Arc<ReadonlyRepo>) {
+    init_jj_with(&fixture_settings())
+}
+
+fn init_jj_with(settings: &UserSettings) -> (TempDir, Arc<ReadonlyRepo>) {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, repo) = Workspace::init_simple(settings, dir.path()).unwrap();
+    (dir, repo)
+}
+
+/// Write a commit on `parent` with the given description and file contents
+fn write_commit(
+    tx: &mut Transaction,
+    parent: &Commit,
+    description: &str,
+    files: &[(&str, &str)],
+) -> Commit {
+    let store = tx.repo().store().clone();
+    let mut tree = MergedTreeBuilder::new(parent.tree());
+    for (path, contents) in files {
+        let path = RepoPathBuf::from_internal_string(*path).unwrap();
+        let id = store
+            .write_file(&path, &mut contents.as_bytes())
+            .block_on()
+            .unwrap();
+        tree.set_or_remove(
+            path,
+            Merge::normal(TreeValue::File {
+                id,
+                executable: false,
+                copy_id: CopyId::placeholder(),
+            }),
+        );
+    }
+    let tree = tree.write_tree().unwrap();
+    tx.repo_mut()
+        .new_commit(vec![parent.id().clone()], tree)
+        .set_description(description)
+        .write()
+        .unwrap()
+}
+
+/// Make `commit` the working copy and publish the transaction
+fn edit_and_commit(mut tx: Transaction, commit: &Commit) -> Arc<ReadonlyRepo> {
+    tx.repo_mut()
+        .edit(WorkspaceName::DEFAULT.to_owned(), commit)
+        .unwrap();
+    // Editing abandons the initial empty working-copy commit
+    tx.repo_mut().rebase_descendants().unwrap();
+    tx.commit("fixture").unwrap()
+}
+
+fn set_bookmark(tx: &mut Transaction, name: &str, commit: &Commit) {
+    tx.repo_mut()
+        .set_local_bookmark_target(RefName::new(name), RefTarget::normal(commit.id().clone()));
+}
+
+fn render_jj(root: &Path, config: &Config, caps: Capabilities) -> String {
+    let info = jj::collect(root, config).unwrap();
+    render(&PromptData::Jj(info), config, caps)
+}
+
+/// Config showing every segment that is off by default
+fn full_config() -> Config {
+    let mut config = Config {
+        jj_symbol: "".into(),
+        git_symbol: "".into(),
+        ..Config::default()
+    };
+    config.diff_stat.enabled = true;
+    config.description.enabled = true;
+    config.issue_key.enabled = true;
+    config.trailers = vec!["Fixes".into()];
+    config
+}
+
+#[test]
+fn snapshot_jj_fresh_repo() {
+    let (dir, _) = init_jj();
+    insta::assert_snapshot!(render_jj(dir.path(), &Config::default(), NO_COLOR));
+}
+
+#[test]
+fn test_repo_status_gather() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wip = write_commit(&mut tx, &root, "wip", &[]);
+    set_bookmark(&mut tx, "feature", &wip);
+    edit_and_commit(tx, &wip);
+
+    let config = full_config();
+    let status = RepoStatus::gather(&dir.path().join("."), &config).unwrap();
+    assert_eq!(status.repo_type, detect::RepoType::Jj);
+    // Irrefutable without the `git` feature
+    #[allow(irrefutable_let_patterns)]
+    let PromptData::Jj(info) = &status.info else {
+        panic!("not a jj repo: {status:?}");
+    };
+    assert_eq!(info.bookmarks, [("feature".to_string(), 0)]);
+    assert_eq!(info.description, "wip");
+    assert_eq!(
+        status.render(&config, NO_COLOR),
+        render_jj(dir.path(), &config, NO_COLOR)
+    );
+    assert!(RepoStatus::gather(Path::new("/"), &config).is_none());
+}
+
+#[test]
+fn test_try_run_prompt_failures() {
+    let config = Config {
+        discovery: detect::Discovery {
+            max_depth: Some(0),
+            ..detect::Discovery::default()
+        },
+        ..full_config()
+    };
+    let (dir, _repo) = init_jj();
+    assert_eq!(
+        try_run_prompt(dir.path(), &config, NO_COLOR),
+        Ok(render_jj(dir.path(), &config, NO_COLOR))
+    );
+
+    let empty = tempfile::tempdir().unwrap();
+    assert_eq!(
+        try_run_prompt(empty.path(), &config, NO_COLOR),
+        Err(Failure::NotARepo)
+    );
+    // A `.jj` with no repo store in it
+    std::fs::create_dir_all(empty.path().join(".jj/repo/store")).unwrap();
+    assert_eq!(
+        try_run_prompt(empty.path(), &config, NO_COLOR),
+        Err(Failure::LoadFailed)
+    );
+}
+
+#[test]
+fn test_debug_timing_phases() {
+    let (dir, _repo) = init_jj();
+    let config = full_config();
+    let (prompt, timings) = timing::run(dir.path(), &config, NO_COLOR);
+    assert_eq!(prompt, Some(render_jj(dir.path(), &config, NO_COLOR)));
+    let names = timings.names();
+    for phase in [
+        "detect",
+        "load workspace",
+        "op heads",
+        "load repo",
+        "diffstat",
+        "render",
+    ] {
+        assert!(names.contains(&phase), "{phase} missing from {names:?}");
+    }
+    assert_eq!(names.first(), Some(&"detect"));
+    assert_eq!(names.last(), Some(&"render"));
+}
+
+#[test]
+fn snapshot_jj_batch_stdin() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wc = write_commit(&mut tx, &root, "add docs", &[("docs/a.md", "a\n")]);
+    set_bookmark(&mut tx, "docs", &wc);
+    edit_and_commit(tx, &wc);
+    std::fs::create_dir(dir.path().join("docs")).unwrap();
+    let outside = tempfile::tempdir().unwrap();
+
+    // The repo root, a subdirectory (relative to the cwd) and a non-repo
+    let input = format!(
+        "{}\ndocs\n{}\n",
+        dir.path().display(),
+        outside.path().display()
+    );
+    let mut output = Vec::new();
+    batch::run(
+        dir.path(),
+        &full_config(),
+        NO_COLOR,
+        BatchFormat::Json,
+        input.as_bytes(),
+        &mut output,
+    )
+    .unwrap();
+    let output = String::from_utf8(output)
+        .unwrap()
+        .replace(&dir.path().display().to_string(), "[REPO]")
+        .replace(&outside.path().display().to_string(), "[OUTSIDE]");
+    insta::assert_snapshot!(output);
+}
+
+#[test]
+fn snapshot_jj_get_fields() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "initial", &[("a.txt", "a\n")]);
+    set_bookmark(&mut tx, "main", &base);
+    let wc = write_commit(
+        &mut tx,
+        &base,
+        "add b\n",
+        &[("a.txt", "a\n"), ("b.txt", "b\n")],
+    );
+    set_bookmark(&mut tx, "feature", &wc);
+    edit_and_commit(tx, &wc);
+
+    let output: Vec<String> = <get::Field as clap::ValueEnum>::value_variants()
+        .iter()
+        .map(|field| {
+            let name = clap::ValueEnum::to_possible_value(field).unwrap();
+            let value = get::get(dir.path(), &Config::default(), *field);
+            format!("{}: {value:?}", name.get_name())
+        })
+        .collect();
+    insta::assert_snapshot!(
+        output
+            .join("\n")
+            .replace(&dir.path().display().to_string(), "[REPO]")
+    );
+}
+
+#[test]
+fn snapshot_jj_check_flags() {
+    let checks = |root: &Path| {
+        [Check::HasConflicts, Check::IsEmpty, Check::IsStale]
+            .map(|check| {
+                format!(
+                    "{check:?}: {}",
+                    check::all_hold(root, &Config::default(), &[check])
+                )
+            })
+            .join("\n")
+    };
+    let (dir, repo) = init_jj();
+    let fresh = checks(dir.path());
+
+    // Rewriting @ without updating the files on disk, as another workspace would
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wc = write_commit(&mut tx, &root, "add a", &[("a.txt", "a\n")]);
+    edit_and_commit(tx, &wc);
+    insta::assert_snapshot!(format!(
+        "fresh repo:\n{fresh}\n\nrewritten @:\n{}",
+        checks(dir.path())
+    ));
+}
+
+#[test]
+fn snapshot_jj_stack_with_ancestor_bookmark() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "initial", &[("a.txt", "a\nb\nc\n")]);
+    let feature = write_commit(
+        &mut tx,
+        &base,
+        "feat: add parser",
+        &[("a.txt", "a\nB\nc\n")],
+    );
+    set_bookmark(&mut tx, "main", &base);
+    set_bookmark(&mut tx, "PROJ-42-parser", &feature);
+    let wc = write_commit(
+        &mut tx,
+        &feature,
+        "wip: parser tests",
+        &[("a.txt", "a\nB\nc\nd\ne\n"), ("b.txt", "new\n")],
+    );
+    edit_and_commit(tx, &wc);
+
+    let config = full_config();
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+
+    let mut bar = full_config();
+    bar.diff_stat.bar = true;
+    insta::assert_snapshot!(
+        "snapshot_jj_stack_with_ancestor_bookmark_colored",
+        render_jj(dir.path(), &bar, Capabilities::default()).replace('\x1b', "\\e")
+    );
+}
+
+#[test]
+fn snapshot_jj_bookmark_on_wc_with_trailers() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wc = write_commit(
+        &mut tx,
+        &root,
+        "Fix crash on empty input\n\nFixes: #123\n",
+        &[("src.rs", "fn main() {}\n")],
+    );
+    set_bookmark(&mut tx, "fix-crash", &wc);
+    edit_and_commit(tx, &wc);
+
+    insta::assert_snapshot!(render_jj(dir.path(), &full_config(), NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_edit_into_history_descendant_bookmark() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let mid = write_commit(&mut tx, &base, "mid", &[("a.txt", "b\n")]);
+    let tip = write_commit(&mut tx, &mid, "tip", &[("a.txt", "c\n")]);
+    let side = write_commit(&mut tx, &tip, "side", &[("b.txt", "d\n")]);
+    set_bookmark(&mut tx, "release", &base);
+    set_bookmark(&mut tx, "main", &tip);
+    set_bookmark(&mut tx, "side", &side);
+    edit_and_commit(tx, &mid);
+
+    insta::assert_snapshot!(render_jj(dir.path(), &full_config(), NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_immutable_heads_alias_stops_ancestor_walk() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let old = write_commit(&mut tx, &root, "old", &[("a.txt", "a\n")]);
+    let stable = write_commit(&mut tx, &old, "stable", &[("a.txt", "b\n")]);
+    let wc = write_commit(&mut tx, &stable, "wip", &[("a.txt", "c\n")]);
+    set_bookmark(&mut tx, "old", &old);
+    set_bookmark(&mut tx, "stable", &stable);
+    edit_and_commit(tx, &wc);
+
+    let before = render_jj(dir.path(), &full_config(), NO_COLOR);
+    std::fs::write(
+        dir.path().join(".jj/repo/config.toml"),
+        "[revset-aliases]\n'immutable_heads()' = 'bookmarks(exact:\"stable\")'\n",
+    )
+    .unwrap();
+    let after = render_jj(dir.path(), &full_config(), NO_COLOR);
+    // --config-toml overrides win over the repo config
+    let overridden = render_jj(
+        dir.path(),
+        &Config {
+            jj_config_toml: vec!["revset-aliases.'immutable_heads()' = 'none()'".into()],
+            ..full_config()
+        },
+        NO_COLOR,
+    );
+    insta::assert_snapshot!(format!("{before}\n{after}\n{overridden}"));
+}
+
+#[test]
+fn test_jj_broken_immutable_heads_alias() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let old = write_commit(&mut tx, &root, "old", &[("a.txt", "a\n")]);
+    let wc = write_commit(&mut tx, &old, "wip", &[("a.txt", "b\n")]);
+    set_bookmark(&mut tx, "old", &old);
+    edit_and_commit(tx, &wc);
+
+    let expected = render_jj(dir.path(), &full_config(), NO_COLOR);
+    // A definition that doesn't parse falls back to the built-in heads
+    for config in [
+        Config {
+            jj_config_toml: vec!["revset-aliases.'immutable_heads()' = 'bogus(('".into()],
+            ..full_config()
+        },
+        Config {
+            trunk_revset: Some("bogus((".into()),
+            ..full_config()
+        },
+    ] {
+        assert_eq!(render_jj(dir.path(), &config, NO_COLOR), expected);
+    }
+}
+
+#[test]
+fn snapshot_jj_unpushed_bookmarks() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let wc = write_commit(&mut tx, &base, "feature", &[("a.txt", "b\n")]);
+    let old = write_commit(&mut tx, &root, "forgotten", &[("c.txt", "c\n")]);
+    set_bookmark(&mut tx, "main", &base);
+    set_bookmark(&mut tx, "feature", &wc);
+    set_bookmark(&mut tx, "old-experiment", &old);
+    // Only `main` was pushed; `feature` also exists on origin but isn't tracked
+    for (name, state) in [
+        ("main", RemoteRefState::Tracked),
+        ("feature", RemoteRefState::New),
+    ] {
+        tx.repo_mut().set_remote_bookmark(
+            RefName::new(name).to_remote_symbol(RemoteName::new("origin")),
+            RemoteRef {
+                target: RefTarget::normal(base.id().clone()),
+                state,
+            },
+        );
+    }
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        unpushed_bookmarks: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_anonymous_heads() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let trunk = write_commit(&mut tx, &root, "trunk", &[("a.txt", "a\n")]);
+    let wc = write_commit(&mut tx, &trunk, "wip", &[("a.txt", "b\n")]);
+    write_commit(&mut tx, &trunk, "abandoned idea", &[("b.txt", "b\n")]);
+    write_commit(&mut tx, &root, "old experiment", &[("c.txt", "c\n")]);
+    let named = write_commit(&mut tx, &trunk, "named", &[("d.txt", "d\n")]);
+    set_bookmark(&mut tx, "named", &named);
+    set_bookmark(&mut tx, "main", &trunk);
+    tx.repo_mut().set_remote_bookmark(
+        RefName::new("main").to_remote_symbol(RemoteName::new("origin")),
+        RemoteRef {
+            target: RefTarget::normal(trunk.id().clone()),
+            state: RemoteRefState::Tracked,
+        },
+    );
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        anonymous_heads: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_stacks() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let trunk = write_commit(&mut tx, &root, "trunk", &[("a.txt", "a\n")]);
+    let first = write_commit(&mut tx, &trunk, "first", &[("a.txt", "b\n")]);
+    write_commit(&mut tx, &first, "second", &[("a.txt", "c\n")]);
+    let parked = write_commit(&mut tx, &trunk, "parked", &[("b.txt", "b\n")]);
+    set_bookmark(&mut tx, "parked", &parked);
+    write_commit(&mut tx, &trunk, "idea", &[("c.txt", "c\n")]);
+    write_commit(&mut tx, &root, "off trunk", &[("d.txt", "d\n")]);
+    set_bookmark(&mut tx, "main", &trunk);
+    tx.repo_mut().set_remote_bookmark(
+        RefName::new("main").to_remote_symbol(RemoteName::new("origin")),
+        RemoteRef {
+            target: RefTarget::normal(trunk.id().clone()),
+            state: RemoteRefState::Tracked,
+        },
+    );
+    // @ in the middle of its stack: the stack's head isn't another stack
+    edit_and_commit(tx, &first);
+
+    let config = Config {
+        stacks: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_trunk_distance() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let fix = write_commit(&mut tx, &base, "upstream fix", &[("b.txt", "b\n")]);
+    let trunk = write_commit(&mut tx, &fix, "upstream feature", &[("c.txt", "c\n")]);
+    let first = write_commit(&mut tx, &base, "first", &[("a.txt", "b\n")]);
+    let wc = write_commit(&mut tx, &first, "second", &[("a.txt", "c\n")]);
+    tx.repo_mut().set_remote_bookmark(
+        RefName::new("main").to_remote_symbol(RemoteName::new("origin")),
+        RemoteRef {
+            target: RefTarget::normal(trunk.id().clone()),
+            state: RemoteRefState::New,
+        },
+    );
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        trunk_distance: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+
+    // Out of time, counting stops and the placeholder renders instead
+    let config = Config {
+        timeout: Some(Duration::from_nanos(1)),
+        ..config
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.trunk_distance, Some(Deferred::Pending));
+    assert!(render_jj(dir.path(), &config, NO_COLOR).starts_with("on rqttpzyw … second"));
+}
+
+#[test]
+fn test_jj_timeout_defers_template() {
+    let (dir, _repo) = init_jj();
+    // Without the cache no background refresh is spawned
+    let config = Config {
+        template: Some("description".into()),
+        cache: false,
+        timeout: Some(Duration::from_nanos(1)),
+        ..full_config()
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.template, Some(Deferred::Pending));
+}
+
+#[test]
+fn test_jj_template_failure_not_cached() {
+    let (dir, repo) = init_jj();
+    // Fails whether or not `jj` is installed
+    let template = "bogus((";
+    let config = Config {
+        template: Some(template.into()),
+        ..full_config()
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.template, Some(Deferred::Ready(String::new())));
+    let key = template::cache_key(
+        &repo.op_id().hex(),
+        &repo
+            .view()
+            .get_wc_commit_id(WorkspaceName::DEFAULT)
+            .unwrap()
+            .hex(),
+        template,
+        &[],
+    );
+    let cache = Cache::new(detect::jj_cache_dir(dir.path()));
+    assert_eq!(cache.get_text("template", &key), None);
+    assert!(cache.get("template_error", &key).is_some());
+    assert!(jj::check_template(dir.path(), template, &config).is_err());
+}
+
+#[test]
+fn test_jj_change_id_covers_unique_prefix() {
+    let (dir, _repo) = init_jj();
+    // Like `jj log`'s `shortest(0)`: just the unique prefix
+    let config = Config {
+        id_length: 0,
+        ..full_config()
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert!(info.change_id_prefix_len > 0);
+    assert_eq!(
+        info.change_id,
+        info.change_id_full[..info.change_id_prefix_len]
+    );
+    let config = Config {
+        id_length: 12,
+        ..config
+    };
+    assert_eq!(
+        jj::collect(dir.path(), &config).unwrap().change_id.len(),
+        12
+    );
+    // Longer than the id: the full id
+    let config = Config {
+        id_length: 40,
+        ..config
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.change_id, info.change_id_full);
+    let config = Config {
+        change_id_encoding: ChangeIdEncoding::Proquint,
+        ..config
+    };
+    assert!(render_jj(dir.path(), &config, NO_COLOR).starts_with("on "));
+}
+
+#[test]
+fn test_jj_trunk_revset() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let develop = write_commit(&mut tx, &base, "on develop", &[("b.txt", "b\n")]);
+    let first = write_commit(&mut tx, &base, "first", &[("a.txt", "b\n")]);
+    let wc = write_commit(&mut tx, &first, "second", &[("a.txt", "c\n")]);
+    set_bookmark(&mut tx, "develop", &develop);
+    edit_and_commit(tx, &wc);
+
+    // Without a remote `main`, jj's `trunk()` is the root and there's no distance
+    let config = Config {
+        trunk_distance: true,
+        ..full_config()
+    };
+    assert_eq!(
+        jj::collect(dir.path(), &config).unwrap().trunk_distance,
+        None
+    );
+
+    let config = Config {
+        trunk_revset: Some("bookmarks(exact:develop)".into()),
+        ..config
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.trunk_distance, Some(Deferred::Ready((2, 1))));
+
+    // The revset may use the user's jj aliases
+    let config = Config {
+        trunk_revset: Some("mainline".into()),
+        jj_config_toml: vec!["revset-aliases.mainline = 'bookmarks(exact:develop)'".into()],
+        ..config
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.trunk_distance, Some(Deferred::Ready((2, 1))));
+}
+
+#[test]
+fn test_jj_config_cache() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let develop = write_commit(&mut tx, &base, "on develop", &[("b.txt", "b\n")]);
+    let wc = write_commit(&mut tx, &base, "first", &[("a.txt", "b\n")]);
+    set_bookmark(&mut tx, "develop", &develop);
+    edit_and_commit(tx, &wc);
+    let repo_config = dir.path().join(".jj/repo/config.toml");
+    let cached = detect::jj_cache_dir(dir.path()).join("jj_config");
+    let trunk_distance = |config: &Config| jj::collect(dir.path(), config).unwrap().trunk_distance;
+
+    let config = Config {
+        trunk_distance: true,
+        ..full_config()
+    };
+    std::fs::write(
+        &repo_config,
+        "[revset-aliases]\n'trunk()' = 'bookmarks(exact:develop)'\n",
+    )
+    .unwrap();
+    assert_eq!(trunk_distance(&config), Some(Deferred::Ready((1, 1))));
+    // Read back from the cache while the file is unchanged
+    let text = std::fs::read_to_string(&cached).unwrap();
+    let key = text.lines().next().unwrap();
+    std::fs::write(
+        &cached,
+        format!("{key}\n[revset-aliases]\n'trunk()' = 'root()'\n"),
+    )
+    .unwrap();
+    assert_eq!(trunk_distance(&config), None);
+    std::fs::write(&cached, text).unwrap();
+    assert_eq!(trunk_distance(&config), Some(Deferred::Ready((1, 1))));
+
+    // An edited config file is read again
+    std::fs::write(&repo_config, "[revset-aliases]\n'trunk()' = 'root()'\n").unwrap();
+    assert_eq!(trunk_distance(&config), None);
+
+    // --no-cache neither reads nor writes it
+    std::fs::remove_file(&cached).unwrap();
+    let config = Config {
+        cache: false,
+        ..config
+    };
+    assert_eq!(trunk_distance(&config), None);
+    assert!(!cached.exists());
+}
+
+#[test]
+fn test_jj_config_scopes() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let develop = write_commit(&mut tx, &base, "on develop", &[("b.txt", "b\n")]);
+    let wc = write_commit(&mut tx, &base, "first", &[("a.txt", "b\n")]);
+    set_bookmark(&mut tx, "develop", &develop);
+    edit_and_commit(tx, &wc);
+    // Only the scope of this repo applies, also under a scope of a higher
+    // layer (which doesn't replace the lower layer's scopes)
+    std::fs::write(
+        dir.path().join(".jj/repo/config.toml"),
+        format!(
+            "[[--scope]]\n--when.repositories = ['/elsewhere']\n\
+             [--scope.revset-aliases]\n'trunk()' = 'root()'\n\
+             [[--scope]]\n--when.repositories = [{:?}]\n\
+             [--scope.revset-aliases]\n'trunk()' = 'bookmarks(exact:develop)'\n",
+            dir.path().display().to_string(),
+        ),
+    )
+    .unwrap();
+    let config = Config {
+        trunk_distance: true,
+        jj_config_toml: vec![
+            "[[--scope]]\n--when.commands = ['status']\n[--scope.ui]\ncolor = 'never'\n".into(),
+        ],
+        ..full_config()
+    };
+    let trunk_distance = |config: &Config| jj::collect(dir.path(), config).unwrap().trunk_distance;
+    assert_eq!(trunk_distance(&config), Some(Deferred::Ready((1, 1))));
+    // Read back from the cache
+    assert_eq!(trunk_distance(&config), Some(Deferred::Ready((1, 1))));
+}
+
+#[test]
+fn snapshot_jj_remote_distance() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let pushed = write_commit(&mut tx, &base, "pushed elsewhere", &[("b.txt", "b\n")]);
+    let first = write_commit(&mut tx, &base, "first", &[("a.txt", "b\n")]);
+    let wc = write_commit(&mut tx, &first, "second", &[("a.txt", "c\n")]);
+    set_bookmark(&mut tx, "feature", &wc);
+    tx.repo_mut().set_remote_bookmark(
+        RefName::new("feature").to_remote_symbol(RemoteName::new("origin")),
+        RemoteRef {
+            target: RefTarget::normal(pushed.id().clone()),
+            state: RemoteRefState::Tracked,
+        },
+    );
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        remote_distance: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+/// Child of `base` with `a.txt` conflicted between `left` and `right`
+fn write_conflicted(tx: &mut Transaction, base: &Commit) -> Commit {
+    let store = tx.repo().store().clone();
+    let path = RepoPathBuf::from_internal_string("a.txt").unwrap();
+    let file = |contents: &str| {
+        let id = store
+            .write_file(&path, &mut contents.as_bytes())
+            .block_on()
+            .unwrap();
+        Some(TreeValue::File {
+            id,
+            executable: false,
+            copy_id: CopyId::placeholder(),
+        })
+    };
+    let mut tree = MergedTreeBuilder::new(base.tree());
+    tree.set_or_remove(
+        path.clone(),
+        Merge::from_vec(vec![file("left\n"), file("a\n"), file("right\n")]),
+    );
+    let tree = tree.write_tree().unwrap();
+    tx.repo_mut()
+        .new_commit(vec![base.id().clone()], tree)
+        .set_description("conflicted")
+        .write()
+        .unwrap()
+}
+
+#[test]
+fn snapshot_jj_conflict_mid_stack() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let conflicted = write_conflicted(&mut tx, &base);
+    // Resolved in @, so only the commit below is conflicted
+    let wc = write_commit(&mut tx, &conflicted, "resolve", &[("a.txt", "left\n")]);
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        stack_conflicts: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_conflict_marker() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let base = write_commit(&mut tx, &root, "base", &[("a.txt", "a\n")]);
+    let wc = write_conflicted(&mut tx, &base);
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        conflict_marker: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+/// Fixture settings signing with jj-lib's test backend, which signs with a
+/// hash, so verification needs no gpg
+fn signing_settings() -> UserSettings {
+    let mut config = fixture_settings().config().clone();
+    let mut signing = ConfigLayer::empty(ConfigSource::User);
+    signing.set_value("signing.backend", "test").unwrap();
+    signing.set_value("signing.key", "fixture").unwrap();
+    config.add_layer(signing);
+    UserSettings::from_config(config).unwrap()
+}
+
+#[test]
+fn snapshot_jj_signed_commit() {
+    let (dir, repo) = init_jj_with(&signing_settings());
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let unsigned = write_commit(&mut tx, &root, "unsigned", &[("a.txt", "a\n")]);
+    let signed = tx
+        .repo_mut()
+        .rewrite_commit(&unsigned)
+        .set_description("signed")
+        .set_sign_behavior(SignBehavior::Own)
+        .write()
+        .unwrap();
+    edit_and_commit(tx, &signed);
+
+    let config = Config {
+        signature: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn test_jj_signature_of_parent_and_unsigned() {
+    let (dir, repo) = init_jj_with(&signing_settings());
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let unsigned = write_commit(&mut tx, &root, "unsigned", &[("a.txt", "a\n")]);
+    let signed = tx
+        .repo_mut()
+        .rewrite_commit(&unsigned)
+        .set_description("signed")
+        .set_sign_behavior(SignBehavior::Own)
+        .write()
+        .unwrap();
+    // A fresh `jj new` on top: the parent is what gets pushed
+    let fresh = write_commit(&mut tx, &signed, "", &[]);
+    let repo = edit_and_commit(tx, &fresh);
+
+    let config = Config {
+        signature: true,
+        warn_unsigned: true,
+        ..full_config()
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.signature, Some(Deferred::Ready(SigStatus::Good)));
+    assert!(!info.unsigned);
+
+    // Described, @ itself counts, and it isn't signed
+    let mut tx = repo.start_transaction();
+    let wip = write_commit(&mut tx, &signed, "wip", &[]);
+    edit_and_commit(tx, &wip);
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.signature, None);
+    assert!(info.unsigned);
+    assert!(render_jj(dir.path(), &config, NO_COLOR).ends_with(" unsigned"));
+}
+
+#[test]
+fn snapshot_jj_cached_checks() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let pushed = write_commit(&mut tx, &root, "feature", &[("a.txt", "a\n")]);
+    let wc = write_commit(&mut tx, &pushed, "wip", &[("a.txt", "b\n")]);
+    set_bookmark(&mut tx, "feature", &pushed);
+    tx.repo_mut().set_remote_bookmark(
+        RefName::new("feature").to_remote_symbol(RemoteName::new("origin")),
+        RemoteRef {
+            target: RefTarget::normal(pushed.id().clone()),
+            state: RemoteRefState::Tracked,
+        },
+    );
+    edit_and_commit(tx, &wc);
+
+    // A fresh cache entry (failed checks) answers without asking the forge
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Cache::new(detect::jj_cache_dir(dir.path())).put(
+        "checks",
+        &pushed.id().hex(),
+        &[1, usize::try_from(now).unwrap()],
+    );
+
+    let config = Config {
+        checks: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+
+    // A miss never asks the forge in the prompt, even without --async
+    let config = Config {
+        cache: false,
+        ..config
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.checks, Some(Deferred::Pending));
+}
+
+#[test]
+fn snapshot_jj_cached_template() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wc = write_commit(&mut tx, &root, "fix parser", &[("a.txt", "a\n")]);
+    let repo = edit_and_commit(tx, &wc);
+
+    // An entry for this operation answers without spawning jj
+    let template = r#"separate(" ", bookmarks, description.first_line())"#;
+    Cache::new(detect::jj_cache_dir(dir.path())).put_text(
+        "template",
+        &template::cache_key(&repo.op_id().hex(), &wc.id().hex(), template, &[]),
+        "fix parser",
+    );
+
+    let mut config = Config {
+        template: Some(template.into()),
+        ..full_config()
+    };
+    config.description.enabled = false;
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn jj_snapshot_only_when_enabled() {
+    let (dir, _) = init_jj();
+    std::fs::write(dir.path().join("new.txt"), "new\n").unwrap();
+    let mut config = full_config();
+
+    // Read-only by default: the edit isn't in @ until something snapshots
+    assert!(jj::collect(dir.path(), &config).unwrap().empty);
+    config.snapshot = true;
+    assert!(!jj::collect(dir.path(), &config).unwrap().empty);
+    config.snapshot = false;
+    assert!(!jj::collect(dir.path(), &config).unwrap().empty);
+}
+
+#[test]
+fn jj_prefix_len_cached_per_operation() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wc = write_commit(&mut tx, &root, "wip", &[("a.txt", "a\n")]);
+    let repo = edit_and_commit(tx, &wc);
+
+    let config = full_config();
+    let computed = jj::collect(dir.path(), &config)
+        .unwrap()
+        .change_id_prefix_len;
+    let cache = Cache::new(detect::jj_cache_dir(dir.path()));
+    let key = format!("{}-{}", repo.op_id().hex(), wc.change_id().hex());
+    assert_eq!(cache.get("prefix", &key), Some(vec![computed]));
+
+    // Later prompts of the same operation read the entry
+    cache.put("prefix", &key, &[computed + 3]);
+    let info = jj::collect(dir.path(), &config).unwrap();
+    assert_eq!(info.change_id_prefix_len, computed + 3);
+}
+
+#[test]
+fn snapshot_jj_stack_diff() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let trunk = write_commit(&mut tx, &root, "trunk", &[("a.txt", "a\nb\n")]);
+    let first = write_commit(&mut tx, &trunk, "first", &[("a.txt", "a\nc\n")]);
+    let wc = write_commit(&mut tx, &first, "second", &[("b.txt", "1\n2\n3\n")]);
+    // trunk moved on after the stack forked; that's not part of the stack
+    write_commit(&mut tx, &trunk, "upstream", &[("c.txt", "c\n")]);
+    set_bookmark(&mut tx, "main", &trunk);
+    tx.repo_mut().set_remote_bookmark(
+        RefName::new("main").to_remote_symbol(RemoteName::new("origin")),
+        RemoteRef {
+            target: RefTarget::normal(trunk.id().clone()),
+            state: RemoteRefState::Tracked,
+        },
+    );
+    edit_and_commit(tx, &wc);
+
+    let render = |base: &str| {
+        let config = Config {
+            stack_diff: Some(base.into()),
+            ..full_config()
+        };
+        render_jj(dir.path(), &config, NO_COLOR)
+    };
+    insta::assert_snapshot!([render("trunk()"), render("@-"), render("nonexistent")].join("\n"));
+}
+
+#[test]
+fn snapshot_jj_file_changes() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let files = [
+        ("keep.txt", "a\n"),
+        ("edit.txt", "a\n"),
+        ("drop.txt", "a\n"),
+    ];
+    let base = write_commit(&mut tx, &root, "base", &files);
+    let edited = write_commit(
+        &mut tx,
+        &base,
+        "wip",
+        &[
+            ("edit.txt", "b\n"),
+            ("new-1.txt", "x\n"),
+            ("new-2.txt", "y\n"),
+        ],
+    );
+    let mut tree = MergedTreeBuilder::new(edited.tree());
+    tree.set_or_remove(
+        RepoPathBuf::from_internal_string("drop.txt").unwrap(),
+        Merge::absent(),
+    );
+    let wc = tx
+        .repo_mut()
+        .rewrite_commit(&edited)
+        .set_tree(tree.write_tree().unwrap())
+        .write()
+        .unwrap();
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        file_changes: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_workspace() {
+    let (dir, repo) = init_jj();
+    let second = tempfile::tempdir().unwrap();
+    let (_, repo) = Workspace::init_workspace_with_existing_repo(
+        second.path(),
+        &dir.path().join(".jj").join("repo"),
+        &repo,
+        &*jj_lib::workspace::default_working_copy_factory(),
+        WorkspaceName::new("feature").to_owned(),
+    )
+    .unwrap();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wip = write_commit(&mut tx, &root, "wip", &[("a.txt", "a\n")]);
+    tx.repo_mut()
+        .edit(WorkspaceName::new("feature").to_owned(), &wip)
+        .unwrap();
+    tx.repo_mut().rebase_descendants().unwrap();
+    tx.commit("fixture").unwrap();
+
+    let config = full_config();
+    // The default workspace isn't named
+    assert!(!render_jj(dir.path(), &config, NO_COLOR).contains('@'));
+    insta::assert_snapshot!(render_jj(second.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_concurrent_operations() {
+    let (dir, repo) = init_jj();
+    // Two transactions from the same operation leave two op heads
+    for description in ["one", "two"] {
+        let mut tx = repo.start_transaction();
+        let root = repo.store().root_commit();
+        write_commit(&mut tx, &root, description, &[]);
+        tx.commit(description).unwrap();
+    }
+
+    let info = jj::collect(dir.path(), &full_config()).unwrap();
+    // Read at the newest head, without merging them
+    assert_eq!(info.op_heads, 2);
+    assert_eq!(info.operation, "two");
+    insta::assert_snapshot!(render_jj(dir.path(), &full_config(), NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_immutable() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let release = write_commit(&mut tx, &root, "release", &[("a.txt", "a\n")]);
+    let hotfix = write_commit(&mut tx, &release, "hotfix", &[("a.txt", "b\n")]);
+    tx.repo_mut().set_local_tag_target(
+        RefName::new("v1.0.0"),
+        RefTarget::normal(release.id().clone()),
+    );
+    let repo = edit_and_commit(tx, &hotfix);
+
+    let config = Config {
+        immutable: true,
+        ..full_config()
+    };
+    // On top of a tag: mutable itself, but not a commit to build on
+    let on_tag = render_jj(dir.path(), &config, NO_COLOR);
+
+    // On the tag itself, which jj won't rewrite
+    edit_and_commit(repo.start_transaction(), &release);
+    insta::assert_snapshot!(format!(
+        "{on_tag}\n{}",
+        render_jj(dir.path(), &config, NO_COLOR)
+    ));
+}
+
+#[test]
+fn snapshot_jj_untracked() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let files = [("tracked.txt", "a\n"), (".gitignore", "target/\n*.log\n")];
+    let base = write_commit(&mut tx, &root, "base", &files);
+    edit_and_commit(tx, &base);
+    for (path, content) in [
+        ("tracked.txt", "a\n"),
+        (".gitignore", "target/\n*.log\n"),
+        ("notes.txt", "todo\n"),
+        ("src/new.rs", "fn main() {}\n"),
+        ("debug.log", "trace\n"),
+        ("target/debug/app", "bin\n"),
+    ] {
+        let path = dir.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    let config = Config {
+        untracked: true,
+        ..full_config()
+    };
+    let info = jj::collect(dir.path(), &config).unwrap();
+    // The ignored directory counts once, without being walked
+    assert!(matches!(
+        info.untracked,
+        Some(Deferred::Ready(files)) if files.untracked == 2 && files.ignored == 2
+    ));
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+
+    // Only what the next snapshot would start tracking counts
+    for (jj_config_toml, untracked) in [
+        ("snapshot.auto-track = 'glob:\"src/**\"'", 1),
+        ("snapshot.auto-track = 'none()'", 0),
+        ("snapshot.max-new-file-size = 5", 1),
+        ("snapshot.max-new-file-size = 0", 2),
+    ] {
+        let config = Config {
+            jj_config_toml: vec![jj_config_toml.into()],
+            ..config.clone()
+        };
+        let info = jj::collect(dir.path(), &config).unwrap();
+        assert!(
+            matches!(
+                info.untracked,
+                Some(Deferred::Ready(files)) if files.untracked == untracked && files.ignored == 2
+            ),
+            "{jj_config_toml}"
+        );
+    }
+}
+
+#[test]
+fn snapshot_jj_tag_distance() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let old = write_commit(&mut tx, &root, "old release", &[("a.txt", "a\n")]);
+    let release = write_commit(&mut tx, &old, "release", &[("a.txt", "b\n")]);
+    let fix = write_commit(&mut tx, &release, "fix", &[("a.txt", "c\n")]);
+    let wc = write_commit(&mut tx, &fix, "wip", &[("a.txt", "d\n")]);
+    for (name, commit) in [("v1.0.0", &old), ("v1.1.0", &release)] {
+        tx.repo_mut()
+            .set_local_tag_target(RefName::new(name), RefTarget::normal(commit.id().clone()));
+    }
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        tag: TagConfig {
+            enabled: true,
+            distance: true,
+            semver: false,
+        },
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_megamerge() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let feat_a = write_commit(&mut tx, &root, "feat a", &[("a.txt", "a\n")]);
+    let feat_b = write_commit(&mut tx, &root, "feat b", &[("b.txt", "b\n")]);
+    let feat_c = write_commit(&mut tx, &root, "feat c", &[("c.txt", "c\n")]);
+    let wip = write_commit(&mut tx, &root, "wip", &[("d.txt", "d\n")]);
+    set_bookmark(&mut tx, "feat-a", &feat_a);
+    set_bookmark(&mut tx, "feat-b", &feat_b);
+    set_bookmark(&mut tx, "feat-c", &feat_c);
+    let parents = [&feat_a, &wip, &feat_b, &feat_c];
+    let tree = merge_commit_trees(tx.repo(), &parents.map(Commit::clone))
+        .block_on()
+        .unwrap();
+    let merge = tx
+        .repo_mut()
+        .new_commit(parents.iter().map(|c| c.id().clone()).collect(), tree)
+        .set_description("megamerge")
+        .write()
+        .unwrap();
+    // @ on top of the merge, as after `jj new`
+    let wc = write_commit(&mut tx, &merge, "", &[]);
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        megamerge: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_parent() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let trunk = write_commit(&mut tx, &root, "trunk", &[("a.txt", "a\n")]);
+    set_bookmark(&mut tx, "main", &trunk);
+    // @ started with `jj new main`
+    let wc = write_commit(&mut tx, &trunk, "", &[]);
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        parent: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[test]
+fn snapshot_jj_parent_merge() {
+    let (dir, repo) = init_jj();
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let feat = write_commit(&mut tx, &root, "feat", &[("a.txt", "a\n")]);
+    let fix = write_commit(&mut tx, &root, "fix", &[("b.txt", "b\n")]);
+    set_bookmark(&mut tx, "feat", &feat);
+    // @ started with `jj new feat fix`
+    let parents = [&feat, &fix];
+    let tree = merge_commit_trees(tx.repo(), &parents.map(Commit::clone))
+        .block_on()
+        .unwrap();
+    let wc = tx
+        .repo_mut()
+        .new_commit(parents.iter().map(|c| c.id().clone()).collect(), tree)
+        .write()
+        .unwrap();
+    edit_and_commit(tx, &wc);
+
+    let config = Config {
+        parent: true,
+        ..full_config()
+    };
+    insta::assert_snapshot!(render_jj(dir.path(), &config, NO_COLOR));
+}
+
+#[cfg(feature = "git")]
+#[test]
+fn snapshot_jj_colocated_git_staged() {
+    let dir = tempfile::tempdir().unwrap();
+    Workspace::init_colocated_git(&fixture_settings(), dir.path()).unwrap();
+    let mut config = full_config();
+    config.git_staged = true;
+    let before = render_jj(dir.path(), &config, NO_COLOR);
+
+    // `git add` stages a file that jj's view of the working copy doesn't share
+    std::fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    let git_repo = git2::Repository::open(dir.path()).unwrap();
+    let mut index = git_repo.index().unwrap();
+    index.add_path(Path::new("a.txt")).unwrap();
+    index.write().unwrap();
+    insta::assert_snapshot!(format!(
+        "{before}\n{}",
+        render_jj(dir.path(), &config, NO_COLOR)
+    ));
+}
+
+#[cfg(feature = "git")]
+#[test]
+fn snapshot_jj_colocated_git_head() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, repo) = Workspace::init_colocated_git(&fixture_settings(), dir.path()).unwrap();
+    let config = Config {
+        git_head: true,
+        ..full_config()
+    };
+    let before = render_jj(dir.path(), &config, NO_COLOR);
+
+    // `git commit` on a branch, behind jj's back
+    let git_repo = git2::Repository::open(dir.path()).unwrap();
+    let tree = git_repo
+        .find_tree(git_repo.treebuilder(None).unwrap().write().unwrap())
+        .unwrap();
+    let sig = git2::Signature::new("a", "a@b", &git2::Time::new(981_147_906, 0)).unwrap();
+    git_repo
+        .commit(Some("refs/heads/main"), &sig, &sig, "git", &tree, &[])
+        .unwrap();
+    git_repo.set_head("refs/heads/main").unwrap();
+    let moved_in_git = render_jj(dir.path(), &config, NO_COLOR);
+
+    // A bookmark jj hasn't exported yet
+    let mut tx = repo.start_transaction();
+    let root = repo.store().root_commit();
+    let wip = write_commit(&mut tx, &root, "wip", &[]);
+    set_bookmark(&mut tx, "feature", &wip);
+    tx.commit("fixture").unwrap();
+    insta::assert_snapshot!(format!(
+        "{before}\n{moved_in_git}\n{}",
+        render_jj(dir.path(), &config, NO_COLOR)
+    ));
+}
+
+#[cfg(feature = "git")]
+#[test]
+fn snapshot_git_dirty_worktree() {
+    use crate::diff_stat::DiffStat;
+    use crate::git;
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let repo = git2::Repository::init(dir.path()).unwrap();
+    fs::write(dir.path().join("a.txt"), "a\nb\n").unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new("a.txt")).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = git2::Signature::new("a", "a@b", &git2::Time::new(981_147_906, 0)).unwrap();
+    repo.commit(Some("refs/heads/main"), &sig, &sig, "initial", &tree, &[])
+        .unwrap();
+    repo.set_head("refs/heads/main").unwrap();
+    fs::write(dir.path().join("a.txt"), "a\nc\nd\n").unwrap();
+    fs::write(dir.path().join("new.txt"), "x\n").unwrap();
+
+    let config = full_config();
+    let info = git::collect(dir.path(), &config).unwrap();
+    insta::assert_snapshot!(render(&PromptData::Git(info), &config, NO_COLOR));
+
+    // Cached while the worktree is unchanged, so async mode has it at once
+    let diff_stat = |config: &Config| git::collect(dir.path(), config).unwrap().diff_stat;
+    let ready = |added, removed| Some(Deferred::Ready(DiffStat { added, removed }));
+    let async_config = Config {
+        async_segments: true,
+        ..full_config()
+    };
+    assert_eq!(diff_stat(&async_config), ready(2, 1));
+    // An edit is a miss, left to the background in async mode or out of time
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    let uncached = Config {
+        cache: false,
+        ..async_config
+    };
+    assert_eq!(diff_stat(&uncached), Some(Deferred::Pending));
+    let out_of_time = Config {
+        async_segments: false,
+        timeout: Some(Duration::from_nanos(1)),
+        ..uncached
+    };
+    assert_eq!(diff_stat(&out_of_time), Some(Deferred::Pending));
+    assert_eq!(diff_stat(&config), ready(0, 1));
+}
+
+#[cfg(feature = "git")]
+#[test]
+fn snapshot_git_tag_distance() {
+    use crate::git;
+
+    let dir = tempfile::tempdir().unwrap();
+    let repo = git2::Repository::init(dir.path()).unwrap();
+    let tree = repo
+        .find_tree(repo.index().unwrap().write_tree().unwrap())
+        .unwrap();
+    let sig = git2::Signature::new("a", "a@b", &git2::Time::new(981_147_906, 0)).unwrap();
+    let mut parents = Vec::new();
+    for message in ["release", "fix", "feature"] {
+        let parent_commits: Vec<git2::Commit> = parents
+            .iter()
+            .map(|&oid| repo.find_commit(oid).unwrap())
+            .collect();
+        let parent_refs: Vec<&git2::Commit> = parent_commits.iter().collect();
+        let oid = repo
+            .commit(
+                Some("refs/heads/main"),
+                &sig,
+                &sig,
+                message,
+                &tree,
+                &parent_refs,
+            )
+            .unwrap();
+        if message == "release" {
+            // Annotated, so the tag has to be peeled to its commit
+            let commit = repo.find_commit(oid).unwrap();
+            repo.tag("v2.0.0", commit.as_object(), &sig, "v2", false)
+                .unwrap();
+        }
+        parents = vec![oid];
+    }
+    repo.set_head("refs/heads/main").unwrap();
+
+    let config = Config {
+        tag: TagConfig {
+            enabled: true,
+            distance: true,
+            semver: true,
+        },
+        ..full_config()
+    };
+    let info = git::collect(dir.path(), &config).unwrap();
+    insta::assert_snapshot!(render(&PromptData::Git(info), &config, NO_COLOR));
+}
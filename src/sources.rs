@@ -0,0 +1,334 @@
+//! Option values beyond the CLI: env vars, then config files
+//!
+//! Every option with a `JJ_STARSHIP_*` env var can also be set in a TOML file,
+//! under the env var's name without the prefix, lowercased:
+//! `JJ_STARSHIP_ID_LENGTH=6` is `id_length = 6`, `JJ_STARSHIP_ASYNC` is
+//! `async = true`. Like jj's `StackedConfig`, files are layered: the user's
+//! `jj-starship.toml` (`$JJ_STARSHIP_CONFIG`, else in `$XDG_CONFIG_HOME` or
+//! `~/.config`), overridden by the repo's `.jj-starship.toml` if the repo is
+//! trusted (see `trust`). An option is taken from the CLI, else its env var,
+//! else the repo file, else the user file, else its default. Flags read from
+//! the env can be turned off with a false value like `JJ_STARSHIP_DIFFSTAT=0`.
+//!
+//! A named profile (`--profile <NAME>`, `JJ_STARSHIP_PROFILE` or a `profile`
+//! key) layers the keys of the files' `[profiles.<NAME>]` tables over all of
+//! their top-level keys, so one file can describe e.g. a rich left prompt
+//! and a terse right prompt.
+//!
+//! The repo file is found with the discovery options (`max_depth`,
+//! `cross_filesystems`, `no_resolve_symlinks`) of the CLI, env and user
+//! file, and the prompt keeps those: a repo file can't move the root it was
+//! found at.
+
+use crate::config::{self, ConfigArgs};
+use crate::detect::{self, Discovery};
+use crate::trust::TrustStore;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Value};
+
+/// Prefix of the env vars, dropped from the file keys
+const ENV_PREFIX: &str = "JJ_STARSHIP_";
+
+/// Table of the named profiles in a config file
+const PROFILES_KEY: &str = "profiles";
+
+/// Name of the per-repo config file at the repo root
+const REPO_FILE: &str = ".jj-starship.toml";
+
+/// Env vars and the config files read, highest precedence first
+#[derive(Debug, Default)]
+pub struct Sources {
+    files: Vec<(PathBuf, DocumentMut)>,
+    /// Selected profile, whose tables are read before the top-level keys
+    profile: Option<String>,
+    /// Discovery options the repo file was found with
+    located: Option<(bool, Discovery)>,
+}
+
+impl Sources {
+    /// The user's config file and, if trusted, the config file of the repo
+    /// containing `cwd`, with `profile` selected; missing and malformed files
+    /// are skipped
+    #[must_use]
+    pub fn load(cwd: &Path, profile: Option<String>, args: &ConfigArgs) -> Self {
+        Self::default().read_files(cwd, profile, args)
+    }
+
+    /// `load` below the files already given: the repo file is then looked
+    /// for with their discovery options too
+    #[must_use]
+    pub(crate) fn read_files(
+        mut self,
+        cwd: &Path,
+        profile: Option<String>,
+        args: &ConfigArgs,
+    ) -> Self {
+        let given = self.files.len();
+        self.files.extend(user_file().and_then(read_file));
+        self.select_profile(profile.clone());
+        let (resolve_symlinks, discovery) = config::discovery(args, &self);
+        let start = detect::start_dir(cwd.to_path_buf(), resolve_symlinks);
+        if let Some(file) = repo_file(&start, discovery).and_then(read_file) {
+            self.files.insert(given, file);
+        }
+        self.located = Some((resolve_symlinks, discovery));
+        self.select_profile(profile);
+        self
+    }
+
+    /// Sources with the given files, highest precedence first
+    #[cfg(test)]
+    pub(crate) fn with_files(files: &[&str]) -> Self {
+        Self {
+            files: files
+                .iter()
+                .map(|text| (PathBuf::new(), text.parse().unwrap()))
+                .collect(),
+            profile: None,
+            located: None,
+        }
+    }
+
+    /// Sources with `doc` read before the files, like a config file of
+    /// higher precedence than theirs
+    #[cfg(feature = "python")]
+    #[must_use]
+    pub fn with_document(mut self, doc: DocumentMut) -> Self {
+        self.files.insert(0, (PathBuf::new(), doc));
+        self
+    }
+
+    /// Select the profile named on the CLI, else by `JJ_STARSHIP_PROFILE` or
+    /// the `profile` key; an empty name selects none
+    pub fn select_profile(&mut self, cli: Option<String>) {
+        self.profile = cli
+            .or_else(|| self.var("JJ_STARSHIP_PROFILE"))
+            .filter(|name| !name.is_empty());
+    }
+
+    /// Name of the selected profile
+    #[must_use]
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Whether symlinks are resolved and the discovery options the repo file
+    /// was looked for with, if it was
+    #[must_use]
+    pub fn located(&self) -> Option<(bool, Discovery)> {
+        self.located
+    }
+
+    /// Config files read, highest precedence first
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Value of env var `var`, else of its key in the first file setting it,
+    /// as text (numbers and booleans as written)
+    #[must_use]
+    pub fn var(&self, var: &str) -> Option<String> {
+        env::var(var).ok().or_else(|| match self.value(var)? {
+            Value::String(text) => Some(text.value().clone()),
+            Value::Integer(n) => Some(n.value().to_string()),
+            Value::Float(n) => Some(n.value().to_string()),
+            Value::Boolean(b) => Some(b.value().to_string()),
+            _ => None,
+        })
+    }
+
+    /// Whether a flag is on: given on the CLI, else its env var unless set to
+    /// a false value (so a shell can turn off what a file turns on), else true
+    /// in the first file setting it
+    pub fn flag(&self, cli: bool, var: &str) -> bool {
+        if cli {
+            return true;
+        }
+        match env::var(var) {
+            Ok(value) => !is_false(&value),
+            Err(_) => self.value(var).and_then(Value::as_bool).unwrap_or_default(),
+        }
+    }
+
+    /// Values of a list option: the env var as one value, else the first
+    /// file's array of strings (or single string)
+    #[must_use]
+    pub fn list(&self, var: &str) -> Option<Vec<String>> {
+        if let Ok(value) = env::var(var) {
+            return Some(vec![value]);
+        }
+        match self.value(var)? {
+            Value::String(text) => Some(vec![text.value().clone()]),
+            Value::Array(items) => Some(
+                items
+                    .iter()
+                    .filter_map(|item| item.as_str().map(ToString::to_string))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// File value of env var `var`'s key, from the first file's table of the
+    /// selected profile setting it, else from the first file setting it
+    fn value(&self, var: &str) -> Option<&Value> {
+        let key = var.strip_prefix(ENV_PREFIX).unwrap_or(var).to_lowercase();
+        let profile = self.profile.as_deref().and_then(|name| {
+            self.files.iter().find_map(|(_, doc)| {
+                doc.get(PROFILES_KEY)?
+                    .get(name)?
+                    .get(&key)
+                    .and_then(Item::as_value)
+            })
+        });
+        profile.or_else(|| {
+            self.files
+                .iter()
+                .find_map(|(_, doc)| doc.get(&key).and_then(Item::as_value))
+        })
+    }
+}
+
+/// Whether a flag's env var value turns it off: `0`, `false`, `no` or `off`
+/// in any case; anything else, even empty, turns it on
+fn is_false(value: &str) -> bool {
+    ["0", "false", "no", "off"]
+        .iter()
+        .any(|off| value.trim().eq_ignore_ascii_case(off))
+}
+
+/// The user's config file: `$JJ_STARSHIP_CONFIG`, else `jj-starship.toml` in
+/// `$XDG_CONFIG_HOME` or `~/.config`
+fn user_file() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("JJ_STARSHIP_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config")))?;
+    Some(dir.join("jj-starship.toml"))
+}
+
+/// Parsed config file at `path`
+fn read_file(path: PathBuf) -> Option<(PathBuf, DocumentMut)> {
+    let doc = fs::read_to_string(&path).ok()?.parse().ok()?;
+    Some((path, doc))
+}
+
+/// Config file of the trusted repo containing `start`
+fn repo_file(start: &Path, discovery: Discovery) -> Option<PathBuf> {
+    let root = detect::detect(start, discovery).repo_root?;
+    let path = root.join(REPO_FILE);
+    (path.is_file() && TrustStore::open().is_some_and(|store| store.is_trusted(&root)))
+        .then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ConfigArgs};
+
+    #[test]
+    fn test_layers() {
+        let sources = Sources::with_files(&[
+            "id_length = 6\nasync = false\n",
+            "id_length = 12\nasync = true\ndiffstat = true\njj_symbol = \"jj \"\n",
+        ]);
+        // The first file setting a key wins, numbers read as written
+        assert_eq!(sources.var("JJ_STARSHIP_ID_LENGTH").as_deref(), Some("6"));
+        assert_eq!(sources.var("JJ_STARSHIP_JJ_SYMBOL").as_deref(), Some("jj "));
+        assert!(!sources.flag(false, "JJ_STARSHIP_ASYNC"));
+        assert!(sources.flag(false, "JJ_STARSHIP_DIFFSTAT"));
+        assert!(sources.flag(true, "JJ_STARSHIP_ASYNC"));
+        assert_eq!(sources.var("JJ_STARSHIP_UNSET_OPTION"), None);
+    }
+
+    #[test]
+    fn test_config_from_files() {
+        let sources = Sources::with_files(&[
+            "id_length = 6\ndiffstat = true\nno_jj_color = true\ntrailers = [\"Fixes\", \"Refs\"]\n",
+            "no_symbol = true\nconfig_toml = 'user.name = \"A\"'\n",
+        ]);
+        let config = Config::new(ConfigArgs::default(), &sources);
+        assert_eq!(config.id_length, 6);
+        assert!(config.diff_stat.enabled);
+        assert!(!config.jj_display.show_color);
+        assert_eq!(config.trailers, ["Fixes", "Refs"]);
+        assert_eq!(config.jj_symbol, "");
+        assert_eq!(config.jj_config_toml, ["user.name = \"A\""]);
+
+        // The CLI wins over the files
+        let args = ConfigArgs {
+            id_length: Some(4),
+            ..ConfigArgs::default()
+        };
+        assert_eq!(Config::new(args, &sources).id_length, 4);
+    }
+
+    #[test]
+    fn test_located() {
+        let mut sources = Sources::with_files(&["max_depth = 1\nno_resolve_symlinks = true\n"]);
+        let (resolve_symlinks, discovery) = config::discovery(&ConfigArgs::default(), &sources);
+        assert!(!resolve_symlinks);
+        assert_eq!(discovery.max_depth, Some(1));
+
+        // A file read after the repo file was found can't move its root
+        sources.located = Some((true, Discovery::default()));
+        let config = Config::new(ConfigArgs::default(), &sources);
+        assert!(config.resolve_symlinks);
+        assert_eq!(config.discovery.max_depth, None);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut sources = Sources::with_files(&[
+            "diffstat = true\n[profiles.rprompt]\nformat = \"$change_id\"\n",
+            "profile = \"rprompt\"\nid_length = 6\n[profiles.rprompt]\ndiffstat = false\n",
+        ]);
+        assert!(sources.flag(false, "JJ_STARSHIP_DIFFSTAT"));
+        sources.select_profile(None);
+        assert_eq!(sources.profile(), Some("rprompt"));
+        // Any file's profile table wins over the top-level keys of all files
+        assert!(!sources.flag(false, "JJ_STARSHIP_DIFFSTAT"));
+        assert_eq!(
+            sources.var("JJ_STARSHIP_FORMAT").as_deref(),
+            Some("$change_id")
+        );
+        assert_eq!(sources.var("JJ_STARSHIP_ID_LENGTH").as_deref(), Some("6"));
+
+        sources.select_profile(Some("left".into()));
+        assert!(sources.flag(false, "JJ_STARSHIP_DIFFSTAT"));
+        assert_eq!(sources.var("JJ_STARSHIP_FORMAT"), None);
+
+        // The built-in minimal profile leaves only the core segments
+        sources.select_profile(Some("minimal".into()));
+        let config = Config::new(ConfigArgs::default(), &sources);
+        assert!(!config.diff_stat.enabled);
+        assert!(config.jj_display.show_color);
+        assert_eq!(config.id_length, 6);
+    }
+
+    #[test]
+    fn test_is_false() {
+        for value in ["0", "false", "FALSE", "no", "Off", " 0 "] {
+            assert!(is_false(value), "{value:?}");
+        }
+        for value in ["", "1", "true", "yes", "on"] {
+            assert!(!is_false(value), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        let sources =
+            Sources::with_files(&["wip_pattern = [\"^wip\", \"^tmp\"]\ntrailers = \"Fixes\"\n"]);
+        assert_eq!(
+            sources.list("JJ_STARSHIP_WIP_PATTERN").unwrap(),
+            ["^wip", "^tmp"]
+        );
+        assert_eq!(sources.list("JJ_STARSHIP_TRAILERS").unwrap(), ["Fixes"]);
+        assert_eq!(sources.list("JJ_STARSHIP_ISSUE_PATTERN"), None);
+    }
+}
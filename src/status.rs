@@ -0,0 +1,45 @@
+//! Repo state as a typed value, for embedding jj-starship in other tools
+//!
+//! The binary is one front-end over this: it gathers a `RepoStatus` and
+//! renders it as a prompt. Status bars and editor plugins can gather the
+//! same state and read the fields they need instead of parsing the prompt.
+
+use crate::collect;
+use crate::config::Config;
+use crate::detect::{self, RepoType};
+use crate::output::{self, Capabilities, PromptData};
+use std::path::{Path, PathBuf};
+
+/// State of the repo containing a directory
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RepoStatus {
+    /// Kind of repo: JJ, colocated JJ or Git
+    pub repo_type: RepoType,
+    /// Root of the workspace (JJ) or worktree (Git)
+    pub root: PathBuf,
+    /// Everything read from the repo, which segments `options` enabled
+    pub info: PromptData,
+}
+
+impl RepoStatus {
+    /// Gather the state of the repo containing `path`, with the segments
+    /// `options` enables (`Config::default()` for the default prompt's). None
+    /// outside a repo, or if the repo can't be read.
+    #[must_use]
+    pub fn gather(path: &Path, options: &Config) -> Option<Self> {
+        let result = detect::detect(path, options.discovery);
+        let info = collect(&result, options)?;
+        Some(Self {
+            repo_type: result.repo_type,
+            root: result.repo_root?,
+            info,
+        })
+    }
+
+    /// The prompt for this state, as the binary prints it
+    #[must_use]
+    pub fn render(&self, options: &Config, caps: Capabilities) -> String {
+        output::render(&self.info, options, caps)
+    }
+}
@@ -0,0 +1,195 @@
+//! Starship style strings and per-segment styles
+//!
+//! A style string is starship's: `bold purple`, `fg:#ff9e64 bg:black`,
+//! colors by name (from the starship palette first), ANSI number or hex. It
+//! styles the `[text](style)` groups of format strings and, given with
+//! `--style <SEGMENT>=<STYLE>` or `JJ_STARSHIP_STYLE_<SEGMENT>`, a segment
+//! as a whole: the segment is then rendered in that style instead of its
+//! built-in colors, like a starship module's `style` option.
+
+use crate::palette::{Palette, parse_color};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Segments that take a style, by their format string variable names
+pub const SEGMENTS: [&str; 33] = [
+    "prefix",
+    "change_id",
+    "branch",
+    "commit",
+    "bookmarks",
+    "workspace",
+    "unpushed",
+    "heads",
+    "stacks",
+    "staged",
+    "git_head",
+    "trunk",
+    "remote",
+    "conflict",
+    "clone",
+    "immutable",
+    "op_heads",
+    "issue_key",
+    "empty",
+    "description",
+    "trailers",
+    "conventional",
+    "diffstat",
+    "files",
+    "untracked",
+    "tag",
+    "stack_diff",
+    "signature",
+    "megamerge",
+    "parent",
+    "checks",
+    "template",
+    "status",
+];
+
+/// Style strings of the segments styled by the user
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Styles(BTreeMap<String, String>);
+
+impl Styles {
+    /// Styles from `segment=style` entries, later ones winning, else from
+    /// `fallback` for each segment. Unknown segments and empty styles are
+    /// skipped.
+    pub fn new(entries: &[String], fallback: impl Fn(&str) -> Option<String>) -> Self {
+        let mut styles: BTreeMap<String, String> = SEGMENTS
+            .iter()
+            .filter_map(|&name| Some((name.to_string(), fallback(name)?)))
+            .collect();
+        for entry in entries {
+            if let Some((name, style)) = entry.split_once('=') {
+                styles.insert(name.trim().to_string(), style.to_string());
+            }
+        }
+        styles.retain(|name, style| SEGMENTS.contains(&name.as_str()) && !style.trim().is_empty());
+        Self(styles)
+    }
+
+    /// Style string of a segment, None to keep its built-in colors
+    pub fn get(&self, segment: &str) -> Option<&str> {
+        self.0.get(segment).map(String::as_str)
+    }
+}
+
+/// `text` without its escape sequences
+pub fn strip_escapes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequence: ESC [ params final-byte
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Escape sequences of a starship style string such as `bold purple` or
+/// `fg:#ff9e64 bg:black`: `bold`, `dimmed`, `italic`, `underline`,
+/// `inverted` and `strikethrough`, plus colors as in starship (names from
+/// `palette` first), bare or as `fg:`/`bg:`. Unknown words are skipped.
+pub fn style_escape(style: &str, palette: &Palette) -> String {
+    let mut out = String::new();
+    for word in style.split_whitespace() {
+        let word = word.to_ascii_lowercase();
+        let attribute = match word.as_str() {
+            "bold" => Some(1),
+            "dimmed" => Some(2),
+            "italic" => Some(3),
+            "underline" => Some(4),
+            "inverted" => Some(7),
+            "strikethrough" => Some(9),
+            _ => None,
+        };
+        if let Some(code) = attribute {
+            let _ = write!(out, "\x1b[{code}m");
+            continue;
+        }
+        let (background, color) = match word.split_once(':') {
+            Some(("bg", color)) => (true, color),
+            Some(("fg", color)) => (false, color),
+            Some(_) => continue,
+            None => (false, word.as_str()),
+        };
+        let escape = palette
+            .color(color)
+            .map(str::to_string)
+            .or_else(|| parse_color(color));
+        if let Some(escape) = escape {
+            out.push_str(&if background {
+                to_background(&escape)
+            } else {
+                escape
+            });
+        }
+    }
+    out
+}
+
+/// Background equivalent of a foreground color escape
+fn to_background(escape: &str) -> String {
+    let code = escape
+        .strip_prefix("\x1b[")
+        .and_then(|rest| rest.strip_suffix('m'))
+        .unwrap_or_default();
+    let code = match code.strip_prefix("38;") {
+        Some(rest) => format!("48;{rest}"),
+        None => code
+            .parse::<u8>()
+            .map_or_else(|_| code.to_string(), |n| (n + 10).to_string()),
+    };
+    format!("\x1b[{code}m")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_escape() {
+        let palette = Palette::parse("palette = \"p\"\n[palettes.p]\naqua = \"#689d6a\"\n");
+        assert_eq!(style_escape("bold purple", &palette), "\x1b[1m\x1b[35m");
+        assert_eq!(
+            style_escape("fg:#ff9e64 bg:bright-black", &palette),
+            "\x1b[38;2;255;158;100m\x1b[100m"
+        );
+        assert_eq!(
+            style_escape("bg:aqua italic", &palette),
+            "\x1b[48;2;104;157;106m\x1b[3m"
+        );
+        assert_eq!(style_escape("none sparkly", &palette), "");
+    }
+
+    #[test]
+    fn test_styles() {
+        let fallback = |name: &str| match name {
+            "change_id" => Some("bold purple".to_string()),
+            "status" => Some("red".to_string()),
+            _ => None,
+        };
+        let styles = Styles::new(
+            &[
+                "status=fg:#ff9e64".to_string(),
+                "nonsense=red".to_string(),
+                "change_id=".to_string(),
+            ],
+            fallback,
+        );
+        // Entries win over the fallback, and an empty one restores the colors
+        assert_eq!(styles.get("status"), Some("fg:#ff9e64"));
+        assert_eq!(styles.get("change_id"), None);
+        assert_eq!(styles.get("nonsense"), None);
+        assert_eq!(styles.get("bookmarks"), None);
+    }
+}
@@ -0,0 +1,212 @@
+//! Glyphs of the prompt, as a preset set with per-glyph overrides
+//!
+//! `nerd-font` (default) is the full set; `unicode` drops the Nerd Font repo
+//! icons, which render as mojibake without a patched font; `ascii` replaces
+//! every glyph with plain ASCII for terminals and fonts with no Unicode
+//! coverage. Single glyphs are overridden with `--symbol <NAME>=<TEXT>` or
+//! `JJ_STARSHIP_SYMBOL_<NAME>`, the repo icons with `--jj-symbol` and
+//! `--git-symbol` as before.
+
+use crate::config::{DEFAULT_GIT_SYMBOL, DEFAULT_JJ_SYMBOL};
+use clap::ValueEnum;
+use std::borrow::Cow;
+
+/// Preset of glyphs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SymbolSet {
+    /// Nerd Font repo icons and Unicode glyphs
+    #[default]
+    NerdFont,
+    /// Unicode glyphs, no repo icons
+    Unicode,
+    /// Plain ASCII only
+    Ascii,
+}
+
+impl SymbolSet {
+    /// Repo icon of JJ repos
+    #[must_use]
+    pub fn jj(self) -> &'static str {
+        match self {
+            Self::NerdFont => DEFAULT_JJ_SYMBOL,
+            Self::Unicode | Self::Ascii => "",
+        }
+    }
+
+    /// Repo icon of Git repos
+    #[must_use]
+    pub fn git(self) -> &'static str {
+        match self {
+            Self::NerdFont => DEFAULT_GIT_SYMBOL,
+            Self::Unicode | Self::Ascii => "",
+        }
+    }
+}
+
+/// Names of the glyphs that can be overridden
+pub const NAMES: [&str; 15] = [
+    "ahead",
+    "behind",
+    "conflict",
+    "stack_conflict",
+    "divergent",
+    "deleted",
+    "good",
+    "bad",
+    "running",
+    "ellipsis",
+    "megamerge",
+    "stacks",
+    "bar_filled",
+    "bar_empty",
+    "prompt",
+];
+
+/// Glyphs the segments are rendered with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbols {
+    /// Commits ahead, e.g. `⇡3`; also the unsynced JJ status
+    pub ahead: Cow<'static, str>,
+    /// Commits behind, e.g. `⇣1`
+    pub behind: Cow<'static, str>,
+    /// Conflict marker, e.g. `✖3`
+    pub conflict: Cow<'static, str>,
+    /// Conflicted commit in the stack, in the JJ status
+    pub stack_conflict: Cow<'static, str>,
+    /// Divergent change, in the JJ status
+    pub divergent: Cow<'static, str>,
+    /// Deleted files, in the Git status
+    pub deleted: Cow<'static, str>,
+    /// Passed checks, good signature, conventional description
+    pub good: Cow<'static, str>,
+    /// Failed checks, bad signature, unconventional description
+    pub bad: Cow<'static, str>,
+    /// Checks still running
+    pub running: Cow<'static, str>,
+    /// Cut text and segments still being computed
+    pub ellipsis: Cow<'static, str>,
+    /// Megamerge composition, e.g. `⊕{a,b}`
+    pub megamerge: Cow<'static, str>,
+    /// Other stacks on trunk, e.g. `⌥4`
+    pub stacks: Cow<'static, str>,
+    /// Filled block of the diffstat bar
+    pub bar_filled: Cow<'static, str>,
+    /// Empty block of the diffstat bar
+    pub bar_empty: Cow<'static, str>,
+    /// Prompt character of the standalone prompt
+    pub prompt: Cow<'static, str>,
+}
+
+impl Default for Symbols {
+    fn default() -> Self {
+        Self::preset(SymbolSet::default())
+    }
+}
+
+impl Symbols {
+    /// Glyphs of a preset
+    #[must_use]
+    pub fn preset(set: SymbolSet) -> Self {
+        let unicode = set != SymbolSet::Ascii;
+        let pick = |unicode_glyph: &'static str, ascii: &'static str| {
+            Cow::Borrowed(if unicode { unicode_glyph } else { ascii })
+        };
+        Self {
+            ahead: pick("⇡", "^"),
+            behind: pick("⇣", "v"),
+            conflict: pick("✖", "x"),
+            stack_conflict: pick("×", "*"),
+            divergent: pick("⇔", "<>"),
+            deleted: pick("✘", "x"),
+            good: pick("✓", "+"),
+            bad: pick("✗", "x"),
+            running: pick("●", "*"),
+            ellipsis: pick("…", "..."),
+            megamerge: pick("⊕", "&"),
+            stacks: pick("⌥", "%"),
+            bar_filled: pick("▰", "#"),
+            bar_empty: pick("▱", "-"),
+            prompt: pick("❯", ">"),
+        }
+    }
+
+    /// Glyphs of a preset with `name=text` overrides, later ones winning,
+    /// else from `fallback` for each glyph. Unknown names are skipped, and
+    /// empty bar blocks keep the preset's so the bar stays visible.
+    pub fn new(
+        set: SymbolSet,
+        overrides: &[String],
+        fallback: impl Fn(&str) -> Option<String>,
+    ) -> Self {
+        let mut symbols = Self::preset(set);
+        let entries = NAMES
+            .iter()
+            .filter_map(|&name| Some((name.to_string(), fallback(name)?)))
+            .chain(overrides.iter().filter_map(|entry| {
+                let (name, text) = entry.split_once('=')?;
+                Some((name.trim().to_string(), text.to_string()))
+            }));
+        for (name, text) in entries {
+            let blank = text.is_empty() && name.starts_with("bar_");
+            if let Some(slot) = symbols.slot(&name).filter(|_| !blank) {
+                *slot = Cow::Owned(text);
+            }
+        }
+        symbols
+    }
+
+    fn slot(&mut self, name: &str) -> Option<&mut Cow<'static, str>> {
+        Some(match name {
+            "ahead" => &mut self.ahead,
+            "behind" => &mut self.behind,
+            "conflict" => &mut self.conflict,
+            "stack_conflict" => &mut self.stack_conflict,
+            "divergent" => &mut self.divergent,
+            "deleted" => &mut self.deleted,
+            "good" => &mut self.good,
+            "bad" => &mut self.bad,
+            "running" => &mut self.running,
+            "ellipsis" => &mut self.ellipsis,
+            "megamerge" => &mut self.megamerge,
+            "stacks" => &mut self.stacks,
+            "bar_filled" => &mut self.bar_filled,
+            "bar_empty" => &mut self.bar_empty,
+            "prompt" => &mut self.prompt,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_preset_is_ascii() {
+        let mut symbols = Symbols::preset(SymbolSet::Ascii);
+        for name in NAMES {
+            let glyph = symbols.slot(name).unwrap();
+            assert!(!glyph.is_empty() && glyph.is_ascii(), "{name}: {glyph}");
+        }
+        assert!(SymbolSet::Ascii.jj().is_ascii() && SymbolSet::Ascii.git().is_ascii());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let fallback = |name: &str| match name {
+            "ahead" => Some("↑".to_string()),
+            "behind" => Some("↓".to_string()),
+            _ => None,
+        };
+        let symbols = Symbols::new(
+            SymbolSet::Unicode,
+            &["behind=v".into(), "bar_empty=".into(), "sparkles=*".into()],
+            fallback,
+        );
+        // Overrides win over the fallback, which wins over the preset
+        assert_eq!(symbols.ahead, "↑");
+        assert_eq!(symbols.behind, "v");
+        assert_eq!(symbols.bar_empty, "▱");
+        assert_eq!(symbols.prompt, "❯");
+    }
+}
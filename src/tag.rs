@@ -0,0 +1,117 @@
+//! Nearest tag and the commits since it (like `git describe`), shared by the
+//! JJ and Git backends
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Ancestors are searched for a tag up to this many parent steps, and at most
+/// this many commits are counted since it
+pub const MAX_TAG_DISTANCE: usize = 1000;
+
+/// Nearest tag reachable from the working copy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TagDistance {
+    /// Tag name
+    pub tag: String,
+    /// Commits reachable from the working copy but not from the tag
+    pub distance: usize,
+}
+
+impl TagDistance {
+    /// Cache fields (tag names never contain whitespace)
+    pub fn to_fields(&self) -> [String; 2] {
+        [self.tag.clone(), self.distance.to_string()]
+    }
+
+    pub fn from_fields(fields: &[String]) -> Option<Self> {
+        match fields {
+            [tag, distance] => Some(Self {
+                tag: tag.clone(),
+                distance: distance.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Short form of a version tag: the prefix before the first digit (`v`,
+/// `release-`, ...) and build metadata dropped, and a zero patch omitted,
+/// e.g. `v1.2.0` as `1.2` and `release-2.0.1-rc.1` as `2.0.1-rc.1`
+/// None if the tag isn't a `MAJOR.MINOR.PATCH` version.
+pub fn short_version(tag: &str) -> Option<String> {
+    let version = &tag[tag.find(|c: char| c.is_ascii_digit())?..];
+    let version = version.split_once('+').map_or(version, |(v, _)| v);
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) if !pre.is_empty() => (core, Some(pre)),
+        Some(_) => return None,
+        None => (version, None),
+    };
+    let numbers: Vec<&str> = core.split('.').collect();
+    let [major, minor, patch] = numbers[..] else {
+        return None;
+    };
+    let numeric = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if !(numeric(major) && numeric(minor) && numeric(patch)) {
+        return None;
+    }
+    let mut short = if patch == "0" {
+        format!("{major}.{minor}")
+    } else {
+        format!("{major}.{minor}.{patch}")
+    };
+    if let Some(pre) = pre {
+        short.push('-');
+        short.push_str(pre);
+    }
+    Some(short)
+}
+
+/// Fingerprint of the repo's tags and their targets, part of cache keys so a
+/// new or moved tag is a miss
+pub fn tags_fingerprint<'a>(tags: impl IntoIterator<Item = (&'a str, String)>) -> String {
+    let mut tags: Vec<_> = tags.into_iter().collect();
+    tags.sort();
+    let mut hasher = DefaultHasher::new();
+    tags.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_roundtrip() {
+        let tag = TagDistance {
+            tag: "v1.2.0".into(),
+            distance: 5,
+        };
+        assert_eq!(TagDistance::from_fields(&tag.to_fields()), Some(tag));
+        assert_eq!(TagDistance::from_fields(&["v1".into()]), None);
+    }
+
+    #[test]
+    fn test_short_version() {
+        for (tag, short) in [
+            ("v1.2.0", Some("1.2")),
+            ("1.2.3", Some("1.2.3")),
+            ("release-2.0.1-rc.1", Some("2.0.1-rc.1")),
+            ("v3.0.0-beta+build.5", Some("3.0-beta")),
+            ("pkg@0.10.0", Some("0.10")),
+            ("v1.2", None),
+            ("nightly", None),
+            ("v1.2.x", None),
+        ] {
+            assert_eq!(short_version(tag).as_deref(), short, "{tag}");
+        }
+    }
+
+    #[test]
+    fn test_tags_fingerprint_order_independent() {
+        let a = tags_fingerprint([("v1", "aa".to_string()), ("v2", "bb".to_string())]);
+        let b = tags_fingerprint([("v2", "bb".to_string()), ("v1", "aa".to_string())]);
+        assert_eq!(a, b);
+        let moved = tags_fingerprint([("v1", "aa".to_string()), ("v2", "cc".to_string())]);
+        assert_ne!(a, moved);
+    }
+}
@@ -0,0 +1,173 @@
+//! Segment from a jj template expression, as in `jj log -T`
+//!
+//! jj's template language lives in jj-cli rather than jj-lib, so the `jj`
+//! binary evaluates the expression, pinned to the operation the rest of the
+//! prompt was read at. Spawning it costs more than a segment should, so
+//! results are cached per operation and, with `--async`, computed by the
+//! background refresh.
+//!
+//! The prompt's `--config-toml` and `--trunk-revset` reach `jj` as `--config`
+//! options, so aliases used in the expression resolve as in the rest of the
+//! prompt.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use toml_edit::{DocumentMut, Item, Key, TableLike, Value};
+
+/// Seconds a failed evaluation is remembered before `jj` is tried again, so
+/// a broken template doesn't spawn it on every prompt
+pub const ERROR_TTL_SECS: usize = 30;
+
+/// Cache key of `template` evaluated for the working copy `commit_id` at
+/// operation `op_id` with the `--config` options `jj_config` (the expression
+/// and options are part of it, so editing them is a miss)
+pub fn cache_key(op_id: &str, commit_id: &str, template: &str, jj_config: &[String]) -> String {
+    let mut hasher = DefaultHasher::new();
+    (op_id, commit_id, template, jj_config).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// `--config NAME=VALUE` options layering the TOML snippets `overrides`, then
+/// `trunk` as the `trunk()` alias, over `jj`'s config; invalid snippets are
+/// skipped, as by the prompt
+pub fn config_args(overrides: &[String], trunk: Option<&str>) -> Vec<String> {
+    let mut args = Vec::new();
+    for toml in overrides {
+        if let Ok(doc) = toml.parse::<DocumentMut>() {
+            push_values(&mut args, "", doc.as_table());
+        }
+    }
+    if let Some(trunk) = trunk {
+        args.push(format!("revset-aliases.\"trunk()\"={}", Value::from(trunk)));
+    }
+    args.into_iter()
+        .flat_map(|arg| ["--config".to_string(), arg])
+        .collect()
+}
+
+/// `NAME=VALUE` of each value in `table`, its names dotted below `prefix`
+fn push_values(args: &mut Vec<String>, prefix: &str, table: &dyn TableLike) {
+    for (key, item) in table.iter() {
+        let name = format!("{prefix}{}", Key::new(key));
+        match item {
+            Item::Value(Value::InlineTable(table)) => {
+                push_values(args, &format!("{name}."), table);
+            }
+            Item::Value(value) => {
+                let value = value.clone().decorated("", "");
+                args.push(format!("{name}={value}"));
+            }
+            Item::Table(table) => push_values(args, &format!("{name}."), table),
+            Item::None | Item::ArrayOfTables(_) => {}
+        }
+    }
+}
+
+/// Evaluate `template` against the working copy commit of the workspace at
+/// `repo_root`, as of operation `op_id`, with the `--config` options
+/// `jj_config`
+///
+/// # Errors
+/// Why it failed: `jj` missing, or its first line of error output, e.g. for
+/// an invalid expression
+pub fn evaluate(
+    repo_root: &Path,
+    op_id: &str,
+    template: &str,
+    jj_config: &[String],
+) -> Result<String, String> {
+    let output = Command::new("jj")
+        .arg("--repository")
+        .arg(repo_root)
+        .args(["--at-operation", op_id])
+        .args(["--ignore-working-copy", "--no-pager", "--color", "never"])
+        .args(jj_config)
+        .args([
+            "log",
+            "--no-graph",
+            "--revisions",
+            "@",
+            "--template",
+            template,
+        ])
+        .stdin(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            Ok(single_line(&String::from_utf8_lossy(&output.stdout)))
+        }
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let error = stderr.lines().map(str::trim).find(|line| !line.is_empty());
+            Err(error.unwrap_or("`jj` failed").to_string())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err("`jj` not found".to_string()),
+        Err(e) => Err(format!("couldn't run `jj`: {e}")),
+    }
+}
+
+/// Template output as one prompt line: lines joined by spaces, blank ones dropped
+fn single_line(output: &str) -> String {
+    output
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_line() {
+        assert_eq!(single_line("main fix the parser\n"), "main fix the parser");
+        assert_eq!(single_line("a\n\nb  \n"), "a b");
+        assert_eq!(single_line(""), "");
+    }
+
+    #[test]
+    fn test_cache_key_covers_inputs() {
+        let key = cache_key("op1", "abc", "bookmarks", &[]);
+        assert_eq!(key, cache_key("op1", "abc", "bookmarks", &[]));
+        assert_ne!(key, cache_key("op2", "abc", "bookmarks", &[]));
+        assert_ne!(key, cache_key("op1", "def", "bookmarks", &[]));
+        assert_ne!(key, cache_key("op1", "abc", "description", &[]));
+        let trunk = config_args(&[], Some("main"));
+        assert_ne!(key, cache_key("op1", "abc", "bookmarks", &trunk));
+    }
+
+    #[test]
+    fn test_evaluate_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        // `jj` missing, or no repo there
+        assert!(evaluate(tmp.path(), "@", "description", &[]).is_err());
+    }
+
+    #[test]
+    fn test_config_args() {
+        let overrides = [
+            "ui.color = 'never'\n[revset-aliases]\n'mine()' = 'author(a)'\n".to_string(),
+            "user = { name = 'A', email = 'a@b' }".to_string(),
+            "not toml [".to_string(),
+        ];
+        assert_eq!(
+            config_args(&overrides, Some("main@origin")),
+            [
+                "--config",
+                "ui.color='never'",
+                "--config",
+                "revset-aliases.\"mine()\"='author(a)'",
+                "--config",
+                "user.name='A'",
+                "--config",
+                "user.email='a@b'",
+                "--config",
+                "revset-aliases.\"trunk()\"=\"main@origin\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! `--debug-timing`: where the time of one prompt goes
+//!
+//! Collection records a span for each phase (loading the workspace, resolving
+//! the op heads, each segment) and rendering one more; the report lists them
+//! by when they started, so segments gathered at the same time on their own
+//! threads show up as overlapping spans rather than adding up.
+
+use crate::config::Config;
+use crate::detect;
+use crate::output::{self, Capabilities};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// One timed phase
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    name: &'static str,
+    /// Since the timings started
+    start: Duration,
+    elapsed: Duration,
+}
+
+/// Spans of the phases of one prompt, recorded from any thread
+#[derive(Debug)]
+pub struct Timings {
+    origin: Instant,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Timings {
+    /// Run `phase`, recording how long it took under `name`
+    pub fn time<T>(&self, name: &'static str, phase: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = phase();
+        let span = Span {
+            name,
+            start: start - self.origin,
+            elapsed: start.elapsed(),
+        };
+        if let Ok(mut spans) = self.spans.lock() {
+            spans.push(span);
+        }
+        value
+    }
+
+    /// Names of the recorded phases, by when they started
+    #[must_use]
+    pub fn names(&self) -> Vec<&'static str> {
+        self.sorted().iter().map(|span| span.name).collect()
+    }
+
+    /// Table of the phases by when they started, with the total at the end
+    #[must_use]
+    pub fn report(&self) -> String {
+        let mut out = format!("{:<16} {:>10} {:>10}\n", "phase", "start", "time");
+        for span in self.sorted() {
+            let _ = writeln!(
+                out,
+                "{:<16} {:>10.2?} {:>10.2?}",
+                span.name, span.start, span.elapsed
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{:<16} {:>10} {:>10.2?}",
+            "total",
+            "",
+            self.origin.elapsed()
+        );
+        out
+    }
+
+    fn sorted(&self) -> Vec<Span> {
+        let mut spans = self
+            .spans
+            .lock()
+            .map(|spans| spans.clone())
+            .unwrap_or_default();
+        spans.sort_by_key(|span| span.start);
+        spans
+    }
+}
+
+/// The prompt for `cwd` (None outside a repo or if it can't be read) with
+/// the timings of its phases
+#[must_use]
+pub fn run(cwd: &Path, config: &Config, caps: Capabilities) -> (Option<String>, Timings) {
+    let timings = Timings::default();
+    let result = timings.time("detect", || detect::detect(cwd, config.discovery));
+    let prompt = crate::collect_timed(&result, config, &timings)
+        .map(|data| timings.time("render", || output::render(&data, config, caps)));
+    (prompt, timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_spans_by_start() {
+        let timings = Timings::default();
+        let answer = timings.time("outer", || {
+            thread::scope(|scope| {
+                scope
+                    .spawn(|| timings.time("threaded", || thread::sleep(Duration::from_millis(5))));
+            });
+            42
+        });
+        assert_eq!(answer, 42);
+        timings.time("render", || ());
+        // The outer span started first but ended after the threaded one
+        assert_eq!(timings.names(), ["outer", "threaded", "render"]);
+        let report = timings.report();
+        assert!(report.starts_with("phase"));
+        assert!(report.lines().last().unwrap().starts_with("total"));
+    }
+}
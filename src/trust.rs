@@ -0,0 +1,161 @@
+//! Repos trusted to configure jj-starship themselves
+//!
+//! Configuration a repo ships (a `.jj-starship.toml`, commands it asks the
+//! prompt to run) comes from whoever wrote the repo, and a prompt renders in
+//! every directory you `cd` into, including freshly cloned ones. Like git's
+//! `safe.directory`, such configuration is only honored in repos listed in
+//! the trust file, managed with `jj-starship trust add/remove/list`.
+//!
+//! The trust file (`$XDG_CONFIG_HOME/jj-starship/trusted`, else
+//! `~/.config/jj-starship/trusted`) lists one repo root per line; a `*` line
+//! trusts every repo.
+
+use crate::detect::{self, Discovery};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Trust file line trusting every repo
+const TRUST_ALL: &str = "*";
+
+/// The set of trusted repos, stored in a file
+#[derive(Debug, Clone)]
+pub struct TrustStore {
+    path: PathBuf,
+}
+
+impl TrustStore {
+    /// Store at the default location (None without a home directory)
+    pub fn open() -> Option<Self> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config")))?;
+        Some(Self::at(dir.join("jj-starship/trusted")))
+    }
+
+    /// Store in the file at `path`
+    #[must_use]
+    pub const fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Trusted repo roots (and `*`) in the order they were added
+    pub fn list(&self) -> Vec<String> {
+        fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Whether the repo at `repo_root` is trusted
+    #[must_use]
+    pub fn is_trusted(&self, repo_root: &Path) -> bool {
+        let root = normalize(repo_root);
+        self.list()
+            .iter()
+            .any(|line| line == TRUST_ALL || Path::new(line) == root)
+    }
+
+    /// Trust the repo at `repo_root`; false if it already was
+    ///
+    /// # Errors
+    /// The trust file couldn't be written
+    pub fn add(&self, repo_root: &Path) -> io::Result<bool> {
+        let root = normalize(repo_root);
+        let mut entries = self.list();
+        if entries.iter().any(|line| Path::new(line) == root) {
+            return Ok(false);
+        }
+        entries.push(root.display().to_string());
+        self.save(&entries)?;
+        Ok(true)
+    }
+
+    /// Stop trusting the repo at `repo_root`; false if it wasn't trusted
+    ///
+    /// # Errors
+    /// The trust file couldn't be written
+    pub fn remove(&self, repo_root: &Path) -> io::Result<bool> {
+        let root = normalize(repo_root);
+        let mut entries = self.list();
+        let before = entries.len();
+        entries.retain(|line| Path::new(line) != root);
+        if entries.len() == before {
+            return Ok(false);
+        }
+        self.save(&entries)?;
+        Ok(true)
+    }
+
+    fn save(&self, entries: &[String]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = entries.join("\n");
+        contents.push('\n');
+        fs::write(&self.path, contents)
+    }
+}
+
+/// Root of the repo containing `dir` (or `dir` itself outside a repo), the
+/// key trust is recorded under
+#[must_use]
+pub fn repo_root_of(dir: &Path) -> PathBuf {
+    detect::detect(dir, Discovery::default())
+        .repo_root
+        .unwrap_or_else(|| dir.to_path_buf())
+}
+
+/// Absolute path without symlinks, so one repo has one entry however it was
+/// reached (`*` is kept for `trust add '*'`)
+fn normalize(path: &Path) -> PathBuf {
+    if path.as_os_str() == TRUST_ALL {
+        return path.to_path_buf();
+    }
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_remove_list() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = TrustStore::at(tmp.path().join("config/trusted"));
+        let repo = tmp.path().join("repo");
+        fs::create_dir(&repo).unwrap();
+
+        assert!(store.list().is_empty());
+        assert!(!store.is_trusted(&repo));
+
+        assert!(store.add(&repo).unwrap());
+        assert!(!store.add(&repo).unwrap());
+        assert!(store.is_trusted(&repo));
+        // The same repo reached through `..` is the same entry
+        assert!(store.is_trusted(&repo.join("../repo")));
+        assert_eq!(store.list().len(), 1);
+
+        assert!(store.remove(&repo).unwrap());
+        assert!(!store.remove(&repo).unwrap());
+        assert!(!store.is_trusted(&repo));
+    }
+
+    #[test]
+    fn test_trust_all() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = TrustStore::at(tmp.path().join("trusted"));
+        fs::write(store.path(), "# every repo is mine\n*\n").unwrap();
+        assert!(store.is_trusted(tmp.path()));
+        assert_eq!(store.list(), ["*"]);
+    }
+}
@@ -0,0 +1,96 @@
+//! `jj-starship watch`: keep printing the prompt as the repo changes
+//!
+//! Prints one line per change (nothing while the output stays the same), for
+//! tmux status lines, editor panes and desktop widgets reading stdout.
+//! By default the prompt is re-rendered every interval. With `--events`, the
+//! repo's metadata (see `DetectResult::watch_paths`) is checked several times
+//! a second and a change re-renders at once; the interval then only matters
+//! for changes the metadata doesn't reflect, like edits in a Git worktree.
+
+use crate::config::Config;
+use crate::detect;
+use crate::output::{self, Capabilities};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often `--events` checks the repo metadata
+const EVENT_POLL: Duration = Duration::from_millis(250);
+
+/// Modification times of `paths` (None for missing ones)
+#[must_use]
+pub fn fingerprint(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Print the prompt for `cwd` whenever it changes, until stdout is closed
+pub fn run(cwd: &Path, config: &Config, caps: Capabilities, interval: Duration, events: bool) {
+    let poll = if events {
+        EVENT_POLL.min(interval)
+    } else {
+        interval
+    };
+    let mut stdout = io::stdout().lock();
+    let mut last_output: Option<String> = None;
+    let mut last_render = Instant::now();
+    let mut last_fingerprint = Vec::new();
+
+    loop {
+        let result = detect::detect(cwd, config.discovery);
+        let changed = events && {
+            let current = fingerprint(&result.watch_paths());
+            current != std::mem::replace(&mut last_fingerprint, current.clone())
+        };
+        if last_output.is_none() || changed || last_render.elapsed() >= interval {
+            last_render = Instant::now();
+            // Outside a repo (or on errors) the line is empty, as in a prompt
+            let output = crate::collect(&result, config)
+                .map(|data| output::render(&data, config, caps))
+                .unwrap_or_default();
+            if last_output.as_ref() != Some(&output) {
+                // A closed pipe means the reader is gone
+                if writeln!(stdout, "{output}")
+                    .and_then(|()| stdout.flush())
+                    .is_err()
+                {
+                    return;
+                }
+                last_output = Some(output);
+            }
+        }
+        thread::sleep(poll);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_tracks_mtime() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("HEAD");
+        let missing = tmp.path().join("index");
+        let paths = [file.clone(), missing];
+
+        fs::write(&file, "ref: refs/heads/main\n").unwrap();
+        let before = fingerprint(&paths);
+        assert!(before[0].is_some());
+        assert_eq!(before[1], None);
+        assert_eq!(fingerprint(&paths), before);
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_ne!(fingerprint(&paths), before);
+    }
+}